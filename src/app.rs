@@ -1,11 +1,16 @@
 use {
     crate::{
+        auth,
+        keys::{KeyConfig, KeyRing},
+        local::{LOCAL_PROVIDER, LocalAuthConfig},
+        oauth::ProviderRegistry,
+        ratelimit::{self, RateLimitConfig},
         routes,
-        state::{AppState, DiscordOAuthConfig, GithubOAuthConfig, OsuOAuthConfig},
+        state::{AppState, UploadLimits},
     },
-    color_eyre::eyre::Context,
+    color_eyre::eyre::{Context, eyre},
     hashbrown::{HashMap, HashSet},
-    rocket::{Build, Rocket, routes},
+    rocket::{Build, Rocket, catchers, routes},
     rocket_async_compression::Compression,
     rocket_dyn_templates::Template,
     tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt},
@@ -49,45 +54,51 @@ pub fn run() -> Rocket<Build> {
     }
 
     let _ = dotenvy::dotenv();
-    let oauth_config = OsuOAuthConfig::from_env()
+    let local_auth = LocalAuthConfig::from_env();
+    let dev_login = std::env::var("DEV_LOGIN")
+        .is_ok_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"));
+    let providers = ProviderRegistry::from_env()
+        .and_then(|providers| {
+            if providers.is_empty() && !local_auth.enabled && !dev_login {
+                Err(eyre!(
+                    "no login methods configured; set OSU_*, GITHUB_* or DISCORD_* \
+                     credentials, OAUTH_PROVIDERS, OAUTH_PROVIDERS_FILE, LOCAL_ACCOUNTS \
+                     or DEV_LOGIN"
+                ))
+            } else {
+                Ok(providers)
+            }
+        })
         .wrap_err("Failed to load OAuth configuration")
         .expect("OAuth config error");
-
-    let github_oauth = GithubOAuthConfig::from_env();
-    if github_oauth.is_some() {
-        tracing::info!("GitHub OAuth configured.");
-    } else {
+    tracing::info!(
+        "Login providers: {}",
+        providers.names().collect::<Vec<_>>().join(", ")
+    );
+    if local_auth.enabled {
         tracing::info!(
-            "GitHub OAuth not configured (set GITHUB_CLIENT_ID, GITHUB_CLIENT_SECRET, GITHUB_REDIRECT_URI to enable)."
+            "Local accounts enabled, registration: {:?}",
+            local_auth.registration
         );
     }
-
-    let discord_oauth = DiscordOAuthConfig::from_env();
-    if discord_oauth.is_some() {
-        tracing::info!("Discord OAuth configured.");
-    } else {
-        tracing::info!(
-            "Discord OAuth not configured (set DISCORD_CLIENT_ID, DISCORD_CLIENT_SECRET, DISCORD_REDIRECT_URI to enable)."
-        );
+    if dev_login {
+        tracing::warn!("DEV_LOGIN is enabled: anyone can sign in as any user with any role");
     }
 
     let mut admin_ids: HashMap<String, HashSet<u64>> = HashMap::new();
-
-    let osu_admins = parse_admin_ids("ADMIN_OSU_IDS");
     let legacy_admins = parse_admin_ids("ADMIN_USER_IDS");
-    let combined_osu: HashSet<u64> = osu_admins.union(&legacy_admins).copied().collect();
-    if !combined_osu.is_empty() {
-        admin_ids.insert("osu".to_owned(), combined_osu);
-    }
-
-    let github_admins = parse_admin_ids("ADMIN_GITHUB_IDS");
-    if !github_admins.is_empty() {
-        admin_ids.insert("github".to_owned(), github_admins);
-    }
-
-    let discord_admins = parse_admin_ids("ADMIN_DISCORD_IDS");
-    if !discord_admins.is_empty() {
-        admin_ids.insert("discord".to_owned(), discord_admins);
+    let local_provider = local_auth.enabled.then_some(LOCAL_PROVIDER);
+    for name in providers.names().chain(local_provider) {
+        let mut ids = parse_admin_ids(&format!(
+            "ADMIN_{}_IDS",
+            name.to_ascii_uppercase().replace('-', "_")
+        ));
+        if name == "osu" {
+            ids.extend(&legacy_admins);
+        }
+        if !ids.is_empty() {
+            admin_ids.insert(name.to_owned(), ids);
+        }
     }
 
     if admin_ids.is_empty() {
@@ -101,41 +112,144 @@ pub fn run() -> Rocket<Build> {
         .wrap_err_with(|| format!("Could not create upload directory: {}", upload_dir))
         .expect("Failed to create upload dir");
 
-    let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| {
-        let secret = uuid::Uuid::new_v4().to_string();
-        tracing::warn!(
-            "JWT_SECRET not set — using auto-generated secret. Sessions will not survive restarts!"
-        );
-        secret
-    });
+    let upload_limits = UploadLimits::from_env();
+    tracing::info!("Upload limits: {:?}", upload_limits);
+
+    let rate_limits = RateLimitConfig::from_env();
+    tracing::info!("Rate limits: {:?}", rate_limits);
+
+    let jwt_keys = KeyRing::load(KeyConfig::from_env(&upload_dir))
+        .wrap_err("Failed to load JWT signing keys")
+        .expect("JWT key error");
+    tracing::info!(
+        "JWT signing key: {}",
+        jwt_keys.signing_kid().unwrap_or_default()
+    );
 
     let app_state = AppState::new(
-        oauth_config,
-        github_oauth,
-        discord_oauth,
+        providers,
         admin_ids,
         upload_dir,
-        jwt_secret,
+        upload_limits,
+        rate_limits,
+        jwt_keys,
+        local_auth,
+        dev_login,
     );
 
     rocket::build()
         .manage(app_state)
         .attach(Template::fairing())
         .attach(Compression::fairing())
+        .register(
+            "/",
+            catchers![auth::forbidden, ratelimit::too_many_requests],
+        )
         .mount(
             "/",
             routes![
                 routes::health,
+                routes::upload_limits,
                 routes::auth::login,
                 routes::auth::callback,
-                routes::auth::github_login,
-                routes::auth::github_callback,
-                routes::auth::discord_login,
-                routes::auth::discord_callback,
+                routes::auth::provider_login,
+                routes::auth::provider_callback,
+                routes::auth::provider_link,
+                routes::auth::provider_link_unauthorized,
                 routes::auth::logout,
                 routes::auth::me,
                 routes::auth::me_unauthenticated,
                 routes::auth::refresh_cookie,
+                routes::collections::list_collections,
+                routes::collections::get_collection,
+                routes::collections::create_collection,
+                routes::collections::create_collection_unauthorized,
+                routes::collections::patch_collection,
+                routes::collections::patch_collection_unauthorized,
+                routes::collections::delete_collection,
+                routes::collections::delete_collection_unauthorized,
+                routes::collections::add_item,
+                routes::collections::add_item_unauthorized,
+                routes::collections::remove_item,
+                routes::collections::remove_item_unauthorized,
+                routes::dev::login_page,
+                routes::dev::login,
+                routes::local::login_page,
+                routes::local::register,
+                routes::local::login,
+                routes::local::change_password,
+                routes::local::change_password_unauthorized,
+                routes::local::totp_setup,
+                routes::local::totp_setup_unauthorized,
+                routes::local::totp_enable,
+                routes::local::totp_enable_unauthorized,
+                routes::local::totp_disable,
+                routes::local::totp_disable_unauthorized,
+                routes::local::reset_password,
+                routes::local::get_registration,
+                routes::local::get_registration_forbidden,
+                routes::local::set_registration,
+                routes::local::set_registration_forbidden,
+                routes::local::create_invite,
+                routes::local::create_invite_forbidden,
+                routes::local::create_reset_link,
+                routes::local::create_reset_link_forbidden,
+                routes::account::account,
+                routes::account::account_unauthorized,
+                routes::account::unlink_identity,
+                routes::account::unlink_identity_unauthorized,
+                routes::sessions::list_sessions,
+                routes::sessions::list_sessions_unauthorized,
+                routes::sessions::revoke_session,
+                routes::sessions::revoke_session_unauthorized,
+                routes::sessions::revoke_all_sessions,
+                routes::sessions::revoke_all_sessions_unauthorized,
+                routes::sessions::admin_revoke_user_sessions,
+                routes::sessions::admin_revoke_user_sessions_forbidden,
+                routes::sessions::admin_revoke_user_sessions_unauthorized,
+                routes::keys::list_keys,
+                routes::keys::list_keys_forbidden,
+                routes::keys::rotate_key,
+                routes::keys::rotate_key_forbidden,
+                routes::profiles::get_profile,
+                routes::roles::list_roles,
+                routes::roles::list_roles_forbidden,
+                routes::roles::set_role,
+                routes::roles::set_role_forbidden,
+                routes::moderation::create_report,
+                routes::moderation::create_report_unauthorized,
+                routes::moderation::list_reports,
+                routes::moderation::list_reports_forbidden,
+                routes::moderation::resolve_report,
+                routes::moderation::resolve_report_forbidden,
+                routes::moderation::hide_comment,
+                routes::moderation::hide_comment_forbidden,
+                routes::moderation::unhide_comment,
+                routes::moderation::unhide_comment_forbidden,
+                routes::moderation::get_rules,
+                routes::moderation::get_rules_forbidden,
+                routes::moderation::put_rules,
+                routes::moderation::put_rules_forbidden,
+                routes::moderation::list_audit,
+                routes::moderation::list_audit_forbidden,
+                routes::sanctions::list_sanctions,
+                routes::sanctions::list_sanctions_forbidden,
+                routes::sanctions::create_sanction,
+                routes::sanctions::create_sanction_forbidden,
+                routes::sanctions::lift_sanction,
+                routes::sanctions::lift_sanction_forbidden,
+                routes::search::search,
+                routes::tags::list_tags,
+                routes::tags::put_alias,
+                routes::tags::put_alias_forbidden,
+                routes::tags::delete_alias,
+                routes::tags::delete_alias_forbidden,
+                routes::tokens::list_tokens,
+                routes::tokens::list_tokens_unauthorized,
+                routes::tokens::create_token,
+                routes::tokens::create_token_unauthorized,
+                routes::tokens::revoke_token,
+                routes::tokens::revoke_token_unauthorized,
                 routes::videos::conversion_progress,
                 routes::videos::list_videos,
                 routes::videos::get_video,
@@ -158,6 +272,14 @@ pub fn run() -> Rocket<Build> {
                 routes::videos::add_comment_unauthorized,
                 routes::videos::delete_comment,
                 routes::videos::delete_comment_unauthorized,
+                routes::videos::get_comment_thread,
+                routes::videos::edit_comment,
+                routes::videos::edit_comment_unauthorized,
+                routes::videos::get_comment_revisions,
+                routes::videos::add_reaction,
+                routes::videos::add_reaction_unauthorized,
+                routes::videos::remove_reaction,
+                routes::videos::remove_reaction_unauthorized,
                 routes::videos::patch_comments_disabled,
                 routes::videos::patch_comments_disabled_unauthorized,
                 routes::videos::patch_meta,
@@ -183,6 +305,14 @@ pub fn run() -> Rocket<Build> {
                 routes::audio::add_comment_unauthorized,
                 routes::audio::delete_comment,
                 routes::audio::delete_comment_unauthorized,
+                routes::audio::get_comment_thread,
+                routes::audio::edit_comment,
+                routes::audio::edit_comment_unauthorized,
+                routes::audio::get_comment_revisions,
+                routes::audio::add_reaction,
+                routes::audio::add_reaction_unauthorized,
+                routes::audio::remove_reaction,
+                routes::audio::remove_reaction_unauthorized,
                 routes::audio::patch_comments_disabled,
                 routes::audio::patch_comments_disabled_unauthorized,
                 routes::audio::patch_meta,
@@ -208,6 +338,14 @@ pub fn run() -> Rocket<Build> {
                 routes::images::add_comment_unauthorized,
                 routes::images::delete_comment,
                 routes::images::delete_comment_unauthorized,
+                routes::images::get_comment_thread,
+                routes::images::edit_comment,
+                routes::images::edit_comment_unauthorized,
+                routes::images::get_comment_revisions,
+                routes::images::add_reaction,
+                routes::images::add_reaction_unauthorized,
+                routes::images::remove_reaction,
+                routes::images::remove_reaction_unauthorized,
                 routes::images::patch_comments_disabled,
                 routes::images::patch_comments_disabled_unauthorized,
                 routes::images::patch_meta,
@@ -234,6 +372,14 @@ pub fn run() -> Rocket<Build> {
                 routes::text::add_comment_unauthorized,
                 routes::text::delete_comment,
                 routes::text::delete_comment_unauthorized,
+                routes::text::get_comment_thread,
+                routes::text::edit_comment,
+                routes::text::edit_comment_unauthorized,
+                routes::text::get_comment_revisions,
+                routes::text::add_reaction,
+                routes::text::add_reaction_unauthorized,
+                routes::text::remove_reaction,
+                routes::text::remove_reaction_unauthorized,
                 routes::text::patch_comments_disabled,
                 routes::text::patch_comments_disabled_unauthorized,
                 routes::text::patch_meta,
@@ -244,14 +390,26 @@ pub fn run() -> Rocket<Build> {
                 routes::ui::video_listing,
                 routes::ui::audio_listing,
                 routes::ui::image_listing,
+                routes::ui::search_page,
                 routes::ui::player,
                 routes::ui::audio_player,
                 routes::ui::image_viewer,
                 routes::ui::embed,
                 routes::ui::text_listing,
                 routes::ui::text_viewer,
+                routes::ui::profile,
+                routes::ui::collections_page,
+                routes::ui::collection_page,
+                routes::ui::playlist_player,
+                routes::ui::embed_playlist,
                 routes::ui::upload_form,
                 routes::ui::admin_panel,
+                routes::ui::login_page,
+                routes::ui::register_page,
+                routes::ui::reset_page,
+                routes::ui::tokens_page,
+                routes::ui::sessions_page,
+                routes::ui::account_page,
                 routes::ui::ui_delete_video,
                 routes::ui::ui_delete_audio,
                 routes::ui::ui_delete_image,
@@ -263,6 +421,9 @@ pub fn run() -> Rocket<Build> {
                 routes::feed::feed_audio,
                 routes::feed::feed_images,
                 routes::feed::feed_text,
+                routes::feed::feed_user,
+                routes::feed::feed_tag,
+                routes::feed::feed_collection,
             ],
         )
 }
@@ -1,14 +1,22 @@
 use {
-    crate::{models::PlatformUser, state::AppState},
-    jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation},
+    crate::{
+        keys::KeyRing,
+        models::{PlatformUser, Role, Sanction, SanctionKind, Session, TokenScope},
+        roles::{Permission, Permissions},
+        state::AppState,
+    },
+    hex::ToHex,
     rocket::{
         http::Status,
         request::{FromRequest, Outcome, Request},
+        serde::json::Json,
     },
     serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
 };
 
 pub const SESSION_COOKIE: &str = "session_token";
+pub const API_TOKEN_PREFIX: &str = "sk67_";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -16,42 +24,155 @@ pub struct Claims {
     pub id: u64,
     pub username: String,
     pub avatar_url: String,
+    /// Id of the server-side [`Session`]; the JWT is only honoured while that
+    /// session exists.
+    pub sid: String,
     pub exp: u64,
 }
 
-pub fn create_jwt(user: &PlatformUser, secret: &str, remember: bool) -> String {
-    let duration = if remember {
-        30 * 24 * 60 * 60
+pub fn session_ttl(remember: bool) -> chrono::Duration {
+    if remember {
+        chrono::Duration::days(30)
     } else {
-        24 * 60 * 60
-    };
+        chrono::Duration::days(1)
+    }
+}
 
+pub fn create_jwt(user: &PlatformUser, session: &Session, keys: &KeyRing) -> String {
     let claims = Claims {
         provider: user.provider.clone(),
         id: user.id,
         username: user.username.clone(),
         avatar_url: user.avatar_url.clone(),
-        exp: jsonwebtoken::get_current_timestamp() + duration,
+        sid: session.id.clone(),
+        exp: session.expires_at.timestamp().max(0) as u64,
     };
 
-    jsonwebtoken::encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
+    keys.sign(&claims)
+}
+
+pub fn validate_jwt(token: &str, keys: &KeyRing) -> Result<Claims, jsonwebtoken::errors::Error> {
+    keys.verify(token)
+}
+
+pub fn generate_api_token() -> String {
+    format!(
+        "{}{}{}",
+        API_TOKEN_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
     )
-    .expect("JWT encoding should not fail")
 }
 
-pub fn validate_jwt(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let token_data = jsonwebtoken::decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )?;
-    Ok(token_data.claims)
+pub fn hash_api_token(raw: &str) -> String {
+    Sha256::digest(raw.as_bytes()).encode_hex::<String>()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthSource {
+    Session { id: String },
+    Token { id: String, scopes: Vec<TokenScope> },
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser(pub PlatformUser, pub AuthSource);
+
+impl AuthenticatedUser {
+    pub fn has_scope(&self, scope: TokenScope) -> bool {
+        match &self.1 {
+            AuthSource::Session { .. } => true,
+            AuthSource::Token { scopes, .. } => scopes.contains(&scope),
+        }
+    }
+
+    pub fn is_session(&self) -> bool {
+        self.session_id().is_some()
+    }
+
+    pub fn session_id(&self) -> Option<&str> {
+        match &self.1 {
+            AuthSource::Session { id } => Some(id),
+            AuthSource::Token { .. } => None,
+        }
+    }
+
+    pub fn role(&self, state: &AppState) -> Role {
+        state.role_of(&self.0)
+    }
+
+    /// Whether this request may use `permission`: the user's role must grant
+    /// it and a personal access token must carry the matching scope, which is
+    /// `admin` for every elevated permission.
+    pub fn can(&self, state: &AppState, permission: Permission) -> bool {
+        self.has_scope(permission.scope()) && self.role(state).can(permission)
+    }
+
+    pub fn permissions(&self, state: &AppState) -> Permissions {
+        let role = self.role(state);
+        Permissions::from_fn(|p| self.has_scope(p.scope()) && role.can(p))
+    }
+}
+
+pub fn bearer_token<'a>(req: &'a Request<'_>) -> Option<&'a str> {
+    req.headers()
+        .get_one("Authorization")
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
+
+struct ResolvedUser(Option<AuthenticatedUser>);
+
+/// Set when the request was refused because its user is banned, so the 403
+/// catcher can say why.
+struct ActiveBan(Option<Sanction>);
+
+async fn resolve_user(req: &Request<'_>, state: &AppState) -> Option<AuthenticatedUser> {
+    let token = req
+        .cookies()
+        .get(SESSION_COOKIE)
+        .map(|c| c.value().to_owned());
+
+    if let Some(t) = token
+        && let Ok(claims) = validate_jwt(&t, &state.jwt_keys)
+        && state.sessions.touch(&claims.sid).is_some()
+    {
+        let user_id = state
+            .users
+            .ensure(&claims.provider, claims.id, &claims.username);
+        return Some(AuthenticatedUser(
+            PlatformUser {
+                provider: claims.provider,
+                id: claims.id,
+                username: claims.username,
+                avatar_url: claims.avatar_url,
+                user_id,
+            },
+            AuthSource::Session { id: claims.sid },
+        ));
+    }
+
+    if let Some(raw) = bearer_token(req)
+        && let Some(token) = state.use_api_token(raw)
+    {
+        return Some(AuthenticatedUser(
+            PlatformUser {
+                provider: token.owner_provider,
+                id: token.owner_id,
+                username: token.owner_name,
+                avatar_url: token.owner_avatar_url,
+                user_id: token.owner_user,
+            },
+            AuthSource::Token {
+                id: token.id,
+                scopes: token.scopes,
+            },
+        ));
+    }
+
+    None
 }
 
-pub struct AuthenticatedUser(pub PlatformUser);
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for AuthenticatedUser {
     type Error = ();
@@ -62,61 +183,98 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
             None => return Outcome::Error((Status::InternalServerError, ())),
         };
 
-        let token = req
-            .cookies()
-            .get(SESSION_COOKIE)
-            .map(|c| c.value().to_owned());
+        let resolved = req
+            .local_cache_async(async { ResolvedUser(resolve_user(req, state).await) })
+            .await;
 
-        if let Some(t) = token
-            && let Ok(claims) = validate_jwt(&t, &state.jwt_secret)
-        {
-            return Outcome::Success(AuthenticatedUser(PlatformUser {
-                provider: claims.provider,
-                id: claims.id,
-                username: claims.username,
-                avatar_url: claims.avatar_url,
-            }));
+        if let Some(user) = &resolved.0 {
+            if let Some(ban) = state
+                .sanctions
+                .active_for(&user.0.user_id, SanctionKind::Ban)
+            {
+                req.local_cache(|| ActiveBan(Some(ban)));
+                return Outcome::Error((Status::Forbidden, ()));
+            }
+            return Outcome::Success(user.clone());
         }
 
-        #[cfg(debug_assertions)]
-        {
-            return Outcome::Success(AuthenticatedUser(PlatformUser {
-                provider: "debug".to_owned(),
-                id: 0,
-                username: "debug_user".to_owned(),
-                avatar_url: String::new(),
-            }));
+        if bearer_token(req).is_some() {
+            return Outcome::Error((Status::Unauthorized, ()));
         }
 
-        #[cfg(not(debug_assertions))]
         Outcome::Forward(Status::Unauthorized)
     }
 }
 
-#[allow(dead_code)]
+async fn user_with_permission(
+    req: &Request<'_>,
+    permission: Permission,
+) -> Outcome<AuthenticatedUser, ()> {
+    let authed = match AuthenticatedUser::from_request(req).await {
+        Outcome::Success(u) => u,
+        Outcome::Error(e) => return Outcome::Error(e),
+        Outcome::Forward(f) => return Outcome::Forward(f),
+    };
+    let state = req.rocket().state::<AppState>().unwrap();
+    if authed.can(state, permission) {
+        Outcome::Success(authed)
+    } else {
+        Outcome::Error((Status::Forbidden, ()))
+    }
+}
+
+macro_rules! permission_guard {
+    ($name:ident, $permission:expr) => {
+        /// An [`AuthenticatedUser`] whose role and credentials allow this
+        /// permission.
+        #[allow(dead_code)]
+        pub struct $name(pub AuthenticatedUser);
+
+        #[rocket::async_trait]
+        impl<'r> FromRequest<'r> for $name {
+            type Error = ();
+
+            async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+                user_with_permission(req, $permission).await.map($name)
+            }
+        }
+    };
+}
+
+permission_guard!(UploadUser, Permission::Upload);
+permission_guard!(CommentUser, Permission::Comment);
+permission_guard!(CommentModerator, Permission::ModerateComments);
+permission_guard!(DeleteMediaUser, Permission::DeleteAnyMedia);
+permission_guard!(NsfwUser, Permission::ToggleNsfw);
+permission_guard!(TagAdmin, Permission::ManageTags);
+
+/// A user allowed to manage other users and the server.
 pub struct AdminUser(pub PlatformUser);
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for AdminUser {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let AuthenticatedUser(user) = match AuthenticatedUser::from_request(req).await {
-            Outcome::Success(u) => u,
-            Outcome::Error(e) => return Outcome::Error(e),
-            Outcome::Forward(f) => return Outcome::Forward(f),
-        };
-
-        let state = req.rocket().state::<AppState>().unwrap();
-
-        #[cfg(debug_assertions)]
-        if user.provider == "debug" {
-            return Outcome::Success(AdminUser(user));
-        }
-
-        if state.is_admin(&user.provider, user.id) {
-            Outcome::Success(AdminUser(user))
-        } else {
-            Outcome::Error((Status::Forbidden, ()))
-        }
+        user_with_permission(req, Permission::ManageUsers)
+            .await
+            .map(|u| AdminUser(u.0))
     }
 }
+
+#[rocket::catch(403)]
+pub fn forbidden(req: &Request<'_>) -> (Status, Json<serde_json::Value>) {
+    let body = match &req.local_cache(|| ActiveBan(None)).0 {
+        Some(ban) => Json(serde_json::json!({
+            "error": "Forbidden",
+            "message": format!("Your account is {}", ban.describe()),
+            "reason": ban.reason,
+            "expires_at": ban.expires_at,
+        })),
+        None => Json(serde_json::json!({
+            "error": "Forbidden",
+            "message": "You do not have permission to do that",
+        })),
+    };
+    (Status::Forbidden, body)
+}
@@ -0,0 +1,183 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::{Collection, CollectionKind},
+    },
+    chrono::Utc,
+    dashmap::DashMap,
+    std::path::{Path, PathBuf},
+};
+
+pub const MAX_TITLE_LEN: usize = 100;
+pub const MAX_DESCRIPTION_LEN: usize = 2000;
+pub const MAX_ITEMS: usize = 1000;
+
+pub fn validate_title(title: &str) -> AppResult<String> {
+    let title = title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_LEN {
+        return Err(AppError::InvalidCollectionTitle);
+    }
+    Ok(title.to_owned())
+}
+
+pub fn validate_description(description: &str) -> AppResult<String> {
+    let description = description.trim();
+    if description.chars().count() > MAX_DESCRIPTION_LEN {
+        return Err(AppError::InvalidCollectionDescription);
+    }
+    Ok(description.to_owned())
+}
+
+/// User collections, kept in `collections.json`.
+pub struct CollectionStore {
+    path: PathBuf,
+    collections: DashMap<String, Collection>,
+}
+
+impl CollectionStore {
+    const FILE_NAME: &'static str = "collections.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let collections: Vec<Collection> = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("could not parse {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path,
+            collections: collections.into_iter().map(|c| (c.id.clone(), c)).collect(),
+        }
+    }
+
+    pub fn create(
+        &self,
+        owner_user: &str,
+        kind: CollectionKind,
+        title: &str,
+        description: &str,
+    ) -> AppResult<Collection> {
+        let now = Utc::now();
+        let collection = Collection {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind,
+            title: validate_title(title)?,
+            description: validate_description(description)?,
+            owner_user: owner_user.to_owned(),
+            items: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        self.collections
+            .insert(collection.id.clone(), collection.clone());
+        self.persist();
+        Ok(collection)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Collection> {
+        self.collections.get(id).map(|c| c.clone())
+    }
+
+    /// Most recently updated first, optionally only those owned by
+    /// `owner_user`.
+    pub fn list(&self, owner_user: Option<&str>) -> Vec<Collection> {
+        let mut out: Vec<Collection> = self
+            .collections
+            .iter()
+            .filter(|c| owner_user.is_none_or(|o| c.owner_user == o))
+            .map(|c| c.clone())
+            .collect();
+        out.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+        out
+    }
+
+    /// Collections that include `media_id`, most recently updated first.
+    pub fn containing(&self, media_id: &str) -> Vec<Collection> {
+        let mut out: Vec<Collection> = self
+            .collections
+            .iter()
+            .filter(|c| c.items.iter().any(|i| i == media_id))
+            .map(|c| c.clone())
+            .collect();
+        out.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+        out
+    }
+
+    /// Applies `edit` and bumps `updated_at`. Nothing is saved if `edit`
+    /// fails or leaves more than [`MAX_ITEMS`] items.
+    pub fn update(
+        &self,
+        id: &str,
+        edit: impl FnOnce(&mut Collection) -> AppResult<()>,
+    ) -> AppResult<Collection> {
+        let mut entry = self
+            .collections
+            .get_mut(id)
+            .ok_or(AppError::CollectionNotFound)?;
+        let mut collection = entry.clone();
+        edit(&mut collection)?;
+        if collection.items.len() > MAX_ITEMS {
+            return Err(AppError::CollectionFull);
+        }
+        collection.updated_at = Utc::now();
+        *entry = collection.clone();
+        drop(entry);
+        self.persist();
+        Ok(collection)
+    }
+
+    pub fn delete(&self, id: &str) -> AppResult<Collection> {
+        let (_, collection) = self
+            .collections
+            .remove(id)
+            .ok_or(AppError::CollectionNotFound)?;
+        self.persist();
+        Ok(collection)
+    }
+
+    /// Drops a deleted upload from every collection.
+    pub fn remove_media(&self, media_id: &str) -> usize {
+        let mut changed = 0;
+        for mut c in self.collections.iter_mut() {
+            let before = c.items.len();
+            c.items.retain(|i| i != media_id);
+            if c.items.len() != before {
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            self.persist();
+        }
+        changed
+    }
+
+    /// Moves collections over when two accounts are merged.
+    pub fn reassign(&self, from: &str, to: &str) -> usize {
+        let mut moved = 0;
+        for mut c in self.collections.iter_mut() {
+            if c.owner_user == from {
+                c.owner_user = to.to_owned();
+                moved += 1;
+            }
+        }
+        if moved > 0 {
+            self.persist();
+        }
+        moved
+    }
+
+    fn persist(&self) {
+        let collections: Vec<Collection> =
+            self.collections.iter().map(|e| e.value().clone()).collect();
+        match serde_json::to_string_pretty(&collections) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&self.path, json) {
+                    tracing::warn!("could not write collections to {:?}: {}", self.path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize collections: {}", e),
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::{Comment, CommentAnchor, CommentRevision, MediaKind, Region, VideoMeta},
+    },
+    chrono::{DateTime, Utc},
+    hashbrown::{HashMap, HashSet},
+    rocket::FromForm,
+    serde::Serialize,
+};
+
+pub const MAX_TEXT_LEN: usize = 2000;
+/// How deep replies can nest; top-level comments are depth 0.
+pub const MAX_DEPTH: usize = 8;
+pub const DEFAULT_THREAD_DEPTH: usize = 3;
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+/// Earlier versions kept per comment; further edits are refused.
+pub const MAX_REVISIONS: usize = 20;
+/// Distinct emoji on one comment.
+pub const MAX_REACTIONS: usize = 20;
+const MAX_EMOJI_CHARS: usize = 8;
+
+pub fn validate_text(text: &str) -> AppResult<String> {
+    let text = text.trim();
+    if text.is_empty() || text.chars().count() > MAX_TEXT_LEN {
+        return Err(AppError::InvalidComment);
+    }
+    Ok(text.to_owned())
+}
+
+/// Checks that `anchor` fits the upload: a position within the length of
+/// audio and video, or a region inside an image.
+pub fn validate_anchor(anchor: CommentAnchor, meta: &VideoMeta) -> AppResult<CommentAnchor> {
+    let invalid = |reason: &str| Err(AppError::InvalidAnchor(reason.to_owned()));
+    match MediaKind::from_mime(&meta.content_type) {
+        Some(MediaKind::Video | MediaKind::Audio) => {
+            if anchor.region.is_some() {
+                return invalid("regions are only for images");
+            }
+            let Some(start_ms) = anchor.start_ms else {
+                return invalid("start_ms is required");
+            };
+            if anchor.end_ms.is_some_and(|end| end <= start_ms) {
+                return invalid("end_ms must be after start_ms");
+            }
+            let length_ms = meta.duration_secs.map(|d| (d * 1000.0).ceil() as u64);
+            let past_end = |ms: u64| length_ms.is_some_and(|len| ms > len);
+            if past_end(start_ms) || anchor.end_ms.is_some_and(past_end) {
+                return invalid("the position is past the end");
+            }
+            Ok(anchor)
+        }
+        Some(MediaKind::Image) => {
+            if anchor.start_ms.is_some() || anchor.end_ms.is_some() {
+                return invalid("positions are only for audio and video");
+            }
+            match anchor.region {
+                Some(region) if region_fits(region) => Ok(anchor),
+                Some(_) => invalid("the region must lie within the image"),
+                None => invalid("region is required"),
+            }
+        }
+        _ => invalid("only audio, video and images can be anchored"),
+    }
+}
+
+fn region_fits(Region { x, y, w, h }: Region) -> bool {
+    // Leaves room for rounding in the fractions the browser sends.
+    const SLACK: f64 = 1e-6;
+    [x, y, w, h].iter().all(|v| v.is_finite())
+        && x >= 0.0
+        && y >= 0.0
+        && w > 0.0
+        && h > 0.0
+        && x + w <= 1.0 + SLACK
+        && y + h <= 1.0 + SLACK
+}
+
+/// Accepts a single emoji, including skin tones and ZWJ sequences. Letters,
+/// digits, ASCII and whitespace are rejected.
+pub fn validate_emoji(raw: &str) -> AppResult<String> {
+    let valid = !raw.is_empty()
+        && raw.chars().count() <= MAX_EMOJI_CHARS
+        && raw.chars().all(|c| {
+            !c.is_ascii() && !c.is_alphanumeric() && !c.is_whitespace() && !c.is_control()
+        });
+    valid
+        .then(|| raw.to_owned())
+        .ok_or(AppError::InvalidReaction)
+}
+
+/// The parent a comment is shown under. Replies whose parent was deleted are
+/// shown at the top level so they stay reachable.
+fn parent_of<'a>(comments: &[Comment], comment: &'a Comment) -> Option<&'a str> {
+    comment
+        .parent_id
+        .as_deref()
+        .filter(|p| comments.iter().any(|c| c.id == *p))
+}
+
+/// Comments grouped by the parent they are shown under, oldest first.
+fn children_of(comments: &[Comment]) -> HashMap<Option<&str>, Vec<&Comment>> {
+    let ids: HashSet<&str> = comments.iter().map(|c| c.id.as_str()).collect();
+    let mut children: HashMap<Option<&str>, Vec<&Comment>> = HashMap::new();
+    for c in comments {
+        let parent = c.parent_id.as_deref().filter(|p| ids.contains(p));
+        children.entry(parent).or_default().push(c);
+    }
+    children
+}
+
+/// How deep `id` is nested, or `None` if there is no such comment.
+pub fn depth_of(comments: &[Comment], id: &str) -> Option<usize> {
+    let mut comment = comments.iter().find(|c| c.id == id)?;
+    let mut depth = 0;
+    while let Some(parent) = parent_of(comments, comment) {
+        depth += 1;
+        if depth > comments.len() {
+            break;
+        }
+        comment = comments.iter().find(|c| c.id == parent)?;
+    }
+    Some(depth)
+}
+
+/// Fails unless a reply to `parent_id` would be a valid comment on the same
+/// upload within [`MAX_DEPTH`].
+pub fn check_parent(comments: &[Comment], parent_id: &str) -> AppResult<()> {
+    let depth = depth_of(comments, parent_id).ok_or(AppError::InvalidParentComment)?;
+    if depth >= MAX_DEPTH {
+        return Err(AppError::CommentTooDeep);
+    }
+    Ok(())
+}
+
+/// Blanks a hidden comment for everyone but its author and moderators. The
+/// comment stays in place so its replies keep their parent.
+pub fn redact_hidden(comment: &mut Comment, viewer: Option<&str>, moderator: bool) {
+    if comment.hidden && !moderator && viewer != Some(comment.author_user.as_str()) {
+        comment.text.clear();
+        comment.revisions.clear();
+        comment.reactions.clear();
+    }
+}
+
+/// Replaces the text, keeping the old one as a revision. Returns whether
+/// anything changed. Fails once [`MAX_REVISIONS`] are stored, so the history
+/// cannot grow without bound.
+pub fn edit(comment: &mut Comment, text: String) -> AppResult<bool> {
+    if comment.text == text {
+        return Ok(false);
+    }
+    if comment.revisions.len() >= MAX_REVISIONS {
+        return Err(AppError::TooManyEdits);
+    }
+    let written_at = comment.edited_at.unwrap_or(comment.created_at);
+    let old = std::mem::replace(&mut comment.text, text);
+    comment.revisions.push(CommentRevision {
+        text: old,
+        written_at,
+    });
+    comment.edited_at = Some(Utc::now());
+    Ok(true)
+}
+
+/// Every version of the text, oldest first and ending with the current one.
+pub fn history(comment: &Comment) -> Vec<CommentRevision> {
+    let mut history = comment.revisions.clone();
+    history.push(CommentRevision {
+        text: comment.text.clone(),
+        written_at: comment.edited_at.unwrap_or(comment.created_at),
+    });
+    history
+}
+
+/// Adds or removes `user_id`'s `emoji` reaction. Each user reacts with a
+/// given emoji at most once, so repeating either is a no-op.
+pub fn react(comment: &mut Comment, emoji: &str, user_id: &str, on: bool) -> AppResult<()> {
+    if on {
+        if !comment.reactions.contains_key(emoji) && comment.reactions.len() >= MAX_REACTIONS {
+            return Err(AppError::TooManyReactions);
+        }
+        let users = comment.reactions.entry(emoji.to_owned()).or_default();
+        if !users.iter().any(|u| u == user_id) {
+            users.push(user_id.to_owned());
+        }
+    } else if let Some(users) = comment.reactions.get_mut(emoji) {
+        users.retain(|u| u != user_id);
+        if users.is_empty() {
+            comment.reactions.remove(emoji);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: usize,
+    /// Whether the viewer is one of them.
+    pub reacted: bool,
+}
+
+/// Most used first.
+pub fn reaction_counts(comment: &Comment, viewer: Option<&str>) -> Vec<ReactionCount> {
+    let mut counts: Vec<ReactionCount> = comment
+        .reactions
+        .iter()
+        .map(|(emoji, users)| ReactionCount {
+            emoji: emoji.clone(),
+            count: users.len(),
+            reacted: viewer.is_some_and(|v| users.iter().any(|u| u == v)),
+        })
+        .collect();
+    counts.sort_by_key(|c| std::cmp::Reverse(c.count));
+    counts
+}
+
+/// Query parameters for a page of a comment thread.
+#[derive(Debug, Default, FromForm)]
+pub struct ThreadParams {
+    /// List the replies to this comment instead of the top level.
+    pub parent: Option<String>,
+    /// Levels of replies to include under each listed comment.
+    pub depth: Option<usize>,
+    pub limit: Option<usize>,
+    /// `next_cursor` from the previous page.
+    pub cursor: Option<String>,
+}
+
+/// A comment as the API returns it, with its text rendered.
+#[derive(Debug, Serialize)]
+pub struct CommentView {
+    #[serde(flatten)]
+    pub comment: Comment,
+    /// Sanitized HTML of `text`; see [`crate::markdown::render`].
+    pub html: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommentNode {
+    pub id: String,
+    pub video_id: String,
+    pub author_provider: String,
+    pub author_id: u64,
+    pub author_name: String,
+    pub author_user: String,
+    pub text: String,
+    pub html: String,
+    pub created_at: DateTime<Utc>,
+    pub parent_id: Option<String>,
+    /// Set once the text has been edited.
+    pub edited_at: Option<DateTime<Utc>>,
+    pub revision_count: usize,
+    /// Hidden by a moderator; `text` is empty unless the viewer may see it.
+    pub hidden: bool,
+    /// The moment or region the comment is about, if any.
+    pub anchor: Option<CommentAnchor>,
+    pub reactions: Vec<ReactionCount>,
+    /// Direct replies, including any not listed in `replies`.
+    pub reply_count: usize,
+    /// Oldest first; empty past the requested depth. Fetch the rest with
+    /// `parent`.
+    pub replies: Vec<CommentNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThreadPage {
+    pub comments: Vec<CommentNode>,
+    /// Comments at this level across all pages.
+    pub total: usize,
+    /// Pass as `cursor` to fetch the next page; absent on the last one.
+    pub next_cursor: Option<String>,
+}
+
+struct Tree<'a> {
+    children: HashMap<Option<&'a str>, Vec<&'a Comment>>,
+    viewer: Option<&'a str>,
+    name: &'a dyn Fn(&Comment) -> String,
+    html: &'a dyn Fn(&Comment) -> String,
+}
+
+impl Tree<'_> {
+    fn node(&self, comment: &Comment, depth: usize) -> CommentNode {
+        let replies = self
+            .children
+            .get(&Some(comment.id.as_str()))
+            .map_or(&[][..], Vec::as_slice);
+        CommentNode {
+            id: comment.id.clone(),
+            video_id: comment.video_id.clone(),
+            author_provider: comment.author_provider.clone(),
+            author_id: comment.author_id,
+            author_name: (self.name)(comment),
+            author_user: comment.author_user.clone(),
+            text: comment.text.clone(),
+            html: (self.html)(comment),
+            created_at: comment.created_at,
+            parent_id: comment.parent_id.clone(),
+            edited_at: comment.edited_at,
+            revision_count: comment.revisions.len(),
+            hidden: comment.hidden,
+            anchor: comment.anchor,
+            reactions: reaction_counts(comment, self.viewer),
+            reply_count: replies.len(),
+            replies: match depth {
+                0 => Vec::new(),
+                _ => replies.iter().map(|r| self.node(r, depth - 1)).collect(),
+            },
+        }
+    }
+}
+
+/// A page of the comments at one level of the thread, oldest first, each
+/// with its replies down to `params.depth` levels.
+pub fn thread(
+    comments: &[Comment],
+    params: &ThreadParams,
+    viewer: Option<&str>,
+    name: &dyn Fn(&Comment) -> String,
+    html: &dyn Fn(&Comment) -> String,
+) -> AppResult<ThreadPage> {
+    let tree = Tree {
+        children: children_of(comments),
+        viewer,
+        name,
+        html,
+    };
+    let parent = params.parent.as_deref().filter(|p| !p.is_empty());
+    if parent.is_some_and(|p| !comments.iter().any(|c| c.id == p)) {
+        return Err(AppError::CommentNotFound);
+    }
+    let level = tree.children.get(&parent).map_or(&[][..], Vec::as_slice);
+    let start = match params.cursor.as_deref().filter(|c| !c.is_empty()) {
+        Some(after) => {
+            level
+                .iter()
+                .position(|c| c.id == after)
+                .ok_or(AppError::InvalidCursor)?
+                + 1
+        }
+        None => 0,
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let end = (start + limit).min(level.len());
+    let depth = params.depth.unwrap_or(DEFAULT_THREAD_DEPTH).min(MAX_DEPTH);
+
+    Ok(ThreadPage {
+        comments: level[start..end]
+            .iter()
+            .map(|c| tree.node(c, depth))
+            .collect(),
+        total: level.len(),
+        next_cursor: (end < level.len()).then(|| level[end - 1].id.clone()),
+    })
+}
+
+/// Every comment with its depth, in reading order: each reply follows its
+/// parent and any earlier replies to it.
+pub fn flatten(comments: &[Comment]) -> Vec<(&Comment, usize)> {
+    let children = children_of(comments);
+    let mut out = Vec::with_capacity(comments.len());
+    let mut stack: Vec<(&Comment, usize)> = children
+        .get(&None)
+        .map(|roots| roots.iter().rev().map(|c| (*c, 0)).collect())
+        .unwrap_or_default();
+    while let Some((comment, depth)) = stack.pop() {
+        out.push((comment, depth));
+        if let Some(replies) = children.get(&Some(comment.id.as_str())) {
+            stack.extend(replies.iter().rev().map(|r| (*r, depth + 1)));
+        }
+    }
+    out
+}
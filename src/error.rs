@@ -24,11 +24,20 @@ pub enum AppError {
     #[error("OAuth token exchange failed: {0}")]
     OAuthTokenExchange(String),
 
-    #[error("Failed to fetch osu! user info: {0}")]
-    OsuUserFetch(String),
+    #[error("The login provider rejected the authorization code ({0}); please log in again")]
+    OAuthGrantRejected(String),
 
-    #[error("Upload exceeds 100 MB limit")]
-    FileTooLarge,
+    #[error("Failed to fetch user info: {0}")]
+    UserInfoFetch(String),
+
+    #[error("OpenID Connect discovery failed: {0}")]
+    OAuthDiscovery(String),
+
+    #[error("Unknown login provider '{0}'")]
+    UnknownProvider(String),
+
+    #[error("Upload exceeds {} limit", crate::util::format_size(*.0))]
+    FileTooLarge(u64),
 
     #[error("Duplicate video — identical content already exists as video '{0}'")]
     DuplicateVideo(String),
@@ -48,6 +57,155 @@ pub enum AppError {
     #[error("Video not found")]
     VideoNotFound,
 
+    #[error("Invalid or expired page cursor")]
+    InvalidCursor,
+
+    #[error("Invalid search query at position {position}: {message}")]
+    InvalidQuery { message: String, position: usize },
+
+    #[error("Token name is required and must be at most 100 characters")]
+    InvalidTokenName,
+
+    #[error("Select at least one token scope")]
+    MissingTokenScopes,
+
+    #[error("API token not found")]
+    TokenNotFound,
+
+    #[error("This action requires a signed-in browser session, not an API token")]
+    SessionRequired,
+
+    #[error("Session not found")]
+    SessionNotFound,
+
+    #[error("User not found")]
+    UserNotFound,
+
+    #[error("That login is not linked to your account")]
+    IdentityNotFound,
+
+    #[error("You cannot unlink the only login on your account")]
+    LastIdentity,
+
+    #[error(
+        "That login belongs to another account; link it again with merging turned on to combine the accounts"
+    )]
+    IdentityInUse,
+
+    #[error("The account link was started from a different session")]
+    LinkSessionMismatch,
+
+    #[error("Your account is {0}")]
+    Sanctioned(String),
+
+    #[error("Sanction not found or no longer active")]
+    SanctionNotFound,
+
+    #[error("A reason is required")]
+    MissingReason,
+
+    #[error("Local accounts are not enabled on this server")]
+    LocalAccountsDisabled,
+
+    #[error("Dev login is not enabled on this server")]
+    DevLoginDisabled,
+
+    #[error("Registration is closed")]
+    RegistrationClosed,
+
+    #[error("A valid invite code is required to register")]
+    InvalidInvite,
+
+    #[error("Username must be 3–32 characters of letters, digits, '_', '-' or '.'")]
+    InvalidUsername,
+
+    #[error("That username is already taken")]
+    UsernameTaken,
+
+    #[error("Password must be between 8 and 1024 characters")]
+    WeakPassword,
+
+    #[error("Incorrect username or password")]
+    InvalidCredentials,
+
+    #[error("A two-factor code is required")]
+    TotpRequired,
+
+    #[error("Invalid two-factor code")]
+    InvalidTotpCode,
+
+    #[error("This password reset link is invalid or has expired")]
+    InvalidResetLink,
+
+    #[error("This action is only available to local accounts")]
+    NotLocalAccount,
+
+    #[error("Invalid tag '{0}' — tags are 1–32 letters, digits, '-', '_' or '.'")]
+    InvalidTag(String),
+
+    #[error("An upload can have at most 20 tags")]
+    TooManyTags,
+
+    #[error("Tag alias not found")]
+    TagNotFound,
+
+    #[error("Collection not found")]
+    CollectionNotFound,
+
+    #[error("Collection title is required and must be at most 100 characters")]
+    InvalidCollectionTitle,
+
+    #[error("Collection description must be at most 2000 characters")]
+    InvalidCollectionDescription,
+
+    #[error("A collection can hold at most 1000 items")]
+    CollectionFull,
+
+    #[error("The new order must list exactly the items already in the collection")]
+    InvalidCollectionOrder,
+
+    #[error("Only video and audio can be added to a playlist")]
+    NotPlayable,
+
+    #[error("Comment not found")]
+    CommentNotFound,
+
+    #[error("The comment being replied to does not exist on this upload")]
+    InvalidParentComment,
+
+    #[error("Replies can be nested at most 8 deep")]
+    CommentTooDeep,
+
+    #[error("Reactions must be a single emoji")]
+    InvalidReaction,
+
+    #[error("A comment can have at most 20 different reactions")]
+    TooManyReactions,
+
+    #[error("A comment can be edited at most 20 times")]
+    TooManyEdits,
+
+    #[error("Comment rejected: {0}")]
+    CommentRejected(String),
+
+    #[error("Invalid comment anchor: {0}")]
+    InvalidAnchor(String),
+
+    #[error("Report details must be at most 1000 characters")]
+    InvalidReport,
+
+    #[error("You have already reported this")]
+    AlreadyReported,
+
+    #[error("Report not found or already resolved")]
+    ReportNotFound,
+
+    #[error("A moderator hid this upload; only a moderator can list it again")]
+    HiddenByModerator,
+
+    #[error("Invalid spam rules: {0}")]
+    InvalidSpamRules(String),
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -64,10 +222,64 @@ impl AppError {
             AppError::NotAuthenticated => Status::Unauthorized,
             AppError::Forbidden => Status::Forbidden,
             AppError::OAuthStateMismatch => Status::BadRequest,
+            AppError::UnknownProvider(_) => Status::NotFound,
+            AppError::OAuthDiscovery(_) => Status::BadGateway,
+            AppError::OAuthTokenExchange(_) => Status::BadGateway,
+            AppError::OAuthGrantRejected(_) => Status::BadRequest,
+            AppError::UserInfoFetch(_) => Status::BadGateway,
             AppError::InvalidTitle => Status::BadRequest,
             AppError::InvalidComment => Status::BadRequest,
             AppError::VideoNotFound => Status::NotFound,
-            AppError::FileTooLarge => Status::PayloadTooLarge,
+            AppError::InvalidCursor => Status::BadRequest,
+            AppError::InvalidQuery { .. } => Status::BadRequest,
+            AppError::InvalidTokenName => Status::BadRequest,
+            AppError::MissingTokenScopes => Status::BadRequest,
+            AppError::TokenNotFound => Status::NotFound,
+            AppError::SessionRequired => Status::Forbidden,
+            AppError::SessionNotFound => Status::NotFound,
+            AppError::UserNotFound => Status::NotFound,
+            AppError::IdentityNotFound => Status::NotFound,
+            AppError::LastIdentity => Status::BadRequest,
+            AppError::IdentityInUse => Status::Conflict,
+            AppError::LinkSessionMismatch => Status::Forbidden,
+            AppError::Sanctioned(_) => Status::Forbidden,
+            AppError::SanctionNotFound => Status::NotFound,
+            AppError::MissingReason => Status::BadRequest,
+            AppError::LocalAccountsDisabled => Status::NotFound,
+            AppError::DevLoginDisabled => Status::NotFound,
+            AppError::RegistrationClosed => Status::Forbidden,
+            AppError::InvalidInvite => Status::Forbidden,
+            AppError::InvalidUsername => Status::BadRequest,
+            AppError::UsernameTaken => Status::Conflict,
+            AppError::WeakPassword => Status::BadRequest,
+            AppError::InvalidCredentials => Status::Unauthorized,
+            AppError::TotpRequired => Status::Unauthorized,
+            AppError::InvalidTotpCode => Status::Unauthorized,
+            AppError::InvalidResetLink => Status::BadRequest,
+            AppError::NotLocalAccount => Status::BadRequest,
+            AppError::InvalidTag(_) => Status::BadRequest,
+            AppError::TooManyTags => Status::BadRequest,
+            AppError::TagNotFound => Status::NotFound,
+            AppError::CollectionNotFound => Status::NotFound,
+            AppError::InvalidCollectionTitle => Status::BadRequest,
+            AppError::InvalidCollectionDescription => Status::BadRequest,
+            AppError::CollectionFull => Status::BadRequest,
+            AppError::InvalidCollectionOrder => Status::BadRequest,
+            AppError::NotPlayable => Status::BadRequest,
+            AppError::CommentNotFound => Status::NotFound,
+            AppError::InvalidParentComment => Status::BadRequest,
+            AppError::CommentTooDeep => Status::BadRequest,
+            AppError::InvalidReaction => Status::BadRequest,
+            AppError::TooManyReactions => Status::BadRequest,
+            AppError::TooManyEdits => Status::BadRequest,
+            AppError::CommentRejected(_) => Status::UnprocessableEntity,
+            AppError::InvalidAnchor(_) => Status::BadRequest,
+            AppError::InvalidReport => Status::BadRequest,
+            AppError::AlreadyReported => Status::Conflict,
+            AppError::ReportNotFound => Status::NotFound,
+            AppError::HiddenByModerator => Status::Forbidden,
+            AppError::InvalidSpamRules(_) => Status::BadRequest,
+            AppError::FileTooLarge(_) => Status::PayloadTooLarge,
             AppError::DuplicateVideo(_) => Status::Conflict,
             AppError::InvalidFileType => Status::UnsupportedMediaType,
             AppError::MagicMismatch => Status::UnsupportedMediaType,
@@ -80,6 +292,9 @@ impl AppError {
 struct ErrorBody<'a> {
     error: &'a str,
     message: &'a str,
+    /// Character offset of a search query syntax error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
 }
 
 impl<'r> Responder<'r, 'static> for AppError {
@@ -92,9 +307,22 @@ impl<'r> Responder<'r, 'static> for AppError {
         let body = ErrorBody {
             error: status.reason().unwrap_or("error"),
             message: &msg,
+            position: match self {
+                AppError::InvalidQuery { position, .. } => Some(position),
+                _ => None,
+            },
         };
         rocket::response::status::Custom(status, Json(body)).respond_to(req)
     }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+impl From<crate::query::QueryError> for AppError {
+    fn from(e: crate::query::QueryError) -> Self {
+        AppError::InvalidQuery {
+            message: e.message,
+            position: e.position,
+        }
+    }
+}
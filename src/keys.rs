@@ -0,0 +1,465 @@
+use {
+    chrono::{DateTime, Duration, Utc},
+    color_eyre::eyre::{Result, WrapErr, eyre},
+    hex::{FromHex, ToHex},
+    jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation},
+    ring::{
+        rand::{SecureRandom, SystemRandom},
+        signature::{Ed25519KeyPair, KeyPair},
+    },
+    serde::{Deserialize, Serialize, de::DeserializeOwned},
+    std::{
+        path::{Path, PathBuf},
+        sync::RwLock,
+    },
+};
+
+/// Kid given to the key built from the `JWT_SECRET` environment variable.
+/// Tokens without a `kid` header are checked against it.
+pub const ENV_KID: &str = "env";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyAlgorithm {
+    HS256,
+    RS256,
+    EdDSA,
+}
+
+impl KeyAlgorithm {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "hs256" => Some(Self::HS256),
+            "rs256" => Some(Self::RS256),
+            "eddsa" | "ed25519" => Some(Self::EdDSA),
+            _ => None,
+        }
+    }
+
+    fn jwt(self) -> Algorithm {
+        match self {
+            Self::HS256 => Algorithm::HS256,
+            Self::RS256 => Algorithm::RS256,
+            Self::EdDSA => Algorithm::EdDSA,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyConfig {
+    /// Algorithm used for keys created on first run or by rotation.
+    pub algorithm: KeyAlgorithm,
+    /// `None` keeps keys in memory only.
+    pub keys_file: Option<PathBuf>,
+    /// How long a rotated-out key keeps validating tokens.
+    pub grace: Duration,
+    /// Rotate on startup once the signing key is older than this.
+    pub rotate_after: Option<Duration>,
+    pub rsa_private_key_file: Option<PathBuf>,
+    pub rsa_public_key_file: Option<PathBuf>,
+    pub env_secret: Option<String>,
+}
+
+impl KeyConfig {
+    pub fn from_env(upload_dir: &str) -> Self {
+        let algorithm = match std::env::var("JWT_ALGORITHM") {
+            Ok(raw) => KeyAlgorithm::parse(&raw).unwrap_or_else(|| {
+                tracing::warn!("unknown JWT_ALGORITHM '{}', using HS256", raw);
+                KeyAlgorithm::HS256
+            }),
+            Err(_) => KeyAlgorithm::HS256,
+        };
+        let keys_file = match std::env::var("JWT_KEYS_FILE") {
+            Ok(v) if v.eq_ignore_ascii_case("off") => None,
+            Ok(v) => Some(PathBuf::from(v)),
+            Err(_) => Some(Path::new(upload_dir).join("jwt_keys.json")),
+        };
+        let days = |var: &str| {
+            std::env::var(var)
+                .ok()
+                .and_then(|v| v.trim().parse::<i64>().ok())
+                .map(Duration::days)
+        };
+
+        Self {
+            algorithm,
+            keys_file,
+            grace: days("JWT_KEY_GRACE_DAYS").unwrap_or_else(|| Duration::days(30)),
+            rotate_after: days("JWT_KEY_ROTATE_DAYS"),
+            rsa_private_key_file: std::env::var("JWT_RSA_PRIVATE_KEY_FILE")
+                .ok()
+                .map(PathBuf::from),
+            rsa_public_key_file: std::env::var("JWT_RSA_PUBLIC_KEY_FILE")
+                .ok()
+                .map(PathBuf::from),
+            env_secret: std::env::var("JWT_SECRET").ok().filter(|s| !s.is_empty()),
+        }
+    }
+}
+
+/// A signing key as written to the keys file. Exactly one set of key
+/// material fields is filled in, depending on `alg`.
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredKey {
+    kid: String,
+    alg: KeyAlgorithm,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    retired_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pkcs8: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private_pem: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_pem: Option<String>,
+}
+
+impl StoredKey {
+    fn generate(alg: KeyAlgorithm, config: &KeyConfig) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let mut key = StoredKey {
+            kid: uuid::Uuid::new_v4().simple().to_string(),
+            alg,
+            created_at: Utc::now(),
+            retired_at: None,
+            secret: None,
+            pkcs8: None,
+            public_key: None,
+            private_pem: None,
+            public_pem: None,
+        };
+
+        match alg {
+            KeyAlgorithm::HS256 => {
+                let mut secret = [0u8; 64];
+                rng.fill(&mut secret)
+                    .map_err(|_| eyre!("could not generate HS256 secret"))?;
+                key.secret = Some(secret.encode_hex());
+            }
+            KeyAlgorithm::EdDSA => {
+                let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
+                    .map_err(|_| eyre!("could not generate Ed25519 key"))?;
+                let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+                    .map_err(|_| eyre!("generated Ed25519 key is invalid"))?;
+                key.pkcs8 = Some(pkcs8.as_ref().encode_hex());
+                key.public_key = Some(pair.public_key().as_ref().encode_hex());
+            }
+            KeyAlgorithm::RS256 => {
+                // RSA keys are not generated here; they come from PEM files so
+                // operators control key size and provenance.
+                let private = config
+                    .rsa_private_key_file
+                    .as_ref()
+                    .ok_or_else(|| eyre!("JWT_RSA_PRIVATE_KEY_FILE not set"))?;
+                let public = config
+                    .rsa_public_key_file
+                    .as_ref()
+                    .ok_or_else(|| eyre!("JWT_RSA_PUBLIC_KEY_FILE not set"))?;
+                key.private_pem = Some(
+                    std::fs::read_to_string(private)
+                        .wrap_err_with(|| format!("could not read {:?}", private))?,
+                );
+                key.public_pem = Some(
+                    std::fs::read_to_string(public)
+                        .wrap_err_with(|| format!("could not read {:?}", public))?,
+                );
+            }
+        }
+
+        Ok(key)
+    }
+}
+
+fn field<'a>(value: &'a Option<String>, name: &str, kid: &str) -> Result<&'a str> {
+    value
+        .as_deref()
+        .ok_or_else(|| eyre!("key '{}' is missing '{}'", kid, name))
+}
+
+fn from_hex(value: &Option<String>, name: &str, kid: &str) -> Result<Vec<u8>> {
+    Vec::from_hex(field(value, name, kid)?).wrap_err_with(|| format!("key '{}': bad {}", kid, name))
+}
+
+struct JwtKey {
+    stored: StoredKey,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// The `JWT_SECRET` key is never written to the keys file.
+    from_env: bool,
+}
+
+impl JwtKey {
+    fn new(stored: StoredKey) -> Result<Self> {
+        let kid = stored.kid.as_str();
+        let (encoding, decoding) = match stored.alg {
+            KeyAlgorithm::HS256 => {
+                let secret = from_hex(&stored.secret, "secret", kid)?;
+                (
+                    EncodingKey::from_secret(&secret),
+                    DecodingKey::from_secret(&secret),
+                )
+            }
+            KeyAlgorithm::EdDSA => (
+                EncodingKey::from_ed_der(&from_hex(&stored.pkcs8, "pkcs8", kid)?),
+                DecodingKey::from_ed_der(&from_hex(&stored.public_key, "public_key", kid)?),
+            ),
+            KeyAlgorithm::RS256 => (
+                EncodingKey::from_rsa_pem(
+                    field(&stored.private_pem, "private_pem", kid)?.as_bytes(),
+                )
+                .wrap_err_with(|| format!("key '{}': bad private_pem", kid))?,
+                DecodingKey::from_rsa_pem(field(&stored.public_pem, "public_pem", kid)?.as_bytes())
+                    .wrap_err_with(|| format!("key '{}': bad public_pem", kid))?,
+            ),
+        };
+        Ok(Self {
+            stored,
+            encoding,
+            decoding,
+            from_env: false,
+        })
+    }
+
+    /// The `JWT_SECRET` key, retired at `retired_at`: it is not in the keys
+    /// file, so its retirement is taken from when the oldest stored key was
+    /// created.
+    fn from_env_secret(secret: &str, retired_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            stored: StoredKey {
+                kid: ENV_KID.to_owned(),
+                alg: KeyAlgorithm::HS256,
+                created_at: DateTime::UNIX_EPOCH,
+                retired_at,
+                secret: None,
+                pkcs8: None,
+                public_key: None,
+                private_pem: None,
+                public_pem: None,
+            },
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            from_env: true,
+        }
+    }
+
+    fn expired(&self, grace: Duration, now: DateTime<Utc>) -> bool {
+        self.stored.retired_at.is_some_and(|t| t + grace <= now)
+    }
+}
+
+/// What the admin panel shows about a key; never includes key material.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyInfo {
+    pub kid: String,
+    pub alg: KeyAlgorithm,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub signing: bool,
+    pub from_env: bool,
+}
+
+/// The set of keys that sessions are signed and checked with. The newest
+/// active key signs; rotated-out keys keep validating for `grace`.
+pub struct KeyRing {
+    config: KeyConfig,
+    keys: RwLock<Vec<JwtKey>>,
+}
+
+impl KeyRing {
+    pub fn load(config: KeyConfig) -> Result<Self> {
+        let mut keys: Vec<JwtKey> = Vec::new();
+
+        if let Some(path) = &config.keys_file
+            && path.exists()
+        {
+            let stored: Vec<StoredKey> = read_json(path)?;
+            for key in stored {
+                keys.push(JwtKey::new(key)?);
+            }
+        }
+
+        let ring = Self {
+            keys: RwLock::new(keys),
+            config,
+        };
+
+        {
+            let mut keys = ring.keys.write().unwrap();
+            let now = Utc::now();
+            keys.retain(|k| !k.expired(ring.config.grace, now));
+            if let Some(secret) = &ring.config.env_secret {
+                let retired_at = keys.iter().map(|k| k.stored.created_at).min();
+                let env_key = JwtKey::from_env_secret(secret, retired_at);
+                if env_key.expired(ring.config.grace, now) {
+                    tracing::info!(
+                        "JWT_SECRET was replaced by a newer signing key more than {} days ago and is no longer accepted",
+                        ring.config.grace.num_days()
+                    );
+                } else {
+                    keys.insert(0, env_key);
+                }
+            }
+        }
+
+        let needs_key = ring.signing_kid().is_none();
+        let stale = ring.config.rotate_after.is_some_and(|max_age| {
+            ring.keys
+                .read()
+                .unwrap()
+                .iter()
+                .rev()
+                .find(|k| !k.from_env && k.stored.retired_at.is_none())
+                .is_some_and(|k| Utc::now() - k.stored.created_at > max_age)
+        });
+        if needs_key || stale {
+            let kid = ring.rotate()?;
+            tracing::info!("created JWT signing key '{}'", kid);
+        }
+
+        if ring.config.keys_file.is_none() && ring.config.env_secret.is_none() {
+            tracing::warn!(
+                "JWT_KEYS_FILE is off and JWT_SECRET is not set — sessions will not survive restarts!"
+            );
+        }
+
+        Ok(ring)
+    }
+
+    fn signing_index(keys: &[JwtKey]) -> Option<usize> {
+        keys.iter()
+            .rposition(|k| !k.from_env && k.stored.retired_at.is_none())
+            .or_else(|| {
+                keys.iter()
+                    .position(|k| k.from_env && k.stored.retired_at.is_none())
+            })
+    }
+
+    pub fn signing_kid(&self) -> Option<String> {
+        let keys = self.keys.read().unwrap();
+        Self::signing_index(&keys).map(|i| keys[i].stored.kid.clone())
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> String {
+        let keys = self.keys.read().unwrap();
+        let key = &keys[Self::signing_index(&keys).expect("key ring always has a signing key")];
+        let mut header = Header::new(key.stored.alg.jwt());
+        header.kid = Some(key.stored.kid.clone());
+        jsonwebtoken::encode(&header, claims, &key.encoding).expect("JWT encoding should not fail")
+    }
+
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> jsonwebtoken::errors::Result<T> {
+        use jsonwebtoken::errors::ErrorKind;
+
+        let header = jsonwebtoken::decode_header(token)?;
+        let kid = header.kid.as_deref().unwrap_or(ENV_KID);
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .iter()
+            .find(|k| k.stored.kid == kid)
+            .filter(|k| !k.expired(self.config.grace, Utc::now()))
+            .ok_or(ErrorKind::InvalidSignature)?;
+        if header.alg != key.stored.alg.jwt() {
+            return Err(ErrorKind::InvalidAlgorithm.into());
+        }
+        let data = jsonwebtoken::decode::<T>(
+            token,
+            &key.decoding,
+            &Validation::new(key.stored.alg.jwt()),
+        )?;
+        Ok(data.claims)
+    }
+
+    /// Creates a new signing key with the configured algorithm and retires the
+    /// previous one, including the `JWT_SECRET` key. Returns the new key's kid.
+    pub fn rotate(&self) -> Result<String> {
+        let key = JwtKey::new(StoredKey::generate(self.config.algorithm, &self.config)?)?;
+        let kid = key.stored.kid.clone();
+        let now = Utc::now();
+
+        let mut keys = self.keys.write().unwrap();
+        if let Some(current) = keys
+            .iter()
+            .rev()
+            .find(|k| !k.from_env && k.stored.retired_at.is_none())
+            && current.stored.public_pem.is_some()
+            && current.stored.public_pem == key.stored.public_pem
+        {
+            return Err(eyre!(
+                "the RSA key files have not changed; replace them before rotating"
+            ));
+        }
+        for k in keys.iter_mut() {
+            k.stored.retired_at.get_or_insert(now);
+        }
+        keys.retain(|k| !k.expired(self.config.grace, now));
+        keys.push(key);
+        self.persist(&keys);
+        Ok(kid)
+    }
+
+    pub fn info(&self) -> Vec<KeyInfo> {
+        let keys = self.keys.read().unwrap();
+        let signing = Self::signing_index(&keys);
+        keys.iter()
+            .enumerate()
+            .rev()
+            .map(|(i, k)| KeyInfo {
+                kid: k.stored.kid.clone(),
+                alg: k.stored.alg,
+                created_at: k.stored.created_at,
+                retired_at: k.stored.retired_at,
+                expires_at: k.stored.retired_at.map(|t| t + self.config.grace),
+                signing: signing == Some(i),
+                from_env: k.from_env,
+            })
+            .collect()
+    }
+
+    fn persist(&self, keys: &[JwtKey]) {
+        let Some(path) = &self.config.keys_file else {
+            return;
+        };
+        let stored: Vec<&StoredKey> = keys
+            .iter()
+            .filter(|k| !k.from_env)
+            .map(|k| &k.stored)
+            .collect();
+        match serde_json::to_string_pretty(&stored) {
+            Ok(json) => {
+                if let Err(e) = write_private(path, &json) {
+                    tracing::warn!("could not write JWT keys to {:?}: {}", path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize JWT keys: {}", e),
+        }
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let json =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("could not read {:?}", path))?;
+    serde_json::from_str(&json).wrap_err_with(|| format!("could not parse {:?}", path))
+}
+
+/// Writes a file that only the owner can read, for signing keys and other
+/// secrets.
+pub(crate) fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::{io::Write, os::unix::fs::OpenOptionsExt};
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(contents.as_bytes())
+    }
+
+    #[cfg(not(unix))]
+    std::fs::write(path, contents)
+}
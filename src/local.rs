@@ -0,0 +1,457 @@
+use {
+    crate::{
+        auth::hash_api_token,
+        error::{AppError, AppResult},
+        keys::write_private,
+        models::{Invite, LocalAccount, PasswordReset, PlatformUser},
+    },
+    argon2::{
+        Argon2,
+        password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    },
+    chrono::{Duration, Utc},
+    dashmap::DashMap,
+    hex::ToHex,
+    ring::{
+        hmac,
+        rand::{SecureRandom, SystemRandom},
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        path::{Path, PathBuf},
+        sync::{Mutex, OnceLock, RwLock},
+    },
+};
+
+/// Provider name recorded on sessions and identities of local accounts.
+pub const LOCAL_PROVIDER: &str = "local";
+
+const TOTP_STEP_SECS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+const TOTP_SECRET_BYTES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    Open,
+    Invite,
+    Closed,
+}
+
+impl RegistrationMode {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "open" => Some(Self::Open),
+            "invite" | "invite-only" => Some(Self::Invite),
+            "closed" | "off" => Some(Self::Closed),
+            _ => None,
+        }
+    }
+}
+
+/// `LOCAL_ACCOUNTS=true` turns on username/password logins.
+/// `LOCAL_REGISTRATION` (`open`, `invite` or `closed`) is the initial
+/// registration mode; admins can change it at runtime.
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalAuthConfig {
+    pub enabled: bool,
+    pub registration: RegistrationMode,
+}
+
+impl LocalAuthConfig {
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("LOCAL_ACCOUNTS")
+            .is_ok_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"));
+        let registration = match std::env::var("LOCAL_REGISTRATION") {
+            Ok(raw) => RegistrationMode::parse(&raw).unwrap_or_else(|| {
+                tracing::warn!(
+                    "unknown LOCAL_REGISTRATION '{}', falling back to invite-only",
+                    raw
+                );
+                RegistrationMode::Invite
+            }),
+            Err(_) => RegistrationMode::Open,
+        };
+        Self {
+            enabled,
+            registration,
+        }
+    }
+}
+
+pub fn platform_user(account: &LocalAccount) -> PlatformUser {
+    PlatformUser {
+        provider: LOCAL_PROVIDER.to_owned(),
+        id: account.id,
+        username: account.username.clone(),
+        avatar_url: String::new(),
+        user_id: String::new(),
+    }
+}
+
+pub fn validate_username(username: &str) -> AppResult<()> {
+    let valid_len = (3..=32).contains(&username.len());
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid_len && valid_chars {
+        Ok(())
+    } else {
+        Err(AppError::InvalidUsername)
+    }
+}
+
+pub fn validate_password(password: &str) -> AppResult<()> {
+    if (8..=1024).contains(&password.len()) {
+        Ok(())
+    } else {
+        Err(AppError::WeakPassword)
+    }
+}
+
+/// Argon2id with the crate's default parameters. Slow on purpose; call it
+/// from a blocking task.
+pub fn hash_password(password: &str) -> AppResult<String> {
+    let salt = SaltString::encode_b64(&random_bytes::<16>())
+        .map_err(|e| AppError::Internal(format!("could not encode salt: {}", e)))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| AppError::Internal(format!("could not hash password: {}", e)))
+}
+
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+/// Spends the same time as a real check when the username does not exist,
+/// so response times do not reveal which usernames are taken.
+pub fn verify_dummy_password(password: &str) {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    let hash = DUMMY.get_or_init(|| hash_password("not a real password").unwrap_or_default());
+    verify_password(hash, password);
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+    bytes
+}
+
+fn random_token() -> String {
+    random_bytes::<32>().encode_hex()
+}
+
+/// RFC 6238 code for one time step (HMAC-SHA1, six digits).
+pub fn totp_code(secret: &[u8], step: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// The time step `code` is valid for, allowing one step of clock drift.
+pub fn verify_totp(secret_b32: &str, code: &str, unix_time: u64) -> Option<u64> {
+    let secret = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret_b32)?;
+    let code: u32 = code.trim().parse().ok()?;
+    let now = unix_time / TOTP_STEP_SECS;
+    [now, now.saturating_sub(1), now + 1]
+        .into_iter()
+        .find(|&step| totp_code(&secret, step) == code)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoredLocalAccounts {
+    #[serde(default)]
+    registration: Option<RegistrationMode>,
+    #[serde(default)]
+    accounts: Vec<LocalAccount>,
+    #[serde(default)]
+    invites: Vec<Invite>,
+    #[serde(default)]
+    resets: Vec<PasswordReset>,
+}
+
+/// Local accounts, invites and password reset links, kept in
+/// `local_accounts.json`.
+pub struct LocalAccountStore {
+    path: PathBuf,
+    default_registration: RegistrationMode,
+    /// Set once an admin changes the mode; saved and preferred over the env
+    /// default.
+    registration: RwLock<Option<RegistrationMode>>,
+    accounts: DashMap<u64, LocalAccount>,
+    invites: DashMap<String, Invite>,
+    resets: DashMap<String, PasswordReset>,
+    /// Held across the username check, id allocation and insert in
+    /// [`Self::register`], so concurrent sign-ups cannot collide.
+    register_lock: Mutex<()>,
+}
+
+impl LocalAccountStore {
+    const FILE_NAME: &'static str = "local_accounts.json";
+    const INVITE_TTL_DAYS: i64 = 7;
+    const RESET_TTL_HOURS: i64 = 24;
+
+    pub fn load(upload_dir: &str, config: &LocalAuthConfig) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let stored: StoredLocalAccounts = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("could not parse {:?}: {}", path, e);
+                StoredLocalAccounts::default()
+            }),
+            Err(_) => StoredLocalAccounts::default(),
+        };
+        let now = Utc::now();
+        Self {
+            path,
+            default_registration: config.registration,
+            registration: RwLock::new(stored.registration),
+            accounts: stored.accounts.into_iter().map(|a| (a.id, a)).collect(),
+            invites: stored
+                .invites
+                .into_iter()
+                .filter(|i| i.expires_at > now)
+                .map(|i| (i.code_hash.clone(), i))
+                .collect(),
+            resets: stored
+                .resets
+                .into_iter()
+                .filter(|r| r.expires_at > now)
+                .map(|r| (r.token_hash.clone(), r))
+                .collect(),
+            register_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn registration(&self) -> RegistrationMode {
+        self.registration
+            .read()
+            .unwrap()
+            .unwrap_or(self.default_registration)
+    }
+
+    pub fn set_registration(&self, mode: RegistrationMode) {
+        *self.registration.write().unwrap() = Some(mode);
+        self.persist();
+    }
+
+    pub fn get(&self, id: u64) -> Option<LocalAccount> {
+        self.accounts.get(&id).map(|a| a.clone())
+    }
+
+    pub fn find_by_username(&self, username: &str) -> Option<LocalAccount> {
+        self.accounts
+            .iter()
+            .find(|a| a.username.eq_ignore_ascii_case(username))
+            .map(|a| a.clone())
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Creates an account if the registration mode allows it, consuming the
+    /// invite when one is required. The password must already be hashed.
+    pub fn register(
+        &self,
+        username: &str,
+        password_hash: String,
+        invite: Option<&str>,
+    ) -> AppResult<LocalAccount> {
+        validate_username(username)?;
+        let _guard = self.register_lock.lock().unwrap();
+        let invite_hash = match self.registration() {
+            RegistrationMode::Closed => return Err(AppError::RegistrationClosed),
+            RegistrationMode::Open => None,
+            RegistrationMode::Invite => {
+                let hash = hash_api_token(invite.unwrap_or_default().trim());
+                match self.invites.get(&hash) {
+                    Some(i) if i.expires_at > Utc::now() => Some(hash),
+                    _ => return Err(AppError::InvalidInvite),
+                }
+            }
+        };
+        if self.find_by_username(username).is_some() {
+            return Err(AppError::UsernameTaken);
+        }
+        if let Some(hash) = invite_hash
+            && self.invites.remove(&hash).is_none()
+        {
+            return Err(AppError::InvalidInvite);
+        }
+
+        let account = LocalAccount {
+            id: self.accounts.iter().map(|a| a.id).max().unwrap_or(0) + 1,
+            username: username.to_owned(),
+            password_hash,
+            totp_secret: None,
+            totp_pending: None,
+            totp_last_step: None,
+            created_at: Utc::now(),
+        };
+        self.accounts.insert(account.id, account.clone());
+        self.persist();
+        Ok(account)
+    }
+
+    pub fn set_password(&self, id: u64, password_hash: String) -> AppResult<()> {
+        let mut account = self.accounts.get_mut(&id).ok_or(AppError::UserNotFound)?;
+        account.password_hash = password_hash;
+        drop(account);
+        self.persist();
+        Ok(())
+    }
+
+    /// Generates a new, unconfirmed TOTP secret and returns it in base32.
+    pub fn begin_totp(&self, id: u64) -> AppResult<String> {
+        let secret = base32::encode(
+            base32::Alphabet::Rfc4648 { padding: false },
+            &random_bytes::<TOTP_SECRET_BYTES>(),
+        );
+        let mut account = self.accounts.get_mut(&id).ok_or(AppError::UserNotFound)?;
+        account.totp_pending = Some(secret.clone());
+        drop(account);
+        self.persist();
+        Ok(secret)
+    }
+
+    /// Confirms the pending secret with a code from the authenticator app.
+    pub fn enable_totp(&self, id: u64, code: &str) -> AppResult<()> {
+        let mut account = self.accounts.get_mut(&id).ok_or(AppError::UserNotFound)?;
+        let pending = account
+            .totp_pending
+            .clone()
+            .ok_or(AppError::InvalidTotpCode)?;
+        let step = verify_totp(&pending, code, unix_now()).ok_or(AppError::InvalidTotpCode)?;
+        account.totp_secret = Some(pending);
+        account.totp_pending = None;
+        account.totp_last_step = Some(step);
+        drop(account);
+        self.persist();
+        Ok(())
+    }
+
+    pub fn disable_totp(&self, id: u64) -> AppResult<()> {
+        let mut account = self.accounts.get_mut(&id).ok_or(AppError::UserNotFound)?;
+        account.totp_secret = None;
+        account.totp_pending = None;
+        account.totp_last_step = None;
+        drop(account);
+        self.persist();
+        Ok(())
+    }
+
+    /// Checks a second-factor code, rejecting codes from a time step that
+    /// was already used.
+    pub fn check_totp(&self, id: u64, code: &str) -> bool {
+        let Some(mut account) = self.accounts.get_mut(&id) else {
+            return false;
+        };
+        let Some(secret) = account.totp_secret.clone() else {
+            return false;
+        };
+        let Some(step) = verify_totp(&secret, code, unix_now()) else {
+            return false;
+        };
+        if account.totp_last_step.is_some_and(|last| step <= last) {
+            return false;
+        }
+        account.totp_last_step = Some(step);
+        drop(account);
+        self.persist();
+        true
+    }
+
+    /// Returns the plaintext invite code; only its hash is kept.
+    pub fn create_invite(&self, created_by: &str) -> (String, Invite) {
+        let code = random_token();
+        let now = Utc::now();
+        let invite = Invite {
+            code_hash: hash_api_token(&code),
+            created_by: created_by.to_owned(),
+            created_at: now,
+            expires_at: now + Duration::days(Self::INVITE_TTL_DAYS),
+        };
+        self.invites
+            .insert(invite.code_hash.clone(), invite.clone());
+        self.persist();
+        (code, invite)
+    }
+
+    pub fn invite_count(&self) -> usize {
+        let now = Utc::now();
+        self.invites.iter().filter(|i| i.expires_at > now).count()
+    }
+
+    /// Returns the plaintext reset token; only its hash is kept. Any earlier
+    /// link for the same account stops working.
+    pub fn create_reset(&self, account_id: u64) -> AppResult<(String, PasswordReset)> {
+        if !self.accounts.contains_key(&account_id) {
+            return Err(AppError::UserNotFound);
+        }
+        self.resets.retain(|_, r| r.account_id != account_id);
+        let token = random_token();
+        let now = Utc::now();
+        let reset = PasswordReset {
+            token_hash: hash_api_token(&token),
+            account_id,
+            created_at: now,
+            expires_at: now + Duration::hours(Self::RESET_TTL_HOURS),
+        };
+        self.resets.insert(reset.token_hash.clone(), reset.clone());
+        self.persist();
+        Ok((token, reset))
+    }
+
+    /// The account a reset token is for, if it is still valid.
+    pub fn reset_account(&self, token: &str) -> Option<u64> {
+        self.resets
+            .get(&hash_api_token(token))
+            .filter(|r| r.expires_at > Utc::now())
+            .map(|r| r.account_id)
+    }
+
+    /// Consumes a reset token and stores the new password hash.
+    pub fn complete_reset(&self, token: &str, password_hash: String) -> AppResult<u64> {
+        let (_, reset) = self
+            .resets
+            .remove(&hash_api_token(token))
+            .ok_or(AppError::InvalidResetLink)?;
+        if reset.expires_at <= Utc::now() {
+            self.persist();
+            return Err(AppError::InvalidResetLink);
+        }
+        self.set_password(reset.account_id, password_hash)?;
+        Ok(reset.account_id)
+    }
+
+    fn persist(&self) {
+        let stored = StoredLocalAccounts {
+            registration: *self.registration.read().unwrap(),
+            accounts: self.accounts.iter().map(|e| e.value().clone()).collect(),
+            invites: self.invites.iter().map(|e| e.value().clone()).collect(),
+            resets: self.resets.iter().map(|e| e.value().clone()).collect(),
+        };
+        match serde_json::to_string_pretty(&stored) {
+            Ok(json) => {
+                if let Err(e) = write_private(&self.path, &json) {
+                    tracing::warn!("could not write local accounts to {:?}: {}", self.path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize local accounts: {}", e),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
@@ -1,12 +1,28 @@
 #![allow(clippy::too_many_arguments)]
 mod app;
 mod auth;
+mod collections;
+mod comments;
 mod error;
+mod keys;
+mod local;
+mod markdown;
 mod models;
+mod moderation;
+mod oauth;
+mod query;
+mod ratelimit;
+mod roles;
+mod sanctions;
 mod routes;
+mod search;
+mod sessions;
 mod state;
+mod tags;
 #[cfg(test)]
 mod tests;
+mod users;
+mod util;
 
 #[global_allocator]
 pub static GLOBAL_ALLOCATOR: mimalloc::MiMalloc = mimalloc::MiMalloc;
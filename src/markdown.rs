@@ -0,0 +1,199 @@
+use {
+    crate::{models::VideoMeta, users::UserStore},
+    ammonia::{Builder, UrlRelative},
+    pulldown_cmark::{CowStr, Event, LinkType, Options, Parser, Tag, TagEnd, TextMergeStream},
+    regex::{Captures, Regex},
+    std::{
+        collections::{HashMap, HashSet},
+        sync::OnceLock,
+    },
+};
+
+/// How comments on one upload are rendered.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenderOptions {
+    /// Turn timestamps like `1:23` into seek links; set for audio and video.
+    pub timestamps: bool,
+    /// Leave timestamps past the end as text, when the length is known.
+    pub duration_secs: Option<f64>,
+}
+
+impl RenderOptions {
+    pub fn for_media(meta: &VideoMeta) -> Self {
+        let timeline =
+            meta.content_type.starts_with("video/") || meta.content_type.starts_with("audio/");
+        Self {
+            timestamps: timeline,
+            duration_secs: meta.duration_secs,
+        }
+    }
+}
+
+/// Bare URLs, timestamps and `@name` or `@provider/name` mentions in plain
+/// text.
+fn inline_pattern() -> &'static Regex {
+    static INLINE: OnceLock<Regex> = OnceLock::new();
+    INLINE.get_or_init(|| {
+        Regex::new(concat!(
+            r#"(?P<url>\bhttps?://[^\s<>]*[^\s<>.,;:!?'")\]])"#,
+            r"|(?P<ts>\b(?:\d{1,2}:)?\d{1,2}:\d{2}\b)",
+            r"|(?:^|[^\w@/])@(?:(?P<provider>[a-z0-9_-]{1,32})/)?(?P<name>[A-Za-z0-9_][A-Za-z0-9_-]{0,31})",
+        ))
+        .unwrap()
+    })
+}
+
+/// Only the markup CommonMark produces for comments, and only links that
+/// stay on the web.
+fn sanitizer() -> &'static Builder<'static> {
+    static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+    SANITIZER.get_or_init(|| {
+        let mut builder = Builder::empty();
+        builder
+            .tags(HashSet::from([
+                "p",
+                "br",
+                "em",
+                "strong",
+                "code",
+                "pre",
+                "blockquote",
+                "ul",
+                "ol",
+                "li",
+                "a",
+                "hr",
+            ]))
+            .tag_attributes(HashMap::from([
+                ("a", HashSet::from(["href", "title"])),
+                ("ol", HashSet::from(["start"])),
+            ]))
+            .url_schemes(HashSet::from(["http", "https", "mailto"]))
+            .url_relative(UrlRelative::PassThrough)
+            .link_rel(Some("nofollow noopener ugc"))
+            .clean_content_tags(HashSet::from(["script", "style"]));
+        builder
+    })
+}
+
+/// `h:mm:ss` or `m:ss` in seconds.
+fn parse_timestamp(raw: &str) -> Option<u32> {
+    let parts: Vec<u32> = raw
+        .split(':')
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+    match parts[..] {
+        [m, s] if s < 60 => Some(m * 60 + s),
+        [h, m, s] if m < 60 && s < 60 => Some(h * 3600 + m * 60 + s),
+        _ => None,
+    }
+}
+
+fn link(events: &mut Vec<Event<'static>>, href: String, text: String) {
+    events.push(Event::Start(Tag::Link {
+        link_type: LinkType::Inline,
+        dest_url: href.into(),
+        title: CowStr::Borrowed(""),
+        id: CowStr::Borrowed(""),
+    }));
+    events.push(Event::Text(text.into()));
+    events.push(Event::End(TagEnd::Link));
+}
+
+/// Splits plain text into text and links.
+fn decorate(
+    text: &str,
+    options: RenderOptions,
+    users: &UserStore,
+    events: &mut Vec<Event<'static>>,
+) {
+    let mut last = 0;
+    for caps in inline_pattern().captures_iter(text) {
+        let Some((start, href)) = target(&caps, options, users) else {
+            continue;
+        };
+        let end = caps.get(0).unwrap().end();
+        if start > last {
+            events.push(Event::Text(text[last..start].to_owned().into()));
+        }
+        link(events, href, text[start..end].to_owned());
+        last = end;
+    }
+    if last < text.len() {
+        events.push(Event::Text(text[last..].to_owned().into()));
+    }
+}
+
+/// Where a match starts and what it links to, or `None` to leave it as text.
+fn target(caps: &Captures, options: RenderOptions, users: &UserStore) -> Option<(usize, String)> {
+    if let Some(url) = caps.name("url") {
+        return Some((url.start(), url.as_str().to_owned()));
+    }
+    if let Some(ts) = caps.name("ts") {
+        let secs = parse_timestamp(ts.as_str()).filter(|_| options.timestamps)?;
+        if options.duration_secs.is_some_and(|d| f64::from(secs) > d) {
+            return None;
+        }
+        return Some((ts.start(), format!("#t={}", secs)));
+    }
+    let name = caps.name("name")?;
+    let provider = caps.name("provider");
+    let identity = users.find_by_username(provider.map(|p| p.as_str()), name.as_str())?;
+    Some((
+        provider.unwrap_or(name).start() - 1,
+        format!("/ui/u/{}/{}", identity.provider, identity.external_id),
+    ))
+}
+
+/// Renders comment text as sanitized HTML: CommonMark without raw HTML,
+/// plus links for bare URLs, `@mentions` and (per `options`) timestamps.
+pub fn render(text: &str, options: RenderOptions, users: &UserStore) -> String {
+    let mut events: Vec<Event<'static>> = Vec::new();
+    // Text inside links and code is left alone.
+    let mut literal = 0usize;
+    for event in TextMergeStream::new(Parser::new_ext(text, Options::empty())) {
+        match event {
+            Event::Start(Tag::Link { .. } | Tag::CodeBlock(_)) => {
+                literal += 1;
+                events.push(event.into_static());
+            }
+            Event::End(TagEnd::Link | TagEnd::CodeBlock) => {
+                literal -= 1;
+                events.push(event.into_static());
+            }
+            // Images would load from anywhere, so they become links.
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                literal += 1;
+                events.push(
+                    Event::Start(Tag::Link {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    })
+                    .into_static(),
+                );
+            }
+            Event::End(TagEnd::Image) => {
+                literal -= 1;
+                events.push(Event::End(TagEnd::Link));
+            }
+            // Raw HTML is shown as typed.
+            Event::Start(Tag::HtmlBlock) => events.push(Event::Start(Tag::Paragraph)),
+            Event::End(TagEnd::HtmlBlock) => events.push(Event::End(TagEnd::Paragraph)),
+            Event::Html(html) | Event::InlineHtml(html) => {
+                events.push(Event::Text(html.into_string().into()));
+            }
+            Event::Text(text) if literal == 0 => decorate(&text, options, users, &mut events),
+            event => events.push(event.into_static()),
+        }
+    }
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    sanitizer().clean(&html).to_string()
+}
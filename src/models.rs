@@ -1,101 +1,96 @@
 use {
     chrono::{DateTime, Utc},
     serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
 };
 
 fn default_true() -> bool {
     true
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[allow(dead_code)]
-pub struct OsuTokenResponse {
-    pub access_token: String,
-    pub token_type: String,
-    pub expires_in: u64,
-    pub refresh_token: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Video,
+    Audio,
+    Image,
+    Text,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct OsuUser {
-    pub id: u64,
-    pub username: String,
-    pub avatar_url: String,
-    pub is_restricted: bool,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-#[allow(dead_code)]
-pub struct GithubTokenResponse {
-    pub access_token: String,
-    pub token_type: String,
-    pub scope: Option<String>,
-}
+impl MediaKind {
+    pub const ALL: [MediaKind; 4] = [
+        MediaKind::Video,
+        MediaKind::Audio,
+        MediaKind::Image,
+        MediaKind::Text,
+    ];
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct GithubUser {
-    pub id: u64,
-    pub login: String,
-    pub avatar_url: String,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-#[allow(dead_code)]
-pub struct DiscordTokenResponse {
-    pub access_token: String,
-    pub token_type: String,
-    pub expires_in: u64,
-    pub refresh_token: Option<String>,
-    pub scope: Option<String>,
-}
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaKind::Video => "video",
+            MediaKind::Audio => "audio",
+            MediaKind::Image => "image",
+            MediaKind::Text => "text",
+        }
+    }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct DiscordUser {
-    pub id: String,
-    pub username: String,
-    pub avatar: Option<String>,
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime.split('/').next()? {
+            "video" => Some(MediaKind::Video),
+            "audio" => Some(MediaKind::Audio),
+            "image" => Some(MediaKind::Image),
+            "text" => Some(MediaKind::Text),
+            _ => None,
+        }
+    }
 }
 
+/// An external identity as returned by a login provider. `user_id` is the
+/// internal [`User`] it is linked to, filled in once the request is
+/// authenticated.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlatformUser {
     pub provider: String,
     pub id: u64,
     pub username: String,
     pub avatar_url: String,
+    #[serde(default)]
+    pub user_id: String,
 }
 
-impl PlatformUser {
-    pub fn from_osu(u: &OsuUser) -> Self {
-        Self {
-            provider: "osu".to_owned(),
-            id: u.id,
-            username: u.username.clone(),
-            avatar_url: u.avatar_url.clone(),
-        }
-    }
+/// A login linked to a [`User`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    pub provider: String,
+    pub external_id: u64,
+    pub username: String,
+    #[serde(default)]
+    pub avatar_url: String,
+    pub linked_at: DateTime<Utc>,
+}
 
-    pub fn from_github(u: &GithubUser) -> Self {
-        Self {
-            provider: "github".to_owned(),
-            id: u.id,
-            username: u.login.clone(),
-            avatar_url: u.avatar_url.clone(),
-        }
-    }
+/// A person on the site. Uploads, comments and API tokens belong to the
+/// user, so any of their linked identities can manage them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub identities: Vec<Identity>,
+    /// Set by an admin; `None` means the default [`Role::User`].
+    #[serde(default)]
+    pub role: Option<Role>,
+}
 
-    pub fn from_discord(u: &DiscordUser) -> Self {
-        let avatar_url = match &u.avatar {
-            Some(hash) => format!("https://cdn.discordapp.com/avatars/{}/{}.png", u.id, hash),
-            None => "https://cdn.discordapp.com/embed/avatars/0.png".to_owned(),
-        };
-        let id: u64 = u.id.parse().unwrap_or(0);
-        Self {
-            provider: "discord".to_owned(),
-            id,
-            username: u.username.clone(),
-            avatar_url,
-        }
-    }
+/// What a [`User`] is allowed to do; see `roles.rs` for the permission
+/// matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Moderator,
+    Trusted,
+    User,
+    Banned,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -118,15 +113,56 @@ pub struct VideoMeta {
     pub uploaded_by_provider: String,
     pub uploaded_by_id: u64,
     pub uploaded_by_name: String,
+    /// Internal [`User`] id of the uploader; backfilled from the provider
+    /// fields on startup for older uploads.
+    #[serde(default)]
+    pub uploaded_by_user: String,
     pub uploaded_at: DateTime<Utc>,
     pub nsfw: bool,
     #[serde(default)]
     pub unlisted: bool,
+    /// Set when a moderator hid the upload over a report. It stays unlisted
+    /// until someone allowed to edit any media lists it again.
+    #[serde(default)]
+    pub hidden_by_moderator: bool,
     #[serde(default = "default_true")]
     pub comments_disabled: bool,
     pub references_id: Option<String>,
     #[serde(default)]
     pub original_extension: Option<String>,
+    /// Length in seconds of video and audio uploads, as reported by ffprobe.
+    #[serde(default)]
+    pub duration_secs: Option<f64>,
+    /// Canonical tags, in the order the uploader gave them.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, rocket::FromFormField,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum CollectionKind {
+    #[default]
+    Collection,
+    /// Only video and audio, played one after another.
+    Playlist,
+}
+
+/// A named, ordered list of uploads that any user can put together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: String,
+    #[serde(default)]
+    pub kind: CollectionKind,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    pub owner_user: String,
+    /// Upload ids, in play order.
+    pub items: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -137,8 +173,324 @@ pub struct Comment {
     pub author_provider: String,
     pub author_id: u64,
     pub author_name: String,
+    #[serde(default)]
+    pub author_user: String,
     pub text: String,
     pub created_at: DateTime<Utc>,
     #[serde(default)]
     pub parent_id: Option<String>,
+    /// When the text was last changed; shown as an "edited" marker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Earlier versions of the text, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revisions: Vec<CommentRevision>,
+    /// Emoji → internal ids of the users who reacted with it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, Vec<String>>,
+    /// Hidden by a moderator: only the author and moderators see the text.
+    #[serde(default)]
+    pub hidden: bool,
+    /// The moment or region of the upload the comment is about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<CommentAnchor>,
+}
+
+/// Ties a comment to part of an upload: a playback position (or clip) of
+/// audio and video, or a region of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CommentAnchor {
+    /// Milliseconds from the start of playback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_ms: Option<u64>,
+    /// End of the clip the comment is about; needs `start_ms`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+}
+
+/// A rectangle on an image, in fractions of its width and height from the
+/// top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+/// A comment's text as it was before an edit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentRevision {
+    pub text: String,
+    /// When this text was written.
+    pub written_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    Upload,
+    Comment,
+    Admin,
+}
+
+/// A personal access token. Only the SHA-256 of the secret is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub owner_provider: String,
+    pub owner_id: u64,
+    pub owner_name: String,
+    #[serde(default)]
+    pub owner_avatar_url: String,
+    #[serde(default)]
+    pub owner_user: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiTokenView {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<&ApiToken> for ApiTokenView {
+    fn from(t: &ApiToken) -> Self {
+        Self {
+            id: t.id.clone(),
+            name: t.name.clone(),
+            scopes: t.scopes.clone(),
+            created_at: t.created_at,
+            last_used_at: t.last_used_at,
+        }
+    }
+}
+
+/// A signed-in browser session. The JWT in the session cookie carries the id;
+/// deleting the record revokes the cookie.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub provider: String,
+    pub user_id: u64,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+}
+
+/// A username/password login. Only the argon2 hash of the password is
+/// stored; `totp_secret` is base32 and set once a second factor is confirmed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalAccount {
+    pub id: u64,
+    pub username: String,
+    pub password_hash: String,
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    /// Secret handed out by TOTP setup that has not been confirmed yet.
+    #[serde(default)]
+    pub totp_pending: Option<String>,
+    /// Last accepted TOTP time step, so a code cannot be replayed.
+    #[serde(default)]
+    pub totp_last_step: Option<u64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A single-use registration invite. Only the SHA-256 of the code is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub code_hash: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// An admin-issued password reset link for a [`LocalAccount`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordReset {
+    pub token_hash: String,
+    pub account_id: u64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SanctionKind {
+    /// Cannot sign in or use the API at all.
+    Ban,
+    /// Cannot upload.
+    Upload,
+    /// Cannot comment.
+    Comment,
+}
+
+/// A ban or suspension on a [`User`]. It stays on record after it expires or
+/// is lifted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sanction {
+    pub id: String,
+    /// Internal id of the sanctioned [`User`].
+    pub user_id: String,
+    pub kind: SanctionKind,
+    pub reason: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    /// `None` for a permanent sanction.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub lifted_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub lifted_by: Option<String>,
+}
+
+impl Sanction {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.lifted_at.is_none() && self.expires_at.is_none_or(|e| e > now)
+    }
+
+    /// Whether this sanction stops the user doing `kind`; a ban stops
+    /// everything.
+    pub fn covers(&self, kind: SanctionKind) -> bool {
+        self.kind == kind || self.kind == SanctionKind::Ban
+    }
+
+    /// Shown to the sanctioned user, e.g. "banned until 2026-01-01 00:00 UTC:
+    /// spam".
+    pub fn describe(&self) -> String {
+        let what = match self.kind {
+            SanctionKind::Ban => "banned",
+            SanctionKind::Upload => "suspended from uploading",
+            SanctionKind::Comment => "suspended from commenting",
+        };
+        match self.expires_at {
+            Some(until) => format!(
+                "{} until {}: {}",
+                what,
+                until.format("%Y-%m-%d %H:%M UTC"),
+                self.reason
+            ),
+            None => format!("{}: {}", what, self.reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportReason {
+    Spam,
+    Harassment,
+    /// Adult content that is not marked NSFW.
+    Nsfw,
+    Illegal,
+    /// Explained in the report's details.
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportOutcome {
+    Dismissed,
+    /// The comment was hidden, or the upload unlisted.
+    Hidden,
+    Deleted,
+}
+
+/// A user's complaint about an upload, or about one of its comments when
+/// `comment_id` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub id: String,
+    pub media_id: String,
+    #[serde(default)]
+    pub comment_id: Option<String>,
+    pub reason: ReportReason,
+    #[serde(default)]
+    pub details: String,
+    pub reporter_user: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub resolved_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub resolved_by: Option<String>,
+    #[serde(default)]
+    pub outcome: Option<ReportOutcome>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModAction {
+    HideComment,
+    UnhideComment,
+    DeleteComment,
+    UnlistMedia,
+    DeleteMedia,
+    DisableComments,
+    EnableComments,
+    DismissReport,
+    UpdateSpamRules,
+    Sanction,
+    LiftSanction,
+}
+
+/// One moderator action, kept in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub at: DateTime<Utc>,
+    /// Internal id of the moderator.
+    pub actor_user: String,
+    pub action: ModAction,
+    #[serde(default)]
+    pub media_id: Option<String>,
+    #[serde(default)]
+    pub comment_id: Option<String>,
+    /// Internal id of the user acted on, e.g. a comment's author.
+    #[serde(default)]
+    pub target_user: Option<String>,
+    #[serde(default)]
+    pub note: String,
+}
+
+fn default_max_links() -> usize {
+    3
+}
+
+/// Checks applied to new and edited comments from everyone but moderators.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpamRules {
+    /// Words and phrases matched case-insensitively as whole words.
+    #[serde(default)]
+    pub blocked_words: Vec<String>,
+    /// Links to these hosts, or any of their subdomains, are refused.
+    #[serde(default)]
+    pub blocked_domains: Vec<String>,
+    #[serde(default = "default_max_links")]
+    pub max_links: usize,
+}
+
+impl Default for SpamRules {
+    fn default() -> Self {
+        Self {
+            blocked_words: Vec::new(),
+            blocked_domains: Vec::new(),
+            max_links: default_max_links(),
+        }
+    }
 }
@@ -0,0 +1,450 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::{AuditEntry, ModAction, Report, ReportOutcome, ReportReason, SpamRules},
+    },
+    chrono::Utc,
+    dashmap::DashMap,
+    regex::Regex,
+    std::{
+        collections::VecDeque,
+        io::{BufRead, BufReader, Write},
+        path::{Path, PathBuf},
+        sync::{OnceLock, RwLock},
+    },
+};
+
+pub const MAX_DETAILS_LEN: usize = 1000;
+const MAX_RULE_ENTRIES: usize = 500;
+const MAX_RULE_LEN: usize = 100;
+
+fn load_json<T: serde::de::DeserializeOwned + Default>(path: &Path) -> T {
+    match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            tracing::warn!("could not parse {:?}: {}", path, e);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+fn write_json<T: serde::Serialize + ?Sized>(path: &Path, what: &str, value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => {
+            if let Err(e) = std::fs::write(path, json) {
+                tracing::warn!("could not write {} to {:?}: {}", what, path, e);
+            }
+        }
+        Err(e) => tracing::warn!("could not serialize {}: {}", what, e),
+    }
+}
+
+/// Appends `entry` as one JSON line, creating the file if needed.
+fn append_line(path: &Path, entry: &AuditEntry) {
+    let mut line = match serde_json::to_string(entry) {
+        Ok(json) => json,
+        Err(e) => {
+            tracing::warn!("could not serialize audit entry: {}", e);
+            return;
+        }
+    };
+    line.push('\n');
+    let written = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = written {
+        tracing::warn!("could not append to {:?}: {}", path, e);
+    }
+}
+
+/// Reports from users, kept in `reports.json` including resolved ones.
+pub struct ReportStore {
+    path: PathBuf,
+    reports: DashMap<String, Report>,
+}
+
+impl ReportStore {
+    const FILE_NAME: &'static str = "reports.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let reports: Vec<Report> = load_json(&path);
+        Self {
+            path,
+            reports: reports.into_iter().map(|r| (r.id.clone(), r)).collect(),
+        }
+    }
+
+    /// Files a report on an upload, or on one of its comments. A user has at
+    /// most one open report per target.
+    pub fn create(
+        &self,
+        reporter_user: &str,
+        media_id: &str,
+        comment_id: Option<&str>,
+        reason: ReportReason,
+        details: &str,
+    ) -> AppResult<Report> {
+        let details = details.trim();
+        if details.chars().count() > MAX_DETAILS_LEN {
+            return Err(AppError::InvalidReport);
+        }
+        if reason == ReportReason::Other && details.is_empty() {
+            return Err(AppError::MissingReason);
+        }
+        let duplicate = self.reports.iter().any(|r| {
+            r.resolved_at.is_none()
+                && r.reporter_user == reporter_user
+                && r.media_id == media_id
+                && r.comment_id.as_deref() == comment_id
+        });
+        if duplicate {
+            return Err(AppError::AlreadyReported);
+        }
+        let report = Report {
+            id: uuid::Uuid::new_v4().to_string(),
+            media_id: media_id.to_owned(),
+            comment_id: comment_id.map(str::to_owned),
+            reason,
+            details: details.to_owned(),
+            reporter_user: reporter_user.to_owned(),
+            created_at: Utc::now(),
+            resolved_at: None,
+            resolved_by: None,
+            outcome: None,
+        };
+        self.reports.insert(report.id.clone(), report.clone());
+        self.persist();
+        Ok(report)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Report> {
+        self.reports.get(id).map(|r| r.clone())
+    }
+
+    /// Open reports oldest first, so the queue is worked in order, or every
+    /// report newest first with `include_resolved`.
+    pub fn list(&self, include_resolved: bool) -> Vec<Report> {
+        let mut out: Vec<Report> = self
+            .reports
+            .iter()
+            .filter(|r| include_resolved || r.resolved_at.is_none())
+            .map(|r| r.clone())
+            .collect();
+        if include_resolved {
+            out.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        } else {
+            out.sort_by_key(|r| r.created_at);
+        }
+        out
+    }
+
+    /// Resolves every open report on the same target: the comment when
+    /// `comment_id` is set, otherwise the upload itself.
+    pub fn resolve_target(
+        &self,
+        media_id: &str,
+        comment_id: Option<&str>,
+        outcome: ReportOutcome,
+        by: &str,
+    ) -> usize {
+        self.resolve_where(
+            |r| r.media_id == media_id && r.comment_id.as_deref() == comment_id,
+            outcome,
+            by,
+        )
+    }
+
+    /// Resolves every open report on a deleted upload and its comments.
+    pub fn resolve_media(&self, media_id: &str, by: &str) -> usize {
+        self.resolve_where(|r| r.media_id == media_id, ReportOutcome::Deleted, by)
+    }
+
+    /// Moves reports filed by one account to another when accounts merge.
+    pub fn reassign(&self, from: &str, to: &str) -> usize {
+        let mut moved = 0;
+        for mut r in self.reports.iter_mut() {
+            if r.reporter_user == from {
+                r.reporter_user = to.to_owned();
+                moved += 1;
+            }
+        }
+        if moved > 0 {
+            self.persist();
+        }
+        moved
+    }
+
+    fn resolve_where(
+        &self,
+        matches: impl Fn(&Report) -> bool,
+        outcome: ReportOutcome,
+        by: &str,
+    ) -> usize {
+        let now = Utc::now();
+        let mut resolved = 0;
+        for mut r in self.reports.iter_mut() {
+            if r.resolved_at.is_none() && matches(&r) {
+                r.resolved_at = Some(now);
+                r.resolved_by = Some(by.to_owned());
+                r.outcome = Some(outcome);
+                resolved += 1;
+            }
+        }
+        if resolved > 0 {
+            self.persist();
+        }
+        resolved
+    }
+
+    fn persist(&self) {
+        let reports: Vec<Report> = self.reports.iter().map(|e| e.value().clone()).collect();
+        write_json(&self.path, "reports", &reports);
+    }
+}
+
+/// Audit entries kept in memory for [`AuditLog::recent`]; older ones stay on
+/// disk only.
+pub const RECENT_AUDIT_ENTRIES: usize = 1000;
+
+/// Moderator actions in the order they happened, appended one JSON object
+/// per line to `audit_log.jsonl`. Only the latest [`RECENT_AUDIT_ENTRIES`]
+/// are held in memory.
+pub struct AuditLog {
+    path: PathBuf,
+    entries: RwLock<VecDeque<AuditEntry>>,
+}
+
+impl AuditLog {
+    const FILE_NAME: &'static str = "audit_log.jsonl";
+    /// The whole-array file written by earlier versions.
+    const LEGACY_FILE_NAME: &'static str = "audit_log.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let legacy = Path::new(upload_dir).join(Self::LEGACY_FILE_NAME);
+        if legacy.exists() && !path.exists() {
+            let old: Vec<AuditEntry> = load_json(&legacy);
+            for entry in &old {
+                append_line(&path, entry);
+            }
+            if let Err(e) = std::fs::remove_file(&legacy) {
+                tracing::warn!("could not remove {:?}: {}", legacy, e);
+            }
+        }
+        let mut entries = VecDeque::new();
+        if let Ok(file) = std::fs::File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                let Ok(line) = line else { break };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(entry) => {
+                        if entries.len() == RECENT_AUDIT_ENTRIES {
+                            entries.pop_front();
+                        }
+                        entries.push_back(entry);
+                    }
+                    Err(e) => tracing::warn!("skipping bad line in {:?}: {}", path, e),
+                }
+            }
+        }
+        Self {
+            entries: RwLock::new(entries),
+            path,
+        }
+    }
+
+    pub fn record(
+        &self,
+        actor_user: &str,
+        action: ModAction,
+        media_id: Option<&str>,
+        comment_id: Option<&str>,
+        target_user: Option<&str>,
+        note: &str,
+    ) {
+        let entry = AuditEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            at: Utc::now(),
+            actor_user: actor_user.to_owned(),
+            action,
+            media_id: media_id.map(str::to_owned),
+            comment_id: comment_id.map(str::to_owned),
+            target_user: target_user.map(str::to_owned),
+            note: note.to_owned(),
+        };
+        tracing::info!(
+            actor = %entry.actor_user,
+            action = ?entry.action,
+            media = ?entry.media_id,
+            comment = ?entry.comment_id,
+            "moderator action"
+        );
+        let mut entries = self.entries.write().unwrap();
+        append_line(&self.path, &entry);
+        if entries.len() == RECENT_AUDIT_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The latest `limit` entries, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+fn link_pattern() -> &'static Regex {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    LINK.get_or_init(|| {
+        Regex::new(r"(?i)\b(?:https?://|www\.)([a-z0-9-]+(?:\.[a-z0-9-]+)*)").unwrap()
+    })
+}
+
+/// Lowercases, trims, de-duplicates and drops empty entries.
+fn clean_list(raw: &[String], what: &str) -> AppResult<Vec<String>> {
+    let mut out: Vec<String> = Vec::new();
+    for entry in raw {
+        let entry = entry.trim().to_lowercase();
+        if entry.chars().count() > MAX_RULE_LEN {
+            return Err(AppError::InvalidSpamRules(format!(
+                "{} must be at most {} characters",
+                what, MAX_RULE_LEN
+            )));
+        }
+        if !entry.is_empty() && !out.contains(&entry) {
+            out.push(entry);
+        }
+    }
+    if out.len() > MAX_RULE_ENTRIES {
+        return Err(AppError::InvalidSpamRules(format!(
+            "at most {} {}s",
+            MAX_RULE_ENTRIES, what
+        )));
+    }
+    Ok(out)
+}
+
+struct CompiledRules {
+    rules: SpamRules,
+    /// Matches any blocked word; `None` when there are none.
+    words: Option<Regex>,
+}
+
+impl CompiledRules {
+    fn compile(rules: SpamRules) -> AppResult<Self> {
+        let words = match rules.blocked_words.is_empty() {
+            true => None,
+            false => {
+                let alternatives: Vec<String> = rules
+                    .blocked_words
+                    .iter()
+                    .map(|w| regex::escape(w))
+                    .collect();
+                let pattern = format!(r"(?i)(?:^|\W)(?:{})(?:$|\W)", alternatives.join("|"));
+                Some(Regex::new(&pattern).map_err(|e| AppError::InvalidSpamRules(e.to_string()))?)
+            }
+        };
+        Ok(Self { rules, words })
+    }
+}
+
+/// Blocked words and link limits, kept in `spam_rules.json`.
+pub struct RulesStore {
+    path: PathBuf,
+    compiled: RwLock<CompiledRules>,
+}
+
+impl RulesStore {
+    const FILE_NAME: &'static str = "spam_rules.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let rules: SpamRules = load_json(&path);
+        let compiled = CompiledRules::compile(rules).unwrap_or_else(|e| {
+            tracing::warn!("ignoring spam rules in {:?}: {}", path, e);
+            CompiledRules {
+                rules: SpamRules::default(),
+                words: None,
+            }
+        });
+        Self {
+            path,
+            compiled: RwLock::new(compiled),
+        }
+    }
+
+    pub fn get(&self) -> SpamRules {
+        self.compiled.read().unwrap().rules.clone()
+    }
+
+    /// Replaces the rules after normalizing them, and returns what was saved.
+    pub fn set(&self, rules: SpamRules) -> AppResult<SpamRules> {
+        let blocked_domains = clean_list(&rules.blocked_domains, "blocked domain")?
+            .into_iter()
+            .map(|d| {
+                let d = d
+                    .trim_start_matches("https://")
+                    .trim_start_matches("http://");
+                d.trim_start_matches("www.")
+                    .trim_end_matches('/')
+                    .to_owned()
+            })
+            .filter(|d| !d.is_empty())
+            .collect();
+        let rules = SpamRules {
+            blocked_words: clean_list(&rules.blocked_words, "blocked word")?,
+            blocked_domains,
+            max_links: rules.max_links,
+        };
+        let compiled = CompiledRules::compile(rules.clone())?;
+        *self.compiled.write().unwrap() = compiled;
+        write_json(&self.path, "spam rules", &rules);
+        Ok(rules)
+    }
+
+    /// Fails with the rule `text` breaks, if any.
+    pub fn check(&self, text: &str) -> AppResult<()> {
+        let compiled = self.compiled.read().unwrap();
+        if compiled.words.as_ref().is_some_and(|w| w.is_match(text)) {
+            return Err(AppError::CommentRejected(
+                "it contains a blocked word".into(),
+            ));
+        }
+        let hosts: Vec<String> = link_pattern()
+            .captures_iter(text)
+            .map(|c| c[1].to_lowercase())
+            .collect();
+        if hosts.len() > compiled.rules.max_links {
+            return Err(AppError::CommentRejected(format!(
+                "it has more than {} link(s)",
+                compiled.rules.max_links
+            )));
+        }
+        let blocked = hosts.iter().any(|host| {
+            let host = host.trim_start_matches("www.");
+            compiled
+                .rules
+                .blocked_domains
+                .iter()
+                .any(|d| host == d || host.ends_with(&format!(".{}", d)))
+        });
+        if blocked {
+            return Err(AppError::CommentRejected(
+                "it links to a blocked site".into(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,641 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::PlatformUser,
+    },
+    color_eyre::eyre::{Result, WrapErr, bail, eyre},
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+    sha2::{Digest, Sha256},
+    std::collections::HashMap,
+    tokio::sync::OnceCell,
+};
+
+/// Names a configured provider cannot take: the built-in login namespaces,
+/// whose identities would collide in the user store, and the fixed routes
+/// under `/auth/` that `/auth/<provider>/...` would shadow.
+const RESERVED_PROVIDER_NAMES: &[&str] = &[
+    crate::local::LOCAL_PROVIDER,
+    crate::routes::dev::DEV_PROVIDER,
+    "account",
+    "callback",
+    "login",
+    "logout",
+    "me",
+    "refresh-cookie",
+    "sessions",
+    "tokens",
+];
+
+/// How the client credentials are sent to the token endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenAuth {
+    /// `client_secret_post` with a form-encoded body.
+    #[default]
+    Post,
+    /// Same fields as `Post`, but as a JSON body (osu!).
+    Json,
+    /// `client_secret_basic`.
+    Basic,
+}
+
+impl TokenAuth {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "post" | "client_secret_post" => Some(Self::Post),
+            "json" => Some(Self::Json),
+            "basic" | "client_secret_basic" => Some(Self::Basic),
+            _ => None,
+        }
+    }
+}
+
+/// Where to find the [`PlatformUser`] fields in the userinfo response.
+/// `id` and `username` are dotted paths such as `data.user.id`. `avatar_url`
+/// is either a path or a template like
+/// `https://cdn.example.com/{id}/{avatar}.png`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldMapping {
+    pub id: String,
+    pub username: String,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    /// Used when `avatar_url` is missing or one of its placeholders is empty.
+    #[serde(default)]
+    pub avatar_fallback: Option<String>,
+}
+
+impl FieldMapping {
+    fn oidc() -> Self {
+        Self {
+            id: "sub".to_owned(),
+            username: "preferred_username".to_owned(),
+            avatar_url: Some("picture".to_owned()),
+            avatar_fallback: None,
+        }
+    }
+
+    pub fn map_user(&self, provider: &str, info: &Value) -> AppResult<PlatformUser> {
+        let id = lookup(info, &self.id)
+            .and_then(external_id)
+            .ok_or_else(|| AppError::UserInfoFetch(format!("missing '{}' field", self.id)))?;
+        let username = lookup(info, &self.username)
+            .and_then(value_to_string)
+            .unwrap_or_else(|| id.to_string());
+        let avatar_url = self
+            .avatar_url
+            .as_deref()
+            .and_then(|spec| render_field(info, spec))
+            .or_else(|| self.avatar_fallback.clone())
+            .unwrap_or_default();
+
+        Ok(PlatformUser {
+            provider: provider.to_owned(),
+            id,
+            username,
+            avatar_url,
+            user_id: String::new(),
+        })
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |v, key| v.get(key))
+        .filter(|v| !v.is_null())
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn render_field(info: &Value, spec: &str) -> Option<String> {
+    if !spec.contains('{') {
+        return lookup(info, spec).and_then(value_to_string);
+    }
+    let mut out = String::with_capacity(spec.len());
+    let mut rest = spec;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}')?;
+        out.push_str(&rest[..start]);
+        out.push_str(&lookup(info, &rest[start + 1..end]).and_then(value_to_string)?);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+/// Maps a provider's user id onto our numeric ids. Numeric ids (osu!,
+/// GitHub, Discord snowflakes) are kept as-is; anything else, such as an OIDC
+/// `sub` UUID, is hashed.
+pub fn external_id(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) if !s.is_empty() => Some(s.parse().unwrap_or_else(|_| {
+            let digest = Sha256::digest(s.as_bytes());
+            u64::from_be_bytes(digest[..8].try_into().unwrap())
+        })),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderConfig {
+    /// Used in `/auth/<name>/login` and stored as the user's provider.
+    pub name: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    /// OIDC issuer. When set, missing endpoints come from
+    /// `<issuer>/.well-known/openid-configuration`.
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub authorize_url: Option<String>,
+    #[serde(default)]
+    pub token_url: Option<String>,
+    #[serde(default)]
+    pub userinfo_url: Option<String>,
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    #[serde(default)]
+    pub token_auth: TokenAuth,
+    #[serde(default)]
+    pub fields: Option<FieldMapping>,
+}
+
+impl ProviderConfig {
+    fn osu(client_id: String, client_secret: String, redirect_uri: String) -> Self {
+        Self {
+            name: "osu".to_owned(),
+            display_name: Some("osu!".to_owned()),
+            client_id,
+            client_secret,
+            redirect_uri,
+            issuer: None,
+            authorize_url: Some("https://osu.ppy.sh/oauth/authorize".to_owned()),
+            token_url: Some("https://osu.ppy.sh/oauth/token".to_owned()),
+            userinfo_url: Some("https://osu.ppy.sh/api/v2/me".to_owned()),
+            scopes: Some(vec!["identify".to_owned(), "public".to_owned()]),
+            token_auth: TokenAuth::Json,
+            fields: Some(FieldMapping {
+                id: "id".to_owned(),
+                username: "username".to_owned(),
+                avatar_url: Some("avatar_url".to_owned()),
+                avatar_fallback: None,
+            }),
+        }
+    }
+
+    fn github(client_id: String, client_secret: String, redirect_uri: String) -> Self {
+        Self {
+            name: "github".to_owned(),
+            display_name: Some("GitHub".to_owned()),
+            client_id,
+            client_secret,
+            redirect_uri,
+            issuer: None,
+            authorize_url: Some("https://github.com/login/oauth/authorize".to_owned()),
+            token_url: Some("https://github.com/login/oauth/access_token".to_owned()),
+            userinfo_url: Some("https://api.github.com/user".to_owned()),
+            scopes: Some(vec!["read:user".to_owned()]),
+            token_auth: TokenAuth::Post,
+            fields: Some(FieldMapping {
+                id: "id".to_owned(),
+                username: "login".to_owned(),
+                avatar_url: Some("avatar_url".to_owned()),
+                avatar_fallback: None,
+            }),
+        }
+    }
+
+    fn discord(client_id: String, client_secret: String, redirect_uri: String) -> Self {
+        Self {
+            name: "discord".to_owned(),
+            display_name: Some("Discord".to_owned()),
+            client_id,
+            client_secret,
+            redirect_uri,
+            issuer: None,
+            authorize_url: Some("https://discord.com/oauth2/authorize".to_owned()),
+            token_url: Some("https://discord.com/api/oauth2/token".to_owned()),
+            userinfo_url: Some("https://discord.com/api/users/@me".to_owned()),
+            scopes: Some(vec!["identify".to_owned()]),
+            token_auth: TokenAuth::Post,
+            fields: Some(FieldMapping {
+                id: "id".to_owned(),
+                username: "username".to_owned(),
+                avatar_url: Some("https://cdn.discordapp.com/avatars/{id}/{avatar}.png".to_owned()),
+                avatar_fallback: Some("https://cdn.discordapp.com/embed/avatars/0.png".to_owned()),
+            }),
+        }
+    }
+
+    /// Reads `{PREFIX}_CLIENT_ID`, `{PREFIX}_CLIENT_SECRET` and
+    /// `{PREFIX}_REDIRECT_URI`. `Ok(None)` when none of the three variables
+    /// are set, an error when only some of them are.
+    fn credentials_from_env(prefix: &str) -> Result<Option<(String, String, String)>> {
+        let var = |suffix: &str| {
+            std::env::var(format!("{}_{}", prefix, suffix))
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+        };
+        match (var("CLIENT_ID"), var("CLIENT_SECRET"), var("REDIRECT_URI")) {
+            (Some(id), Some(secret), Some(redirect)) => Ok(Some((id, secret, redirect))),
+            (None, None, None) => Ok(None),
+            _ => bail!(
+                "{0}_CLIENT_ID, {0}_CLIENT_SECRET and {0}_REDIRECT_URI must all be set",
+                prefix
+            ),
+        }
+    }
+
+    /// A provider listed in `OAUTH_PROVIDERS`, configured by
+    /// `OAUTH_<NAME>_*` variables.
+    fn from_env(name: &str) -> Result<Self> {
+        let prefix = format!("OAUTH_{}", name.to_ascii_uppercase().replace('-', "_"));
+        let var = |suffix: &str| std::env::var(format!("{}_{}", prefix, suffix)).ok();
+        let (client_id, client_secret, redirect_uri) = Self::credentials_from_env(&prefix)?
+            .ok_or_else(|| {
+                eyre!(
+                    "{0}_CLIENT_ID, {0}_CLIENT_SECRET and {0}_REDIRECT_URI must be set",
+                    prefix
+                )
+            })?;
+
+        let fields = var("ID_FIELD").map(|id| FieldMapping {
+            username: var("USERNAME_FIELD").unwrap_or_else(|| id.clone()),
+            id,
+            avatar_url: var("AVATAR_FIELD"),
+            avatar_fallback: var("AVATAR_FALLBACK"),
+        });
+        let token_auth = match var("TOKEN_AUTH") {
+            Some(raw) => TokenAuth::parse(&raw)
+                .ok_or_else(|| eyre!("{}_TOKEN_AUTH: unknown '{}'", prefix, raw))?,
+            None => TokenAuth::default(),
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            display_name: var("DISPLAY_NAME"),
+            client_id,
+            client_secret,
+            redirect_uri,
+            issuer: var("ISSUER"),
+            authorize_url: var("AUTHORIZE_URL"),
+            token_url: var("TOKEN_URL"),
+            userinfo_url: var("USERINFO_URL"),
+            scopes: var("SCOPES").map(|s| {
+                s.split([',', ' '])
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            }),
+            token_auth,
+            fields,
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        {
+            bail!(
+                "provider name '{}' must be lowercase letters, digits, '-' or '_'",
+                self.name
+            );
+        }
+        if RESERVED_PROVIDER_NAMES.contains(&self.name.as_str()) {
+            bail!(
+                "provider name '{}' is reserved for built-in logins or routes",
+                self.name
+            );
+        }
+        if self.issuer.is_none()
+            && (self.authorize_url.is_none()
+                || self.token_url.is_none()
+                || self.userinfo_url.is_none())
+        {
+            bail!(
+                "provider '{}' needs either an issuer or authorize, token and userinfo URLs",
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Endpoints {
+    authorize_url: String,
+    token_url: String,
+    userinfo_url: String,
+}
+
+#[derive(Deserialize)]
+struct DiscoveryDocument {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+pub struct Provider {
+    pub config: ProviderConfig,
+    endpoints: OnceCell<Endpoints>,
+}
+
+impl Provider {
+    pub fn new(config: ProviderConfig) -> Self {
+        Self {
+            config,
+            endpoints: OnceCell::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.config
+            .display_name
+            .as_deref()
+            .unwrap_or(&self.config.name)
+    }
+
+    fn scopes(&self) -> Vec<String> {
+        match &self.config.scopes {
+            Some(scopes) => scopes.clone(),
+            None if self.config.issuer.is_some() => {
+                vec!["openid".to_owned(), "profile".to_owned()]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn fields(&self) -> FieldMapping {
+        self.config
+            .fields
+            .clone()
+            .unwrap_or_else(FieldMapping::oidc)
+    }
+
+    /// Resolves the endpoints, running OIDC discovery the first time it is
+    /// needed. Explicitly configured URLs take precedence.
+    async fn endpoints(&self, client: &reqwest::Client) -> AppResult<&Endpoints> {
+        self.endpoints
+            .get_or_try_init(|| async {
+                let c = &self.config;
+                let discovered = match &c.issuer {
+                    Some(issuer)
+                        if c.authorize_url.is_none()
+                            || c.token_url.is_none()
+                            || c.userinfo_url.is_none() =>
+                    {
+                        let url = format!(
+                            "{}/.well-known/openid-configuration",
+                            issuer.trim_end_matches('/')
+                        );
+                        let doc: DiscoveryDocument = client
+                            .get(&url)
+                            .send()
+                            .await
+                            .and_then(|r| r.error_for_status())
+                            .map_err(|e| AppError::OAuthDiscovery(e.to_string()))?
+                            .json()
+                            .await
+                            .map_err(|e| AppError::OAuthDiscovery(e.to_string()))?;
+                        Some(doc)
+                    }
+                    _ => None,
+                };
+
+                let pick = |configured: &Option<String>, found: Option<&String>, what: &str| {
+                    configured
+                        .clone()
+                        .or_else(|| found.cloned())
+                        .ok_or_else(|| {
+                            AppError::OAuthDiscovery(format!("{} has no {} endpoint", c.name, what))
+                        })
+                };
+                Ok(Endpoints {
+                    authorize_url: pick(
+                        &c.authorize_url,
+                        discovered.as_ref().map(|d| &d.authorization_endpoint),
+                        "authorization",
+                    )?,
+                    token_url: pick(
+                        &c.token_url,
+                        discovered.as_ref().map(|d| &d.token_endpoint),
+                        "token",
+                    )?,
+                    userinfo_url: pick(
+                        &c.userinfo_url,
+                        discovered
+                            .as_ref()
+                            .and_then(|d| d.userinfo_endpoint.as_ref()),
+                        "userinfo",
+                    )?,
+                })
+            })
+            .await
+    }
+
+    pub async fn authorize_url(&self, csrf_state: &str) -> AppResult<String> {
+        let client = reqwest::Client::new();
+        let endpoints = self.endpoints(&client).await?;
+        let separator = if endpoints.authorize_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        Ok(format!(
+            "{}{}client_id={}&redirect_uri={}&response_type=code&scope={}&state={}",
+            endpoints.authorize_url,
+            separator,
+            urlencoded(&self.config.client_id),
+            urlencoded(&self.config.redirect_uri),
+            urlencoded(&self.scopes().join(" ")),
+            urlencoded(csrf_state),
+        ))
+    }
+
+    /// Exchanges an authorization code and maps the userinfo response into a
+    /// [`PlatformUser`].
+    pub async fn fetch_user(&self, code: &str) -> AppResult<PlatformUser> {
+        let client = reqwest::Client::new();
+        let endpoints = self.endpoints(&client).await?;
+        let c = &self.config;
+
+        let mut form = HashMap::new();
+        form.insert("grant_type", "authorization_code");
+        form.insert("code", code);
+        form.insert("redirect_uri", &c.redirect_uri);
+        if c.token_auth != TokenAuth::Basic {
+            form.insert("client_id", &c.client_id);
+            form.insert("client_secret", &c.client_secret);
+        }
+
+        let request = client
+            .post(&endpoints.token_url)
+            .header("Accept", "application/json");
+        let request = match c.token_auth {
+            TokenAuth::Post => request.form(&form),
+            TokenAuth::Json => request.json(&form),
+            TokenAuth::Basic => request
+                .basic_auth(&c.client_id, Some(&c.client_secret))
+                .form(&form),
+        };
+        let response = request.send().await.map_err(AppError::Reqwest)?;
+        // A stale, reused or forged code comes back as 400 `invalid_grant`;
+        // anything else means the provider or our configuration is broken.
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let body: Value = response.json().await.unwrap_or_default();
+            let reason = body["error"].as_str().unwrap_or("invalid_grant");
+            return Err(AppError::OAuthGrantRejected(reason.to_owned()));
+        }
+        let token: TokenResponse = response
+            .error_for_status()
+            .map_err(|e| AppError::OAuthTokenExchange(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AppError::OAuthTokenExchange(e.to_string()))?;
+
+        let info: Value = client
+            .get(&endpoints.userinfo_url)
+            .header("Accept", "application/json")
+            .header("User-Agent", "skibidi67")
+            .bearer_auth(&token.access_token)
+            .send()
+            .await
+            .map_err(AppError::Reqwest)?
+            .error_for_status()
+            .map_err(|e| AppError::UserInfoFetch(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AppError::UserInfoFetch(e.to_string()))?;
+
+        self.fields().map_user(&c.name, &info)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderLink {
+    pub name: String,
+    pub display_name: String,
+}
+
+/// Every configured login provider, in the order they appear on the login UI.
+#[derive(Default)]
+pub struct ProviderRegistry {
+    providers: Vec<Provider>,
+}
+
+impl ProviderRegistry {
+    pub fn new(configs: Vec<ProviderConfig>) -> Result<Self> {
+        let mut registry = Self::default();
+        for config in configs {
+            config.validate()?;
+            if registry.get(&config.name).is_some() {
+                bail!("login provider '{}' is configured twice", config.name);
+            }
+            registry.providers.push(Provider::new(config));
+        }
+        Ok(registry)
+    }
+
+    /// Built-in osu!, GitHub and Discord providers from their `*_CLIENT_ID`
+    /// variables, then any providers from `OAUTH_PROVIDERS_FILE` (a JSON array
+    /// of [`ProviderConfig`]) and `OAUTH_PROVIDERS` (a comma-separated list of
+    /// names configured by `OAUTH_<NAME>_*`).
+    pub fn from_env() -> Result<Self> {
+        let mut configs = Vec::new();
+
+        if let Some((id, secret, redirect)) = ProviderConfig::credentials_from_env("OSU")? {
+            configs.push(ProviderConfig::osu(id, secret, redirect));
+        }
+        if let Some((id, secret, redirect)) = ProviderConfig::credentials_from_env("GITHUB")? {
+            configs.push(ProviderConfig::github(id, secret, redirect));
+        }
+        if let Some((id, secret, redirect)) = ProviderConfig::credentials_from_env("DISCORD")? {
+            configs.push(ProviderConfig::discord(id, secret, redirect));
+        }
+
+        if let Ok(path) = std::env::var("OAUTH_PROVIDERS_FILE") {
+            let json = std::fs::read_to_string(&path)
+                .wrap_err_with(|| format!("could not read {}", path))?;
+            let from_file: Vec<ProviderConfig> = serde_json::from_str(&json)
+                .wrap_err_with(|| format!("could not parse {}", path))?;
+            configs.extend(from_file);
+        }
+
+        if let Ok(names) = std::env::var("OAUTH_PROVIDERS") {
+            for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                configs.push(ProviderConfig::from_env(name)?);
+            }
+        }
+
+        Self::new(configs)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// The provider `/auth/login` sends people to: osu! when it is
+    /// configured, otherwise the only provider if there is just one.
+    pub fn default_provider(&self) -> Option<&Provider> {
+        self.get("osu").or(match self.providers.as_slice() {
+            [only] => Some(only),
+            _ => None,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Provider> {
+        self.providers.iter().find(|p| p.name() == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.providers.iter().map(Provider::name)
+    }
+
+    pub fn links(&self) -> Vec<ProviderLink> {
+        self.providers
+            .iter()
+            .map(|p| ProviderLink {
+                name: p.name().to_owned(),
+                display_name: p.display_name().to_owned(),
+            })
+            .collect()
+    }
+}
+
+pub fn urlencoded(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char);
+            }
+            _ => {
+                out.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    out
+}
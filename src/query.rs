@@ -0,0 +1,753 @@
+//! The search query language.
+//!
+//! ```text
+//! query   = or
+//! or      = and { "OR" and }
+//! and     = unary { [ "AND" ] unary }
+//! unary   = ( "NOT" | "-" | "!" ) unary | primary
+//! primary = "(" or ")" | filter | word | "quoted text"
+//! filter  = field op value | field "." method "(" value ")"
+//! op      = "=" | "!=" | ">" | ">=" | "<" | "<=" | "~" | "=~"
+//! ```
+//!
+//! Bare words and quoted text are looked up in the full-text index (see
+//! [`crate::search`]): adjacent bare words must all appear somewhere in an
+//! upload, while quoted text must appear as a phrase. `~` means "contains" and
+//! `=~` is a case-insensitive regex match (`title.matches(...)`). The method
+//! form (`title.contains("x")`, `size.gt(100)`) is the older syntax and is
+//! still accepted; its value may be left unquoted and then runs to the `)`.
+
+use {
+    crate::models::MediaKind,
+    chrono::NaiveDate,
+    regex::{Regex, RegexBuilder},
+    rocket::data::ByteUnit,
+    std::fmt,
+};
+
+/// Longest regex accepted, in characters.
+pub const MAX_PATTERN_LEN: usize = 256;
+/// Memory budget for a compiled regex and its lazy DFA. The regex engine
+/// runs in linear time, so this and the length cap bound the cost of a
+/// hostile pattern.
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+const PATTERN_NEST_LIMIT: u32 = 16;
+
+/// Longest query accepted, in bytes.
+pub const MAX_QUERY_LEN: usize = 512;
+/// How deep parentheses and negations can nest.
+pub const MAX_NESTING: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Source,
+    Uploader,
+    Sha2,
+    Tlsh,
+    Mime,
+    Id,
+    Replies,
+    /// Matches an upload's tags after resolving aliases.
+    Tag,
+    Kind,
+    Nsfw,
+    Size,
+    Date,
+}
+
+impl Field {
+    pub const ALL: [Field; 13] = [
+        Field::Title,
+        Field::Source,
+        Field::Uploader,
+        Field::Sha2,
+        Field::Tlsh,
+        Field::Mime,
+        Field::Id,
+        Field::Replies,
+        Field::Tag,
+        Field::Kind,
+        Field::Nsfw,
+        Field::Size,
+        Field::Date,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Source => "source",
+            Field::Uploader => "uploader",
+            Field::Sha2 => "sha2",
+            Field::Tlsh => "tlsh",
+            Field::Mime => "mime",
+            Field::Id => "id",
+            Field::Replies => "replies",
+            Field::Tag => "tag",
+            Field::Kind => "kind",
+            Field::Nsfw => "nsfw",
+            Field::Size => "size",
+            Field::Date => "date",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Field::ALL
+            .into_iter()
+            .find(|f| f.as_str().eq_ignore_ascii_case(name))
+    }
+
+    /// Operators that make sense for this field.
+    pub fn ops(self) -> &'static [Op] {
+        use Op::*;
+        match self {
+            Field::Title | Field::Source => &[Eq, Ne, Contains, Matches],
+            Field::Uploader | Field::Sha2 | Field::Mime | Field::Id => &[Eq, Ne, Contains],
+            Field::Tlsh => &[Eq, Ne, Near],
+            Field::Replies => &[Contains, Matches],
+            Field::Tag | Field::Kind | Field::Nsfw => &[Eq, Ne],
+            Field::Size | Field::Date => &[Eq, Ne, Gt, Ge, Lt, Le],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+    /// Case-insensitive regex match.
+    Matches,
+    /// TLSH distance below 100.
+    Near,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Contains => "~",
+            Op::Matches => "=~",
+            Op::Near => "near",
+        }
+    }
+
+    fn from_method(name: &str) -> Option<Self> {
+        Some(match name {
+            "is" => Op::Eq,
+            "contains" => Op::Contains,
+            "matches" => Op::Matches,
+            "near" => Op::Near,
+            "gt" | "after" => Op::Gt,
+            "lt" | "before" => Op::Lt,
+            _ => return None,
+        })
+    }
+
+    /// Whether `ordering` (value of the upload compared to the filter value)
+    /// satisfies this comparison.
+    pub fn compare(self, ordering: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            Op::Eq => ordering == Equal,
+            Op::Ne => ordering != Equal,
+            Op::Gt => ordering == Greater,
+            Op::Ge => ordering != Less,
+            Op::Lt => ordering == Less,
+            Op::Le => ordering != Greater,
+            Op::Contains | Op::Matches | Op::Near => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Text(String),
+    Bool(bool),
+    Bytes(u64),
+    Date(NaiveDate),
+    Kind(MediaKind),
+    Regex(Pattern),
+}
+
+/// A compiled regex from a query. Compares by its source text.
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self, String> {
+        if source.chars().count() > MAX_PATTERN_LEN {
+            return Err(format!("regex is longer than {MAX_PATTERN_LEN} characters"));
+        }
+        RegexBuilder::new(source)
+            .case_insensitive(true)
+            .size_limit(PATTERN_SIZE_LIMIT)
+            .dfa_size_limit(PATTERN_SIZE_LIMIT)
+            .nest_limit(PATTERN_NEST_LIMIT)
+            .build()
+            .map(Pattern)
+            .map_err(|e| match e {
+                regex::Error::CompiledTooBig(_) => "regex is too complex".to_owned(),
+                e => format!(
+                    "invalid regex: {}",
+                    e.to_string()
+                        .lines()
+                        .last()
+                        .unwrap_or_default()
+                        .trim_start_matches("error: ")
+                ),
+            })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.0.is_match(haystack)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub field: Field,
+    pub op: Op,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    /// Bare words, which must all appear in the upload.
+    Text(String),
+    /// Quoted text, which must appear in the upload in this order.
+    Phrase(String),
+    Filter(Filter),
+}
+
+/// Free text from a query, as looked up in the full-text index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FreeText<'a> {
+    Words(&'a str),
+    Phrase(&'a str),
+}
+
+impl Expr {
+    pub fn eval(
+        &self,
+        text: &impl Fn(FreeText<'_>) -> bool,
+        filter: &impl Fn(&Filter) -> bool,
+    ) -> bool {
+        match self {
+            Expr::And(items) => items.iter().all(|e| e.eval(text, filter)),
+            Expr::Or(items) => items.iter().any(|e| e.eval(text, filter)),
+            Expr::Not(inner) => !inner.eval(text, filter),
+            Expr::Text(t) => text(FreeText::Words(t)),
+            Expr::Phrase(t) => text(FreeText::Phrase(t)),
+            Expr::Filter(f) => filter(f),
+        }
+    }
+
+    /// Every piece of free text in the query, negated or not.
+    pub fn all_text(&self) -> Vec<FreeText<'_>> {
+        let mut out = Vec::new();
+        self.collect_text(true, &mut out);
+        out
+    }
+
+    /// Free text that is not negated, for relevance ranking and highlighting.
+    pub fn free_text(&self) -> Vec<FreeText<'_>> {
+        let mut out = Vec::new();
+        self.collect_text(false, &mut out);
+        out
+    }
+
+    fn collect_text<'a>(&'a self, negated: bool, out: &mut Vec<FreeText<'a>>) {
+        match self {
+            Expr::And(items) | Expr::Or(items) => {
+                items.iter().for_each(|e| e.collect_text(negated, out))
+            }
+            Expr::Not(inner) if negated => inner.collect_text(negated, out),
+            Expr::Text(t) => out.push(FreeText::Words(t)),
+            Expr::Phrase(t) => out.push(FreeText::Phrase(t)),
+            Expr::Not(_) | Expr::Filter(_) => {}
+        }
+    }
+}
+
+/// Whether `text` can be printed as bare words that parse back to
+/// [`Expr::Text`].
+fn is_bare(text: &str) -> bool {
+    text.split(' ').all(|w| {
+        !w.is_empty()
+            && w.chars().all(is_word_char)
+            && !w.starts_with('-')
+            && !matches!(w, "AND" | "OR" | "NOT")
+    })
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            f.write_str("\\")?;
+        }
+        write!(f, "{c}")?;
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(s) => write_quoted(f, s),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Bytes(n) => write!(f, "{n}"),
+            Value::Date(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            Value::Kind(k) => f.write_str(k.as_str()),
+            Value::Regex(p) => write_quoted(f, p.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            Op::Near => write!(f, "{}.near({})", self.field.as_str(), self.value),
+            op => write!(f, "{} {} {}", self.field.as_str(), op.as_str(), self.value),
+        }
+    }
+}
+
+/// Prints the canonical form, which parses back to the same expression as
+/// long as [`Expr::Text`] holds single-space-separated words.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let group = |f: &mut fmt::Formatter<'_>, e: &Expr, parens: bool| {
+            if parens {
+                write!(f, "({e})")
+            } else {
+                write!(f, "{e}")
+            }
+        };
+        match self {
+            Expr::And(items) => {
+                for (i, e) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" AND ")?;
+                    }
+                    group(f, e, matches!(e, Expr::And(_) | Expr::Or(_)))?;
+                }
+                Ok(())
+            }
+            Expr::Or(items) => {
+                for (i, e) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" OR ")?;
+                    }
+                    group(f, e, matches!(e, Expr::Or(_)))?;
+                }
+                Ok(())
+            }
+            Expr::Not(inner) => {
+                f.write_str("NOT ")?;
+                let parens = match &**inner {
+                    Expr::And(_) | Expr::Or(_) => true,
+                    // Otherwise only the first word would be negated.
+                    Expr::Text(t) => is_bare(t) && t.contains(' '),
+                    _ => false,
+                };
+                group(f, inner, parens)
+            }
+            Expr::Text(t) if is_bare(t) => f.write_str(t),
+            // Text that cannot be written bare prints as a phrase.
+            Expr::Text(t) | Expr::Phrase(t) => write_quoted(f, t),
+            Expr::Filter(filter) => write!(f, "{filter}"),
+        }
+    }
+}
+
+/// A syntax error. `position` counts characters from the start of the query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl QueryError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    LParen,
+    RParen,
+    Op(Op),
+    Not,
+    And,
+    Or,
+    Word(String),
+    Quoted(String),
+}
+
+impl Tok {
+    fn describe(&self) -> String {
+        match self {
+            Tok::LParen => "`(`".into(),
+            Tok::RParen => "`)`".into(),
+            Tok::Op(op) => format!("`{}`", op.as_str()),
+            Tok::Not => "`NOT`".into(),
+            Tok::And => "`AND`".into(),
+            Tok::Or => "`OR`".into(),
+            Tok::Word(w) => format!("`{w}`"),
+            Tok::Quoted(_) => "quoted text".into(),
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | '=' | '<' | '>' | '!' | '~')
+}
+
+/// In the method form an unquoted value runs to the next `)`, as it did
+/// before the grammar existed, so `title.contains(funny cat)` still works.
+/// Returns the index of that `)` and the trimmed value.
+fn legacy_method_value(word: &str, chars: &[char], paren: usize) -> Option<(usize, String)> {
+    let (name, method) = word.rsplit_once('.')?;
+    Field::parse(name)?;
+    Op::from_method(method)?;
+    if chars.get(paren) != Some(&'(') {
+        return None;
+    }
+    let rest = &chars[paren + 1..];
+    let close = rest.iter().position(|&c| c == ')')?;
+    let value: String = rest[..close].iter().collect::<String>().trim().to_owned();
+    if value.is_empty() || value.starts_with('"') {
+        return None;
+    }
+    Some((paren + 1 + close, value))
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Tok)>, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let next = chars.get(i + 1).copied();
+        let tok = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            '~' => Tok::Op(Op::Contains),
+            '=' if next == Some('~') => Tok::Op(Op::Matches),
+            '=' => Tok::Op(Op::Eq),
+            '!' if next == Some('=') => Tok::Op(Op::Ne),
+            '!' => Tok::Not,
+            '>' if next == Some('=') => Tok::Op(Op::Ge),
+            '>' => Tok::Op(Op::Gt),
+            '<' if next == Some('=') => Tok::Op(Op::Le),
+            '<' => Tok::Op(Op::Lt),
+            '-' if next.is_some_and(|n| n == '(' || n == '"' || is_word_char(n)) => Tok::Not,
+            '"' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(QueryError::new(start, "unterminated quote")),
+                        Some('"') => break,
+                        // Only `\"` and `\\` are escapes, so regexes like `\d` can be
+                        // written as-is.
+                        Some('\\') if matches!(chars.get(i + 1), Some('"' | '\\')) => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                Tok::Quoted(text)
+            }
+            _ => {
+                let end = (i..chars.len())
+                    .find(|&j| !is_word_char(chars[j]))
+                    .unwrap_or(chars.len());
+                let word: String = chars[i..end].iter().collect();
+                i = end;
+                if let Some((close, value)) = legacy_method_value(&word, &chars, end) {
+                    tokens.push((start, Tok::Word(word)));
+                    tokens.push((end, Tok::LParen));
+                    tokens.push((end + 1, Tok::Quoted(value)));
+                    tokens.push((close, Tok::RParen));
+                    i = close + 1;
+                    continue;
+                }
+                tokens.push((
+                    start,
+                    match word.as_str() {
+                        "AND" => Tok::And,
+                        "OR" => Tok::Or,
+                        "NOT" => Tok::Not,
+                        _ => Tok::Word(word),
+                    },
+                ));
+                continue;
+            }
+        };
+        i += match tok {
+            Tok::Op(Op::Ne | Op::Ge | Op::Le | Op::Matches) => 2,
+            _ => 1,
+        };
+        tokens.push((start, tok));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Tok)>,
+    pos: usize,
+    /// Character length of the input, for errors at the end.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn bump(&mut self) -> Option<(usize, Tok)> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Expr, QueryError> {
+        let mut items = vec![self.and(depth)?];
+        while self.peek() == Some(&Tok::Or) {
+            self.bump();
+            items.push(self.and(depth)?);
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            Expr::Or(items)
+        })
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Expr, QueryError> {
+        let mut items: Vec<Expr> = Vec::new();
+        // Whether the last item was a bare word, which the next bare word
+        // joins to make a phrase.
+        let mut last_bare = false;
+        loop {
+            let explicit = match self.peek() {
+                None | Some(Tok::Or) | Some(Tok::RParen) if !items.is_empty() => break,
+                Some(Tok::And) if !items.is_empty() => {
+                    self.bump();
+                    true
+                }
+                _ => false,
+            };
+            let bare = matches!(self.peek(), Some(Tok::Word(_)));
+            let item = self.unary(depth)?;
+            match (&item, items.last_mut()) {
+                (Expr::Text(word), Some(Expr::Text(phrase))) if bare && last_bare && !explicit => {
+                    phrase.push(' ');
+                    phrase.push_str(word);
+                }
+                _ => items.push(item),
+            }
+            last_bare = bare && matches!(items.last(), Some(Expr::Text(_)));
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            Expr::And(items)
+        })
+    }
+
+    /// Fails once `depth` passes [`MAX_NESTING`], so hostile input cannot
+    /// recurse deep enough to overflow the stack.
+    fn nest(&self, depth: usize) -> Result<usize, QueryError> {
+        match depth < MAX_NESTING {
+            true => Ok(depth + 1),
+            false => Err(QueryError::new(
+                self.offset(),
+                format!("groups and negations can nest at most {MAX_NESTING} deep"),
+            )),
+        }
+    }
+
+    fn unary(&mut self, depth: usize) -> Result<Expr, QueryError> {
+        if self.peek() == Some(&Tok::Not) {
+            let depth = self.nest(depth)?;
+            self.bump();
+            return Ok(Expr::Not(Box::new(self.unary(depth)?)));
+        }
+        self.primary(depth)
+    }
+
+    fn primary(&mut self, depth: usize) -> Result<Expr, QueryError> {
+        let offset = self.offset();
+        match self.bump() {
+            None => Err(QueryError::new(offset, "expected a search term")),
+            Some((_, Tok::LParen)) => {
+                let depth = self.nest(depth)?;
+                let inner = self.or(depth)?;
+                match self.bump() {
+                    Some((_, Tok::RParen)) => Ok(inner),
+                    _ => Err(QueryError::new(offset, "unclosed `(`")),
+                }
+            }
+            Some((_, Tok::Quoted(text))) => Ok(Expr::Phrase(text)),
+            Some((_, Tok::Word(word))) => self.word(offset, word),
+            Some((_, tok)) => Err(QueryError::new(
+                offset,
+                format!("unexpected {}", tok.describe()),
+            )),
+        }
+    }
+
+    fn word(&mut self, offset: usize, word: String) -> Result<Expr, QueryError> {
+        match self.peek() {
+            Some(Tok::Op(op)) => {
+                let op = *op;
+                let field = Field::parse(&word)
+                    .ok_or_else(|| QueryError::new(offset, format!("unknown field `{word}`")))?;
+                self.bump();
+                self.filter(offset, field, op)
+            }
+            Some(Tok::LParen) if word.contains('.') => {
+                let (name, method) = word.rsplit_once('.').unwrap();
+                let field = Field::parse(name)
+                    .ok_or_else(|| QueryError::new(offset, format!("unknown field `{name}`")))?;
+                let op = Op::from_method(method).ok_or_else(|| {
+                    QueryError::new(
+                        offset + name.chars().count() + 1,
+                        format!("unknown filter `{word}`"),
+                    )
+                })?;
+                self.bump();
+                let filter = self.filter(offset, field, op)?;
+                match self.bump() {
+                    Some((_, Tok::RParen)) => Ok(filter),
+                    _ => Err(QueryError::new(offset, format!("unclosed `{word}(`"))),
+                }
+            }
+            _ => Ok(Expr::Text(word)),
+        }
+    }
+
+    fn filter(&mut self, offset: usize, field: Field, op: Op) -> Result<Expr, QueryError> {
+        if !field.ops().contains(&op) {
+            return Err(QueryError::new(
+                offset,
+                format!("`{}` does not support `{}`", field.as_str(), op.as_str()),
+            ));
+        }
+        let value_at = self.offset();
+        let raw = match self.bump() {
+            Some((_, Tok::Word(w) | Tok::Quoted(w))) => w,
+            _ => {
+                return Err(QueryError::new(
+                    value_at,
+                    format!("expected a value for `{}`", field.as_str()),
+                ));
+            }
+        };
+        let value = match field {
+            _ if op == Op::Matches => {
+                Value::Regex(Pattern::new(&raw).map_err(|e| QueryError::new(value_at, e))?)
+            }
+            Field::Kind => Value::Kind(
+                MediaKind::ALL
+                    .into_iter()
+                    .find(|k| k.as_str().eq_ignore_ascii_case(&raw))
+                    .ok_or_else(|| {
+                        QueryError::new(value_at, "expected `video`, `audio`, `image` or `text`")
+                    })?,
+            ),
+            Field::Nsfw => match raw.to_ascii_lowercase().as_str() {
+                "true" | "yes" => Value::Bool(true),
+                "false" | "no" => Value::Bool(false),
+                _ => return Err(QueryError::new(value_at, "expected `true` or `false`")),
+            },
+            Field::Size => Value::Bytes(
+                raw.parse::<ByteUnit>()
+                    .map_err(|_| QueryError::new(value_at, "expected a size like `10MB`"))?
+                    .as_u64(),
+            ),
+            Field::Date => Value::Date(
+                NaiveDate::parse_from_str(&raw, "%Y-%m-%d")
+                    .map_err(|_| QueryError::new(value_at, "expected a date like `2025-01-31`"))?,
+            ),
+            _ => Value::Text(raw),
+        };
+        Ok(Expr::Filter(Filter { field, op, value }))
+    }
+}
+
+/// Parses a search query. An empty query is `None` and matches everything.
+pub fn parse(input: &str) -> Result<Option<Expr>, QueryError> {
+    if input.len() > MAX_QUERY_LEN {
+        let position = input
+            .char_indices()
+            .take_while(|(i, _)| *i < MAX_QUERY_LEN)
+            .count();
+        return Err(QueryError::new(
+            position,
+            format!("queries can be at most {MAX_QUERY_LEN} bytes"),
+        ));
+    }
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: input.chars().count(),
+    };
+    let expr = parser.or(0)?;
+    if let Some((offset, tok)) = parser.bump() {
+        return Err(QueryError::new(
+            offset,
+            format!("unexpected {}", tok.describe()),
+        ));
+    }
+    Ok(Some(expr))
+}
@@ -0,0 +1,358 @@
+use {
+    crate::{auth::AuthenticatedUser, roles::Permission, state::AppState},
+    dashmap::DashMap,
+    rocket::{
+        Request,
+        http::Status,
+        request::{FromRequest, Outcome},
+        response::{self, Responder},
+        serde::json::Json,
+    },
+    serde::Serialize,
+    std::{
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteGroup {
+    Upload,
+    Comment,
+    Login,
+    Search,
+}
+
+impl RouteGroup {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RouteGroup::Upload => "upload",
+            RouteGroup::Comment => "comment",
+            RouteGroup::Login => "login",
+            RouteGroup::Search => "search",
+        }
+    }
+}
+
+/// A token bucket that holds up to `capacity` requests and refills completely
+/// over `period`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BucketConfig {
+    pub capacity: u32,
+    pub period_secs: u64,
+}
+
+impl BucketConfig {
+    fn refill_per_sec(&self) -> f64 {
+        self.capacity as f64 / self.period_secs.max(1) as f64
+    }
+
+    /// Parses `<requests>/<seconds>`, e.g. `30/60`. `off` disables the limit.
+    pub fn parse(raw: &str) -> Option<Option<Self>> {
+        let raw = raw.trim();
+        if raw.eq_ignore_ascii_case("off") || raw == "0" {
+            return Some(None);
+        }
+        let (capacity, period) = raw.split_once('/')?;
+        let capacity: u32 = capacity.trim().parse().ok()?;
+        let period_secs: u64 = period.trim().parse().ok()?;
+        if capacity == 0 || period_secs == 0 {
+            return Some(None);
+        }
+        Some(Some(Self {
+            capacity,
+            period_secs,
+        }))
+    }
+
+    fn from_env(env_var: &str, default: BucketConfig) -> Option<Self> {
+        match std::env::var(env_var) {
+            Ok(raw) => Self::parse(&raw).unwrap_or_else(|| {
+                tracing::warn!(
+                    "could not parse rate limit '{}' from {}, using default",
+                    raw,
+                    env_var
+                );
+                Some(default)
+            }),
+            Err(_) => Some(default),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitConfig {
+    pub upload: Option<BucketConfig>,
+    pub comment: Option<BucketConfig>,
+    pub login: Option<BucketConfig>,
+    pub search: Option<BucketConfig>,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        Self {
+            upload: BucketConfig::from_env(
+                "RATE_LIMIT_UPLOAD",
+                BucketConfig {
+                    capacity: 60,
+                    period_secs: 3600,
+                },
+            ),
+            comment: BucketConfig::from_env(
+                "RATE_LIMIT_COMMENT",
+                BucketConfig {
+                    capacity: 10,
+                    period_secs: 60,
+                },
+            ),
+            login: BucketConfig::from_env(
+                "RATE_LIMIT_LOGIN",
+                BucketConfig {
+                    capacity: 20,
+                    period_secs: 300,
+                },
+            ),
+            search: BucketConfig::from_env(
+                "RATE_LIMIT_SEARCH",
+                BucketConfig {
+                    capacity: 120,
+                    period_secs: 60,
+                },
+            ),
+        }
+    }
+
+    pub fn get(&self, group: RouteGroup) -> Option<BucketConfig> {
+        match group {
+            RouteGroup::Upload => self.upload,
+            RouteGroup::Comment => self.comment,
+            RouteGroup::Login => self.login,
+            RouteGroup::Search => self.search,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketSnapshot {
+    pub group: RouteGroup,
+    pub key: String,
+    pub remaining: u32,
+    pub capacity: u32,
+    pub idle_secs: u64,
+}
+
+pub struct RateLimiter {
+    pub config: RateLimitConfig,
+    buckets: DashMap<(RouteGroup, String), Bucket>,
+    last_prune: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    const MAX_TRACKED_BUCKETS: usize = 10_000;
+    /// Pruning walks every bucket, so under load it runs at most this often
+    /// rather than on every request.
+    const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: DashMap::new(),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Takes one token from each of `keys`' buckets, or none if any of them
+    /// is empty. On failure returns the key that was out of tokens and how
+    /// long to wait.
+    pub fn check_all<'k>(
+        &self,
+        group: RouteGroup,
+        keys: &'k [String],
+    ) -> Result<(), (&'k str, Duration)> {
+        for (i, key) in keys.iter().enumerate() {
+            if let Err(wait) = self.check(group, key) {
+                for taken in &keys[..i] {
+                    self.refund(group, taken);
+                }
+                return Err((key, wait));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a token taken by [`RateLimiter::check`].
+    fn refund(&self, group: RouteGroup, key: &str) {
+        let Some(cfg) = self.config.get(group) else {
+            return;
+        };
+        if let Some(mut bucket) = self.buckets.get_mut(&(group, key.to_owned())) {
+            bucket.tokens = (bucket.tokens + 1.0).min(cfg.capacity as f64);
+        }
+    }
+
+    /// Takes one token from the bucket for `key`. Returns how long the caller
+    /// has to wait if the bucket is empty.
+    pub fn check(&self, group: RouteGroup, key: &str) -> Result<(), Duration> {
+        let Some(cfg) = self.config.get(group) else {
+            return Ok(());
+        };
+
+        self.maybe_prune();
+
+        let now = Instant::now();
+        let rate = cfg.refill_per_sec();
+        let mut bucket = self
+            .buckets
+            .entry((group, key.to_owned()))
+            .or_insert_with(|| Bucket {
+                tokens: cfg.capacity as f64,
+                updated_at: now,
+            });
+
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(cfg.capacity as f64);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    fn maybe_prune(&self) {
+        if self.buckets.len() <= Self::MAX_TRACKED_BUCKETS {
+            return;
+        }
+        // Another request is already pruning.
+        let Ok(mut last) = self.last_prune.try_lock() else {
+            return;
+        };
+        if last.elapsed() < Self::PRUNE_INTERVAL {
+            return;
+        }
+        *last = Instant::now();
+        drop(last);
+        self.prune();
+    }
+
+    fn prune(&self) {
+        let now = Instant::now();
+        self.buckets.retain(|(group, _), bucket| {
+            self.config.get(*group).is_some_and(|cfg| {
+                now.duration_since(bucket.updated_at).as_secs() < cfg.period_secs
+            })
+        });
+    }
+
+    pub fn snapshot(&self) -> Vec<BucketSnapshot> {
+        let now = Instant::now();
+        let mut out: Vec<BucketSnapshot> = self
+            .buckets
+            .iter()
+            .filter_map(|entry| {
+                let (group, key) = entry.key();
+                let cfg = self.config.get(*group)?;
+                let bucket = entry.value();
+                let elapsed = now.duration_since(bucket.updated_at);
+                let tokens = (bucket.tokens + elapsed.as_secs_f64() * cfg.refill_per_sec())
+                    .min(cfg.capacity as f64);
+                Some(BucketSnapshot {
+                    group: *group,
+                    key: key.clone(),
+                    remaining: tokens.floor() as u32,
+                    capacity: cfg.capacity,
+                    idle_secs: elapsed.as_secs(),
+                })
+            })
+            .filter(|s| s.remaining < s.capacity)
+            .collect();
+        out.sort_unstable_by(|a, b| a.remaining.cmp(&b.remaining).then(a.key.cmp(&b.key)));
+        out
+    }
+}
+
+#[derive(Clone, Copy)]
+struct RetryAfter(Option<u64>);
+
+async fn check_request(req: &Request<'_>, group: RouteGroup) -> Outcome<(), ()> {
+    let Some(state) = req.rocket().state::<AppState>() else {
+        return Outcome::Error((Status::InternalServerError, ()));
+    };
+
+    let mut keys = Vec::with_capacity(2);
+    if let Some(ip) = req.client_ip() {
+        keys.push(format!("ip:{}", ip));
+    }
+    if let Outcome::Success(authed) = req.guard::<AuthenticatedUser>().await {
+        if group != RouteGroup::Login && authed.can(state, Permission::BypassQuotas) {
+            return Outcome::Success(());
+        }
+        keys.push(format!("user:{}", authed.0.user_id));
+    }
+    if keys.is_empty() {
+        // Without an IP or a user there is nothing to tell clients apart, so
+        // they share one bucket rather than going unlimited.
+        keys.push("anon".to_owned());
+    }
+
+    // Either every bucket pays for the request or none does, so requests
+    // the user's bucket turns away do not also drain the IP's.
+    if let Err((key, wait)) = state.rate_limiter.check_all(group, &keys) {
+        let secs = wait.as_secs() + 1;
+        req.local_cache(|| RetryAfter(Some(secs)));
+        tracing::info!(group = group.as_str(), key = %key, "rate limit exceeded");
+        return Outcome::Error((Status::TooManyRequests, ()));
+    }
+
+    Outcome::Success(())
+}
+
+macro_rules! rate_limit_guard {
+    ($name:ident, $group:expr) => {
+        pub struct $name;
+
+        #[rocket::async_trait]
+        impl<'r> FromRequest<'r> for $name {
+            type Error = ();
+
+            async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+                check_request(req, $group).await.map(|_| $name)
+            }
+        }
+    };
+}
+
+rate_limit_guard!(UploadLimit, RouteGroup::Upload);
+rate_limit_guard!(CommentLimit, RouteGroup::Comment);
+rate_limit_guard!(LoginLimit, RouteGroup::Login);
+rate_limit_guard!(SearchLimit, RouteGroup::Search);
+
+pub struct TooManyRequests(Option<u64>);
+
+impl<'r> Responder<'r, 'static> for TooManyRequests {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let retry_after = self.0.unwrap_or(60);
+        let body = serde_json::json!({
+            "error": "Too Many Requests",
+            "message": format!("Rate limit exceeded — try again in {} seconds", retry_after),
+            "retry_after": retry_after,
+        });
+        let mut res = rocket::response::status::Custom(Status::TooManyRequests, Json(body))
+            .respond_to(req)?;
+        res.set_raw_header("Retry-After", retry_after.to_string());
+        Ok(res)
+    }
+}
+
+#[rocket::catch(429)]
+pub fn too_many_requests(req: &Request<'_>) -> TooManyRequests {
+    TooManyRequests(req.local_cache(|| RetryAfter(None)).0)
+}
@@ -0,0 +1,148 @@
+use {
+    crate::models::{Role, TokenScope},
+    serde::Serialize,
+};
+
+/// Something a role may be allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Upload,
+    Comment,
+    /// Edit the title, source and flags of someone else's upload.
+    EditAnyMedia,
+    DeleteAnyMedia,
+    ToggleNsfw,
+    ManageDailyQueue,
+    /// Delete other people's comments and toggle comments on their uploads.
+    ModerateComments,
+    /// Admin upload size limits and no upload, comment or search rate limits.
+    BypassQuotas,
+    /// Roles, sessions, local accounts and signing keys.
+    ManageUsers,
+    /// Tag aliases and merges.
+    ManageTags,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 10] = [
+        Permission::Upload,
+        Permission::Comment,
+        Permission::EditAnyMedia,
+        Permission::DeleteAnyMedia,
+        Permission::ToggleNsfw,
+        Permission::ManageDailyQueue,
+        Permission::ModerateComments,
+        Permission::BypassQuotas,
+        Permission::ManageUsers,
+        Permission::ManageTags,
+    ];
+
+    /// The scope a personal access token needs to use this permission.
+    /// Anything acting on other people's content or the server needs `admin`.
+    pub fn scope(self) -> TokenScope {
+        match self {
+            Permission::Upload | Permission::BypassQuotas => TokenScope::Upload,
+            Permission::Comment => TokenScope::Comment,
+            _ => TokenScope::Admin,
+        }
+    }
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::Admin,
+        Role::Moderator,
+        Role::Trusted,
+        Role::User,
+        Role::Banned,
+    ];
+
+    pub fn can(self, permission: Permission) -> bool {
+        use Permission::*;
+        match self {
+            Role::Admin => true,
+            Role::Moderator => matches!(
+                permission,
+                Upload
+                    | Comment
+                    | EditAnyMedia
+                    | DeleteAnyMedia
+                    | ToggleNsfw
+                    | ManageDailyQueue
+                    | ModerateComments
+                    | ManageTags
+            ),
+            Role::Trusted => matches!(permission, Upload | Comment | BypassQuotas),
+            Role::User => matches!(permission, Upload | Comment),
+            Role::Banned => false,
+        }
+    }
+
+    pub fn permissions(self) -> Vec<Permission> {
+        Permission::ALL
+            .into_iter()
+            .filter(|&p| self.can(p))
+            .collect()
+    }
+
+    /// Whether the role has any permission behind the `admin` token scope.
+    pub fn is_staff(self) -> bool {
+        self.permissions()
+            .into_iter()
+            .any(|p| p.scope() == TokenScope::Admin)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+            Role::Trusted => "trusted",
+            Role::User => "user",
+            Role::Banned => "banned",
+        }
+    }
+}
+
+/// What the current request may do, for templates.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Permissions {
+    pub upload: bool,
+    pub comment: bool,
+    pub edit_any_media: bool,
+    pub delete_any_media: bool,
+    pub toggle_nsfw: bool,
+    pub manage_daily_queue: bool,
+    pub moderate_comments: bool,
+    pub bypass_quotas: bool,
+    pub manage_users: bool,
+    pub manage_tags: bool,
+    /// Any moderation permission, i.e. the admin panel is worth showing.
+    pub moderate: bool,
+}
+
+impl Permissions {
+    pub fn from_fn(can: impl Fn(Permission) -> bool) -> Self {
+        let mut p = Self {
+            upload: can(Permission::Upload),
+            comment: can(Permission::Comment),
+            edit_any_media: can(Permission::EditAnyMedia),
+            delete_any_media: can(Permission::DeleteAnyMedia),
+            toggle_nsfw: can(Permission::ToggleNsfw),
+            manage_daily_queue: can(Permission::ManageDailyQueue),
+            moderate_comments: can(Permission::ModerateComments),
+            bypass_quotas: can(Permission::BypassQuotas),
+            manage_users: can(Permission::ManageUsers),
+            manage_tags: can(Permission::ManageTags),
+            moderate: false,
+        };
+        p.moderate = p.edit_any_media
+            || p.delete_any_media
+            || p.toggle_nsfw
+            || p.manage_daily_queue
+            || p.moderate_comments
+            || p.manage_users
+            || p.manage_tags;
+        p
+    }
+}
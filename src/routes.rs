@@ -1,12 +1,36 @@
+pub mod account;
 pub mod audio;
 pub mod auth;
+pub mod collections;
+pub mod dev;
 pub mod feed;
 pub mod images;
+pub mod keys;
+pub mod listing;
+pub mod local;
 pub mod media;
+pub mod moderation;
+pub mod profiles;
+pub mod roles;
+pub mod sanctions;
+pub mod search;
+pub mod sessions;
+pub mod tags;
 pub mod text;
+pub mod tokens;
 pub mod ui;
 pub mod videos;
 
+use {
+    crate::{
+        auth::AuthenticatedUser,
+        models::Role,
+        roles::Permission,
+        state::AppState,
+    },
+    rocket::{State, serde::json::Json},
+};
+
 #[rocket::get("/health")]
 pub fn health() -> rocket::serde::json::Json<serde_json::Value> {
     rocket::serde::json::Json(serde_json::json!({
@@ -14,3 +38,19 @@ pub fn health() -> rocket::serde::json::Json<serde_json::Value> {
         "service": "skibidi67",
     }))
 }
+
+#[rocket::get("/upload/limits")]
+pub fn upload_limits(
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Json<serde_json::Value> {
+    let role = user.as_ref().map_or(Role::User, |u| u.role(state));
+    let bypass_quotas = user
+        .as_ref()
+        .is_some_and(|u| u.can(state, Permission::BypassQuotas));
+    Json(serde_json::json!({
+        "role": role,
+        "max_bytes": state.upload_limits.for_role(bypass_quotas),
+        "chunk_bytes": state.upload_limits.chunk_bytes,
+    }))
+}
@@ -0,0 +1,62 @@
+use {
+    crate::{
+        auth::{AuthenticatedUser, SESSION_COOKIE},
+        error::{AppError, AppResult},
+        models::User,
+        state::AppState,
+    },
+    rocket::{
+        State,
+        http::{Cookie, CookieJar, Status},
+        serde::json::Json,
+    },
+};
+
+#[rocket::get("/auth/account")]
+pub fn account(user: AuthenticatedUser, state: &State<AppState>) -> AppResult<Json<User>> {
+    state
+        .users
+        .get(&user.0.user_id)
+        .map(Json)
+        .ok_or(AppError::UserNotFound)
+}
+
+#[rocket::get("/auth/account", rank = 2)]
+pub fn account_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+/// Detaches a login from the account and signs out its sessions. Logging in
+/// with it again afterwards creates a separate account.
+#[rocket::delete("/auth/account/identities/<provider>/<id>")]
+pub fn unlink_identity(
+    provider: &str,
+    id: u64,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+    cookies: &CookieJar<'_>,
+) -> AppResult<Json<User>> {
+    if !user.is_session() {
+        return Err(AppError::SessionRequired);
+    }
+    let account = state.users.unlink(&user.0.user_id, provider, id)?;
+    state.sessions.revoke_all_for(provider, id);
+    if user.0.provider == provider && user.0.id == id {
+        cookies.remove(Cookie::from(SESSION_COOKIE));
+    }
+    Ok(Json(account))
+}
+
+#[rocket::delete("/auth/account/identities/<_provider>/<_id>", rank = 2)]
+pub fn unlink_identity_unauthorized(
+    _provider: &str,
+    _id: u64,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
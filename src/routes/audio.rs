@@ -1,11 +1,16 @@
 use {
     crate::{
-        auth::{AdminUser, AuthenticatedUser},
+        auth::{AuthenticatedUser, CommentUser, DeleteMediaUser, NsfwUser, UploadUser},
+        comments::{CommentView, ReactionCount, ThreadPage, ThreadParams},
         error::{AppError, AppResult},
-        models::{Comment, VideoMeta},
-        routes::media::{
-            self, ALLOWED_AUDIO_TYPES, CommentBody, CommentsDisabledPatch, MediaResponse, MetaPatch,
-            NsfwPatch, RangeHeader,
+        models::{CommentRevision, VideoMeta},
+        ratelimit::{CommentLimit, SearchLimit, UploadLimit},
+        routes::{
+            listing::{ListParams, ListResponse},
+            media::{
+                self, ALLOWED_AUDIO_TYPES, CommentBody, CommentEdit, CommentsDisabledPatch,
+                MediaResponse, MetaPatch, NsfwPatch, RangeHeader,
+            },
         },
         state::AppState,
     },
@@ -17,14 +22,19 @@ use {
     },
 };
 
-#[get("/audio?<q>")]
-pub fn list_audio(state: &State<AppState>, q: Option<&str>) -> Json<Vec<VideoMeta>> {
-    media::handle_list(state, "audio/", q)
+#[get("/audio?<q>&<list..>")]
+pub fn list_audio(
+    _limit: SearchLimit,
+    state: &State<AppState>,
+    q: Option<&str>,
+    list: ListParams,
+) -> AppResult<Json<ListResponse>> {
+    media::handle_list(state, "audio/", q, list)
 }
 
 #[get("/audio/<id>")]
-pub fn get_audio(id: &str, state: &State<AppState>) -> AppResult<Json<VideoMeta>> {
-    media::handle_get(id, state)
+pub async fn get_audio(id: &str, state: &State<AppState>) -> AppResult<Json<VideoMeta>> {
+    media::handle_get(id, state).await
 }
 
 #[get("/audio/<id>/file")]
@@ -42,6 +52,7 @@ pub async fn stream_audio(
     data = "<data>"
 )]
 pub async fn upload_audio(
+    _limit: UploadLimit,
     title: &str,
     source_name: Option<&str>,
     source_link: Option<&str>,
@@ -50,7 +61,7 @@ pub async fn upload_audio(
     comments_disabled: Option<bool>,
     data: Data<'_>,
     content_type: &ContentType,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<(Status, Json<serde_json::Value>), AppError> {
     media::handle_upload(
@@ -62,7 +73,7 @@ pub async fn upload_audio(
         comments_disabled,
         data,
         content_type,
-        user,
+        user.0,
         state,
         ALLOWED_AUDIO_TYPES,
         None,
@@ -72,11 +83,12 @@ pub async fn upload_audio(
 
 #[post("/audio/upload/init?<content_type>")]
 pub async fn init_upload(
+    _limit: UploadLimit,
     content_type: &str,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_init_upload(content_type, user, state, ALLOWED_AUDIO_TYPES).await
+    media::handle_init_upload(content_type, user.0, state, ALLOWED_AUDIO_TYPES).await
 }
 
 #[put("/audio/upload/<upload_id>/<chunk_index>", data = "<data>")]
@@ -84,10 +96,10 @@ pub async fn upload_chunk(
     upload_id: &str,
     chunk_index: usize,
     data: Data<'_>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_upload_chunk(upload_id, chunk_index, data, user, state).await
+    media::handle_upload_chunk(upload_id, chunk_index, data, user.0, state).await
 }
 
 #[post(
@@ -101,7 +113,7 @@ pub async fn complete_upload(
     nsfw: Option<bool>,
     unlisted: Option<bool>,
     comments_disabled: Option<bool>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<(Status, Json<serde_json::Value>), AppError> {
     media::handle_complete_upload(
@@ -112,7 +124,7 @@ pub async fn complete_upload(
         nsfw,
         unlisted,
         comments_disabled,
-        user,
+        user.0,
         state,
         ALLOWED_AUDIO_TYPES,
         None,
@@ -187,7 +199,7 @@ pub async fn upload_audio_unauthorized(
 pub fn patch_nsfw(
     id: &str,
     body: Json<NsfwPatch>,
-    _admin: AdminUser,
+    _moderator: NsfwUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
     media::handle_patch_nsfw(id, body, state)
@@ -200,17 +212,17 @@ pub fn patch_nsfw_forbidden(
 ) -> (Status, Json<serde_json::Value>) {
     (
         Status::Forbidden,
-        Json(serde_json::json!({ "error": "Admin privileges required" })),
+        Json(serde_json::json!({ "error": "Moderator privileges required" })),
     )
 }
 
 #[delete("/audio/<id>")]
 pub async fn delete_audio(
     id: &str,
-    _admin: AdminUser,
+    moderator: DeleteMediaUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_delete(id, state).await
+    media::handle_delete(id, &moderator.0, state).await
 }
 
 #[delete("/audio/<_id>", rank = 2)]
@@ -220,7 +232,7 @@ pub fn delete_audio_forbidden(
 ) -> (Status, Json<serde_json::Value>) {
     (
         Status::Forbidden,
-        Json(serde_json::json!({ "error": "Admin privileges required" })),
+        Json(serde_json::json!({ "error": "Moderator privileges required" })),
     )
 }
 
@@ -233,18 +245,23 @@ pub fn delete_audio_unauthorized(_id: &str) -> (Status, Json<serde_json::Value>)
 }
 
 #[get("/audio/<id>/comments")]
-pub fn get_comments(id: &str, state: &State<AppState>) -> Result<Json<Vec<Comment>>, AppError> {
-    media::handle_get_comments(id, state)
+pub fn get_comments(
+    id: &str,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Result<Json<Vec<CommentView>>, AppError> {
+    media::handle_get_comments(id, viewer.as_ref(), state)
 }
 
 #[post("/audio/<id>/comments", format = "json", data = "<body>")]
 pub fn add_comment(
+    _limit: CommentLimit,
     id: &str,
     body: Json<CommentBody>,
-    user: AuthenticatedUser,
+    user: CommentUser,
     state: &State<AppState>,
-) -> Result<(Status, Json<Comment>), AppError> {
-    media::handle_add_comment(id, body, user, state)
+) -> Result<(Status, Json<CommentView>), AppError> {
+    media::handle_add_comment(id, body, user.0, state)
 }
 
 #[post("/audio/<_id>/comments", format = "json", data = "<_body>", rank = 2)]
@@ -262,10 +279,10 @@ pub fn add_comment_unauthorized(
 pub fn delete_comment(
     id: &str,
     comment_id: &str,
-    user: AuthenticatedUser,
+    user: CommentUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_delete_comment(id, comment_id, user, state)
+    media::handle_delete_comment(id, comment_id, user.0, state)
 }
 
 #[delete("/audio/<_id>/comments/<_comment_id>", rank = 2)]
@@ -279,14 +296,110 @@ pub fn delete_comment_unauthorized(
     )
 }
 
+#[get("/audio/<id>/comments/thread?<params..>")]
+pub fn get_comment_thread(
+    id: &str,
+    params: ThreadParams,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<ThreadPage>> {
+    media::handle_get_thread(id, &params, viewer.as_ref(), state)
+}
+
+#[patch("/audio/<id>/comments/<comment_id>", format = "json", data = "<body>")]
+pub fn edit_comment(
+    _limit: CommentLimit,
+    id: &str,
+    comment_id: &str,
+    body: Json<CommentEdit>,
+    user: CommentUser,
+    state: &State<AppState>,
+) -> AppResult<Json<CommentView>> {
+    media::handle_edit_comment(id, comment_id, body, user.0, state)
+}
+
+#[patch(
+    "/audio/<_id>/comments/<_comment_id>",
+    format = "json",
+    data = "<_body>",
+    rank = 2
+)]
+pub fn edit_comment_unauthorized(
+    _id: &str,
+    _comment_id: &str,
+    _body: Json<CommentEdit>,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[get("/audio/<id>/comments/<comment_id>/revisions")]
+pub fn get_comment_revisions(
+    id: &str,
+    comment_id: &str,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<CommentRevision>>> {
+    media::handle_get_revisions(id, comment_id, viewer.as_ref(), state)
+}
+
+#[put("/audio/<id>/comments/<comment_id>/reactions/<emoji>")]
+pub fn add_reaction(
+    _limit: CommentLimit,
+    id: &str,
+    comment_id: &str,
+    emoji: &str,
+    user: CommentUser,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<ReactionCount>>> {
+    media::handle_react(id, comment_id, emoji, true, user.0, state)
+}
+
+#[put("/audio/<_id>/comments/<_comment_id>/reactions/<_emoji>", rank = 2)]
+pub fn add_reaction_unauthorized(
+    _id: &str,
+    _comment_id: &str,
+    _emoji: &str,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[delete("/audio/<id>/comments/<comment_id>/reactions/<emoji>")]
+pub fn remove_reaction(
+    id: &str,
+    comment_id: &str,
+    emoji: &str,
+    user: CommentUser,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<ReactionCount>>> {
+    media::handle_react(id, comment_id, emoji, false, user.0, state)
+}
+
+#[delete("/audio/<_id>/comments/<_comment_id>/reactions/<_emoji>", rank = 2)]
+pub fn remove_reaction_unauthorized(
+    _id: &str,
+    _comment_id: &str,
+    _emoji: &str,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
 #[patch("/audio/<id>/comments_disabled", format = "json", data = "<body>")]
 pub fn patch_comments_disabled(
     id: &str,
     body: Json<CommentsDisabledPatch>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_patch_comments_disabled(id, body, user, state)
+    media::handle_patch_comments_disabled(id, body, user.0, state)
 }
 
 #[patch(
@@ -309,10 +422,10 @@ pub fn patch_comments_disabled_unauthorized(
 pub fn patch_meta(
     id: &str,
     body: Json<MetaPatch>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_patch_meta(id, body, user, state)
+    media::handle_patch_meta(id, body, user.0, state)
 }
 
 #[patch("/audio/<_id>", format = "json", data = "<_body>", rank = 2)]
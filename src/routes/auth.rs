@@ -1,275 +1,231 @@
 use {
     crate::{
-        auth::{create_jwt, validate_jwt, AuthenticatedUser, SESSION_COOKIE},
-        error::AppError,
-        models::{
-            DiscordTokenResponse, DiscordUser, GithubTokenResponse, GithubUser, OsuTokenResponse,
-            OsuUser, PlatformUser,
-        },
-        state::AppState,
+        auth::{AuthenticatedUser, SESSION_COOKIE, create_jwt, session_ttl, validate_jwt},
+        error::{AppError, AppResult},
+        models::{PlatformUser, SanctionKind},
+        ratelimit::LoginLimit,
+        sessions::ClientInfo,
+        state::{AppState, PendingLink, PendingLogin},
     },
-    hashbrown::HashMap,
     rocket::{
         State, get,
         http::{Cookie, CookieJar, SameSite, Status},
         response::Redirect,
         serde::json::Json,
     },
-    serde_json::Value,
     uuid::Uuid,
 };
 
-const OSU_AUTHORIZE_URL: &str = "https://osu.ppy.sh/oauth/authorize";
-const OSU_TOKEN_URL: &str = "https://osu.ppy.sh/oauth/token";
-const OSU_ME_URL: &str = "https://osu.ppy.sh/api/v2/me";
-
-const GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
-const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
-const GITHUB_USER_URL: &str = "https://api.github.com/user";
+/// The osu! routes predate the provider registry and stay registered with
+/// osu! as the redirect URI, so they keep working as aliases.
+const LEGACY_PROVIDER: &str = "osu";
 
-const DISCORD_AUTHORIZE_URL: &str = "https://discord.com/oauth2/authorize";
-const DISCORD_TOKEN_URL: &str = "https://discord.com/api/oauth2/token";
-const DISCORD_USER_URL: &str = "https://discord.com/api/users/@me";
+async fn begin_login(
+    provider: &str,
+    link: Option<PendingLink>,
+    state: &AppState,
+) -> Result<Redirect, AppError> {
+    let provider = state
+        .providers
+        .get(provider)
+        .ok_or_else(|| AppError::UnknownProvider(provider.to_owned()))?;
 
-#[get("/auth/login")]
-pub fn login(state: &State<AppState>) -> Redirect {
     if state.pending_states.len() > 10_000 {
         state.pending_states.clear();
     }
 
     let csrf_state = Uuid::new_v4().to_string();
-    state.pending_states.insert(csrf_state.clone(), ());
-
-    let url = format!(
-        "{}?client_id={}&redirect_uri={}&response_type=code&scope=identify+public&state={}",
-        OSU_AUTHORIZE_URL,
-        state.oauth.client_id,
-        urlencoded(&state.oauth.redirect_uri),
-        urlencoded(&csrf_state),
+    state.pending_states.insert(
+        csrf_state.clone(),
+        PendingLogin {
+            provider: provider.name().to_owned(),
+            link,
+        },
     );
 
-    Redirect::to(url)
+    Ok(Redirect::to(provider.authorize_url(&csrf_state).await?))
 }
 
-fn set_jwt_cookie(cookies: &CookieJar<'_>, user: &PlatformUser, secret: &str) {
-    let remember = cookies.get("remember_me").map(|c| c.value()) == Some("true");
-    let jwt = create_jwt(user, secret, remember);
-
-    let mut cookie = Cookie::new(SESSION_COOKIE, jwt);
-    cookie.set_http_only(true);
-    cookie.set_same_site(SameSite::Lax);
-    cookie.set_secure(true);
-    cookie.set_path("/");
-    if remember {
-        cookie.set_max_age(rocket::time::Duration::days(30));
-    }
-    cookies.add(cookie);
-}
-
-#[get("/auth/callback?<code>&<state>")]
-pub async fn callback(
+async fn finish_login(
+    provider: &str,
     code: &str,
     state: &str,
-    app_state: &State<AppState>,
+    user: Option<AuthenticatedUser>,
+    app_state: &AppState,
     cookies: &CookieJar<'_>,
+    client_info: ClientInfo,
 ) -> Result<Redirect, AppError> {
-    if app_state.pending_states.remove(state).is_none() {
-        return Err(AppError::OAuthStateMismatch);
+    let pending = match app_state.pending_states.remove(state) {
+        Some((_, pending)) if pending.provider == provider => pending,
+        _ => return Err(AppError::OAuthStateMismatch),
+    };
+    if let Some(link) = &pending.link {
+        let same_session = user.as_ref().is_some_and(|u| {
+            u.0.user_id == link.user_id && u.session_id() == Some(link.session_id.as_str())
+        });
+        if !same_session {
+            return Err(AppError::LinkSessionMismatch);
+        }
     }
 
-    let client = reqwest::Client::new();
-
-    let mut form = HashMap::new();
-    form.insert("client_id", app_state.oauth.client_id.to_string());
-    form.insert("client_secret", app_state.oauth.client_secret.clone());
-    form.insert("code", code.to_owned());
-    form.insert("grant_type", "authorization_code".to_owned());
-    form.insert("redirect_uri", app_state.oauth.redirect_uri.clone());
+    let provider = app_state
+        .providers
+        .get(provider)
+        .ok_or_else(|| AppError::UnknownProvider(provider.to_owned()))?;
+    let platform_user = provider.fetch_user(code).await?;
 
-    let token_res: OsuTokenResponse = client
-        .post(OSU_TOKEN_URL)
-        .json(&form)
-        .send()
-        .await
-        .map_err(AppError::Reqwest)?
-        .json()
-        .await
-        .map_err(|e| AppError::OAuthTokenExchange(e.to_string()))?;
-
-    let me_res: Value = client
-        .get(OSU_ME_URL)
-        .bearer_auth(&token_res.access_token)
-        .send()
-        .await
-        .map_err(AppError::Reqwest)?
-        .json()
-        .await
-        .map_err(|e| AppError::OsuUserFetch(e.to_string()))?;
-
-    let user: OsuUser =
-        serde_json::from_value(me_res).map_err(|e| AppError::OsuUserFetch(e.to_string()))?;
+    if let Some(link) = pending.link {
+        app_state.link_identity(&link.user_id, &platform_user, link.merge)?;
+        return Ok(Redirect::to("/ui/account"));
+    }
 
-    let platform_user = PlatformUser::from_osu(&user);
-    set_jwt_cookie(cookies, &platform_user, &app_state.jwt_secret);
+    sign_in(cookies, platform_user, app_state, client_info)?;
 
     Ok(Redirect::to("/ui"))
 }
 
-#[get("/auth/github/login")]
-pub fn github_login(state: &State<AppState>) -> Result<Redirect, AppError> {
-    let gh = state
-        .github_oauth
-        .as_ref()
-        .ok_or(AppError::Internal("GitHub OAuth not configured".to_owned()))?;
-
-    if state.pending_states.len() > 10_000 {
-        state.pending_states.clear();
+/// Goes straight to the default provider, or to the login page when there
+/// is more than one way to sign in.
+#[get("/auth/login")]
+pub async fn login(_limit: LoginLimit, state: &State<AppState>) -> Result<Redirect, AppError> {
+    match state.providers.default_provider() {
+        Some(provider) if state.local_accounts.is_none() && !state.dev_login => {
+            begin_login(provider.name(), None, state).await
+        }
+        _ => Ok(Redirect::to("/ui/login")),
     }
-
-    let csrf_state = Uuid::new_v4().to_string();
-    state.pending_states.insert(csrf_state.clone(), ());
-
-    let url = format!(
-        "{}?client_id={}&redirect_uri={}&scope=read:user&state={}",
-        GITHUB_AUTHORIZE_URL,
-        urlencoded(&gh.client_id),
-        urlencoded(&gh.redirect_uri),
-        urlencoded(&csrf_state),
-    );
-
-    Ok(Redirect::to(url))
 }
 
-#[get("/auth/github/callback?<code>&<state>")]
-pub async fn github_callback(
+#[get("/auth/callback?<code>&<state>")]
+pub async fn callback(
+    _limit: LoginLimit,
     code: &str,
     state: &str,
+    user: Option<AuthenticatedUser>,
     app_state: &State<AppState>,
     cookies: &CookieJar<'_>,
+    client_info: ClientInfo,
 ) -> Result<Redirect, AppError> {
-    if app_state.pending_states.remove(state).is_none() {
-        return Err(AppError::OAuthStateMismatch);
-    }
-
-    let gh = app_state
-        .github_oauth
-        .as_ref()
-        .ok_or(AppError::Internal("GitHub OAuth not configured".to_owned()))?;
-
-    let client = reqwest::Client::new();
-
-    let mut form = HashMap::new();
-    form.insert("client_id", gh.client_id.clone());
-    form.insert("client_secret", gh.client_secret.clone());
-    form.insert("code", code.to_owned());
-    form.insert("redirect_uri", gh.redirect_uri.clone());
-
-    let token_res: GithubTokenResponse = client
-        .post(GITHUB_TOKEN_URL)
-        .header("Accept", "application/json")
-        .json(&form)
-        .send()
-        .await
-        .map_err(AppError::Reqwest)?
-        .json()
-        .await
-        .map_err(|e| AppError::OAuthTokenExchange(e.to_string()))?;
-
-    let gh_user: GithubUser = client
-        .get(GITHUB_USER_URL)
-        .header("User-Agent", "skibidi67")
-        .bearer_auth(&token_res.access_token)
-        .send()
-        .await
-        .map_err(AppError::Reqwest)?
-        .json()
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to fetch GitHub user: {}", e)))?;
-
-    let platform_user = PlatformUser::from_github(&gh_user);
-    set_jwt_cookie(cookies, &platform_user, &app_state.jwt_secret);
-
-    Ok(Redirect::to("/ui"))
+    finish_login(
+        LEGACY_PROVIDER,
+        code,
+        state,
+        user,
+        app_state,
+        cookies,
+        client_info,
+    )
+    .await
 }
 
-#[get("/auth/discord/login")]
-pub fn discord_login(state: &State<AppState>) -> Result<Redirect, AppError> {
-    let dc = state
-        .discord_oauth
-        .as_ref()
-        .ok_or(AppError::Internal("Discord OAuth not configured".to_owned()))?;
-
-    if state.pending_states.len() > 10_000 {
-        state.pending_states.clear();
-    }
-
-    let csrf_state = Uuid::new_v4().to_string();
-    state.pending_states.insert(csrf_state.clone(), ());
-
-    let url = format!(
-        "{}?client_id={}&redirect_uri={}&response_type=code&scope=identify&state={}",
-        DISCORD_AUTHORIZE_URL,
-        urlencoded(&dc.client_id),
-        urlencoded(&dc.redirect_uri),
-        urlencoded(&csrf_state),
-    );
-
-    Ok(Redirect::to(url))
+#[get("/auth/<provider>/login")]
+pub async fn provider_login(
+    _limit: LoginLimit,
+    provider: &str,
+    state: &State<AppState>,
+) -> Result<Redirect, AppError> {
+    begin_login(provider, None, state).await
 }
 
-#[get("/auth/discord/callback?<code>&<state>")]
-pub async fn discord_callback(
+#[get("/auth/<provider>/callback?<code>&<state>")]
+pub async fn provider_callback(
+    _limit: LoginLimit,
+    provider: &str,
     code: &str,
     state: &str,
+    user: Option<AuthenticatedUser>,
     app_state: &State<AppState>,
     cookies: &CookieJar<'_>,
+    client_info: ClientInfo,
 ) -> Result<Redirect, AppError> {
-    if app_state.pending_states.remove(state).is_none() {
-        return Err(AppError::OAuthStateMismatch);
-    }
-
-    let dc = app_state
-        .discord_oauth
-        .as_ref()
-        .ok_or(AppError::Internal("Discord OAuth not configured".to_owned()))?;
+    finish_login(provider, code, state, user, app_state, cookies, client_info).await
+}
 
-    let client = reqwest::Client::new();
+/// Starts a login whose identity is linked to the signed-in user's account
+/// instead of signing in as it. If the identity already has an account of
+/// its own, the link fails unless `merge` is set.
+#[get("/auth/<provider>/link?<merge>")]
+pub async fn provider_link(
+    _limit: LoginLimit,
+    provider: &str,
+    merge: Option<bool>,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> Result<Redirect, AppError> {
+    let Some(session_id) = user.session_id() else {
+        return Err(AppError::SessionRequired);
+    };
+    let link = PendingLink {
+        user_id: user.0.user_id.clone(),
+        session_id: session_id.to_owned(),
+        merge: merge.unwrap_or(false),
+    };
+    begin_login(provider, Some(link), state).await
+}
 
-    let mut form = HashMap::new();
-    form.insert("client_id", dc.client_id.clone());
-    form.insert("client_secret", dc.client_secret.clone());
-    form.insert("code", code.to_owned());
-    form.insert("grant_type", "authorization_code".to_owned());
-    form.insert("redirect_uri", dc.redirect_uri.clone());
+#[get("/auth/<_provider>/link", rank = 2)]
+pub fn provider_link_unauthorized(_provider: &str) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
 
-    let token_res: DiscordTokenResponse = client
-        .post(DISCORD_TOKEN_URL)
-        .form(&form)
-        .send()
-        .await
-        .map_err(AppError::Reqwest)?
-        .json()
-        .await
-        .map_err(|e| AppError::OAuthTokenExchange(e.to_string()))?;
+fn remember_me(cookies: &CookieJar<'_>) -> bool {
+    cookies.get("remember_me").map(|c| c.value()) == Some("true")
+}
 
-    let dc_user: DiscordUser = client
-        .get(DISCORD_USER_URL)
-        .bearer_auth(&token_res.access_token)
-        .send()
-        .await
-        .map_err(AppError::Reqwest)?
-        .json()
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to fetch Discord user: {}", e)))?;
+/// Records the login against the user's account and starts a session for
+/// it, unless the account is banned.
+pub(crate) fn sign_in(
+    cookies: &CookieJar<'_>,
+    mut platform_user: PlatformUser,
+    app_state: &AppState,
+    client_info: ClientInfo,
+) -> AppResult<PlatformUser> {
+    platform_user.user_id = app_state.users.sign_in(&platform_user).id;
+    app_state
+        .sanctions
+        .check(&platform_user.user_id, SanctionKind::Ban)?;
+    start_session(cookies, &platform_user, app_state, client_info);
+    Ok(platform_user)
+}
 
-    let platform_user = PlatformUser::from_discord(&dc_user);
-    set_jwt_cookie(cookies, &platform_user, &app_state.jwt_secret);
+/// Starts a new server-side session for `user` and hands the browser a JWT
+/// that refers to it.
+fn start_session(
+    cookies: &CookieJar<'_>,
+    user: &PlatformUser,
+    app_state: &AppState,
+    client: ClientInfo,
+) {
+    let remember = remember_me(cookies);
+    let session = app_state
+        .sessions
+        .create(user, session_ttl(remember), client);
+    let jwt = create_jwt(user, &session, &app_state.jwt_keys);
+    set_jwt_cookie(cookies, jwt, remember);
+}
 
-    Ok(Redirect::to("/ui"))
+fn set_jwt_cookie(cookies: &CookieJar<'_>, jwt: String, remember: bool) {
+    let mut cookie = Cookie::new(SESSION_COOKIE, jwt);
+    cookie.set_http_only(true);
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_secure(true);
+    cookie.set_path("/");
+    if remember {
+        cookie.set_max_age(rocket::time::Duration::days(30));
+    }
+    cookies.add(cookie);
 }
 
 #[get("/auth/logout")]
-pub fn logout(cookies: &CookieJar<'_>) -> Redirect {
+pub fn logout(cookies: &CookieJar<'_>, app_state: &State<AppState>) -> Redirect {
+    if let Some(session_cookie) = cookies.get(SESSION_COOKIE)
+        && let Ok(claims) = validate_jwt(session_cookie.value(), &app_state.jwt_keys)
+    {
+        app_state.sessions.revoke(&claims.sid);
+    }
     cookies.remove(Cookie::from(SESSION_COOKIE));
     Redirect::to("/ui")
 }
@@ -282,6 +238,7 @@ pub fn me(user: AuthenticatedUser) -> Json<serde_json::Value> {
         "id": u.id,
         "username": u.username,
         "avatar_url": u.avatar_url,
+        "user_id": u.user_id,
     }))
 }
 
@@ -297,30 +254,23 @@ pub fn me_unauthenticated() -> (Status, Json<serde_json::Value>) {
 pub fn refresh_cookie(cookies: &CookieJar<'_>, app_state: &State<AppState>) -> Redirect {
     if let Some(session_cookie) = cookies.get(SESSION_COOKIE) {
         let token = session_cookie.value();
-        if let Ok(claims) = validate_jwt(token, &app_state.jwt_secret) {
-            let user = PlatformUser {
-                provider: claims.provider,
-                id: claims.id,
-                username: claims.username,
-                avatar_url: claims.avatar_url,
-            };
-            set_jwt_cookie(cookies, &user, &app_state.jwt_secret);
-        }
-    }
-    Redirect::to("/ui")
-}
-
-fn urlencoded(s: &str) -> String {
-    let mut out = String::with_capacity(s.len());
-    for byte in s.bytes() {
-        match byte {
-            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
-                out.push(byte as char);
-            }
-            _ => {
-                out.push_str(&format!("%{:02X}", byte));
+        if let Ok(claims) = validate_jwt(token, &app_state.jwt_keys) {
+            let remember = remember_me(cookies);
+            if let Some(session) = app_state
+                .sessions
+                .extend(&claims.sid, session_ttl(remember))
+            {
+                let user = PlatformUser {
+                    provider: claims.provider,
+                    id: claims.id,
+                    username: claims.username,
+                    avatar_url: claims.avatar_url,
+                    user_id: String::new(),
+                };
+                let jwt = create_jwt(&user, &session, &app_state.jwt_keys);
+                set_jwt_cookie(cookies, jwt, remember);
             }
         }
     }
-    out
+    Redirect::to("/ui")
 }
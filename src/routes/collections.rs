@@ -0,0 +1,330 @@
+use {
+    crate::{
+        auth::{AuthenticatedUser, UploadUser},
+        collections,
+        error::{AppError, AppResult},
+        models::{Collection, CollectionKind, Identity, MediaKind, VideoMeta},
+        roles::Permission,
+        state::AppState,
+    },
+    chrono::{DateTime, Utc},
+    rocket::{State, delete, get, http::Status, patch, post, serde::json::Json},
+    serde::{Deserialize, Serialize},
+};
+
+#[derive(Debug, Serialize)]
+pub struct CollectionView {
+    pub id: String,
+    pub kind: CollectionKind,
+    pub title: String,
+    pub description: String,
+    pub owner_user: String,
+    /// The owner's first login, for linking to their profile.
+    pub owner_provider: String,
+    pub owner_id: u64,
+    pub owner_name: String,
+    /// Number of items the viewer can see.
+    pub item_count: usize,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// In play order; only included when a single collection is fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<VideoMeta>>,
+}
+
+#[derive(Deserialize)]
+pub struct NewCollection {
+    #[serde(default)]
+    pub kind: CollectionKind,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionPatch {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// The new item order. Must list exactly the ids already in the
+    /// collection.
+    pub items: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct NewItem {
+    pub media_id: String,
+    /// Zero-based; appended when omitted. An item already in the collection
+    /// is moved here.
+    #[serde(default)]
+    pub position: Option<usize>,
+}
+
+/// Items of `collection` that `viewer` may see, in order. Unlisted uploads
+/// only show up for the collection owner, the uploader and staff who can
+/// edit them, so adding one to a collection does not publish it.
+pub fn visible_items(
+    state: &AppState,
+    collection: &Collection,
+    viewer: Option<&AuthenticatedUser>,
+) -> Vec<VideoMeta> {
+    let viewer_id = viewer.map(|v| v.0.user_id.as_str());
+    let staff = viewer.is_some_and(|v| v.can(state, Permission::EditAnyMedia));
+    collection
+        .items
+        .iter()
+        .filter_map(|id| state.videos.get(id).map(|v| v.clone()))
+        .filter(|v| {
+            !v.unlisted
+                || staff
+                || viewer_id.is_some_and(|u| u == collection.owner_user || u == v.uploaded_by_user)
+        })
+        .map(|v| state.with_current_names(v))
+        .collect()
+}
+
+/// The owner's first login, which names them and links to their profile.
+pub fn owner_identity(state: &AppState, collection: &Collection) -> Option<Identity> {
+    state
+        .users
+        .get(&collection.owner_user)
+        .and_then(|u| u.identities.into_iter().next())
+}
+
+pub fn view_of(
+    state: &AppState,
+    collection: Collection,
+    viewer: Option<&AuthenticatedUser>,
+    with_items: bool,
+) -> CollectionView {
+    let items = visible_items(state, &collection, viewer);
+    let identity = owner_identity(state, &collection);
+    let identity = identity.as_ref();
+    CollectionView {
+        owner_provider: identity.map(|i| i.provider.clone()).unwrap_or_default(),
+        owner_id: identity.map_or(0, |i| i.external_id),
+        owner_name: identity.map(|i| i.username.clone()).unwrap_or_default(),
+        item_count: items.len(),
+        items: with_items.then_some(items),
+        id: collection.id,
+        kind: collection.kind,
+        title: collection.title,
+        description: collection.description,
+        owner_user: collection.owner_user,
+        created_at: collection.created_at,
+        updated_at: collection.updated_at,
+    }
+}
+
+/// Fails unless `user` owns the collection or may edit anyone's media.
+fn editable(state: &AppState, id: &str, user: &AuthenticatedUser) -> AppResult<()> {
+    let collection = state
+        .collections
+        .get(id)
+        .ok_or(AppError::CollectionNotFound)?;
+    if collection.owner_user != user.0.user_id && !user.can(state, Permission::EditAnyMedia) {
+        return Err(AppError::Forbidden);
+    }
+    Ok(())
+}
+
+/// Collections, most recently updated first. `owner` is an internal user
+/// id; `media` limits the list to collections containing that upload.
+#[get("/collections?<owner>&<media>&<kind>")]
+pub fn list_collections(
+    owner: Option<&str>,
+    media: Option<&str>,
+    kind: Option<CollectionKind>,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Json<Vec<CollectionView>> {
+    let found = match media {
+        Some(media_id) => state.collections.containing(media_id),
+        None => state.collections.list(None),
+    };
+    Json(
+        found
+            .into_iter()
+            .filter(|c| owner.is_none_or(|o| c.owner_user == o))
+            .filter(|c| kind.is_none_or(|k| c.kind == k))
+            .map(|c| view_of(state, c, viewer.as_ref(), false))
+            .collect(),
+    )
+}
+
+#[get("/collections/<id>")]
+pub fn get_collection(
+    id: &str,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<CollectionView>> {
+    let collection = state
+        .collections
+        .get(id)
+        .ok_or(AppError::CollectionNotFound)?;
+    Ok(Json(view_of(state, collection, viewer.as_ref(), true)))
+}
+
+#[post("/collections", format = "json", data = "<body>")]
+pub fn create_collection(
+    body: Json<NewCollection>,
+    user: UploadUser,
+    state: &State<AppState>,
+) -> AppResult<(Status, Json<CollectionView>)> {
+    let collection = state
+        .collections
+        .create(&user.0.0.user_id, body.kind, &body.title, &body.description)?;
+    Ok((
+        Status::Created,
+        Json(view_of(state, collection, Some(&user.0), true)),
+    ))
+}
+
+#[post("/collections", format = "json", data = "<_body>", rank = 2)]
+pub fn create_collection_unauthorized(
+    _body: Json<NewCollection>,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[patch("/collections/<id>", format = "json", data = "<body>")]
+pub fn patch_collection(
+    id: &str,
+    body: Json<CollectionPatch>,
+    user: UploadUser,
+    state: &State<AppState>,
+) -> AppResult<Json<CollectionView>> {
+    editable(state, id, &user.0)?;
+    let title = body
+        .title
+        .as_deref()
+        .map(collections::validate_title)
+        .transpose()?;
+    let description = body
+        .description
+        .as_deref()
+        .map(collections::validate_description)
+        .transpose()?;
+    let collection = state.collections.update(id, |c| {
+        if let Some(items) = &body.items {
+            let mut old = c.items.clone();
+            let mut new = items.clone();
+            old.sort_unstable();
+            new.sort_unstable();
+            if old != new {
+                return Err(AppError::InvalidCollectionOrder);
+            }
+            c.items = items.clone();
+        }
+        if let Some(title) = title {
+            c.title = title;
+        }
+        if let Some(description) = description {
+            c.description = description;
+        }
+        Ok(())
+    })?;
+    Ok(Json(view_of(state, collection, Some(&user.0), true)))
+}
+
+#[patch("/collections/<_id>", format = "json", data = "<_body>", rank = 2)]
+pub fn patch_collection_unauthorized(
+    _id: &str,
+    _body: Json<CollectionPatch>,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[delete("/collections/<id>")]
+pub fn delete_collection(
+    id: &str,
+    user: UploadUser,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    editable(state, id, &user.0)?;
+    state.collections.delete(id)?;
+    Ok(Json(serde_json::json!({
+        "message": "Collection deleted",
+        "id": id,
+    })))
+}
+
+#[delete("/collections/<_id>", rank = 2)]
+pub fn delete_collection_unauthorized(_id: &str) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[post("/collections/<id>/items", format = "json", data = "<body>")]
+pub fn add_item(
+    id: &str,
+    body: Json<NewItem>,
+    user: UploadUser,
+    state: &State<AppState>,
+) -> AppResult<Json<CollectionView>> {
+    editable(state, id, &user.0)?;
+    let media_kind = state
+        .videos
+        .get(&body.media_id)
+        .map(|v| MediaKind::from_mime(&v.content_type))
+        .ok_or(AppError::VideoNotFound)?;
+    let collection = state.collections.update(id, |c| {
+        if c.kind == CollectionKind::Playlist
+            && !matches!(media_kind, Some(MediaKind::Video | MediaKind::Audio))
+        {
+            return Err(AppError::NotPlayable);
+        }
+        c.items.retain(|i| *i != body.media_id);
+        let at = body.position.unwrap_or(c.items.len()).min(c.items.len());
+        c.items.insert(at, body.media_id.clone());
+        Ok(())
+    })?;
+    Ok(Json(view_of(state, collection, Some(&user.0), true)))
+}
+
+#[post(
+    "/collections/<_id>/items",
+    format = "json",
+    data = "<_body>",
+    rank = 2
+)]
+pub fn add_item_unauthorized(_id: &str, _body: Json<NewItem>) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[delete("/collections/<id>/items/<media_id>")]
+pub fn remove_item(
+    id: &str,
+    media_id: &str,
+    user: UploadUser,
+    state: &State<AppState>,
+) -> AppResult<Json<CollectionView>> {
+    editable(state, id, &user.0)?;
+    let collection = state.collections.update(id, |c| {
+        let before = c.items.len();
+        c.items.retain(|i| i != media_id);
+        if c.items.len() == before {
+            return Err(AppError::VideoNotFound);
+        }
+        Ok(())
+    })?;
+    Ok(Json(view_of(state, collection, Some(&user.0), true)))
+}
+
+#[delete("/collections/<_id>/items/<_media_id>", rank = 2)]
+pub fn remove_item_unauthorized(_id: &str, _media_id: &str) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
@@ -0,0 +1,85 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::{PlatformUser, Role},
+        ratelimit::LoginLimit,
+        routes::auth::sign_in,
+        sessions::ClientInfo,
+        state::AppState,
+    },
+    rocket::{State, http::CookieJar, response::Redirect, serde::json::Json},
+    serde::Deserialize,
+};
+
+/// Provider recorded on identities created through dev login, unless the
+/// developer picks another one.
+pub const DEV_PROVIDER: &str = "dev";
+
+/// Login links point at `/auth/<provider>/login`; for dev login that is the
+/// form on the login page.
+#[rocket::get("/auth/dev/login")]
+pub fn login_page() -> Redirect {
+    Redirect::to("/ui/login")
+}
+
+#[derive(Deserialize)]
+pub struct DevLoginRequest {
+    #[serde(default)]
+    pub provider: Option<String>,
+    pub id: u64,
+    pub username: String,
+    /// Stored on the account like an admin role edit; omitted leaves the
+    /// account's role alone.
+    #[serde(default)]
+    pub role: Option<Role>,
+}
+
+/// Signs in as any identity with any role, without a password. Only works
+/// when `DEV_LOGIN` is set.
+#[rocket::post("/auth/dev/login", data = "<body>")]
+pub fn login(
+    _limit: LoginLimit,
+    body: Json<DevLoginRequest>,
+    state: &State<AppState>,
+    cookies: &CookieJar<'_>,
+    client_info: ClientInfo,
+) -> AppResult<Json<serde_json::Value>> {
+    if !state.dev_login {
+        return Err(AppError::DevLoginDisabled);
+    }
+    let provider = body
+        .provider
+        .as_deref()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .unwrap_or(DEV_PROVIDER);
+    let username = body.username.trim();
+    if username.is_empty() {
+        return Err(AppError::InvalidUsername);
+    }
+
+    let user = sign_in(
+        cookies,
+        PlatformUser {
+            provider: provider.to_owned(),
+            id: body.id,
+            username: username.to_owned(),
+            avatar_url: String::new(),
+            user_id: String::new(),
+        },
+        state,
+        client_info,
+    )?;
+    if let Some(role) = body.role {
+        state.users.set_role(&user.user_id, Some(role))?;
+    }
+    tracing::warn!(provider, id = body.id, username, "dev login");
+
+    Ok(Json(serde_json::json!({
+        "provider": user.provider,
+        "id": user.id,
+        "username": user.username,
+        "user_id": user.user_id,
+        "role": state.role_of(&user),
+    })))
+}
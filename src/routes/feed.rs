@@ -1,5 +1,11 @@
 use {
-    crate::{routes::ui::SiteInfo, state::AppState},
+    crate::{
+        error::{AppError, AppResult},
+        models::VideoMeta,
+        routes::{collections, profiles, ui::SiteInfo},
+        state::AppState,
+        tags,
+    },
     rocket::{State, get, http::ContentType},
 };
 
@@ -60,10 +66,30 @@ fn build_feed(
                         .unwrap_or(true)
             }
         })
-        .map(|e| e.value().clone())
+        .map(|e| state.with_current_names(e.value().clone()))
         .collect();
 
     items.sort_unstable_by_key(|v| std::cmp::Reverse(v.uploaded_at));
+
+    let slug = match filter {
+        Some("video/") => "videos",
+        Some("audio/") => "audio",
+        Some("image/") => "images",
+        Some("text/") => "text",
+        _ => "all",
+    };
+    let self_url = format!("{}/ui/{}.rss", site.base_url, slug);
+    let description = format!("Latest uploads on {}", site.site_host);
+    render_feed(site, title, &description, &self_url, items)
+}
+
+fn render_feed(
+    site: &SiteInfo,
+    title: &str,
+    description: &str,
+    self_url: &str,
+    mut items: Vec<VideoMeta>,
+) -> (ContentType, String) {
     items.truncate(50);
 
     let mut xml = format!(
@@ -77,18 +103,12 @@ fn build_feed(
 <link>{base_url}/ui</link>
 <description>{description}</description>
 <language>en-us</language>
-<atom:link href="{base_url}/ui/{slug}.rss" rel="self" type="application/rss+xml"/>
+<atom:link href="{self_url}" rel="self" type="application/rss+xml"/>
 "#,
         title = xml_escape(title),
         base_url = xml_escape(&site.base_url),
-        description = xml_escape(&format!("Latest uploads on {}", site.site_host)),
-        slug = match filter {
-            Some("video/") => "videos",
-            Some("audio/") => "audio",
-            Some("image/") => "images",
-            Some("text/") => "text",
-            _ => "all",
-        },
+        description = xml_escape(description),
+        self_url = xml_escape(self_url),
     );
 
     for v in &items {
@@ -193,6 +213,12 @@ fn build_feed(
             String::new()
         };
 
+        let categories: String = v
+            .tags
+            .iter()
+            .map(|t| format!("<category>{}</category>\n", xml_escape(t)))
+            .collect();
+
         xml.push_str(&format!(
             r#"<item>
 <title>{title}</title>
@@ -200,13 +226,14 @@ fn build_feed(
 <guid isPermaLink="true">{link}</guid>
 <pubDate>{pub_date}</pubDate>
 <dc:creator>{creator}</dc:creator>
-<description><![CDATA[{desc}]]></description>
+{categories}<description><![CDATA[{desc}]]></description>
 {enclosure}{media_content}</item>
 "#,
             title = xml_escape(&v.title),
             link = xml_escape(&item_url),
             pub_date = pub_date,
             creator = xml_escape(&v.uploaded_by_name),
+            categories = categories,
             desc = desc,
             enclosure = enclosure_xml,
             media_content = media_content_xml,
@@ -282,3 +309,96 @@ pub fn feed_text(
         show_nsfw,
     )
 }
+
+/// Recent uploads by the account owning `<provider>/<id>`.
+#[get("/feed/u/<provider>/<id>?<show_nsfw>")]
+pub fn feed_user(
+    provider: &str,
+    id: u64,
+    state: &State<AppState>,
+    site: SiteInfo,
+    show_nsfw: bool,
+) -> AppResult<(ContentType, String)> {
+    let user = profiles::lookup(state, provider, id)?;
+    let name = user
+        .identities
+        .iter()
+        .find(|i| i.provider == provider && i.external_id == id)
+        .map(|i| i.username.clone())
+        .unwrap_or_default();
+    let items = profiles::uploads_by(state, &user.id, None)
+        .into_iter()
+        .filter(|v| show_nsfw || !v.nsfw)
+        .collect();
+    let self_url = format!("{}/feed/u/{}/{}", site.base_url, provider, id);
+    Ok(render_feed(
+        &site,
+        &format!("Uploads by {}", name),
+        &format!("Latest uploads by {} on {}", name, site.site_host),
+        &self_url,
+        items,
+    ))
+}
+
+/// Recent listed uploads tagged `<tag>` or one of its aliases.
+#[get("/feed/tags/<tag>?<show_nsfw>")]
+pub fn feed_tag(
+    tag: &str,
+    state: &State<AppState>,
+    site: SiteInfo,
+    show_nsfw: bool,
+) -> AppResult<(ContentType, String)> {
+    let tag = tags::normalize(tag)
+        .map(|t| state.tags.canonical(&t))
+        .ok_or_else(|| AppError::InvalidTag(tag.to_owned()))?;
+    let mut items: Vec<VideoMeta> = state
+        .videos
+        .iter()
+        .filter(|e| {
+            let v = e.value();
+            !v.unlisted && (show_nsfw || !v.nsfw) && v.tags.contains(&tag)
+        })
+        .map(|e| state.with_current_names(e.value().clone()))
+        .collect();
+    items.sort_unstable_by_key(|v| std::cmp::Reverse(v.uploaded_at));
+    let self_url = format!("{}/feed/tags/{}", site.base_url, tag);
+    Ok(render_feed(
+        &site,
+        &format!("Uploads tagged {}", tag),
+        &format!("Latest uploads tagged {} on {}", tag, site.site_host),
+        &self_url,
+        items,
+    ))
+}
+
+/// The uploads in a collection, last first, since new ones are appended.
+#[get("/feed/c/<id>?<show_nsfw>")]
+pub fn feed_collection(
+    id: &str,
+    state: &State<AppState>,
+    site: SiteInfo,
+    show_nsfw: bool,
+) -> AppResult<(ContentType, String)> {
+    let collection = state
+        .collections
+        .get(id)
+        .ok_or(AppError::CollectionNotFound)?;
+    let items = collections::visible_items(state, &collection, None)
+        .into_iter()
+        .rev()
+        .filter(|v| show_nsfw || !v.nsfw)
+        .collect();
+    let self_url = format!("{}/feed/c/{}", site.base_url, id);
+    let description = if collection.description.is_empty() {
+        format!("A collection on {}", site.site_host)
+    } else {
+        collection.description.clone()
+    };
+    Ok(render_feed(
+        &site,
+        &collection.title,
+        &description,
+        &self_url,
+        items,
+    ))
+}
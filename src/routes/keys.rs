@@ -0,0 +1,42 @@
+use {
+    crate::{
+        auth::{AdminUser, AuthenticatedUser},
+        error::{AppError, AppResult},
+        keys::KeyInfo,
+        state::AppState,
+    },
+    rocket::{State, http::Status, serde::json::Json},
+};
+
+#[rocket::get("/admin/jwt-keys")]
+pub fn list_keys(_admin: AdminUser, state: &State<AppState>) -> Json<Vec<KeyInfo>> {
+    Json(state.jwt_keys.info())
+}
+
+#[rocket::get("/admin/jwt-keys", rank = 2)]
+pub fn list_keys_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+/// Starts signing with a fresh key. Existing sessions stay valid until the
+/// old key's grace period runs out.
+#[rocket::post("/admin/jwt-keys/rotate")]
+pub fn rotate_key(admin: AdminUser, state: &State<AppState>) -> AppResult<Json<serde_json::Value>> {
+    let kid = state
+        .jwt_keys
+        .rotate()
+        .map_err(|e| AppError::Internal(format!("{:#}", e)))?;
+    tracing::info!(kid, by = %admin.0.username, "rotated JWT signing key");
+    Ok(Json(serde_json::json!({ "kid": kid })))
+}
+
+#[rocket::post("/admin/jwt-keys/rotate", rank = 2)]
+pub fn rotate_key_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
@@ -0,0 +1,237 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::VideoMeta,
+        search::Snippet,
+        state::AppState,
+    },
+    hex::ToHex,
+    rocket::{FromForm, FromFormField},
+    serde::{Deserialize, Serialize},
+    std::{cmp::Ordering, collections::HashSet},
+};
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum SortField {
+    /// Full-text relevance; only meaningful with free text in the query.
+    Relevance,
+    Date,
+    Size,
+    Title,
+    Duration,
+    Comments,
+}
+
+impl SortField {
+    fn default_order(self) -> SortOrder {
+        match self {
+            SortField::Title => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Query parameters shared by the media list endpoints.
+#[derive(Debug, Default, FromForm)]
+pub struct ListParams {
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+    pub limit: Option<usize>,
+    /// `next_cursor` from the previous page.
+    pub cursor: Option<String>,
+    /// Comma-separated [`VideoMeta`] fields to return. `id` is always
+    /// included.
+    pub fields: Option<String>,
+}
+
+impl ListParams {
+    /// Whether the client asked for a page. Without `limit` or `cursor` the
+    /// list endpoints answer with every match as a bare array, as they did
+    /// before pagination existed.
+    pub fn is_paged(&self) -> bool {
+        self.limit.is_some() || self.cursor.is_some()
+    }
+
+    /// The sort to use, falling back to relevance when there is free text to
+    /// rank by and upload date otherwise.
+    pub fn sort(&self, has_free_text: bool) -> SortField {
+        match self.sort {
+            Some(SortField::Relevance) | None if has_free_text => SortField::Relevance,
+            Some(SortField::Relevance) | None => SortField::Date,
+            Some(field) => field,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SortKey {
+    Int(i64),
+    Text(String),
+}
+
+impl SortKey {
+    /// `score` is the full-text relevance in thousandths, used for
+    /// [`SortField::Relevance`].
+    pub fn of(field: SortField, meta: &VideoMeta, state: &AppState, score: i64) -> Self {
+        match field {
+            SortField::Relevance => SortKey::Int(score),
+            SortField::Date => SortKey::Int(meta.uploaded_at.timestamp_micros()),
+            SortField::Size => SortKey::Int(meta.size_bytes as i64),
+            SortField::Title => SortKey::Text(meta.title.to_lowercase()),
+            SortField::Duration => SortKey::Int(
+                meta.duration_secs
+                    .map(|d| (d * 1000.0) as i64)
+                    .unwrap_or(-1),
+            ),
+            SortField::Comments => {
+                SortKey::Int(state.comments.get(&meta.id).map_or(0, |c| c.len() as i64))
+            }
+        }
+    }
+}
+
+/// A matching upload, remembered by id so only the returned page is cloned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hit {
+    pub key: SortKey,
+    pub id: String,
+}
+
+impl Hit {
+    fn cmp_in(&self, other: &Hit, order: SortOrder) -> Ordering {
+        let ord = self
+            .key
+            .cmp(&other.key)
+            .then_with(|| self.id.cmp(&other.id));
+        match order {
+            SortOrder::Asc => ord,
+            SortOrder::Desc => ord.reverse(),
+        }
+    }
+
+    fn encode(&self) -> String {
+        serde_json::to_vec(self)
+            .expect("sort keys serialize")
+            .encode_hex()
+    }
+
+    fn decode(cursor: &str) -> AppResult<Self> {
+        hex::decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(AppError::InvalidCursor)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListPage {
+    /// Uploads, each with a `snippet` of the text that matched when the
+    /// query has free text.
+    pub items: Vec<serde_json::Value>,
+    /// Number of matches across all pages.
+    pub total: usize,
+    /// Pass as `cursor` to fetch the next page; absent on the last one.
+    pub next_cursor: Option<String>,
+}
+
+/// What the media list endpoints return: a [`ListPage`] when the client
+/// paginates, otherwise every item in a bare array.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ListResponse {
+    Page(ListPage),
+    All(Vec<serde_json::Value>),
+}
+
+/// [`paginate`] for the list endpoints, honouring [`ListParams::is_paged`].
+pub fn respond(
+    state: &AppState,
+    hits: Vec<Hit>,
+    sort: SortField,
+    params: &ListParams,
+    snippet: impl Fn(&str) -> Option<Snippet>,
+) -> AppResult<ListResponse> {
+    if params.is_paged() {
+        return paginate(state, hits, sort, params, snippet).map(ListResponse::Page);
+    }
+    let limit = hits.len();
+    page_of(state, hits, sort, params, limit, snippet).map(|page| ListResponse::All(page.items))
+}
+
+/// Sorts `hits` and returns the page after `params.cursor`, with the
+/// requested fields of each upload.
+pub fn paginate(
+    state: &AppState,
+    hits: Vec<Hit>,
+    sort: SortField,
+    params: &ListParams,
+    snippet: impl Fn(&str) -> Option<Snippet>,
+) -> AppResult<ListPage> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    page_of(state, hits, sort, params, limit, snippet)
+}
+
+fn page_of(
+    state: &AppState,
+    mut hits: Vec<Hit>,
+    sort: SortField,
+    params: &ListParams,
+    limit: usize,
+    snippet: impl Fn(&str) -> Option<Snippet>,
+) -> AppResult<ListPage> {
+    let order = params.order.unwrap_or(sort.default_order());
+    hits.sort_unstable_by(|a, b| a.cmp_in(b, order));
+
+    let start = match params.cursor.as_deref().filter(|c| !c.is_empty()) {
+        Some(cursor) => {
+            let after = Hit::decode(cursor)?;
+            hits.partition_point(|h| h.cmp_in(&after, order) != Ordering::Greater)
+        }
+        None => 0,
+    };
+    let end = (start + limit).min(hits.len());
+    let page = &hits[start.min(end)..end];
+    let next_cursor = (end < hits.len()).then(|| page[page.len() - 1].encode());
+
+    let fields: Option<HashSet<&str>> = params.fields.as_deref().map(|f| {
+        f.split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .chain(["id"])
+            .collect()
+    });
+    let items = page
+        .iter()
+        .filter_map(|hit| state.videos.get(&hit.id).map(|v| v.clone()))
+        .map(|meta| {
+            let snippet = snippet(&meta.id);
+            let mut value =
+                serde_json::to_value(state.with_current_names(meta)).unwrap_or_default();
+            if let Some(map) = value.as_object_mut() {
+                if let Some(snippet) = snippet {
+                    map.insert("snippet".into(), serde_json::json!(snippet));
+                }
+                if let Some(fields) = &fields {
+                    map.retain(|k, _| fields.contains(k.as_str()));
+                }
+            }
+            value
+        })
+        .collect();
+
+    Ok(ListPage {
+        items,
+        total: hits.len(),
+        next_cursor,
+    })
+}
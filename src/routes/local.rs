@@ -0,0 +1,431 @@
+use {
+    crate::{
+        auth::{AdminUser, AuthenticatedUser},
+        error::{AppError, AppResult},
+        local::{
+            LOCAL_PROVIDER, LocalAccountStore, RegistrationMode, hash_password, platform_user,
+            validate_password, validate_username, verify_dummy_password, verify_password,
+        },
+        oauth::urlencoded,
+        ratelimit::LoginLimit,
+        routes::{auth::sign_in, ui::SiteInfo},
+        sessions::ClientInfo,
+        state::AppState,
+    },
+    rocket::{
+        State,
+        http::{CookieJar, Status},
+        response::Redirect,
+        serde::json::Json,
+    },
+    serde::Deserialize,
+};
+
+fn local_store(state: &AppState) -> AppResult<&LocalAccountStore> {
+    state
+        .local_accounts
+        .as_ref()
+        .ok_or(AppError::LocalAccountsDisabled)
+}
+
+/// The local account behind a browser session.
+fn local_account_id(user: &AuthenticatedUser) -> AppResult<u64> {
+    if !user.is_session() {
+        return Err(AppError::SessionRequired);
+    }
+    if user.0.provider != LOCAL_PROVIDER {
+        return Err(AppError::NotLocalAccount);
+    }
+    Ok(user.0.id)
+}
+
+/// Argon2 takes tens of milliseconds, so keep it off the async workers.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> AppResult<T> {
+    rocket::tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| AppError::Internal(format!("password task failed: {}", e)))
+}
+
+async fn hash_new_password(password: &str) -> AppResult<String> {
+    validate_password(password)?;
+    let password = password.to_owned();
+    blocking(move || hash_password(&password)).await?
+}
+
+/// Login links point at `/auth/<provider>/login`; for local accounts that is
+/// the form on the login page.
+#[rocket::get("/auth/local/login")]
+pub fn login_page() -> Redirect {
+    Redirect::to("/ui/login")
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub invite: Option<String>,
+}
+
+#[rocket::post("/auth/local/register", data = "<body>")]
+pub async fn register(
+    _limit: LoginLimit,
+    body: Json<RegisterRequest>,
+    state: &State<AppState>,
+    cookies: &CookieJar<'_>,
+    client_info: ClientInfo,
+) -> AppResult<(Status, Json<serde_json::Value>)> {
+    let local = local_store(state)?;
+    let username = body.username.trim();
+    validate_username(username)?;
+    if local.registration() == RegistrationMode::Closed {
+        return Err(AppError::RegistrationClosed);
+    }
+    let password_hash = hash_new_password(&body.password).await?;
+    let account = local.register(username, password_hash, body.invite.as_deref())?;
+    tracing::info!(id = account.id, username = %account.username, "local account registered");
+
+    let user = sign_in(cookies, platform_user(&account), state, client_info)?;
+    Ok((
+        Status::Created,
+        Json(serde_json::json!({
+            "provider": user.provider,
+            "id": user.id,
+            "username": user.username,
+        })),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub totp: Option<String>,
+}
+
+#[rocket::post("/auth/local/login", data = "<body>")]
+pub async fn login(
+    _limit: LoginLimit,
+    body: Json<LoginRequest>,
+    state: &State<AppState>,
+    cookies: &CookieJar<'_>,
+    client_info: ClientInfo,
+) -> AppResult<Json<serde_json::Value>> {
+    let local = local_store(state)?;
+    let account = local.find_by_username(body.username.trim());
+    let password = body.password.clone();
+    let valid = match &account {
+        Some(a) => {
+            let hash = a.password_hash.clone();
+            blocking(move || verify_password(&hash, &password)).await?
+        }
+        None => {
+            blocking(move || {
+                verify_dummy_password(&password);
+                false
+            })
+            .await?
+        }
+    };
+    let account = account
+        .filter(|_| valid)
+        .ok_or(AppError::InvalidCredentials)?;
+
+    if account.totp_secret.is_some() {
+        let code = body
+            .totp
+            .as_deref()
+            .filter(|c| !c.trim().is_empty())
+            .ok_or(AppError::TotpRequired)?;
+        if !local.check_totp(account.id, code) {
+            return Err(AppError::InvalidTotpCode);
+        }
+    }
+
+    let user = sign_in(cookies, platform_user(&account), state, client_info)?;
+    Ok(Json(serde_json::json!({
+        "provider": user.provider,
+        "id": user.id,
+        "username": user.username,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[rocket::post("/auth/local/password", data = "<body>")]
+pub async fn change_password(
+    body: Json<PasswordChange>,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<Status> {
+    let local = local_store(state)?;
+    let id = local_account_id(&user)?;
+    let account = local.get(id).ok_or(AppError::UserNotFound)?;
+
+    let current = body.current_password.clone();
+    if !blocking(move || verify_password(&account.password_hash, &current)).await? {
+        return Err(AppError::InvalidCredentials);
+    }
+    local.set_password(id, hash_new_password(&body.new_password).await?)?;
+    Ok(Status::NoContent)
+}
+
+#[rocket::post("/auth/local/password", rank = 2)]
+pub fn change_password_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+/// Replacing an existing second factor needs the password, so a stolen
+/// session cannot swap in an authenticator its holder controls.
+async fn check_password_if_totp(
+    local: &LocalAccountStore,
+    id: u64,
+    password: Option<&str>,
+) -> AppResult<()> {
+    let account = local.get(id).ok_or(AppError::UserNotFound)?;
+    if account.totp_secret.is_none() {
+        return Ok(());
+    }
+    let password = password.unwrap_or_default().to_owned();
+    if !blocking(move || verify_password(&account.password_hash, &password)).await? {
+        return Err(AppError::InvalidCredentials);
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct TotpSetup {
+    /// Required when two-factor login is already on.
+    pub current_password: Option<String>,
+}
+
+/// Hands out a new TOTP secret. It only takes effect once confirmed with a
+/// code through `/auth/local/totp/enable`.
+#[rocket::post("/auth/local/totp/setup", data = "<body>")]
+pub async fn totp_setup(
+    body: Option<Json<TotpSetup>>,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> AppResult<Json<serde_json::Value>> {
+    let local = local_store(state)?;
+    let id = local_account_id(&user)?;
+    let password = body.as_ref().and_then(|b| b.current_password.as_deref());
+    check_password_if_totp(local, id, password).await?;
+    let secret = local.begin_totp(id)?;
+    let label = format!("{}:{}", site.site_host, user.0.username);
+    let otpauth_url = format!(
+        "otpauth://totp/{}?secret={}&issuer={}",
+        urlencoded(&label),
+        secret,
+        urlencoded(&site.site_host)
+    );
+    Ok(Json(serde_json::json!({
+        "secret": secret,
+        "otpauth_url": otpauth_url,
+    })))
+}
+
+#[rocket::post("/auth/local/totp/setup", rank = 2)]
+pub fn totp_setup_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct TotpConfirm {
+    pub code: String,
+    /// Required when two-factor login is already on.
+    pub current_password: Option<String>,
+}
+
+#[rocket::post("/auth/local/totp/enable", data = "<body>")]
+pub async fn totp_enable(
+    body: Json<TotpConfirm>,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<Status> {
+    let local = local_store(state)?;
+    let id = local_account_id(&user)?;
+    check_password_if_totp(local, id, body.current_password.as_deref()).await?;
+    local.enable_totp(id, &body.code)?;
+    Ok(Status::NoContent)
+}
+
+#[rocket::post("/auth/local/totp/enable", rank = 2)]
+pub fn totp_enable_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct TotpDisable {
+    pub password: String,
+}
+
+#[rocket::post("/auth/local/totp/disable", data = "<body>")]
+pub async fn totp_disable(
+    body: Json<TotpDisable>,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<Status> {
+    let local = local_store(state)?;
+    let id = local_account_id(&user)?;
+    let account = local.get(id).ok_or(AppError::UserNotFound)?;
+
+    let password = body.password.clone();
+    if !blocking(move || verify_password(&account.password_hash, &password)).await? {
+        return Err(AppError::InvalidCredentials);
+    }
+    local.disable_totp(id)?;
+    Ok(Status::NoContent)
+}
+
+#[rocket::post("/auth/local/totp/disable", rank = 2)]
+pub fn totp_disable_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    pub token: String,
+    pub password: String,
+}
+
+/// Sets a new password from an admin-issued reset link and signs out every
+/// session of that account.
+#[rocket::post("/auth/local/reset", data = "<body>")]
+pub async fn reset_password(
+    _limit: LoginLimit,
+    body: Json<PasswordResetRequest>,
+    state: &State<AppState>,
+) -> AppResult<Status> {
+    let local = local_store(state)?;
+    if local.reset_account(&body.token).is_none() {
+        return Err(AppError::InvalidResetLink);
+    }
+    let password_hash = hash_new_password(&body.password).await?;
+    let id = local.complete_reset(&body.token, password_hash)?;
+    state.sessions.revoke_all_for(LOCAL_PROVIDER, id);
+    Ok(Status::NoContent)
+}
+
+#[derive(Deserialize)]
+pub struct RegistrationUpdate {
+    pub mode: RegistrationMode,
+}
+
+#[rocket::get("/admin/local/registration")]
+pub fn get_registration(
+    _admin: AdminUser,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    let local = local_store(state)?;
+    Ok(Json(serde_json::json!({
+        "mode": local.registration(),
+        "accounts": local.account_count(),
+        "invites": local.invite_count(),
+    })))
+}
+
+#[rocket::get("/admin/local/registration", rank = 2)]
+pub fn get_registration_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+#[rocket::put("/admin/local/registration", data = "<body>")]
+pub fn set_registration(
+    body: Json<RegistrationUpdate>,
+    _admin: AdminUser,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    let local = local_store(state)?;
+    local.set_registration(body.mode);
+    tracing::info!(mode = ?body.mode, "local registration mode changed");
+    Ok(Json(serde_json::json!({ "mode": body.mode })))
+}
+
+#[rocket::put("/admin/local/registration", rank = 2)]
+pub fn set_registration_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+#[rocket::post("/admin/local/invites")]
+pub fn create_invite(
+    admin: AdminUser,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> AppResult<(Status, Json<serde_json::Value>)> {
+    let local = local_store(state)?;
+    let (code, invite) = local.create_invite(&admin.0.user_id);
+    Ok((
+        Status::Created,
+        Json(serde_json::json!({
+            "invite": code,
+            "url": format!("{}/ui/register?invite={}", site.base_url, code),
+            "expires_at": invite.expires_at,
+        })),
+    ))
+}
+
+#[rocket::post("/admin/local/invites", rank = 2)]
+pub fn create_invite_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+#[rocket::post("/admin/local/accounts/<username>/reset-link")]
+pub fn create_reset_link(
+    username: &str,
+    _admin: AdminUser,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> AppResult<(Status, Json<serde_json::Value>)> {
+    let local = local_store(state)?;
+    let account = local
+        .find_by_username(username)
+        .ok_or(AppError::UserNotFound)?;
+    let (token, reset) = local.create_reset(account.id)?;
+    tracing::info!(id = account.id, "password reset link issued");
+    Ok((
+        Status::Created,
+        Json(serde_json::json!({
+            "url": format!("{}/ui/reset?token={}", site.base_url, token),
+            "expires_at": reset.expires_at,
+        })),
+    ))
+}
+
+#[rocket::post("/admin/local/accounts/<_username>/reset-link", rank = 2)]
+pub fn create_reset_link_forbidden(
+    _username: &str,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
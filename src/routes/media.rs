@@ -1,11 +1,21 @@
 use {
     crate::{
         auth::AuthenticatedUser,
+        comments::{self, CommentView, ReactionCount, ThreadPage, ThreadParams},
         error::{AppError, AppResult},
-        models::{Comment, VideoMeta},
+        markdown::{self, RenderOptions},
+        models::{
+            Comment, CommentAnchor, CommentRevision, MediaKind, ModAction, ReportOutcome,
+            SanctionKind, VideoMeta,
+        },
+        query::{self, Expr, Field, Filter, Op, Value},
+        roles::Permission,
+        routes::listing::{self, Hit, ListParams, ListResponse, SortField, SortKey},
+        search::TextQuery,
         state::AppState,
+        tags,
     },
-    fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2},
+    fuzzy_matcher::skim::SkimMatcherV2,
     hex::ToHex,
     rocket::{
         Data, State,
@@ -16,7 +26,7 @@ use {
     },
     serde::Deserialize,
     sha2::{Digest, Sha256},
-    std::{io::SeekFrom, path::Path, process::Stdio},
+    std::{collections::BTreeMap, io::SeekFrom, path::Path, process::Stdio},
     tlsh2::TlshDefaultBuilder,
     tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
     uuid::Uuid,
@@ -104,6 +114,14 @@ pub struct CommentBody {
     pub text: String,
     #[serde(default)]
     pub parent_id: Option<String>,
+    /// Ties the comment to a playback position or image region.
+    #[serde(default)]
+    pub anchor: Option<CommentAnchor>,
+}
+
+#[derive(Deserialize)]
+pub struct CommentEdit {
+    pub text: String,
 }
 
 #[derive(Deserialize)]
@@ -119,6 +137,8 @@ pub struct MetaPatch {
     pub nsfw: Option<bool>,
     pub unlisted: Option<bool>,
     pub comments_disabled: Option<bool>,
+    /// Replaces every tag. Aliases are resolved to their canonical tag.
+    pub tags: Option<Vec<String>>,
 }
 
 pub fn is_video_mime(mime: &str) -> bool {
@@ -379,6 +399,30 @@ async fn read_magic_bytes(path: &Path) -> std::io::Result<Vec<u8>> {
     Ok(buf)
 }
 
+/// Media length in seconds according to ffprobe, if it can tell.
+async fn probe_duration(path: &Path) -> Option<f64> {
+    let output = tokio::process::Command::new("ffprobe")
+        .args([
+            "-v",
+            "quiet",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "csv=p=0",
+        ])
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|d| d.is_finite() && *d >= 0.0)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn process_uploaded_file(
     temp_path: std::path::PathBuf,
@@ -447,30 +491,9 @@ pub async fn process_uploaded_file(
     if is_video_mime(base_mime_in) && base_mime != "video/mp4" {
         let converted_path = Path::new(&state.upload_dir).join(format!("{}.mp4", temp_id));
 
-        let duration_us: Option<u64> = {
-            let probe = tokio::process::Command::new("ffprobe")
-                .args([
-                    "-v",
-                    "quiet",
-                    "-show_entries",
-                    "format=duration",
-                    "-of",
-                    "csv=p=0",
-                    temp_path.to_str().unwrap(),
-                ])
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()
-                .await
-                .ok();
-            probe.and_then(|o| {
-                let s = String::from_utf8_lossy(&o.stdout);
-                s.trim()
-                    .parse::<f64>()
-                    .ok()
-                    .map(|d| (d * 1_000_000.0) as u64)
-            })
-        };
+        let duration_us: Option<u64> = probe_duration(&temp_path)
+            .await
+            .map(|d| (d * 1_000_000.0) as u64);
 
         if let Some(uid) = upload_id {
             state.conversion_progress.insert(uid.to_owned(), 0);
@@ -547,6 +570,12 @@ pub async fn process_uploaded_file(
         size_bytes = fs::metadata(&temp_path).await?.len();
     }
 
+    let duration_secs = if is_video_mime(&base_mime) || is_audio_mime(&base_mime) {
+        probe_duration(&temp_path).await
+    } else {
+        None
+    };
+
     let hash_path = temp_path.clone();
     let compute_tlsh = is_video_mime(&base_mime);
     let hash_result =
@@ -615,12 +644,16 @@ pub async fn process_uploaded_file(
             uploaded_by_provider: user.0.provider.clone(),
             uploaded_by_id: user.0.id,
             uploaded_by_name: user.0.username.clone(),
+            uploaded_by_user: user.0.user_id.clone(),
             uploaded_at: chrono::Utc::now(),
             nsfw: is_nsfw,
             unlisted: is_unlisted,
+            hidden_by_moderator: false,
             comments_disabled: is_comments_disabled,
             references_id: Some(original_id.clone()),
             original_extension: original_ext.clone(),
+            duration_secs,
+            tags: Vec::new(),
         };
 
         state.videos.insert(video_id.clone(), meta.clone());
@@ -657,12 +690,16 @@ pub async fn process_uploaded_file(
         uploaded_by_provider: user.0.provider.clone(),
         uploaded_by_id: user.0.id,
         uploaded_by_name: user.0.username.clone(),
+        uploaded_by_user: user.0.user_id.clone(),
         uploaded_at: chrono::Utc::now(),
         nsfw: is_nsfw,
         unlisted: is_unlisted,
+        hidden_by_moderator: false,
         comments_disabled: is_comments_disabled,
         references_id: None,
         original_extension: original_ext,
+        duration_secs,
+        tags: Vec::new(),
     };
 
     state.persist_video(&meta);
@@ -684,6 +721,13 @@ pub async fn process_uploaded_file(
     ))
 }
 
+pub fn upload_limit_for(user: &AuthenticatedUser, mime: &str, state: &AppState) -> u64 {
+    let bypass_quotas = user.can(state, Permission::BypassQuotas);
+    MediaKind::from_mime(mime)
+        .map(|kind| state.upload_limits.max_bytes(kind, bypass_quotas))
+        .unwrap_or(0)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn handle_upload(
     title: &str,
@@ -699,6 +743,9 @@ pub async fn handle_upload(
     allowed_types: &[&str],
     original_filename: Option<&str>,
 ) -> Result<(Status, Json<serde_json::Value>), AppError> {
+    state
+        .sanctions
+        .check(&user.0.user_id, SanctionKind::Upload)?;
     let title = title.trim();
     if title.is_empty() || title.len() > 200 {
         return Err(AppError::InvalidTitle);
@@ -722,11 +769,12 @@ pub async fn handle_upload(
     let temp_filename = format!("tmp_{}{}", temp_id, ext);
     let temp_path = Path::new(&state.upload_dir).join(&temp_filename);
 
-    let written = data.open(250.mebibytes()).into_file(&temp_path).await?;
+    let max_bytes = upload_limit_for(&user, base_mime, state);
+    let written = data.open(max_bytes.bytes()).into_file(&temp_path).await?;
 
     if !written.is_complete() {
         let _ = fs::remove_file(&temp_path).await;
-        return Err(AppError::FileTooLarge);
+        return Err(AppError::FileTooLarge(max_bytes));
     }
 
     process_uploaded_file(
@@ -752,6 +800,9 @@ pub async fn handle_init_upload(
     state: &State<AppState>,
     allowed_types: &[&str],
 ) -> Result<Json<serde_json::Value>, AppError> {
+    state
+        .sanctions
+        .check(&user.0.user_id, SanctionKind::Upload)?;
     let base_mime = content_type.split(';').next().unwrap_or("").trim();
     if !allowed_types.contains(&base_mime) {
         return Err(AppError::InvalidFileType);
@@ -808,10 +859,14 @@ pub async fn handle_upload_chunk(
     let chunk_dir = Path::new(&state.upload_dir).join(format!("tmp_chunks_{}", upload_id));
     let chunk_path = chunk_dir.join(format!("{}", chunk_index));
 
-    let written = data.open(6.mebibytes()).into_file(&chunk_path).await?;
+    let chunk_limit = state.upload_limits.chunk_bytes;
+    let written = data
+        .open(chunk_limit.bytes())
+        .into_file(&chunk_path)
+        .await?;
     if !written.is_complete() {
         let _ = fs::remove_file(&chunk_path).await;
-        return Err(AppError::FileTooLarge);
+        return Err(AppError::FileTooLarge(chunk_limit));
     }
 
     {
@@ -860,6 +915,7 @@ pub async fn handle_complete_upload(
         return Err(AppError::InvalidFileType);
     }
 
+    let max_bytes = upload_limit_for(&user, &session.content_type, state);
     let chunk_dir = Path::new(&state.upload_dir).join(format!("tmp_chunks_{}", upload_id));
     let temp_id = Uuid::new_v4().to_string();
     let ext = extension_for_mime(&session.content_type);
@@ -878,10 +934,10 @@ pub async fn handle_complete_upload(
                 .map_err(|_| AppError::Internal(format!("Missing chunk {}", i)))?;
 
             total_size += chunk_meta.len();
-            if total_size > 250 * 1024 * 1024 {
+            if total_size > max_bytes {
                 let _ = fs::remove_file(&temp_path).await;
                 let _ = fs::remove_dir_all(&chunk_dir).await;
-                return Err(AppError::FileTooLarge);
+                return Err(AppError::FileTooLarge(max_bytes));
             }
 
             let mut chunk_file = fs::File::open(&chunk_path)
@@ -924,303 +980,160 @@ pub fn get_conversion_progress(
     Json(serde_json::json!({ "progress": progress }))
 }
 
-enum FilterExpr {
-    TitleContains(String),
-    UploaderIs(String),
-    UploaderContains(String),
-    Sha2Is(String),
-    TlshIs(String),
-    TlshNear(String),
-    NsfwIs(bool),
-    SizeGt(u64),
-    SizeLt(u64),
-    DateAfter(String),
-    DateBefore(String),
-    MimeIs(String),
-    IdIs(String),
-    RepliesContains(String),
+fn text_matches(s: &str, op: Op, value: &Value) -> bool {
+    match value {
+        Value::Text(v) => match op {
+            Op::Contains => s.to_lowercase().contains(&v.to_lowercase()),
+            op => op.compare(s.to_lowercase().cmp(&v.to_lowercase())),
+        },
+        Value::Regex(p) => p.is_match(s),
+        _ => false,
+    }
 }
 
-fn parse_search_query(query: &str) -> (Vec<FilterExpr>, String) {
-    let mut filters = Vec::new();
-    let mut free_text = String::new();
-    let mut remaining = query;
-
-    while !remaining.is_empty() {
-        remaining = remaining.trim_start();
-        if remaining.is_empty() {
-            break;
-        }
-
-        if let Some(filter) = try_parse_filter(&mut remaining) {
-            filters.push(filter);
-        } else {
-            let end = remaining
-                .find(char::is_whitespace)
-                .unwrap_or(remaining.len());
-            if !free_text.is_empty() {
-                free_text.push(' ');
+fn filter_matches(meta: &VideoMeta, filter: &Filter, state: &AppState) -> bool {
+    let text = |s: &str| text_matches(s, filter.op, &filter.value);
+    match (filter.field, &filter.value) {
+        (Field::Title, _) => text(&meta.title),
+        (Field::Source, _) => {
+            let mut sources = [&meta.source_name, &meta.source_link, &meta.source]
+                .into_iter()
+                .flatten()
+                .filter(|s| !s.is_empty());
+            // `!=` holds when no source is equal, not when any one differs.
+            if filter.op == Op::Ne {
+                !sources.any(|s| text_matches(s, Op::Eq, &filter.value))
+            } else {
+                sources.any(|s| text(s))
             }
-            free_text.push_str(&remaining[..end]);
-            remaining = &remaining[end..];
         }
-    }
-
-    (filters, free_text)
-}
-
-type FilterFn = fn(&str) -> FilterExpr;
-
-fn try_parse_filter(input: &mut &str) -> Option<FilterExpr> {
-    let patterns: &[(&str, FilterFn)] = &[
-        ("title.contains(", |v| {
-            FilterExpr::TitleContains(v.to_owned())
-        }),
-        ("uploader.is(", |v| FilterExpr::UploaderIs(v.to_owned())),
-        ("uploader.contains(", |v| {
-            FilterExpr::UploaderContains(v.to_owned())
-        }),
-        ("sha2.is(", |v| FilterExpr::Sha2Is(v.to_owned())),
-        ("tlsh.is(", |v| FilterExpr::TlshIs(v.to_owned())),
-        ("tlsh.near(", |v| FilterExpr::TlshNear(v.to_owned())),
-        ("mime.is(", |v| FilterExpr::MimeIs(v.to_owned())),
-        ("id.is(", |v| FilterExpr::IdIs(v.to_owned())),
-        ("replies.contains(", |v| {
-            FilterExpr::RepliesContains(v.to_owned())
-        }),
-    ];
-
-    for (prefix, constructor) in patterns {
-        if let Some(rest) = input.strip_prefix(*prefix)
-            && let Some(val) = extract_quoted_or_paren(rest)
-        {
-            let consumed = prefix.len() + val.1;
-            *input = &input[consumed..];
-            return Some(constructor(&val.0));
+        (Field::Uploader, _) => text(&state.uploader_name(meta)),
+        (Field::Sha2, _) => text(&meta.sha256),
+        (Field::Mime, _) => text(&meta.content_type),
+        (Field::Id, _) => text(&meta.id),
+        (Field::Tlsh, Value::Text(hash)) if filter.op == Op::Near => {
+            use tlsh2::TlshDefault;
+            meta.tlsh_hash
+                .as_ref()
+                .and_then(|h| h.parse::<TlshDefault>().ok())
+                .zip(hash.parse::<TlshDefault>().ok())
+                .is_some_and(|(existing, target)| existing.diff(&target, true) < 100)
         }
+        (Field::Tlsh, _) => meta.tlsh_hash.as_deref().is_some_and(text),
+        // Hidden comments are redacted for most viewers, so searching them
+        // would reveal what they say.
+        (Field::Replies, _) => state
+            .comments
+            .get(&meta.id)
+            .is_some_and(|cs| cs.iter().any(|c| !c.hidden && text(&c.text))),
+        (Field::Tag, Value::Text(tag)) => {
+            let tag = tags::normalize(tag).map(|t| state.tags.canonical(&t));
+            let has = tag.is_some_and(|t| meta.tags.contains(&t));
+            if filter.op == Op::Ne { !has } else { has }
+        }
+        (Field::Kind, Value::Kind(kind)) => {
+            let is = MediaKind::from_mime(&meta.content_type) == Some(*kind);
+            if filter.op == Op::Ne { !is } else { is }
+        }
+        (Field::Nsfw, Value::Bool(b)) => filter.op.compare(meta.nsfw.cmp(b)),
+        (Field::Size, Value::Bytes(n)) => filter.op.compare(meta.size_bytes.cmp(n)),
+        (Field::Date, Value::Date(d)) => filter.op.compare(meta.uploaded_at.date_naive().cmp(d)),
+        _ => false,
     }
-
-    if let Some(rest) = input.strip_prefix("nsfw.is(")
-        && let Some((val, len)) = extract_quoted_or_paren(rest)
-    {
-        *input = &input[8 + len..];
-        return Some(FilterExpr::NsfwIs(val == "true"));
-    }
-
-    if let Some(rest) = input.strip_prefix("size.gt(")
-        && let Some((val, len)) = extract_quoted_or_paren(rest)
-        && let Ok(n) = val.parse::<u64>()
-    {
-        *input = &input[8 + len..];
-        return Some(FilterExpr::SizeGt(n));
-    }
-
-    if let Some(rest) = input.strip_prefix("size.lt(")
-        && let Some((val, len)) = extract_quoted_or_paren(rest)
-        && let Ok(n) = val.parse::<u64>()
-    {
-        *input = &input[8 + len..];
-        return Some(FilterExpr::SizeLt(n));
-    }
-
-    if let Some(rest) = input.strip_prefix("date.after(")
-        && let Some((val, len)) = extract_quoted_or_paren(rest)
-    {
-        *input = &input[11 + len..];
-        return Some(FilterExpr::DateAfter(val));
-    }
-
-    if let Some(rest) = input.strip_prefix("date.before(")
-        && let Some((val, len)) = extract_quoted_or_paren(rest)
-    {
-        *input = &input[12 + len..];
-        return Some(FilterExpr::DateBefore(val));
-    }
-
-    None
 }
 
-fn extract_quoted_or_paren(s: &str) -> Option<(String, usize)> {
-    if let Some(rest) = s.strip_prefix('"') {
-        let (val, after_quote) = rest.split_once('"')?;
-        let (consumed, trailing) = match after_quote.strip_prefix(')') {
-            Some(t) => (1, t),
-            None => (0, after_quote),
-        };
-
-        let n = 1 + val.len() + 1 + consumed;
-        let _ = trailing;
-        Some((val.to_owned(), n))
-    } else {
-        let (val, _rest) = s.split_once(')')?;
-        Some((val.to_owned(), val.len() + 1))
+/// Listed uploads whose type starts with `mime_prefix` and that match
+/// `expr`, keyed for `sort`. Relevance is the same across media types, so
+/// hits for different prefixes can be merged.
+pub fn search_hits(
+    state: &AppState,
+    mime_prefix: &str,
+    expr: Option<&Expr>,
+    text: &TextQuery<'_>,
+    sort: SortField,
+) -> Vec<Hit> {
+    let hits = collect_hits(state, mime_prefix, expr, text, sort, None);
+    if !hits.is_empty() || text.is_empty() {
+        return hits;
     }
+    // No whole word matched; fall back to fuzzy title matching, as lists did
+    // before the full-text index, so partial titles still find something.
+    let matcher = SkimMatcherV2::default();
+    collect_hits(state, mime_prefix, expr, text, sort, Some(&matcher))
 }
 
-fn apply_filters(meta: &VideoMeta, filters: &[FilterExpr], state: &AppState) -> bool {
-    for filter in filters {
-        match filter {
-            FilterExpr::TitleContains(text) => {
-                if !meta.title.to_lowercase().contains(&text.to_lowercase()) {
-                    return false;
-                }
-            }
-            FilterExpr::UploaderIs(name) => {
-                if meta.uploaded_by_name.to_lowercase() != name.to_lowercase() {
-                    return false;
-                }
-            }
-            FilterExpr::UploaderContains(text) => {
-                if !meta
-                    .uploaded_by_name
-                    .to_lowercase()
-                    .contains(&text.to_lowercase())
-                {
-                    return false;
-                }
-            }
-            FilterExpr::Sha2Is(hash) => {
-                if meta.sha256.to_lowercase() != hash.to_lowercase() {
-                    return false;
-                }
-            }
-            FilterExpr::TlshIs(hash) => match &meta.tlsh_hash {
-                Some(h) if h.to_lowercase() == hash.to_lowercase() => {}
-                _ => return false,
-            },
-            FilterExpr::TlshNear(hash) => match &meta.tlsh_hash {
-                Some(h) => {
-                    use tlsh2::TlshDefault;
-                    let ok = h
-                        .parse::<TlshDefault>()
-                        .ok()
-                        .and_then(|existing| {
-                            hash.parse::<TlshDefault>()
-                                .ok()
-                                .map(|target| existing.diff(&target, true) < 100)
-                        })
-                        .unwrap_or(false);
-                    if !ok {
-                        return false;
-                    }
-                }
-                None => return false,
-            },
-            FilterExpr::NsfwIs(val) => {
-                if meta.nsfw != *val {
-                    return false;
-                }
-            }
-            FilterExpr::SizeGt(n) => {
-                if meta.size_bytes <= *n {
-                    return false;
-                }
-            }
-            FilterExpr::SizeLt(n) => {
-                if meta.size_bytes >= *n {
-                    return false;
-                }
-            }
-            FilterExpr::DateAfter(date_str) => {
-                let meta_date = meta.uploaded_at.format("%Y-%m-%d").to_string();
-                if meta_date.as_str() <= date_str.as_str() {
-                    return false;
-                }
-            }
-            FilterExpr::DateBefore(date_str) => {
-                let meta_date = meta.uploaded_at.format("%Y-%m-%d").to_string();
-                if meta_date.as_str() >= date_str.as_str() {
-                    return false;
-                }
-            }
-            FilterExpr::MimeIs(mime) => {
-                if meta.content_type != *mime {
-                    return false;
-                }
-            }
-            FilterExpr::IdIs(id) => {
-                if meta.id != *id {
-                    return false;
-                }
+fn collect_hits(
+    state: &AppState,
+    mime_prefix: &str,
+    expr: Option<&Expr>,
+    text: &TextQuery<'_>,
+    sort: SortField,
+    fuzzy: Option<&SkimMatcherV2>,
+) -> Vec<Hit> {
+    state
+        .videos
+        .iter()
+        .filter_map(|entry| {
+            let meta = entry.value();
+            if meta.unlisted || !meta.content_type.starts_with(mime_prefix) {
+                return None;
             }
-            FilterExpr::RepliesContains(pattern) => {
-                let comments = state.comments.get(&meta.id);
-                let matches = comments.as_ref().is_some_and(|cs| {
-                    if let Ok(re) = regex_lite_match(pattern) {
-                        cs.iter().any(|c| re(&c.text))
-                    } else {
-                        let pat_lower = pattern.to_lowercase();
-                        cs.iter()
-                            .any(|c| c.text.to_lowercase().contains(&pat_lower))
-                    }
-                });
-                if !matches {
-                    return false;
-                }
+            if let Some(expr) = expr
+                && !expr.eval(
+                    &|t| {
+                        state.search.matches(&meta.id, text, t)
+                            || fuzzy
+                                .is_some_and(|m| state.search.fuzzy_title(&meta.id, t, m).is_some())
+                    },
+                    &|filter| filter_matches(meta, filter, state),
+                )
+            {
+                return None;
             }
-        }
-    }
-    true
-}
-
-fn regex_lite_match(pattern: &str) -> Result<impl Fn(&str) -> bool + '_, ()> {
-    let pat_lower = pattern.to_lowercase();
-    Ok(move |text: &str| text.to_lowercase().contains(&pat_lower))
+            let score = match fuzzy {
+                _ if sort != SortField::Relevance => 0,
+                Some(matcher) => state.search.fuzzy_score(&meta.id, text, matcher),
+                None => (state.search.score(&meta.id, text) * 1000.0) as i64,
+            };
+            Some(Hit {
+                key: SortKey::of(sort, meta, state, score),
+                id: meta.id.clone(),
+            })
+        })
+        .collect()
 }
 
+/// Backs `GET /videos`, `/images`, `/audio` and `/text`. With `limit` or
+/// `cursor` the answer is a [`listing::ListPage`]; without either it is every
+/// match in a bare array, the shape these endpoints had before pagination,
+/// so existing clients keep working.
 pub fn handle_list(
     state: &State<AppState>,
     mime_prefix: &str,
     query: Option<&str>,
-) -> Json<Vec<VideoMeta>> {
-    let mut items: Vec<VideoMeta> = state
-        .videos
-        .iter()
-        .filter(|entry| {
-            !entry.value().unlisted && entry.value().content_type.starts_with(mime_prefix)
-        })
-        .map(|entry| entry.value().clone())
-        .collect();
-
-    if let Some(q) = query {
-        let q = q.trim();
-        if !q.is_empty() {
-            let (filters, free_text) = parse_search_query(q);
-
-            if !filters.is_empty() {
-                items.retain(|meta| apply_filters(meta, &filters, state));
-            }
-
-            if !free_text.is_empty() {
-                let matcher = SkimMatcherV2::default();
-                let mut scored: Vec<(VideoMeta, i64)> = items
-                    .into_iter()
-                    .filter_map(|meta| {
-                        matcher
-                            .fuzzy_match(&meta.title, &free_text)
-                            .map(|score| (meta, score))
-                    })
-                    .collect();
-                scored.sort_unstable_by_key(|b| std::cmp::Reverse(b.1));
-                return Json(scored.into_iter().map(|(m, _)| m).collect());
-            }
-        }
-    }
-
-    items.sort_unstable_by_key(|b| std::cmp::Reverse(b.uploaded_at));
-    Json(items)
+    params: ListParams,
+) -> AppResult<Json<ListResponse>> {
+    let expr = query::parse(query.unwrap_or_default())?;
+    let text = TextQuery::of(expr.as_ref());
+    let sort = params.sort(!text.is_empty());
+    let hits = search_hits(state, mime_prefix, expr.as_ref(), &text, sort);
+    listing::respond(state, hits, sort, &params, |id| {
+        state.search.snippet(id, &text)
+    })
+    .map(Json)
 }
 
-pub fn handle_get(id: &str, state: &State<AppState>) -> AppResult<Json<VideoMeta>> {
-    let meta = state
+pub async fn handle_get(id: &str, state: &State<AppState>) -> AppResult<Json<VideoMeta>> {
+    let mut meta = state
         .videos
         .get(id)
         .map(|v| v.clone())
         .ok_or(AppError::VideoNotFound)?;
 
     maybe_backfill_tlsh(&meta, state);
+    backfill_duration(&mut meta, state).await;
 
-    Ok(Json(meta))
+    Ok(Json(state.with_current_names(meta)))
 }
 
 fn maybe_backfill_tlsh(meta: &VideoMeta, state: &State<AppState>) {
@@ -1264,6 +1177,25 @@ fn maybe_backfill_tlsh(meta: &VideoMeta, state: &State<AppState>) {
     });
 }
 
+/// Probes and stores the duration of media uploaded before durations were
+/// recorded.
+async fn backfill_duration(meta: &mut VideoMeta, state: &AppState) {
+    if meta.duration_secs.is_some()
+        || !(is_video_mime(&meta.content_type) || is_audio_mime(&meta.content_type))
+    {
+        return;
+    }
+    let path = Path::new(&state.upload_dir).join(&meta.filename);
+    let Some(duration) = probe_duration(&path).await else {
+        return;
+    };
+    meta.duration_secs = Some(duration);
+    if let Some(mut entry) = state.videos.get_mut(&meta.id) {
+        entry.duration_secs = Some(duration);
+    }
+    state.persist_video(meta);
+}
+
 pub fn handle_patch_nsfw(
     id: &str,
     body: Json<NsfwPatch>,
@@ -1287,11 +1219,23 @@ pub fn handle_patch_nsfw(
 
 pub async fn handle_delete(
     id: &str,
+    moderator: &AuthenticatedUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
     let (_, meta) = state.videos.remove(id).ok_or(AppError::VideoNotFound)?;
 
     state.delete_video_meta(id);
+    state.reports.resolve_media(id, &moderator.0.user_id);
+    if meta.uploaded_by_user != moderator.0.user_id {
+        state.audit.record(
+            &moderator.0.user_id,
+            ModAction::DeleteMedia,
+            Some(id),
+            None,
+            Some(&meta.uploaded_by_user),
+            &meta.title,
+        );
+    }
 
     if meta.references_id.is_none() {
         let has_references = state
@@ -1315,19 +1259,63 @@ pub async fn handle_delete(
     })))
 }
 
-pub fn handle_get_comments(
+/// A copy of the comments on `id`, with hidden ones blanked for `viewer`.
+fn visible_comments(
     id: &str,
-    state: &State<AppState>,
-) -> Result<Json<Vec<Comment>>, AppError> {
+    viewer: Option<&AuthenticatedUser>,
+    state: &AppState,
+) -> AppResult<Vec<Comment>> {
     if !state.videos.contains_key(id) {
         return Err(AppError::VideoNotFound);
     }
-    let comments = state
+    let mut comments = state
         .comments
         .get(id)
         .map(|c| c.value().clone())
         .unwrap_or_default();
-    Ok(Json(comments))
+    let moderator = viewer.is_some_and(|v| v.can(state, Permission::ModerateComments));
+    for c in &mut comments {
+        comments::redact_hidden(c, viewer.map(|v| v.0.user_id.as_str()), moderator);
+    }
+    Ok(comments)
+}
+
+fn render_options(id: &str, state: &AppState) -> RenderOptions {
+    state
+        .videos
+        .get(id)
+        .map(|m| RenderOptions::for_media(&m))
+        .unwrap_or_default()
+}
+
+/// `comment` with the author's current name and its text rendered.
+fn comment_view(mut comment: Comment, options: RenderOptions, state: &AppState) -> CommentView {
+    comment.author_name = state.author_name(&comment);
+    let html = markdown::render(&comment.text, options, &state.users);
+    CommentView { comment, html }
+}
+
+/// Spam rules apply to everyone who cannot moderate comments.
+fn check_spam(text: &str, user: &AuthenticatedUser, state: &AppState) -> AppResult<()> {
+    if user.can(state, Permission::ModerateComments) {
+        return Ok(());
+    }
+    state.spam_rules.check(text)
+}
+
+pub fn handle_get_comments(
+    id: &str,
+    viewer: Option<&AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Result<Json<Vec<CommentView>>, AppError> {
+    let comments = visible_comments(id, viewer, state)?;
+    let options = render_options(id, state);
+    Ok(Json(
+        comments
+            .into_iter()
+            .map(|c| comment_view(c, options, state))
+            .collect(),
+    ))
 }
 
 pub fn handle_add_comment(
@@ -1335,16 +1323,21 @@ pub fn handle_add_comment(
     body: Json<CommentBody>,
     user: AuthenticatedUser,
     state: &State<AppState>,
-) -> Result<(Status, Json<Comment>), AppError> {
-    let trimmed_text = body.text.trim();
-    if trimmed_text.is_empty() || trimmed_text.len() > 2000 {
-        return Err(AppError::InvalidComment);
-    }
+) -> Result<(Status, Json<CommentView>), AppError> {
+    state
+        .sanctions
+        .check(&user.0.user_id, SanctionKind::Comment)?;
+    let text = comments::validate_text(&body.text)?;
+    check_spam(&text, &user, state)?;
 
     let meta = state.videos.get(id).ok_or(AppError::VideoNotFound)?;
     if meta.comments_disabled {
         return Err(AppError::Forbidden);
     }
+    let anchor = body
+        .anchor
+        .map(|a| comments::validate_anchor(a, &meta))
+        .transpose()?;
     drop(meta);
 
     let comment = Comment {
@@ -1353,19 +1346,133 @@ pub fn handle_add_comment(
         author_provider: user.0.provider.clone(),
         author_id: user.0.id,
         author_name: user.0.username.clone(),
-        text: trimmed_text.to_owned(),
+        author_user: user.0.user_id.clone(),
+        text,
         created_at: chrono::Utc::now(),
-        parent_id: body.parent_id.clone(),
+        parent_id: body.parent_id.clone().filter(|p| !p.is_empty()),
+        edited_at: None,
+        revisions: Vec::new(),
+        reactions: BTreeMap::new(),
+        hidden: false,
+        anchor,
     };
 
-    state
+    let mut thread = state.comments.entry(id.to_owned()).or_default();
+    if let Some(parent_id) = &comment.parent_id {
+        comments::check_parent(&thread, parent_id)?;
+    }
+    thread.push(comment.clone());
+    drop(thread);
+    state.persist_comments(id);
+
+    let options = render_options(id, state);
+    Ok((Status::Created, Json(comment_view(comment, options, state))))
+}
+
+pub fn handle_get_thread(
+    id: &str,
+    params: &ThreadParams,
+    viewer: Option<&AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<ThreadPage>> {
+    let thread = visible_comments(id, viewer, state)?;
+    let options = render_options(id, state);
+    let page = comments::thread(
+        &thread,
+        params,
+        viewer.map(|v| v.0.user_id.as_str()),
+        &|c| state.author_name(c),
+        &|c| markdown::render(&c.text, options, &state.users),
+    )?;
+    Ok(Json(page))
+}
+
+/// Runs `f` on comment `comment_id` of upload `id` and saves the thread if
+/// it succeeds.
+pub fn update_comment<T>(
+    state: &AppState,
+    id: &str,
+    comment_id: &str,
+    f: impl FnOnce(&mut Comment) -> AppResult<T>,
+) -> AppResult<T> {
+    if !state.videos.contains_key(id) {
+        return Err(AppError::VideoNotFound);
+    }
+    let mut thread = state
         .comments
-        .entry(id.to_owned())
-        .or_default()
-        .push(comment.clone());
+        .get_mut(id)
+        .ok_or(AppError::CommentNotFound)?;
+    let comment = thread
+        .iter_mut()
+        .find(|c| c.id == comment_id)
+        .ok_or(AppError::CommentNotFound)?;
+    let out = f(comment)?;
+    drop(thread);
     state.persist_comments(id);
+    Ok(out)
+}
+
+/// Only the author can edit a comment; each edit keeps the previous text.
+pub fn handle_edit_comment(
+    id: &str,
+    comment_id: &str,
+    body: Json<CommentEdit>,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<Json<CommentView>> {
+    state
+        .sanctions
+        .check(&user.0.user_id, SanctionKind::Comment)?;
+    let text = comments::validate_text(&body.text)?;
+    check_spam(&text, &user, state)?;
+    if state.videos.get(id).is_some_and(|m| m.comments_disabled) {
+        return Err(AppError::Forbidden);
+    }
+    let comment = update_comment(state, id, comment_id, |c| {
+        if c.author_user != user.0.user_id {
+            return Err(AppError::Forbidden);
+        }
+        comments::edit(c, text)?;
+        Ok(c.clone())
+    })?;
+    let options = render_options(id, state);
+    Ok(Json(comment_view(comment, options, state)))
+}
 
-    Ok((Status::Created, Json(comment)))
+pub fn handle_get_revisions(
+    id: &str,
+    comment_id: &str,
+    viewer: Option<&AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<CommentRevision>>> {
+    let thread = visible_comments(id, viewer, state)?;
+    let comment = thread
+        .iter()
+        .find(|c| c.id == comment_id)
+        .ok_or(AppError::CommentNotFound)?;
+    Ok(Json(comments::history(comment)))
+}
+
+/// Adds (`on`) or removes the user's `emoji` reaction and returns the new
+/// counts.
+pub fn handle_react(
+    id: &str,
+    comment_id: &str,
+    emoji: &str,
+    on: bool,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<ReactionCount>>> {
+    state
+        .sanctions
+        .check(&user.0.user_id, SanctionKind::Comment)?;
+    let emoji = comments::validate_emoji(emoji)?;
+    let user_id = user.0.user_id.as_str();
+    let counts = update_comment(state, id, comment_id, |c| {
+        comments::react(c, &emoji, user_id, on)?;
+        Ok(comments::reaction_counts(c, Some(user_id)))
+    })?;
+    Ok(Json(counts))
 }
 
 pub fn handle_delete_comment(
@@ -1377,7 +1484,7 @@ pub fn handle_delete_comment(
     if !state.videos.contains_key(id) {
         return Err(AppError::VideoNotFound);
     }
-    let is_admin = state.is_admin(&user.0.provider, user.0.id);
+    let can_moderate = user.can(state, Permission::ModerateComments);
 
     let mut comments = state.comments.get_mut(id).ok_or(AppError::VideoNotFound)?;
     let idx = comments
@@ -1385,15 +1492,30 @@ pub fn handle_delete_comment(
         .position(|c| c.id == comment_id)
         .ok_or(AppError::VideoNotFound)?;
 
-    let is_own_comment =
-        comments[idx].author_id == user.0.id && comments[idx].author_provider == user.0.provider;
-    if !is_own_comment && !is_admin {
+    let is_own_comment = comments[idx].author_user == user.0.user_id;
+    if !is_own_comment && !can_moderate {
         return Err(AppError::Forbidden);
     }
 
-    comments.remove(idx);
+    let removed = comments.remove(idx);
     drop(comments);
     state.persist_comments(id);
+    state.reports.resolve_target(
+        id,
+        Some(comment_id),
+        ReportOutcome::Deleted,
+        &user.0.user_id,
+    );
+    if !is_own_comment {
+        state.audit.record(
+            &user.0.user_id,
+            ModAction::DeleteComment,
+            Some(id),
+            Some(comment_id),
+            Some(&removed.author_user),
+            "",
+        );
+    }
 
     Ok(Json(serde_json::json!({ "message": "Comment deleted" })))
 }
@@ -1405,15 +1527,29 @@ pub fn handle_patch_comments_disabled(
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
     let mut meta = state.videos.get_mut(id).ok_or(AppError::VideoNotFound)?;
-    let is_admin = state.is_admin(&user.0.provider, user.0.id);
-    let is_owner = meta.uploaded_by_id == user.0.id && meta.uploaded_by_provider == user.0.provider;
-    if !is_owner && !is_admin {
+    let can_moderate = user.can(state, Permission::ModerateComments);
+    let is_owner = meta.uploaded_by_user == user.0.user_id;
+    if !is_owner && !can_moderate {
         return Err(AppError::Forbidden);
     }
     meta.comments_disabled = body.comments_disabled;
     let updated = meta.clone();
     drop(meta);
     state.persist_video(&updated);
+    if !is_owner {
+        let action = match body.comments_disabled {
+            true => ModAction::DisableComments,
+            false => ModAction::EnableComments,
+        };
+        state.audit.record(
+            &user.0.user_id,
+            action,
+            Some(id),
+            None,
+            Some(&updated.uploaded_by_user),
+            "",
+        );
+    }
     Ok(Json(serde_json::json!({
         "message": "Comments disabled flag updated",
         "id": id,
@@ -1428,11 +1564,19 @@ pub fn handle_patch_meta(
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
     let mut meta = state.videos.get_mut(id).ok_or(AppError::VideoNotFound)?;
-    let is_admin = state.is_admin(&user.0.provider, user.0.id);
-    let is_owner = meta.uploaded_by_id == user.0.id && meta.uploaded_by_provider == user.0.provider;
-    if !is_owner && !is_admin {
+    let can_edit = user.can(state, Permission::EditAnyMedia);
+    let is_owner = meta.uploaded_by_user == user.0.user_id;
+    if !is_owner && !can_edit {
         return Err(AppError::Forbidden);
     }
+    if body.unlisted == Some(false) && meta.hidden_by_moderator && !can_edit {
+        return Err(AppError::HiddenByModerator);
+    }
+    let tags = body
+        .tags
+        .as_deref()
+        .map(|t| state.tags.resolve(t))
+        .transpose()?;
     if let Some(ref title) = body.title {
         let trimmed = title.trim();
         if trimmed.is_empty() || trimmed.len() > 200 {
@@ -1451,10 +1595,16 @@ pub fn handle_patch_meta(
     }
     if let Some(unlisted) = body.unlisted {
         meta.unlisted = unlisted;
+        if !unlisted {
+            meta.hidden_by_moderator = false;
+        }
     }
     if let Some(comments_disabled) = body.comments_disabled {
         meta.comments_disabled = comments_disabled;
     }
+    if let Some(tags) = tags {
+        meta.tags = tags;
+    }
     let updated = meta.clone();
     drop(meta);
     state.persist_video(&updated);
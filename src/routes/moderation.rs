@@ -0,0 +1,388 @@
+use {
+    crate::{
+        auth::{AdminUser, AuthenticatedUser, CommentModerator},
+        error::{AppError, AppResult},
+        models::{
+            AuditEntry, MediaKind, ModAction, Report, ReportOutcome, ReportReason, SpamRules,
+        },
+        ratelimit::CommentLimit,
+        roles::Permission,
+        routes::media,
+        state::AppState,
+    },
+    rocket::{State, http::Status, serde::json::Json},
+    serde::{Deserialize, Serialize},
+};
+
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = crate::moderation::RECENT_AUDIT_ENTRIES;
+
+fn forbidden() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+/// The account's first username, or its id if the account is gone.
+pub fn user_name(state: &AppState, user_id: &str) -> String {
+    state
+        .users
+        .get(user_id)
+        .and_then(|u| u.identities.first().map(|i| i.username.clone()))
+        .unwrap_or_else(|| user_id.to_owned())
+}
+
+#[derive(Deserialize)]
+pub struct NewReport {
+    pub media_id: String,
+    /// Report one comment rather than the upload.
+    #[serde(default)]
+    pub comment_id: Option<String>,
+    pub reason: ReportReason,
+    /// Required for [`ReportReason::Other`].
+    #[serde(default)]
+    pub details: String,
+}
+
+/// Reports an upload or a comment to the moderators.
+#[rocket::post("/reports", format = "json", data = "<body>")]
+pub fn create_report(
+    _limit: CommentLimit,
+    body: Json<NewReport>,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<(Status, Json<Report>)> {
+    if !state.videos.contains_key(&body.media_id) {
+        return Err(AppError::VideoNotFound);
+    }
+    let comment_id = body.comment_id.as_deref().filter(|c| !c.is_empty());
+    if let Some(comment_id) = comment_id {
+        let exists = state
+            .comments
+            .get(&body.media_id)
+            .is_some_and(|t| t.iter().any(|c| c.id == comment_id));
+        if !exists {
+            return Err(AppError::CommentNotFound);
+        }
+    }
+    let report = state.reports.create(
+        &user.0.user_id,
+        &body.media_id,
+        comment_id,
+        body.reason,
+        &body.details,
+    )?;
+    tracing::info!(
+        id = %report.id,
+        media = %report.media_id,
+        comment = ?report.comment_id,
+        reason = ?report.reason,
+        "report filed"
+    );
+    Ok((Status::Created, Json(report)))
+}
+
+#[rocket::post("/reports", rank = 2)]
+pub fn create_report_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+/// A report with what it is about, for the moderation queue.
+#[derive(Serialize)]
+pub struct ReportView {
+    #[serde(flatten)]
+    pub report: Report,
+    pub reporter_name: String,
+    /// `None` once the upload is gone.
+    pub media_title: Option<String>,
+    pub media_type: Option<&'static str>,
+    /// The reported comment, if it still exists.
+    pub comment_text: Option<String>,
+    pub comment_author: Option<String>,
+}
+
+pub fn report_view(state: &AppState, report: Report) -> ReportView {
+    let media = state.videos.get(&report.media_id);
+    let comment = report.comment_id.as_deref().and_then(|id| {
+        let thread = state.comments.get(&report.media_id)?;
+        thread.iter().find(|c| c.id == id).cloned()
+    });
+    ReportView {
+        reporter_name: user_name(state, &report.reporter_user),
+        media_title: media.as_ref().map(|m| m.title.clone()),
+        media_type: media
+            .as_ref()
+            .and_then(|m| MediaKind::from_mime(&m.content_type))
+            .map(MediaKind::as_str),
+        comment_text: comment.as_ref().map(|c| c.text.clone()),
+        comment_author: comment.as_ref().map(|c| state.author_name(c)),
+        report,
+    }
+}
+
+/// Open reports oldest first, or every report on record with `?all=true`.
+#[rocket::get("/admin/reports?<all>")]
+pub fn list_reports(
+    all: Option<bool>,
+    _moderator: CommentModerator,
+    state: &State<AppState>,
+) -> Json<Vec<ReportView>> {
+    let reports = state.reports.list(all.unwrap_or(false));
+    Json(reports.into_iter().map(|r| report_view(state, r)).collect())
+}
+
+#[rocket::get("/admin/reports", rank = 2)]
+pub fn list_reports_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    forbidden()
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolveAction {
+    /// Take no action.
+    Dismiss,
+    /// Hide the comment, or unlist the upload.
+    Hide,
+    /// Delete the comment or upload.
+    Delete,
+}
+
+#[derive(Deserialize)]
+pub struct Resolution {
+    pub action: ResolveAction,
+    #[serde(default)]
+    pub note: String,
+}
+
+/// Acts on a report. Every other open report on the same target is resolved
+/// with it.
+#[rocket::post("/admin/reports/<id>/resolve", format = "json", data = "<body>")]
+pub async fn resolve_report(
+    id: &str,
+    body: Json<Resolution>,
+    moderator: CommentModerator,
+    state: &State<AppState>,
+) -> AppResult<Json<ReportView>> {
+    let report = state
+        .reports
+        .get(id)
+        .filter(|r| r.resolved_at.is_none())
+        .ok_or(AppError::ReportNotFound)?;
+    let actor = &moderator.0;
+    let by = actor.0.user_id.as_str();
+    let media_id = report.media_id.as_str();
+    let comment_id = report.comment_id.as_deref();
+    let note = body.note.trim();
+
+    match (body.action, comment_id) {
+        (ResolveAction::Dismiss, _) => {
+            state
+                .reports
+                .resolve_target(media_id, comment_id, ReportOutcome::Dismissed, by);
+            state.audit.record(
+                by,
+                ModAction::DismissReport,
+                Some(media_id),
+                comment_id,
+                None,
+                note,
+            );
+        }
+        (ResolveAction::Hide, Some(comment_id)) => {
+            set_hidden(state, media_id, comment_id, true, by, note)?;
+        }
+        (ResolveAction::Hide, None) => {
+            let mut meta = state
+                .videos
+                .get_mut(media_id)
+                .ok_or(AppError::VideoNotFound)?;
+            meta.unlisted = true;
+            meta.hidden_by_moderator = true;
+            let updated = meta.clone();
+            drop(meta);
+            state.persist_video(&updated);
+            state
+                .reports
+                .resolve_target(media_id, None, ReportOutcome::Hidden, by);
+            state.audit.record(
+                by,
+                ModAction::UnlistMedia,
+                Some(media_id),
+                None,
+                Some(&updated.uploaded_by_user),
+                note,
+            );
+        }
+        (ResolveAction::Delete, Some(comment_id)) => {
+            media::handle_delete_comment(media_id, comment_id, actor.clone(), state)?;
+        }
+        (ResolveAction::Delete, None) => {
+            if !actor.can(state, Permission::DeleteAnyMedia) {
+                return Err(AppError::Forbidden);
+            }
+            media::handle_delete(media_id, actor, state).await?;
+        }
+    }
+    tracing::info!(id, action = ?body.action, by, "report resolved");
+    let report = state.reports.get(id).ok_or(AppError::ReportNotFound)?;
+    Ok(Json(report_view(state, report)))
+}
+
+#[rocket::post("/admin/reports/<_id>/resolve", rank = 2)]
+pub fn resolve_report_forbidden(
+    _id: &str,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    forbidden()
+}
+
+/// Hides or unhides a comment and records it. Hiding also resolves the open
+/// reports on it.
+fn set_hidden(
+    state: &AppState,
+    media_id: &str,
+    comment_id: &str,
+    hidden: bool,
+    by: &str,
+    note: &str,
+) -> AppResult<()> {
+    let author = media::update_comment(state, media_id, comment_id, |c| {
+        c.hidden = hidden;
+        Ok(c.author_user.clone())
+    })?;
+    let action = match hidden {
+        true => {
+            state
+                .reports
+                .resolve_target(media_id, Some(comment_id), ReportOutcome::Hidden, by);
+            ModAction::HideComment
+        }
+        false => ModAction::UnhideComment,
+    };
+    state.audit.record(
+        by,
+        action,
+        Some(media_id),
+        Some(comment_id),
+        Some(&author),
+        note,
+    );
+    Ok(())
+}
+
+#[rocket::put("/admin/comments/<media_id>/<comment_id>/hidden")]
+pub fn hide_comment(
+    media_id: &str,
+    comment_id: &str,
+    moderator: CommentModerator,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    set_hidden(
+        state,
+        media_id,
+        comment_id,
+        true,
+        &moderator.0.0.user_id,
+        "",
+    )?;
+    Ok(Json(serde_json::json!({ "message": "Comment hidden" })))
+}
+
+#[rocket::put("/admin/comments/<_media_id>/<_comment_id>/hidden", rank = 2)]
+pub fn hide_comment_forbidden(
+    _media_id: &str,
+    _comment_id: &str,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    forbidden()
+}
+
+#[rocket::delete("/admin/comments/<media_id>/<comment_id>/hidden")]
+pub fn unhide_comment(
+    media_id: &str,
+    comment_id: &str,
+    moderator: CommentModerator,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    set_hidden(
+        state,
+        media_id,
+        comment_id,
+        false,
+        &moderator.0.0.user_id,
+        "",
+    )?;
+    Ok(Json(serde_json::json!({ "message": "Comment unhidden" })))
+}
+
+#[rocket::delete("/admin/comments/<_media_id>/<_comment_id>/hidden", rank = 2)]
+pub fn unhide_comment_forbidden(
+    _media_id: &str,
+    _comment_id: &str,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    forbidden()
+}
+
+#[rocket::get("/admin/moderation/rules")]
+pub fn get_rules(_moderator: CommentModerator, state: &State<AppState>) -> Json<SpamRules> {
+    Json(state.spam_rules.get())
+}
+
+#[rocket::get("/admin/moderation/rules", rank = 2)]
+pub fn get_rules_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    forbidden()
+}
+
+/// Replaces the spam rules. They apply to new comments and edits, not to
+/// existing comments.
+#[rocket::put("/admin/moderation/rules", format = "json", data = "<body>")]
+pub fn put_rules(
+    body: Json<SpamRules>,
+    moderator: CommentModerator,
+    state: &State<AppState>,
+) -> AppResult<Json<SpamRules>> {
+    let rules = state.spam_rules.set(body.into_inner())?;
+    let note = format!(
+        "{} word(s), {} domain(s), at most {} link(s)",
+        rules.blocked_words.len(),
+        rules.blocked_domains.len(),
+        rules.max_links
+    );
+    state.audit.record(
+        &moderator.0.0.user_id,
+        ModAction::UpdateSpamRules,
+        None,
+        None,
+        None,
+        &note,
+    );
+    Ok(Json(rules))
+}
+
+#[rocket::put("/admin/moderation/rules", rank = 2)]
+pub fn put_rules_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    forbidden()
+}
+
+/// The latest moderator actions, newest first.
+#[rocket::get("/admin/audit?<limit>")]
+pub fn list_audit(
+    limit: Option<usize>,
+    _admin: AdminUser,
+    state: &State<AppState>,
+) -> Json<Vec<AuditEntry>> {
+    let limit = limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+    Json(state.audit.recent(limit))
+}
+
+#[rocket::get("/admin/audit", rank = 2)]
+pub fn list_audit_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    forbidden()
+}
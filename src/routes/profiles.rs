@@ -0,0 +1,211 @@
+use {
+    crate::{
+        auth::AuthenticatedUser,
+        error::{AppError, AppResult},
+        markdown::{self, RenderOptions},
+        models::{MediaKind, User, VideoMeta},
+        roles::Permission,
+        state::AppState,
+    },
+    chrono::{DateTime, Utc},
+    rocket::{State, get, serde::json::Json},
+    serde::Serialize,
+};
+
+pub const DEFAULT_PER_PAGE: usize = 24;
+pub const MAX_PER_PAGE: usize = 100;
+const RECENT_COMMENTS: usize = 10;
+
+#[derive(Debug, Default, Serialize)]
+pub struct UploadStats {
+    pub video: usize,
+    pub audio: usize,
+    pub image: usize,
+    pub text: usize,
+    pub total: usize,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileComment {
+    pub id: String,
+    pub media_id: String,
+    pub media_title: String,
+    pub content_type: String,
+    pub text: String,
+    /// Rendered without seek links, which only work next to the player.
+    pub html: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MediaPage {
+    pub items: Vec<VideoMeta>,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+    pub pages: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub user_id: String,
+    /// The identity the profile was looked up by.
+    pub provider: String,
+    pub id: u64,
+    pub username: String,
+    pub avatar_url: String,
+    pub joined_at: DateTime<Utc>,
+    pub stats: UploadStats,
+    pub recent_comments: Vec<ProfileComment>,
+    pub media: MediaPage,
+}
+
+/// Uploads owned by `user_id` that `viewer` may see, newest first. Unlisted
+/// uploads only show up for their owner and for staff who can edit them.
+pub fn uploads_by(
+    state: &AppState,
+    user_id: &str,
+    viewer: Option<&AuthenticatedUser>,
+) -> Vec<VideoMeta> {
+    let show_unlisted =
+        viewer.is_some_and(|v| v.0.user_id == user_id || v.can(state, Permission::EditAnyMedia));
+    let mut items: Vec<VideoMeta> = state
+        .videos
+        .iter()
+        .filter(|e| e.value().uploaded_by_user == user_id && (show_unlisted || !e.value().unlisted))
+        .map(|e| state.with_current_names(e.value().clone()))
+        .collect();
+    items.sort_unstable_by_key(|v| std::cmp::Reverse(v.uploaded_at));
+    items
+}
+
+fn stats_for(items: &[VideoMeta]) -> UploadStats {
+    let mut stats = UploadStats::default();
+    for v in items {
+        match MediaKind::from_mime(&v.content_type) {
+            Some(MediaKind::Video) => stats.video += 1,
+            Some(MediaKind::Audio) => stats.audio += 1,
+            Some(MediaKind::Image) => stats.image += 1,
+            Some(MediaKind::Text) => stats.text += 1,
+            None => {}
+        }
+        stats.total += 1;
+        stats.total_bytes += v.size_bytes;
+    }
+    stats
+}
+
+fn recent_comments(
+    state: &AppState,
+    user_id: &str,
+    viewer: Option<&AuthenticatedUser>,
+) -> Vec<ProfileComment> {
+    let sees_hidden = viewer
+        .is_some_and(|v| v.0.user_id == user_id || v.can(state, Permission::ModerateComments));
+    let mut comments: Vec<ProfileComment> = state
+        .comments
+        .iter()
+        .filter_map(|entry| {
+            let media = state.videos.get(entry.key())?;
+            let visible = !media.unlisted
+                || viewer.is_some_and(|v| {
+                    v.0.user_id == media.uploaded_by_user || v.can(state, Permission::EditAnyMedia)
+                });
+            visible.then(|| {
+                entry
+                    .value()
+                    .iter()
+                    .filter(|c| c.author_user == user_id && (!c.hidden || sees_hidden))
+                    .map(|c| ProfileComment {
+                        id: c.id.clone(),
+                        media_id: media.id.clone(),
+                        media_title: media.title.clone(),
+                        content_type: media.content_type.clone(),
+                        text: c.text.clone(),
+                        html: String::new(),
+                        created_at: c.created_at,
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .flatten()
+        .collect();
+    comments.sort_unstable_by_key(|c| std::cmp::Reverse(c.created_at));
+    comments.truncate(RECENT_COMMENTS);
+    // Rendering is the expensive part, so only the comments shown get it.
+    for c in &mut comments {
+        c.html = markdown::render(&c.text, RenderOptions::default(), &state.users);
+    }
+    comments
+}
+
+/// Finds the account owning the identity `<provider>/<id>`.
+pub fn lookup(state: &AppState, provider: &str, id: u64) -> AppResult<User> {
+    state
+        .users
+        .user_id_for(provider, id)
+        .and_then(|user_id| state.users.get(&user_id))
+        .ok_or(AppError::UserNotFound)
+}
+
+pub fn build_profile(
+    state: &AppState,
+    provider: &str,
+    id: u64,
+    viewer: Option<&AuthenticatedUser>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+) -> AppResult<Profile> {
+    let user = lookup(state, provider, id)?;
+    let identity = user
+        .identities
+        .iter()
+        .find(|i| i.provider == provider && i.external_id == id)
+        .ok_or(AppError::UserNotFound)?;
+
+    let items = uploads_by(state, &user.id, viewer);
+    let stats = stats_for(&items);
+
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let total = items.len();
+    let pages = total.div_ceil(per_page).max(1);
+    let page = page.unwrap_or(1).clamp(1, pages);
+    let items = items
+        .into_iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect();
+
+    Ok(Profile {
+        user_id: user.id.clone(),
+        provider: identity.provider.clone(),
+        id: identity.external_id,
+        username: identity.username.clone(),
+        avatar_url: identity.avatar_url.clone(),
+        joined_at: user.created_at,
+        stats,
+        recent_comments: recent_comments(state, &user.id, viewer),
+        media: MediaPage {
+            items,
+            page,
+            per_page,
+            total,
+            pages,
+        },
+    })
+}
+
+/// Public profile of the account owning `<provider>/<id>`: upload counts,
+/// storage used, recent comments and one page of uploads.
+#[get("/users/<provider>/<id>?<page>&<per_page>")]
+pub fn get_profile(
+    provider: &str,
+    id: u64,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<Profile>> {
+    build_profile(state, provider, id, user.as_ref(), page, per_page).map(Json)
+}
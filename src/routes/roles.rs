@@ -0,0 +1,83 @@
+use {
+    crate::{
+        auth::{AdminUser, AuthenticatedUser},
+        error::{AppError, AppResult},
+        models::{PlatformUser, Role},
+        state::AppState,
+    },
+    rocket::{State, http::Status, serde::json::Json},
+    serde::Deserialize,
+};
+
+/// The permission matrix and every user with a non-default role.
+#[rocket::get("/admin/roles")]
+pub fn list_roles(_admin: AdminUser, state: &State<AppState>) -> Json<serde_json::Value> {
+    let matrix: serde_json::Map<String, serde_json::Value> = Role::ALL
+        .into_iter()
+        .map(|r| (r.as_str().to_owned(), serde_json::json!(r.permissions())))
+        .collect();
+    Json(serde_json::json!({
+        "roles": matrix,
+        "users": state.users.with_roles(),
+    }))
+}
+
+#[rocket::get("/admin/roles", rank = 2)]
+pub fn list_roles_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct RoleUpdate {
+    pub role: Role,
+}
+
+/// Sets the role of the account that owns the identity `<provider>/<id>`.
+/// Takes effect on the user's next request.
+#[rocket::put("/admin/users/<provider>/<id>/role", data = "<body>")]
+pub fn set_role(
+    provider: &str,
+    id: u64,
+    body: Json<RoleUpdate>,
+    admin: AdminUser,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    let user_id = state
+        .users
+        .user_id_for(provider, id)
+        .ok_or(AppError::UserNotFound)?;
+    let user = state.users.set_role(&user_id, Some(body.role))?;
+    let effective = state.role_of(&PlatformUser {
+        provider: provider.to_owned(),
+        id,
+        username: String::new(),
+        avatar_url: String::new(),
+        user_id: user.id.clone(),
+    });
+    tracing::info!(
+        user = %user.id,
+        role = body.role.as_str(),
+        by = %admin.0.user_id,
+        "role changed"
+    );
+    Ok(Json(serde_json::json!({
+        "user_id": user.id,
+        "role": body.role,
+        "effective_role": effective,
+    })))
+}
+
+#[rocket::put("/admin/users/<_provider>/<_id>/role", rank = 2)]
+pub fn set_role_forbidden(
+    _provider: &str,
+    _id: u64,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
@@ -0,0 +1,129 @@
+use {
+    crate::{
+        auth::{AdminUser, AuthenticatedUser},
+        error::{AppError, AppResult},
+        models::{ModAction, Sanction, SanctionKind},
+        state::AppState,
+    },
+    chrono::{Duration, Utc},
+    rocket::{State, http::Status, serde::json::Json},
+    serde::Deserialize,
+};
+
+/// Active bans and suspensions, or every one on record with `?all=true`.
+#[rocket::get("/admin/sanctions?<all>")]
+pub fn list_sanctions(
+    all: Option<bool>,
+    _admin: AdminUser,
+    state: &State<AppState>,
+) -> Json<Vec<Sanction>> {
+    Json(state.sanctions.list(all.unwrap_or(false)))
+}
+
+#[rocket::get("/admin/sanctions", rank = 2)]
+pub fn list_sanctions_forbidden(_user: AuthenticatedUser) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct NewSanction {
+    pub kind: SanctionKind,
+    pub reason: String,
+    /// Omit for a permanent sanction.
+    #[serde(default)]
+    pub hours: Option<u32>,
+}
+
+/// Bans or suspends the account that owns the identity `<provider>/<id>`.
+/// A ban also signs the account out everywhere.
+#[rocket::post("/admin/users/<provider>/<id>/sanctions", data = "<body>")]
+pub fn create_sanction(
+    provider: &str,
+    id: u64,
+    body: Json<NewSanction>,
+    admin: AdminUser,
+    state: &State<AppState>,
+) -> AppResult<(Status, Json<Sanction>)> {
+    let reason = body.reason.trim();
+    if reason.is_empty() || reason.chars().count() > 500 {
+        return Err(AppError::MissingReason);
+    }
+    let user_id = state
+        .users
+        .user_id_for(provider, id)
+        .ok_or(AppError::UserNotFound)?;
+    let expires_at = body
+        .hours
+        .filter(|&h| h > 0)
+        .map(|h| Utc::now() + Duration::hours(h.into()));
+
+    let sanction = state
+        .sanctions
+        .add(&user_id, body.kind, reason, &admin.0.user_id, expires_at);
+    let revoked = match body.kind {
+        SanctionKind::Ban => state.revoke_user_sessions(&user_id),
+        _ => 0,
+    };
+    tracing::info!(
+        user = %user_id,
+        kind = ?body.kind,
+        expires_at = ?expires_at,
+        by = %admin.0.user_id,
+        revoked,
+        "user sanctioned"
+    );
+    state.audit.record(
+        &admin.0.user_id,
+        ModAction::Sanction,
+        None,
+        None,
+        Some(&user_id),
+        &format!("{:?}: {}", body.kind, reason),
+    );
+    Ok((Status::Created, Json(sanction)))
+}
+
+#[rocket::post("/admin/users/<_provider>/<_id>/sanctions", rank = 2)]
+pub fn create_sanction_forbidden(
+    _provider: &str,
+    _id: u64,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+#[rocket::delete("/admin/sanctions/<id>")]
+pub fn lift_sanction(
+    id: &str,
+    admin: AdminUser,
+    state: &State<AppState>,
+) -> AppResult<Json<Sanction>> {
+    let sanction = state.sanctions.lift(id, &admin.0.user_id)?;
+    tracing::info!(id, user = %sanction.user_id, by = %admin.0.user_id, "sanction lifted");
+    state.audit.record(
+        &admin.0.user_id,
+        ModAction::LiftSanction,
+        None,
+        None,
+        Some(&sanction.user_id),
+        &sanction.reason,
+    );
+    Ok(Json(sanction))
+}
+
+#[rocket::delete("/admin/sanctions/<_id>", rank = 2)]
+pub fn lift_sanction_forbidden(
+    _id: &str,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
@@ -0,0 +1,114 @@
+use {
+    crate::{
+        error::AppResult,
+        models::MediaKind,
+        query,
+        ratelimit::SearchLimit,
+        routes::{
+            listing::{self, Hit, ListPage, ListParams},
+            media,
+        },
+        search::TextQuery,
+        state::AppState,
+    },
+    hashbrown::HashMap,
+    rocket::{State, get, serde::json::Json},
+    serde::Serialize,
+};
+
+/// Most uploaders listed in [`Facets::uploaders`].
+pub const MAX_UPLOADER_FACETS: usize = 10;
+
+#[derive(Debug, Default, Serialize)]
+pub struct KindCounts {
+    pub video: usize,
+    pub audio: usize,
+    pub image: usize,
+    pub text: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploaderFacet {
+    pub user_id: String,
+    pub provider: String,
+    pub id: u64,
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct NsfwCounts {
+    pub nsfw: usize,
+    pub sfw: usize,
+}
+
+/// Counts over every match, not just the returned page.
+#[derive(Debug, Default, Serialize)]
+pub struct Facets {
+    pub kinds: KindCounts,
+    /// The uploaders with the most matches, most first.
+    pub uploaders: Vec<UploaderFacet>,
+    pub nsfw: NsfwCounts,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    #[serde(flatten)]
+    pub page: ListPage,
+    pub facets: Facets,
+}
+
+fn facets_for(state: &AppState, hits: &[Hit]) -> Facets {
+    let mut facets = Facets::default();
+    let mut uploaders: HashMap<String, UploaderFacet> = HashMap::new();
+    for meta in hits.iter().filter_map(|h| state.videos.get(&h.id)) {
+        match MediaKind::from_mime(&meta.content_type) {
+            Some(MediaKind::Video) => facets.kinds.video += 1,
+            Some(MediaKind::Audio) => facets.kinds.audio += 1,
+            Some(MediaKind::Image) => facets.kinds.image += 1,
+            Some(MediaKind::Text) => facets.kinds.text += 1,
+            None => {}
+        }
+        if meta.nsfw {
+            facets.nsfw.nsfw += 1;
+        } else {
+            facets.nsfw.sfw += 1;
+        }
+        uploaders
+            .entry_ref(meta.uploaded_by_user.as_str())
+            .or_insert_with(|| UploaderFacet {
+                user_id: meta.uploaded_by_user.clone(),
+                provider: meta.uploaded_by_provider.clone(),
+                id: meta.uploaded_by_id,
+                name: state.uploader_name(&meta),
+                count: 0,
+            })
+            .count += 1;
+    }
+    facets.uploaders = uploaders.into_values().collect();
+    facets
+        .uploaders
+        .sort_unstable_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    facets.uploaders.truncate(MAX_UPLOADER_FACETS);
+    facets
+}
+
+/// Searches every kind of media at once. Narrow it down with
+/// `kind.is(video)` and friends in `q`.
+#[get("/search?<q>&<list..>")]
+pub fn search(
+    _limit: SearchLimit,
+    state: &State<AppState>,
+    q: Option<&str>,
+    list: ListParams,
+) -> AppResult<Json<SearchResults>> {
+    let expr = query::parse(q.unwrap_or_default())?;
+    let text = TextQuery::of(expr.as_ref());
+    let sort = list.sort(!text.is_empty());
+    let hits = media::search_hits(state, "", expr.as_ref(), &text, sort);
+    let facets = facets_for(state, &hits);
+    let page = listing::paginate(state, hits, sort, &list, |id| {
+        state.search.snippet(id, &text)
+    })?;
+    Ok(Json(SearchResults { page, facets }))
+}
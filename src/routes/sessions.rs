@@ -0,0 +1,142 @@
+use {
+    crate::{
+        auth::{AdminUser, AuthenticatedUser, SESSION_COOKIE},
+        error::{AppError, AppResult},
+        models::Session,
+        state::AppState,
+    },
+    rocket::{
+        State,
+        http::{Cookie, CookieJar, Status},
+        serde::json::Json,
+    },
+    serde::Serialize,
+};
+
+#[derive(Serialize)]
+pub struct SessionView {
+    #[serde(flatten)]
+    pub session: Session,
+    pub current: bool,
+}
+
+pub fn sessions_for(user: &AuthenticatedUser, state: &AppState) -> Vec<SessionView> {
+    state
+        .sessions
+        .list_for(&user.0.provider, user.0.id)
+        .into_iter()
+        .map(|session| SessionView {
+            current: user.session_id() == Some(session.id.as_str()),
+            session,
+        })
+        .collect()
+}
+
+#[rocket::get("/auth/sessions")]
+pub fn list_sessions(
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<SessionView>>> {
+    if !user.is_session() {
+        return Err(AppError::SessionRequired);
+    }
+    Ok(Json(sessions_for(&user, state)))
+}
+
+#[rocket::get("/auth/sessions", rank = 2)]
+pub fn list_sessions_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+#[rocket::delete("/auth/sessions/<id>")]
+pub fn revoke_session(
+    id: &str,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+    cookies: &CookieJar<'_>,
+) -> AppResult<Status> {
+    if !user.is_session() {
+        return Err(AppError::SessionRequired);
+    }
+    let owned = state
+        .sessions
+        .get(id)
+        .is_some_and(|s| s.provider == user.0.provider && s.user_id == user.0.id);
+    if !owned {
+        return Err(AppError::SessionNotFound);
+    }
+    state.sessions.revoke(id);
+    if user.session_id() == Some(id) {
+        cookies.remove(Cookie::from(SESSION_COOKIE));
+    }
+    Ok(Status::NoContent)
+}
+
+#[rocket::delete("/auth/sessions/<_id>", rank = 2)]
+pub fn revoke_session_unauthorized(_id: &str) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+/// Signs the user out everywhere, including the session making the request.
+#[rocket::post("/auth/sessions/revoke-all")]
+pub fn revoke_all_sessions(
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+    cookies: &CookieJar<'_>,
+) -> AppResult<Json<serde_json::Value>> {
+    if !user.is_session() {
+        return Err(AppError::SessionRequired);
+    }
+    let revoked = state.sessions.revoke_all_for(&user.0.provider, user.0.id);
+    cookies.remove(Cookie::from(SESSION_COOKIE));
+    Ok(Json(serde_json::json!({ "revoked": revoked })))
+}
+
+#[rocket::post("/auth/sessions/revoke-all", rank = 2)]
+pub fn revoke_all_sessions_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+#[rocket::delete("/admin/users/<provider>/<id>/sessions")]
+pub fn admin_revoke_user_sessions(
+    provider: &str,
+    id: u64,
+    _admin: AdminUser,
+    state: &State<AppState>,
+) -> Json<serde_json::Value> {
+    let revoked = state.sessions.revoke_all_for(provider, id);
+    tracing::info!(provider, id, revoked, "admin revoked user sessions");
+    Json(serde_json::json!({ "revoked": revoked }))
+}
+
+#[rocket::delete("/admin/users/<_provider>/<_id>/sessions", rank = 2)]
+pub fn admin_revoke_user_sessions_forbidden(
+    _provider: &str,
+    _id: u64,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+#[rocket::delete("/admin/users/<_provider>/<_id>/sessions", rank = 3)]
+pub fn admin_revoke_user_sessions_unauthorized(
+    _provider: &str,
+    _id: u64,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
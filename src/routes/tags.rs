@@ -0,0 +1,120 @@
+use {
+    crate::{
+        auth::{AuthenticatedUser, TagAdmin},
+        error::AppResult,
+        state::AppState,
+    },
+    hashbrown::HashMap,
+    rocket::{State, delete, get, http::Status, put, serde::json::Json},
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagList {
+    /// Tags on listed uploads, most used first.
+    pub tags: Vec<TagCount>,
+    /// Alias → canonical tag.
+    pub aliases: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct AliasBody {
+    pub target: String,
+}
+
+/// Every tag on a listed upload, with how many uploads carry it.
+pub fn tag_counts(state: &AppState) -> Vec<TagCount> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for entry in state.videos.iter().filter(|e| !e.value().unlisted) {
+        for tag in &entry.value().tags {
+            *counts.entry_ref(tag.as_str()).or_insert(0) += 1;
+        }
+    }
+    let mut tags: Vec<TagCount> = counts
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect();
+    tags.sort_unstable_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    tags
+}
+
+#[get("/tags")]
+pub fn list_tags(state: &State<AppState>) -> Json<TagList> {
+    Json(TagList {
+        tags: tag_counts(state),
+        aliases: state.tags.aliases(),
+    })
+}
+
+/// Makes `<alias>` an alias of `target` and merges it into `target` on every
+/// upload. Renaming a tag is an alias to a tag nobody has used yet.
+#[put("/admin/tags/aliases/<alias>", format = "json", data = "<body>")]
+pub fn put_alias(
+    alias: &str,
+    body: Json<AliasBody>,
+    admin: TagAdmin,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    let target = state.tags.set_alias(alias, &body.target)?;
+    let alias = crate::tags::normalize(alias).unwrap_or_default();
+    let retagged = state.merge_tag(&alias, &target);
+    tracing::info!(%alias, %target, retagged, by = %admin.0.0.user_id, "tag alias set");
+    Ok(Json(serde_json::json!({
+        "alias": alias,
+        "target": target,
+        "retagged": retagged,
+    })))
+}
+
+#[put(
+    "/admin/tags/aliases/<_alias>",
+    format = "json",
+    data = "<_body>",
+    rank = 2
+)]
+pub fn put_alias_forbidden(
+    _alias: &str,
+    _body: Json<AliasBody>,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
+
+/// Stops `<alias>` standing for another tag. Uploads merged by the alias
+/// keep the tag they were merged into.
+#[delete("/admin/tags/aliases/<alias>")]
+pub fn delete_alias(
+    alias: &str,
+    admin: TagAdmin,
+    state: &State<AppState>,
+) -> AppResult<Json<serde_json::Value>> {
+    let target = state.tags.remove_alias(alias)?;
+    let alias = crate::tags::normalize(alias).unwrap_or_default();
+    tracing::info!(%alias, %target, by = %admin.0.0.user_id, "tag alias removed");
+    Ok(Json(serde_json::json!({
+        "message": "Alias removed",
+        "alias": alias,
+        "target": target,
+    })))
+}
+
+#[delete("/admin/tags/aliases/<_alias>", rank = 2)]
+pub fn delete_alias_forbidden(
+    _alias: &str,
+    _user: AuthenticatedUser,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Forbidden,
+        Json(serde_json::json!({ "error": "Admin privileges required" })),
+    )
+}
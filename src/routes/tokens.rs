@@ -0,0 +1,130 @@
+use {
+    crate::{
+        auth::{AuthenticatedUser, generate_api_token, hash_api_token},
+        error::{AppError, AppResult},
+        models::{ApiToken, ApiTokenView, TokenScope},
+        state::AppState,
+    },
+    rocket::{State, http::Status, serde::json::Json},
+    serde::Deserialize,
+};
+
+#[derive(Deserialize)]
+pub struct CreateTokenBody {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<TokenScope>,
+}
+
+fn owned_by(token: &ApiToken, user: &AuthenticatedUser) -> bool {
+    token.owner_user == user.0.user_id
+}
+
+#[rocket::get("/auth/tokens")]
+pub fn list_tokens(user: AuthenticatedUser, state: &State<AppState>) -> Json<Vec<ApiTokenView>> {
+    let mut tokens: Vec<ApiTokenView> = state
+        .api_tokens
+        .iter()
+        .filter(|e| owned_by(e.value(), &user))
+        .map(|e| ApiTokenView::from(e.value()))
+        .collect();
+    tokens.sort_unstable_by_key(|t| std::cmp::Reverse(t.created_at));
+    Json(tokens)
+}
+
+#[rocket::get("/auth/tokens", rank = 2)]
+pub fn list_tokens_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+/// Creates a token and returns its plaintext value. This is the only time the
+/// secret is ever shown.
+#[rocket::post("/auth/tokens", data = "<body>")]
+pub fn create_token(
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+    body: Json<CreateTokenBody>,
+) -> AppResult<(Status, Json<serde_json::Value>)> {
+    // Tokens can't mint further tokens; otherwise a leaked upload-only token
+    // could be escalated into one with every scope.
+    if !user.is_session() {
+        return Err(AppError::SessionRequired);
+    }
+
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(AppError::InvalidTokenName);
+    }
+
+    let mut scopes = body.scopes.clone();
+    scopes.sort_unstable();
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(AppError::MissingTokenScopes);
+    }
+    if scopes.contains(&TokenScope::Admin) && !user.role(state).is_staff() {
+        return Err(AppError::Forbidden);
+    }
+
+    let raw = generate_api_token();
+    let token = ApiToken {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name.to_owned(),
+        token_hash: hash_api_token(&raw),
+        owner_provider: user.0.provider.clone(),
+        owner_id: user.0.id,
+        owner_name: user.0.username.clone(),
+        owner_avatar_url: user.0.avatar_url.clone(),
+        owner_user: user.0.user_id.clone(),
+        scopes,
+        created_at: chrono::Utc::now(),
+        last_used_at: None,
+    };
+    let view = ApiTokenView::from(&token);
+    state.api_tokens.insert(token.token_hash.clone(), token);
+    state.persist_api_tokens();
+
+    Ok((
+        Status::Created,
+        Json(serde_json::json!({
+            "token": raw,
+            "info": view,
+        })),
+    ))
+}
+
+#[rocket::post("/auth/tokens", rank = 2)]
+pub fn create_token_unauthorized() -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
+
+#[rocket::delete("/auth/tokens/<id>")]
+pub fn revoke_token(
+    id: &str,
+    user: AuthenticatedUser,
+    state: &State<AppState>,
+) -> AppResult<Status> {
+    let hash = state
+        .api_tokens
+        .iter()
+        .find(|e| e.value().id == id && owned_by(e.value(), &user))
+        .map(|e| e.key().clone())
+        .ok_or(AppError::TokenNotFound)?;
+    state.api_tokens.remove(&hash);
+    state.persist_api_tokens();
+    Ok(Status::NoContent)
+}
+
+#[rocket::delete("/auth/tokens/<_id>", rank = 2)]
+pub fn revoke_token_unauthorized(_id: &str) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Not authenticated" })),
+    )
+}
@@ -1,11 +1,22 @@
 use {
-    crate::{auth::AuthenticatedUser, models::PlatformUser, state::AppState},
-    rocket::{
-        State, get,
-        http::Status,
-        response::Redirect,
-        serde::json::Json,
+    crate::{
+        auth::AuthenticatedUser,
+        comments::{self, ReactionCount},
+        local::LOCAL_PROVIDER,
+        markdown::{self, RenderOptions},
+        models::{ApiTokenView, CollectionKind, CommentAnchor, PlatformUser, Role},
+        roles::Permission,
+        routes::{
+            collections::{self, CollectionView},
+            moderation::{self, ReportView},
+            profiles,
+            sessions::sessions_for,
+            tags,
+        },
+        state::AppState,
+        util::format_size,
     },
+    rocket::{State, get, http::Status, response::Redirect, serde::json::Json},
     rocket_dyn_templates::{Template, context},
     serde::{Deserialize, Serialize},
     std::{
@@ -70,26 +81,30 @@ struct CommentCtx {
     author_provider: String,
     author_id: u64,
     author_name: String,
+    author_user: String,
     text: String,
+    html: String,
     created_at: String,
     parent_id: Option<String>,
     parent_author: Option<String>,
+    /// Nesting level, for indenting replies under their parent.
+    depth: usize,
+    edited: bool,
+    /// Hidden by a moderator; `text` is empty unless the viewer may see it.
+    hidden: bool,
+    anchor: Option<CommentAnchor>,
+    /// `anchor` as `m:ss` or `m:ss–m:ss`, for time anchors.
+    anchor_label: Option<String>,
+    reactions: Vec<ReactionCount>,
 }
 
-pub(crate) fn format_size(bytes: u64) -> String {
-    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
-    let mut size = bytes as f64;
-    for unit in UNITS {
-        if size < 1024.0 {
-            return if size.fract() < 0.05 {
-                format!("{:.0} {}", size, unit)
-            } else {
-                format!("{:.1} {}", size, unit)
-            };
-        }
-        size /= 1024.0;
+/// A playback position as `m:ss`, or `h:mm:ss` past the first hour.
+fn format_position(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        h => format!("{}:{:02}:{:02}", h, secs / 60 % 60, secs % 60),
     }
-    format!("{:.1} PB", size)
 }
 
 #[derive(Serialize)]
@@ -98,6 +113,7 @@ struct UserCtx {
     username: String,
     avatar_url: String,
     provider: String,
+    user_id: String,
 }
 
 impl UserCtx {
@@ -107,6 +123,7 @@ impl UserCtx {
             username: u.username.clone(),
             avatar_url: u.avatar_url.clone(),
             provider: u.provider.clone(),
+            user_id: u.user_id.clone(),
         }
     }
 }
@@ -127,6 +144,7 @@ struct VideoCtx {
     uploaded_by_provider: String,
     uploaded_by_id: u64,
     uploaded_by_name: String,
+    uploaded_by_user: String,
     uploaded_at: chrono::DateTime<chrono::Utc>,
     uploaded_at_display: String,
     nsfw: bool,
@@ -134,20 +152,24 @@ struct VideoCtx {
     comments_disabled: bool,
     references_id: Option<String>,
     original_extension: Option<String>,
+    tags: Vec<String>,
 }
 
 impl VideoCtx {
-    fn from_meta(v: &crate::models::VideoMeta) -> Self {
-        let media_type = if v.content_type.starts_with("audio/") {
+    fn media_type_of(content_type: &str) -> &'static str {
+        if content_type.starts_with("audio/") {
             "audio"
-        } else if v.content_type.starts_with("image/") {
+        } else if content_type.starts_with("image/") {
             "image"
-        } else if v.content_type.starts_with("text/") {
+        } else if content_type.starts_with("text/") {
             "text"
         } else {
             "video"
         }
-        .to_owned();
+    }
+
+    fn from_meta(v: &crate::models::VideoMeta, state: &AppState) -> Self {
+        let media_type = Self::media_type_of(&v.content_type).to_owned();
 
         let source = {
             let name = v
@@ -179,7 +201,8 @@ impl VideoCtx {
             tlsh_hash: v.tlsh_hash.clone(),
             uploaded_by_provider: v.uploaded_by_provider.clone(),
             uploaded_by_id: v.uploaded_by_id,
-            uploaded_by_name: v.uploaded_by_name.clone(),
+            uploaded_by_name: state.uploader_name(v),
+            uploaded_by_user: v.uploaded_by_user.clone(),
             uploaded_at: v.uploaded_at,
             uploaded_at_display: v.uploaded_at.format("%Y-%m-%d %H:%M UTC").to_string(),
             nsfw: v.nsfw,
@@ -187,6 +210,7 @@ impl VideoCtx {
             comments_disabled: v.comments_disabled,
             references_id: v.references_id.clone(),
             original_extension: v.original_extension.clone(),
+            tags: v.tags.clone(),
         }
     }
 }
@@ -245,9 +269,11 @@ pub fn listing(
 ) -> Template {
     let show_nsfw_on_homepage = show_nsfw_on_homepage();
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
 
     let mut videos: Vec<VideoCtx> = Vec::new();
     let mut audio: Vec<VideoCtx> = Vec::new();
@@ -259,7 +285,7 @@ pub fn listing(
         if v.unlisted {
             continue;
         }
-        let ctx = VideoCtx::from_meta(v);
+        let ctx = VideoCtx::from_meta(v, state);
         match ctx.media_type.as_str() {
             "video" => videos.push(ctx),
             "audio" => audio.push(ctx),
@@ -305,14 +331,14 @@ pub fn listing(
             state
                 .videos
                 .get(media_id.as_str())
-                .map(|v| VideoCtx::from_meta(v.value()))
+                .map(|v| VideoCtx::from_meta(v.value(), state))
         } else {
             let mut queue = state.daily_pick_queue.write().unwrap();
             let mut picked = None;
             while !queue.is_empty() {
                 let id = queue.remove(0);
                 if let Some(v) = state.videos.get(id.as_str()) {
-                    picked = Some((id, VideoCtx::from_meta(v.value())));
+                    picked = Some((id, VideoCtx::from_meta(v.value(), state)));
                     break;
                 }
             }
@@ -363,9 +389,8 @@ pub fn listing(
         "listing",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
             site_host: site.site_host,
             base_url: site.base_url,
             latest_videos,
@@ -384,17 +409,18 @@ pub fn video_listing(
     site: SiteInfo,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
 
     Template::render(
         "videos",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
             site_host: site.site_host,
         },
     )
@@ -407,18 +433,45 @@ pub fn audio_listing(
     site: SiteInfo,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
 
     Template::render(
         "audio_listing",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
+            site_host: site.site_host,
+        },
+    )
+}
+
+#[get("/ui/search?<q>")]
+pub fn search_page(
+    q: Option<&str>,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+
+    Template::render(
+        "search",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
             site_host: site.site_host,
+            q: q.unwrap_or_default(),
         },
     )
 }
@@ -430,45 +483,61 @@ pub fn image_listing(
     site: SiteInfo,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
 
     Template::render(
         "image_listing",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
             site_host: site.site_host,
         },
     )
 }
 
+/// The playlist a player page is playing through.
+#[derive(Serialize)]
+struct PlaylistCtx {
+    id: String,
+    title: String,
+    owner_name: String,
+    items: Vec<VideoCtx>,
+    /// Position of the current item in `items`.
+    index: usize,
+    /// Set when the previous item ended and this one should start by itself.
+    autoplay: bool,
+}
+
 fn render_media_player(
     id: &str,
     template_name: &'static str,
     site: SiteInfo,
     user: Option<AuthenticatedUser>,
     state: &State<AppState>,
+    playlist: Option<PlaylistCtx>,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
     let site_host = site.site_host;
     let base_url = site.base_url;
-    let video = state.videos.get(id).map(|v| VideoCtx::from_meta(v.value()));
+    let video = state.videos.get(id).map(|v| VideoCtx::from_meta(v.value(), state));
 
     if video.is_none() {
         return Template::render(
             "message",
             context! {
                 user: platform_user.map(UserCtx::from_platform),
-                is_admin,
-                has_github_oauth,
-                has_discord_oauth,
+                can,
+                login_providers,
                 site_host: site_host.clone(),
                 title: "Not Found",
                 message: "This media does not exist or has been deleted.",
@@ -483,9 +552,8 @@ fn render_media_player(
             "message",
             context! {
                 user: Option::<UserCtx>::None,
-                is_admin,
-                has_github_oauth,
-                has_discord_oauth,
+                can,
+                login_providers,
                 site_host: site_host.clone(),
                 title: "Login Required",
                 message: "You must be logged in to view NSFW content.",
@@ -493,52 +561,85 @@ fn render_media_player(
         );
     }
 
-    let is_owner = platform_user.is_some_and(|u| {
-        u.id == video.uploaded_by_id && u.provider == video.uploaded_by_provider
-    });
+    let is_owner = platform_user.is_some_and(|u| u.user_id == video.uploaded_by_user);
     let api_prefix = media_url_prefix(&video.media_type);
     let file_url = format!("{}/{}/{}/file", base_url, api_prefix, id);
     let embed_url = format!("{}/e/{}", base_url, id);
 
-    let raw_comments: Vec<crate::models::Comment> = state
+    let viewer_id = platform_user.map(|u| u.user_id.as_str());
+    let mut raw_comments: Vec<crate::models::Comment> = state
         .comments
         .get(id)
         .map(|c| c.value().clone())
         .unwrap_or_default();
+    for c in &mut raw_comments {
+        comments::redact_hidden(c, viewer_id, can.moderate_comments);
+    }
 
-    let author_by_id: HashMap<&str, &str> = raw_comments
+    let author_by_id: HashMap<&str, String> = raw_comments
         .iter()
-        .map(|c| (c.id.as_str(), c.author_name.as_str()))
+        .map(|c| (c.id.as_str(), state.author_name(c)))
         .collect();
 
-    let comments: Vec<CommentCtx> = raw_comments
-        .iter()
-        .map(|c| {
+    let render_options = state
+        .videos
+        .get(id)
+        .map(|m| RenderOptions::for_media(&m))
+        .unwrap_or_default();
+    let comments: Vec<CommentCtx> = comments::flatten(&raw_comments)
+        .into_iter()
+        .map(|(c, depth)| {
             let parent_author = c
                 .parent_id
                 .as_ref()
-                .and_then(|pid| author_by_id.get(pid.as_str()).map(|s| s.to_string()));
+                .and_then(|pid| author_by_id.get(pid.as_str()).cloned());
             CommentCtx {
                 id: c.id.clone(),
                 author_provider: c.author_provider.clone(),
                 author_id: c.author_id,
-                author_name: c.author_name.clone(),
+                author_name: author_by_id[c.id.as_str()].clone(),
+                author_user: c.author_user.clone(),
                 text: c.text.clone(),
+                html: markdown::render(&c.text, render_options, &state.users),
                 created_at: c.created_at.format("%Y-%m-%d %H:%M UTC").to_string(),
                 parent_id: c.parent_id.clone(),
                 parent_author,
+                depth,
+                edited: c.edited_at.is_some(),
+                hidden: c.hidden,
+                anchor: c.anchor,
+                anchor_label: c.anchor.and_then(|a| {
+                    let start = format_position(a.start_ms?);
+                    Some(match a.end_ms {
+                        Some(end) => format!("{}–{}", start, format_position(end)),
+                        None => start,
+                    })
+                }),
+                reactions: comments::reaction_counts(c, viewer_id),
             }
         })
         .collect();
 
+    let collections: Vec<CollectionView> = state
+        .collections
+        .containing(id)
+        .into_iter()
+        .map(|c| collections::view_of(state, c, user.as_ref(), false))
+        .collect();
+    let my_collections: Vec<CollectionView> = platform_user
+        .map(|u| state.collections.list(Some(&u.user_id)))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| collections::view_of(state, c, user.as_ref(), false))
+        .collect();
+
     Template::render(
         template_name,
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
+            can,
             is_owner,
-            has_github_oauth,
-            has_discord_oauth,
+            login_providers,
             site_host,
             base_url,
             video,
@@ -546,6 +647,9 @@ fn render_media_player(
             embed_url,
             api_prefix,
             comments,
+            collections,
+            my_collections,
+            playlist,
         },
     )
 }
@@ -557,7 +661,7 @@ pub fn player(
     user: Option<AuthenticatedUser>,
     state: &State<AppState>,
 ) -> Template {
-    render_media_player(id, "player", site, user, state)
+    render_media_player(id, "player", site, user, state, None)
 }
 
 #[get("/ui/audio/<id>")]
@@ -567,7 +671,7 @@ pub fn audio_player(
     user: Option<AuthenticatedUser>,
     state: &State<AppState>,
 ) -> Template {
-    render_media_player(id, "audio_player", site, user, state)
+    render_media_player(id, "audio_player", site, user, state, None)
 }
 
 #[get("/ui/images/<id>")]
@@ -577,7 +681,7 @@ pub fn image_viewer(
     user: Option<AuthenticatedUser>,
     state: &State<AppState>,
 ) -> Template {
-    render_media_player(id, "image_viewer", site, user, state)
+    render_media_player(id, "image_viewer", site, user, state, None)
 }
 
 #[get("/ui/text")]
@@ -587,15 +691,17 @@ pub fn text_listing(
     site: SiteInfo,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
 
     let mut items: Vec<VideoCtx> = state
         .videos
         .iter()
         .filter(|e| !e.value().unlisted && e.value().content_type.starts_with("text/"))
-        .map(|e| VideoCtx::from_meta(e.value()))
+        .map(|e| VideoCtx::from_meta(e.value(), state))
         .collect();
     items.sort_unstable_by_key(|v| std::cmp::Reverse(v.uploaded_at));
 
@@ -603,9 +709,8 @@ pub fn text_listing(
         "text_listing",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
             site_host: site.site_host,
             items,
         },
@@ -619,7 +724,223 @@ pub fn text_viewer(
     user: Option<AuthenticatedUser>,
     state: &State<AppState>,
 ) -> Template {
-    render_media_player(id, "text_viewer", site, user, state)
+    render_media_player(id, "text_viewer", site, user, state, None)
+}
+
+/// Plays a playlist through the video and audio players, one item per page.
+/// `index` picks the item; the page moves on to the next by itself.
+#[get("/ui/playlists/<id>?<index>&<autoplay>")]
+pub fn playlist_player(
+    id: &str,
+    index: Option<usize>,
+    autoplay: Option<bool>,
+    site: SiteInfo,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Template {
+    let collection = state
+        .collections
+        .get(id)
+        .filter(|c| c.kind == CollectionKind::Playlist);
+    let items: Vec<VideoCtx> = collection
+        .as_ref()
+        .map(|c| collections::visible_items(state, c, user.as_ref()))
+        .unwrap_or_default()
+        .iter()
+        .map(|v| VideoCtx::from_meta(v, state))
+        .collect();
+    let (Some(collection), false) = (collection, items.is_empty()) else {
+        let platform_user = user.as_ref().map(|u| &u.0);
+        let can = user
+            .as_ref()
+            .map(|u| u.permissions(state))
+            .unwrap_or_default();
+        return Template::render(
+            "message",
+            context! {
+                user: platform_user.map(UserCtx::from_platform),
+                can,
+                login_providers: state.login_links(),
+                site_host: site.site_host,
+                title: "Not Found",
+                message: "This playlist does not exist or has nothing to play.",
+            },
+        );
+    };
+
+    let index = index.unwrap_or(0).min(items.len() - 1);
+    let current = items[index].id.clone();
+    let template_name = if items[index].media_type == "audio" {
+        "audio_player"
+    } else {
+        "player"
+    };
+    let playlist = PlaylistCtx {
+        owner_name: collections::owner_identity(state, &collection)
+            .map(|i| i.username)
+            .unwrap_or_default(),
+        id: collection.id,
+        title: collection.title,
+        items,
+        index,
+        autoplay: autoplay.unwrap_or(false),
+    };
+    render_media_player(&current, template_name, site, user, state, Some(playlist))
+}
+
+#[get("/ui/u/<provider>/<id>?<page>")]
+pub fn profile(
+    provider: &str,
+    id: u64,
+    page: Option<usize>,
+    site: SiteInfo,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+
+    let Ok(profile) = profiles::build_profile(state, provider, id, user.as_ref(), page, None)
+    else {
+        return Template::render(
+            "message",
+            context! {
+                user: platform_user.map(UserCtx::from_platform),
+                can,
+                login_providers,
+                site_host: site.site_host,
+                title: "Not Found",
+                message: "This user does not exist.",
+            },
+        );
+    };
+
+    let items: Vec<VideoCtx> = profile
+        .media
+        .items
+        .iter()
+        .map(|v| VideoCtx::from_meta(v, state))
+        .collect();
+    let recent_comments: Vec<_> = profile
+        .recent_comments
+        .iter()
+        .map(|c| {
+            serde_json::json!({
+                "media_id": c.media_id,
+                "media_title": c.media_title,
+                "media_prefix": media_url_prefix(
+                    VideoCtx::media_type_of(&c.content_type)
+                ),
+                "html": c.html,
+                "created_at_display": c.created_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            })
+        })
+        .collect();
+
+    Template::render(
+        "profile",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            base_url: site.base_url,
+            is_self: platform_user.is_some_and(|u| u.user_id == profile.user_id),
+            joined_at_display: profile.joined_at.format("%Y-%m-%d").to_string(),
+            total_size_human: format_size(profile.stats.total_bytes),
+            items,
+            recent_comments,
+            profile,
+        },
+    )
+}
+
+/// Every collection, or only the viewer's own with `?mine=true`.
+#[get("/ui/collections?<mine>")]
+pub fn collections_page(
+    mine: Option<bool>,
+    site: SiteInfo,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+    let mine = mine.unwrap_or(false) && platform_user.is_some();
+    let owner = platform_user.filter(|_| mine).map(|u| u.user_id.as_str());
+    let collections: Vec<CollectionView> = state
+        .collections
+        .list(owner)
+        .into_iter()
+        .map(|c| collections::view_of(state, c, user.as_ref(), false))
+        .collect();
+
+    Template::render(
+        "collections",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            mine,
+            collections,
+        },
+    )
+}
+
+#[get("/ui/collections/<id>")]
+pub fn collection_page(
+    id: &str,
+    site: SiteInfo,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+
+    let Some(collection) = state.collections.get(id) else {
+        return Template::render(
+            "message",
+            context! {
+                user: platform_user.map(UserCtx::from_platform),
+                can,
+                login_providers,
+                site_host: site.site_host,
+                title: "Not Found",
+                message: "This collection does not exist or has been deleted.",
+            },
+        );
+    };
+    let is_owner = platform_user.is_some_and(|u| u.user_id == collection.owner_user);
+    let items: Vec<VideoCtx> = collections::visible_items(state, &collection, user.as_ref())
+        .iter()
+        .map(|v| VideoCtx::from_meta(v, state))
+        .collect();
+
+    Template::render(
+        "collection",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            embed_url: format!("{}/e/playlists/{}", site.base_url, collection.id),
+            is_owner,
+            items,
+            collection: collections::view_of(state, collection, user.as_ref(), false),
+        },
+    )
 }
 
 #[get("/e/<id>?<start>&<end>")]
@@ -630,7 +951,7 @@ pub fn embed(
     site: SiteInfo,
     state: &State<AppState>,
 ) -> Template {
-    let video = state.videos.get(id).map(|v| VideoCtx::from_meta(v.value()));
+    let video = state.videos.get(id).map(|v| VideoCtx::from_meta(v.value(), state));
 
     if video.is_none() {
         return Template::render(
@@ -673,6 +994,61 @@ pub fn embed(
     )
 }
 
+/// A playlist in one frame: a single player that moves through the items.
+#[get("/e/playlists/<id>?<index>")]
+pub fn embed_playlist(
+    id: &str,
+    index: Option<usize>,
+    site: SiteInfo,
+    state: &State<AppState>,
+) -> Template {
+    let collection = state
+        .collections
+        .get(id)
+        .filter(|c| c.kind == CollectionKind::Playlist);
+    let items: Vec<VideoCtx> = collection
+        .as_ref()
+        .map(|c| collections::visible_items(state, c, None))
+        .unwrap_or_default()
+        .iter()
+        .map(|v| VideoCtx::from_meta(v, state))
+        .collect();
+    let (Some(collection), false) = (collection, items.is_empty()) else {
+        return Template::render(
+            "message",
+            context! {
+                title: "Not Found",
+                message: "This playlist does not exist or has nothing to play.",
+            },
+        );
+    };
+
+    let files: Vec<String> = items
+        .iter()
+        .map(|v| {
+            format!(
+                "{}/{}/{}/file",
+                site.base_url,
+                media_url_prefix(&v.media_type),
+                v.id
+            )
+        })
+        .collect();
+    Template::render(
+        "embed_playlist",
+        context! {
+            site_host: site.site_host,
+            base_url: site.base_url,
+            id: collection.id,
+            title: collection.title,
+            nsfw: items.iter().any(|v| v.nsfw),
+            index: index.unwrap_or(0).min(items.len() - 1),
+            items,
+            files,
+        },
+    )
+}
+
 #[get("/ui/upload")]
 pub fn upload_form(
     user: Option<AuthenticatedUser>,
@@ -680,22 +1056,26 @@ pub fn upload_form(
     site: SiteInfo,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
 
     Template::render(
         "upload",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
             site_host: site.site_host,
         },
     )
 }
 
+/// Moderator actions shown on the admin panel; the rest are in `/admin/audit`.
+const ADMIN_AUDIT_ENTRIES: usize = 50;
+
 #[get("/ui/admin")]
 pub fn admin_panel(
     user: Option<AuthenticatedUser>,
@@ -703,9 +1083,11 @@ pub fn admin_panel(
     site: SiteInfo,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
     let mut videos: Vec<VideoCtx> = Vec::with_capacity(state.videos.len());
     let mut total_bytes: u64 = 0;
 
@@ -714,10 +1096,63 @@ pub fn admin_panel(
         if v.references_id.is_none() {
             total_bytes += v.size_bytes;
         }
-        videos.push(VideoCtx::from_meta(v));
+        videos.push(VideoCtx::from_meta(v, state));
     }
     videos.sort_unstable_by_key(|v| std::cmp::Reverse(v.uploaded_at));
     let disk_human = format_size(total_bytes);
+    let rate_limits = state.rate_limiter.snapshot();
+    let rate_limit_config = &state.rate_limiter.config;
+    let active_sessions = state.sessions.active_count();
+    let jwt_keys = state.jwt_keys.info();
+    let local_registration = state.local_accounts.as_ref().map(|l| l.registration());
+    let roles: Vec<_> = Role::ALL
+        .into_iter()
+        .map(|r| serde_json::json!({ "name": r, "permissions": r.permissions() }))
+        .collect();
+    let role_users = state.users.with_roles();
+    let sanctions: Vec<_> = state
+        .sanctions
+        .list(false)
+        .into_iter()
+        .map(|s| {
+            let identities = state
+                .users
+                .get(&s.user_id)
+                .map(|u| u.identities)
+                .unwrap_or_default();
+            serde_json::json!({ "sanction": s, "identities": identities })
+        })
+        .collect();
+
+    let reports: Vec<ReportView> = match can.moderate_comments {
+        true => state
+            .reports
+            .list(false)
+            .into_iter()
+            .map(|r| moderation::report_view(state, r))
+            .collect(),
+        false => Vec::new(),
+    };
+    let spam_rules = state.spam_rules.get();
+    let audit_log: Vec<_> = match can.manage_users {
+        true => state
+            .audit
+            .recent(ADMIN_AUDIT_ENTRIES)
+            .into_iter()
+            .map(|e| {
+                let actor_name = moderation::user_name(state, &e.actor_user);
+                let target_name = e
+                    .target_user
+                    .as_deref()
+                    .map(|u| moderation::user_name(state, u));
+                serde_json::json!({ "entry": e, "actor_name": actor_name, "target_name": target_name })
+            })
+            .collect(),
+        false => Vec::new(),
+    };
+
+    let tag_counts = tags::tag_counts(state);
+    let tag_aliases = state.tags.aliases();
 
     let daily_queue: Vec<VideoCtx> = {
         let queue = state.daily_pick_queue.read().unwrap();
@@ -727,7 +1162,7 @@ pub fn admin_panel(
                 state
                     .videos
                     .get(id.as_str())
-                    .map(|v| VideoCtx::from_meta(v.value()))
+                    .map(|v| VideoCtx::from_meta(v.value(), state))
             })
             .collect()
     };
@@ -736,14 +1171,225 @@ pub fn admin_panel(
         "admin",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
             site_host: site.site_host,
             videos,
             video_count: state.videos.len(),
             disk_human,
             daily_queue,
+            rate_limits,
+            rate_limit_config,
+            active_sessions,
+            jwt_keys,
+            local_registration,
+            roles,
+            role_users,
+            sanctions,
+            reports,
+            spam_rules,
+            audit_log,
+            tag_counts,
+            tag_aliases,
+        },
+    )
+}
+
+#[get("/ui/login")]
+pub fn login_page(
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+    let local_registration = state.local_accounts.as_ref().map(|l| l.registration());
+    let dev_roles = state.dev_login.then_some(Role::ALL);
+
+    Template::render(
+        "login",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            local_registration,
+            dev_roles,
+        },
+    )
+}
+
+#[get("/ui/register?<invite>")]
+pub fn register_page(
+    invite: Option<&str>,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+    let local_registration = state.local_accounts.as_ref().map(|l| l.registration());
+
+    Template::render(
+        "register",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            local_registration,
+            invite,
+        },
+    )
+}
+
+#[get("/ui/reset?<token>")]
+pub fn reset_page(
+    token: Option<&str>,
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+    let token_valid = match (token, state.local_accounts.as_ref()) {
+        (Some(t), Some(local)) => local.reset_account(t).is_some(),
+        _ => false,
+    };
+
+    Template::render(
+        "reset",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            token,
+            token_valid,
+        },
+    )
+}
+
+#[get("/ui/tokens")]
+pub fn tokens_page(
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+
+    let mut tokens: Vec<ApiTokenView> = match platform_user {
+        Some(u) => state
+            .api_tokens
+            .iter()
+            .filter(|e| e.value().owner_user == u.user_id)
+            .map(|e| ApiTokenView::from(e.value()))
+            .collect(),
+        None => Vec::new(),
+    };
+    tokens.sort_unstable_by_key(|t| std::cmp::Reverse(t.created_at));
+
+    Template::render(
+        "tokens",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            tokens,
+        },
+    )
+}
+
+#[get("/ui/sessions")]
+pub fn sessions_page(
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+    let sessions = user
+        .as_ref()
+        .map(|u| sessions_for(u, state))
+        .unwrap_or_default();
+
+    Template::render(
+        "sessions",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            sessions,
+        },
+    )
+}
+
+#[get("/ui/account")]
+pub fn account_page(
+    user: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+    site: SiteInfo,
+) -> Template {
+    let platform_user = user.as_ref().map(|u| &u.0);
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
+    let account = platform_user.and_then(|u| state.users.get(&u.user_id));
+    let local_account = platform_user
+        .filter(|u| u.provider == LOCAL_PROVIDER)
+        .and_then(|u| state.local_accounts.as_ref()?.get(u.id))
+        .map(|a| {
+            serde_json::json!({
+                "username": a.username,
+                "totp_enabled": a.totp_secret.is_some(),
+            })
+        });
+    let linkable: Vec<_> = state
+        .providers
+        .links()
+        .into_iter()
+        .filter(|p| {
+            account
+                .as_ref()
+                .is_some_and(|a| !a.identities.iter().any(|i| i.provider == p.name))
+        })
+        .collect();
+
+    Template::render(
+        "account",
+        context! {
+            user: platform_user.map(UserCtx::from_platform),
+            can,
+            login_providers,
+            site_host: site.site_host,
+            account,
+            linkable,
+            local_account,
         },
     )
 }
@@ -755,12 +1401,14 @@ async fn ui_delete_impl(
     site: SiteInfo,
 ) -> Template {
     let platform_user = user.as_ref().map(|u| &u.0);
-    let is_admin = platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id));
-    let has_github_oauth = state.github_oauth.is_some();
-    let has_discord_oauth = state.discord_oauth.is_some();
+    let can = user
+        .as_ref()
+        .map(|u| u.permissions(state))
+        .unwrap_or_default();
+    let login_providers = state.login_links();
 
-    let (title, message) = if !is_admin {
-        ("Error".to_owned(), "Admin access required.".to_owned())
+    let (title, message) = if !can.delete_any_media {
+        ("Error".to_owned(), "Moderator access required.".to_owned())
     } else {
         match state.videos.remove(id) {
             None => ("Error".to_owned(), "Media not found.".to_owned()),
@@ -791,9 +1439,8 @@ async fn ui_delete_impl(
         "message",
         context! {
             user: platform_user.map(UserCtx::from_platform),
-            is_admin,
-            has_github_oauth,
-            has_discord_oauth,
+            can,
+            login_providers,
             site_host: site.site_host,
             title,
             message,
@@ -852,8 +1499,10 @@ pub fn add_to_daily_queue(
     state: &State<AppState>,
     body: Json<DailyQueueBody>,
 ) -> Status {
-    let platform_user = user.as_ref().map(|u| &u.0);
-    if !platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id)) {
+    if !user
+        .as_ref()
+        .is_some_and(|u| u.can(state, Permission::ManageDailyQueue))
+    {
         return Status::Forbidden;
     }
     let mut queue = state.daily_pick_queue.write().unwrap();
@@ -871,8 +1520,10 @@ pub fn remove_from_daily_queue(
     user: Option<AuthenticatedUser>,
     state: &State<AppState>,
 ) -> Status {
-    let platform_user = user.as_ref().map(|u| &u.0);
-    if !platform_user.is_some_and(|u| state.is_admin(&u.provider, u.id)) {
+    if !user
+        .as_ref()
+        .is_some_and(|u| u.can(state, Permission::ManageDailyQueue))
+    {
         return Status::Forbidden;
     }
     let mut queue = state.daily_pick_queue.write().unwrap();
@@ -1,11 +1,16 @@
 use {
     crate::{
-        auth::{AdminUser, AuthenticatedUser},
+        auth::{AuthenticatedUser, CommentUser, DeleteMediaUser, NsfwUser, UploadUser},
+        comments::{CommentView, ReactionCount, ThreadPage, ThreadParams},
         error::{AppError, AppResult},
-        models::{Comment, VideoMeta},
-        routes::media::{
-            self, ALLOWED_VIDEO_TYPES, CommentBody, CommentsDisabledPatch, MediaResponse, MetaPatch,
-            NsfwPatch, RangeHeader,
+        models::{CommentRevision, VideoMeta},
+        ratelimit::{CommentLimit, SearchLimit, UploadLimit},
+        routes::{
+            listing::{ListParams, ListResponse},
+            media::{
+                self, ALLOWED_VIDEO_TYPES, CommentBody, CommentEdit, CommentsDisabledPatch,
+                MediaResponse, MetaPatch, NsfwPatch, RangeHeader,
+            },
         },
         state::AppState,
     },
@@ -22,14 +27,19 @@ pub fn conversion_progress(upload_id: &str, state: &State<AppState>) -> Json<ser
     media::get_conversion_progress(upload_id, state)
 }
 
-#[get("/videos?<q>")]
-pub fn list_videos(state: &State<AppState>, q: Option<&str>) -> Json<Vec<VideoMeta>> {
-    media::handle_list(state, "video/", q)
+#[get("/videos?<q>&<list..>")]
+pub fn list_videos(
+    _limit: SearchLimit,
+    state: &State<AppState>,
+    q: Option<&str>,
+    list: ListParams,
+) -> AppResult<Json<ListResponse>> {
+    media::handle_list(state, "video/", q, list)
 }
 
 #[get("/videos/<id>")]
-pub fn get_video(id: &str, state: &State<AppState>) -> AppResult<Json<VideoMeta>> {
-    media::handle_get(id, state)
+pub async fn get_video(id: &str, state: &State<AppState>) -> AppResult<Json<VideoMeta>> {
+    media::handle_get(id, state).await
 }
 
 #[get("/videos/<id>/file?<start>&<end>")]
@@ -49,6 +59,7 @@ pub async fn stream_video(
     data = "<data>"
 )]
 pub async fn upload_video(
+    _limit: UploadLimit,
     title: &str,
     source_name: Option<&str>,
     source_link: Option<&str>,
@@ -57,7 +68,7 @@ pub async fn upload_video(
     comments_disabled: Option<bool>,
     data: Data<'_>,
     content_type: &ContentType,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<(Status, Json<serde_json::Value>), AppError> {
     media::handle_upload(
@@ -69,7 +80,7 @@ pub async fn upload_video(
         comments_disabled,
         data,
         content_type,
-        user,
+        user.0,
         state,
         ALLOWED_VIDEO_TYPES,
         None,
@@ -79,11 +90,12 @@ pub async fn upload_video(
 
 #[post("/videos/upload/init?<content_type>")]
 pub async fn init_upload(
+    _limit: UploadLimit,
     content_type: &str,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_init_upload(content_type, user, state, ALLOWED_VIDEO_TYPES).await
+    media::handle_init_upload(content_type, user.0, state, ALLOWED_VIDEO_TYPES).await
 }
 
 #[put("/videos/upload/<upload_id>/<chunk_index>", data = "<data>")]
@@ -91,10 +103,10 @@ pub async fn upload_chunk(
     upload_id: &str,
     chunk_index: usize,
     data: Data<'_>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_upload_chunk(upload_id, chunk_index, data, user, state).await
+    media::handle_upload_chunk(upload_id, chunk_index, data, user.0, state).await
 }
 
 #[post(
@@ -108,7 +120,7 @@ pub async fn complete_upload(
     nsfw: Option<bool>,
     unlisted: Option<bool>,
     comments_disabled: Option<bool>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<(Status, Json<serde_json::Value>), AppError> {
     media::handle_complete_upload(
@@ -119,7 +131,7 @@ pub async fn complete_upload(
         nsfw,
         unlisted,
         comments_disabled,
-        user,
+        user.0,
         state,
         ALLOWED_VIDEO_TYPES,
         None,
@@ -194,7 +206,7 @@ pub async fn upload_video_unauthorized(
 pub fn patch_nsfw(
     id: &str,
     body: Json<NsfwPatch>,
-    _admin: AdminUser,
+    _moderator: NsfwUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
     media::handle_patch_nsfw(id, body, state)
@@ -207,17 +219,17 @@ pub fn patch_nsfw_forbidden(
 ) -> (Status, Json<serde_json::Value>) {
     (
         Status::Forbidden,
-        Json(serde_json::json!({ "error": "Admin privileges required" })),
+        Json(serde_json::json!({ "error": "Moderator privileges required" })),
     )
 }
 
 #[delete("/videos/<id>")]
 pub async fn delete_video(
     id: &str,
-    _admin: AdminUser,
+    moderator: DeleteMediaUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_delete(id, state).await
+    media::handle_delete(id, &moderator.0, state).await
 }
 
 #[delete("/videos/<_id>", rank = 2)]
@@ -227,7 +239,7 @@ pub fn delete_video_forbidden(
 ) -> (Status, Json<serde_json::Value>) {
     (
         Status::Forbidden,
-        Json(serde_json::json!({ "error": "Admin privileges required" })),
+        Json(serde_json::json!({ "error": "Moderator privileges required" })),
     )
 }
 
@@ -240,18 +252,23 @@ pub fn delete_video_unauthorized(_id: &str) -> (Status, Json<serde_json::Value>)
 }
 
 #[get("/videos/<id>/comments")]
-pub fn get_comments(id: &str, state: &State<AppState>) -> Result<Json<Vec<Comment>>, AppError> {
-    media::handle_get_comments(id, state)
+pub fn get_comments(
+    id: &str,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> Result<Json<Vec<CommentView>>, AppError> {
+    media::handle_get_comments(id, viewer.as_ref(), state)
 }
 
 #[post("/videos/<id>/comments", format = "json", data = "<body>")]
 pub fn add_comment(
+    _limit: CommentLimit,
     id: &str,
     body: Json<CommentBody>,
-    user: AuthenticatedUser,
+    user: CommentUser,
     state: &State<AppState>,
-) -> Result<(Status, Json<Comment>), AppError> {
-    media::handle_add_comment(id, body, user, state)
+) -> Result<(Status, Json<CommentView>), AppError> {
+    media::handle_add_comment(id, body, user.0, state)
 }
 
 #[post("/videos/<_id>/comments", format = "json", data = "<_body>", rank = 2)]
@@ -269,10 +286,10 @@ pub fn add_comment_unauthorized(
 pub fn delete_comment(
     id: &str,
     comment_id: &str,
-    user: AuthenticatedUser,
+    user: CommentUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_delete_comment(id, comment_id, user, state)
+    media::handle_delete_comment(id, comment_id, user.0, state)
 }
 
 #[delete("/videos/<_id>/comments/<_comment_id>", rank = 2)]
@@ -286,14 +303,110 @@ pub fn delete_comment_unauthorized(
     )
 }
 
+#[get("/videos/<id>/comments/thread?<params..>")]
+pub fn get_comment_thread(
+    id: &str,
+    params: ThreadParams,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<ThreadPage>> {
+    media::handle_get_thread(id, &params, viewer.as_ref(), state)
+}
+
+#[patch("/videos/<id>/comments/<comment_id>", format = "json", data = "<body>")]
+pub fn edit_comment(
+    _limit: CommentLimit,
+    id: &str,
+    comment_id: &str,
+    body: Json<CommentEdit>,
+    user: CommentUser,
+    state: &State<AppState>,
+) -> AppResult<Json<CommentView>> {
+    media::handle_edit_comment(id, comment_id, body, user.0, state)
+}
+
+#[patch(
+    "/videos/<_id>/comments/<_comment_id>",
+    format = "json",
+    data = "<_body>",
+    rank = 2
+)]
+pub fn edit_comment_unauthorized(
+    _id: &str,
+    _comment_id: &str,
+    _body: Json<CommentEdit>,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[get("/videos/<id>/comments/<comment_id>/revisions")]
+pub fn get_comment_revisions(
+    id: &str,
+    comment_id: &str,
+    viewer: Option<AuthenticatedUser>,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<CommentRevision>>> {
+    media::handle_get_revisions(id, comment_id, viewer.as_ref(), state)
+}
+
+#[put("/videos/<id>/comments/<comment_id>/reactions/<emoji>")]
+pub fn add_reaction(
+    _limit: CommentLimit,
+    id: &str,
+    comment_id: &str,
+    emoji: &str,
+    user: CommentUser,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<ReactionCount>>> {
+    media::handle_react(id, comment_id, emoji, true, user.0, state)
+}
+
+#[put("/videos/<_id>/comments/<_comment_id>/reactions/<_emoji>", rank = 2)]
+pub fn add_reaction_unauthorized(
+    _id: &str,
+    _comment_id: &str,
+    _emoji: &str,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
+#[delete("/videos/<id>/comments/<comment_id>/reactions/<emoji>")]
+pub fn remove_reaction(
+    id: &str,
+    comment_id: &str,
+    emoji: &str,
+    user: CommentUser,
+    state: &State<AppState>,
+) -> AppResult<Json<Vec<ReactionCount>>> {
+    media::handle_react(id, comment_id, emoji, false, user.0, state)
+}
+
+#[delete("/videos/<_id>/comments/<_comment_id>/reactions/<_emoji>", rank = 2)]
+pub fn remove_reaction_unauthorized(
+    _id: &str,
+    _comment_id: &str,
+    _emoji: &str,
+) -> (Status, Json<serde_json::Value>) {
+    (
+        Status::Unauthorized,
+        Json(serde_json::json!({ "error": "Authentication required" })),
+    )
+}
+
 #[patch("/videos/<id>/comments_disabled", format = "json", data = "<body>")]
 pub fn patch_comments_disabled(
     id: &str,
     body: Json<CommentsDisabledPatch>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_patch_comments_disabled(id, body, user, state)
+    media::handle_patch_comments_disabled(id, body, user.0, state)
 }
 
 #[patch(
@@ -316,10 +429,10 @@ pub fn patch_comments_disabled_unauthorized(
 pub fn patch_meta(
     id: &str,
     body: Json<MetaPatch>,
-    user: AuthenticatedUser,
+    user: UploadUser,
     state: &State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    media::handle_patch_meta(id, body, user, state)
+    media::handle_patch_meta(id, body, user.0, state)
 }
 
 #[patch("/videos/<_id>", format = "json", data = "<_body>", rank = 2)]
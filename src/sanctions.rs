@@ -0,0 +1,137 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::{Sanction, SanctionKind},
+    },
+    chrono::{DateTime, Utc},
+    dashmap::DashMap,
+    std::path::{Path, PathBuf},
+};
+
+/// Bans and suspensions, kept in `sanctions.json`, including lifted and
+/// expired ones.
+pub struct SanctionStore {
+    path: PathBuf,
+    sanctions: DashMap<String, Sanction>,
+}
+
+impl SanctionStore {
+    const FILE_NAME: &'static str = "sanctions.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let sanctions: Vec<Sanction> = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("could not parse {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path,
+            sanctions: sanctions.into_iter().map(|s| (s.id.clone(), s)).collect(),
+        }
+    }
+
+    pub fn add(
+        &self,
+        user_id: &str,
+        kind: SanctionKind,
+        reason: &str,
+        created_by: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Sanction {
+        let sanction = Sanction {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_owned(),
+            kind,
+            reason: reason.to_owned(),
+            created_by: created_by.to_owned(),
+            created_at: Utc::now(),
+            expires_at,
+            lifted_at: None,
+            lifted_by: None,
+        };
+        self.sanctions.insert(sanction.id.clone(), sanction.clone());
+        self.persist();
+        sanction
+    }
+
+    /// Ends a sanction early. It stays on record.
+    pub fn lift(&self, id: &str, lifted_by: &str) -> AppResult<Sanction> {
+        let mut entry = self
+            .sanctions
+            .get_mut(id)
+            .ok_or(AppError::SanctionNotFound)?;
+        if !entry.is_active(Utc::now()) {
+            return Err(AppError::SanctionNotFound);
+        }
+        entry.lifted_at = Some(Utc::now());
+        entry.lifted_by = Some(lifted_by.to_owned());
+        let sanction = entry.clone();
+        drop(entry);
+        self.persist();
+        Ok(sanction)
+    }
+
+    /// The active sanction that stops `user_id` doing `kind`, preferring the
+    /// one that lasts longest.
+    pub fn active_for(&self, user_id: &str, kind: SanctionKind) -> Option<Sanction> {
+        let now = Utc::now();
+        self.sanctions
+            .iter()
+            .filter(|s| s.user_id == user_id && s.covers(kind) && s.is_active(now))
+            .max_by_key(|s| s.expires_at.map_or(i64::MAX, |e| e.timestamp()))
+            .map(|s| s.clone())
+    }
+
+    /// Fails with a message for the user if a sanction stops them doing
+    /// `kind`.
+    pub fn check(&self, user_id: &str, kind: SanctionKind) -> AppResult<()> {
+        match self.active_for(user_id, kind) {
+            Some(s) => Err(AppError::Sanctioned(s.describe())),
+            None => Ok(()),
+        }
+    }
+
+    /// Newest first; lifted and expired sanctions only with `include_inactive`.
+    pub fn list(&self, include_inactive: bool) -> Vec<Sanction> {
+        let now = Utc::now();
+        let mut out: Vec<Sanction> = self
+            .sanctions
+            .iter()
+            .filter(|s| include_inactive || s.is_active(now))
+            .map(|s| s.clone())
+            .collect();
+        out.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+        out
+    }
+
+    /// Moves sanctions over when two accounts are merged, so linking an
+    /// identity cannot shake off a ban.
+    pub fn reassign(&self, from: &str, to: &str) -> usize {
+        let mut moved = 0;
+        for mut s in self.sanctions.iter_mut() {
+            if s.user_id == from {
+                s.user_id = to.to_owned();
+                moved += 1;
+            }
+        }
+        if moved > 0 {
+            self.persist();
+        }
+        moved
+    }
+
+    fn persist(&self) {
+        let sanctions: Vec<Sanction> = self.sanctions.iter().map(|e| e.value().clone()).collect();
+        match serde_json::to_string_pretty(&sanctions) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&self.path, json) {
+                    tracing::warn!("could not write sanctions to {:?}: {}", self.path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize sanctions: {}", e),
+        }
+    }
+}
@@ -0,0 +1,495 @@
+//! Full-text index over titles, sources, comments and the bodies of text
+//! uploads.
+//!
+//! Text is split into lowercase alphanumeric tokens and stemmed with the first
+//! steps of the Porter stemmer, so "cats" finds "cat" and "played" finds
+//! "playing". Matches are ranked with BM25F, with titles weighing the most.
+//! The index lives in memory: it is built when the server starts and updated
+//! whenever an upload or its comments are saved or deleted.
+
+use {
+    crate::{
+        models::{Comment, VideoMeta},
+        query::{Expr, FreeText},
+    },
+    fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2},
+    hashbrown::{HashMap, HashSet},
+    serde::Serialize,
+    std::sync::RwLock,
+};
+
+/// Only the start of a long text upload is indexed.
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+/// Tokens shown around the first match in a snippet.
+const SNIPPET_TOKENS: usize = 24;
+const SNIPPET_LEAD: usize = 6;
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchField {
+    Title,
+    Source,
+    Body,
+    Comments,
+}
+
+impl SearchField {
+    const ALL: [SearchField; 4] = [
+        SearchField::Title,
+        SearchField::Source,
+        SearchField::Body,
+        SearchField::Comments,
+    ];
+
+    fn weight(self) -> f64 {
+        match self {
+            SearchField::Title => 3.0,
+            SearchField::Source => 1.5,
+            SearchField::Body | SearchField::Comments => 1.0,
+        }
+    }
+}
+
+/// A piece of the text around a match, HTML-escaped with the matching words
+/// wrapped in `<mark>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Snippet {
+    pub field: SearchField,
+    pub html: String,
+}
+
+/// One indexed field of an upload.
+#[derive(Debug, Default)]
+struct FieldText {
+    /// The original text, for snippets.
+    text: String,
+    /// Stemmed term → positions.
+    terms: HashMap<String, Vec<u32>>,
+    /// Byte offset in `text` where each position starts, so snippets only
+    /// re-tokenize the few words they show.
+    starts: Vec<u32>,
+    len: u32,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Upload id → field → text.
+    docs: HashMap<String, HashMap<SearchField, FieldText>>,
+    /// Stemmed term → ids of uploads containing it in any field.
+    postings: HashMap<String, HashSet<String>>,
+    /// Sum of field lengths across uploads, for BM25 length normalisation.
+    total_len: HashMap<SearchField, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    inner: RwLock<Inner>,
+}
+
+/// Splits `text` into `(byte range, stemmed term)` pairs.
+fn tokens(text: &str) -> impl Iterator<Item = (usize, usize, String)> + '_ {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || {
+        let (start, _) = chars.find(|(_, c)| c.is_alphanumeric())?;
+        let mut end = text.len();
+        while let Some(&(i, c)) = chars.peek() {
+            if !c.is_alphanumeric() {
+                end = i;
+                break;
+            }
+            chars.next();
+        }
+        Some((start, end, stem(&text[start..end].to_lowercase())))
+    })
+}
+
+/// Stemmed terms of `text`, for queries.
+pub fn terms(text: &str) -> Vec<String> {
+    tokens(text).map(|(_, _, t)| t).collect()
+}
+
+fn is_vowel(word: &[u8], i: usize) -> bool {
+    match word[i] {
+        b'a' | b'e' | b'i' | b'o' | b'u' => true,
+        b'y' => i > 0 && !is_vowel(word, i - 1),
+        _ => false,
+    }
+}
+
+fn has_vowel(word: &[u8]) -> bool {
+    (0..word.len()).any(|i| is_vowel(word, i))
+}
+
+/// Porter stemmer steps 1a–1c, which undo plurals and `-ed`/`-ing`. Words
+/// with non-ASCII letters are left alone.
+fn stem(word: &str) -> String {
+    if word.len() < 4 || !word.bytes().all(|b| b.is_ascii_lowercase()) {
+        return word.to_owned();
+    }
+    let mut w = word.as_bytes().to_vec();
+
+    if w.ends_with(b"sses") || w.ends_with(b"ies") {
+        w.truncate(w.len() - 2);
+    } else if w.ends_with(b"s") && !w.ends_with(b"ss") && !w.ends_with(b"us") {
+        w.pop();
+    }
+
+    if w.ends_with(b"eed") {
+        w.pop();
+    } else {
+        let suffix = [&b"ed"[..], b"ing"]
+            .into_iter()
+            .find(|s| w.ends_with(s) && has_vowel(&w[..w.len() - s.len()]));
+        if let Some(suffix) = suffix {
+            w.truncate(w.len() - suffix.len());
+            let n = w.len();
+            if w.ends_with(b"at") || w.ends_with(b"bl") || w.ends_with(b"iz") {
+                w.push(b'e');
+            } else if n >= 2
+                && w[n - 1] == w[n - 2]
+                && !b"lsz".contains(&w[n - 1])
+                && !is_vowel(&w, n - 1)
+            {
+                w.pop();
+            } else if n == 3
+                && !is_vowel(&w, 0)
+                && is_vowel(&w, 1)
+                && !is_vowel(&w, 2)
+                && !b"wxy".contains(&w[2])
+            {
+                w.push(b'e');
+            }
+        }
+    }
+
+    if w.len() > 2 && w.ends_with(b"y") && has_vowel(&w[..w.len() - 1]) {
+        *w.last_mut().unwrap() = b'i';
+    }
+    String::from_utf8(w).expect("stemming keeps ASCII")
+}
+
+impl FieldText {
+    /// Indexes `parts` as one field. Positions jump between parts so phrases
+    /// do not run from one comment into the next.
+    fn new<'a>(parts: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut field = FieldText::default();
+        let mut pos = 0;
+        for part in parts {
+            if !field.text.is_empty() {
+                field.starts.push(field.text.len() as u32);
+                field.text.push('\n');
+                pos += 1;
+            }
+            let base = field.text.len();
+            field.text.push_str(part);
+            for (start, _, term) in tokens(part) {
+                field.starts.push((base + start) as u32);
+                field.terms.entry(term).or_default().push(pos);
+                pos += 1;
+                field.len += 1;
+            }
+        }
+        field
+    }
+
+    fn has_phrase(&self, terms: &[String]) -> bool {
+        let Some(first) = self.terms.get(&terms[0]) else {
+            return false;
+        };
+        first.iter().any(|&start| {
+            terms[1..].iter().zip(1..).all(|(term, offset)| {
+                self.terms
+                    .get(term)
+                    .is_some_and(|p| p.binary_search(&(start + offset)).is_ok())
+            })
+        })
+    }
+}
+
+impl Inner {
+    fn set_field(&mut self, id: &str, field: SearchField, text: FieldText) {
+        let doc = self.docs.entry_ref(id).or_default();
+        let old = doc.insert(field, text);
+        let new = &doc[&field];
+        *self.total_len.entry(field).or_default() += u64::from(new.len);
+        for term in new.terms.keys() {
+            self.postings
+                .entry_ref(term.as_str())
+                .or_default()
+                .insert(id.to_owned());
+        }
+        if let Some(old) = old {
+            *self.total_len.entry(field).or_default() -= u64::from(old.len);
+            self.unpost(id, old.terms.keys());
+        }
+    }
+
+    /// Drops `id` from the postings of those `terms` it no longer contains.
+    fn unpost<'a>(&mut self, id: &str, terms: impl Iterator<Item = &'a String>) {
+        let doc = self.docs.get(id);
+        for term in terms {
+            if doc.is_some_and(|d| d.values().any(|f| f.terms.contains_key(term))) {
+                continue;
+            }
+            if let Some(ids) = self.postings.get_mut(term) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+
+    fn avg_len(&self, field: SearchField) -> f64 {
+        let total = self.total_len.get(&field).copied().unwrap_or(0);
+        (total as f64 / self.docs.len().max(1) as f64).max(1.0)
+    }
+
+    fn matches(&self, id: &str, text: FreeText<'_>, terms: &[String]) -> bool {
+        let Some(doc) = self.docs.get(id) else {
+            return false;
+        };
+        match text {
+            FreeText::Words(_) => terms
+                .iter()
+                .all(|t| doc.values().any(|f| f.terms.contains_key(t))),
+            FreeText::Phrase(_) => doc.values().any(|f| f.has_phrase(terms)),
+        }
+    }
+
+    /// BM25F score of `id` for `terms`.
+    fn score(&self, id: &str, terms: &[String]) -> f64 {
+        let Some(doc) = self.docs.get(id) else {
+            return 0.0;
+        };
+        let n = self.docs.len() as f64;
+        terms
+            .iter()
+            .map(|term| {
+                let df = self.postings.get(term).map_or(0, |ids| ids.len()) as f64;
+                let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+                let tf: f64 = doc
+                    .iter()
+                    .map(|(&field, text)| {
+                        let count = text.terms.get(term).map_or(0, Vec::len) as f64;
+                        let norm = 1.0 - B + B * f64::from(text.len) / self.avg_len(field);
+                        field.weight() * count / norm
+                    })
+                    .sum();
+                idf * tf * (K1 + 1.0) / (tf + K1)
+            })
+            .sum()
+    }
+}
+
+/// The free text of one query, with its terms stemmed once up front.
+pub struct TextQuery<'a> {
+    parts: Vec<(FreeText<'a>, Vec<String>)>,
+    /// Terms of free text that is not negated.
+    positive: HashSet<String>,
+}
+
+impl<'a> TextQuery<'a> {
+    /// `all` is every piece of free text in the query and `positive` the ones
+    /// that are not negated.
+    pub fn new(all: &[FreeText<'a>], positive: &[FreeText<'a>]) -> Self {
+        let split = |t: &FreeText<'a>| match *t {
+            FreeText::Words(s) | FreeText::Phrase(s) => terms(s),
+        };
+        Self {
+            parts: all.iter().map(|t| (*t, split(t))).collect(),
+            positive: positive.iter().flat_map(split).collect(),
+        }
+    }
+
+    /// The free text of `expr`; empty for an empty query.
+    pub fn of(expr: Option<&'a Expr>) -> Self {
+        match expr {
+            Some(e) => Self::new(&e.all_text(), &e.free_text()),
+            None => Self::new(&[], &[]),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positive.is_empty()
+    }
+}
+
+impl SearchIndex {
+    /// Indexes the title, source and, for text uploads, `body` of `meta`.
+    pub fn index_media(&self, meta: &VideoMeta, body: Option<&str>) {
+        let mut inner = self.inner.write().unwrap();
+        inner.set_field(
+            &meta.id,
+            SearchField::Title,
+            FieldText::new([meta.title.as_str()]),
+        );
+        let sources = [&meta.source_name, &meta.source_link, &meta.source]
+            .into_iter()
+            .flatten()
+            .map(String::as_str);
+        inner.set_field(&meta.id, SearchField::Source, FieldText::new(sources));
+        if let Some(body) = body {
+            let mut end = body.len().min(MAX_BODY_BYTES);
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            inner.set_field(&meta.id, SearchField::Body, FieldText::new([&body[..end]]));
+        }
+    }
+
+    pub fn has_body(&self, id: &str) -> bool {
+        self.inner
+            .read()
+            .unwrap()
+            .docs
+            .get(id)
+            .is_some_and(|d| d.contains_key(&SearchField::Body))
+    }
+
+    pub fn index_comments(&self, id: &str, comments: &[Comment]) {
+        let text = FieldText::new(
+            comments
+                .iter()
+                .filter(|c| !c.hidden)
+                .map(|c| c.text.as_str()),
+        );
+        self.inner
+            .write()
+            .unwrap()
+            .set_field(id, SearchField::Comments, text);
+    }
+
+    pub fn remove(&self, id: &str) {
+        let mut inner = self.inner.write().unwrap();
+        if let Some(doc) = inner.docs.remove(id) {
+            for (field, text) in &doc {
+                *inner.total_len.entry(*field).or_default() -= u64::from(text.len);
+                inner.unpost(id, text.terms.keys());
+            }
+        }
+    }
+
+    /// Whether upload `id` matches `text`. Text without any words matches
+    /// everything.
+    pub fn matches(&self, id: &str, query: &TextQuery<'_>, text: FreeText<'_>) -> bool {
+        let Some((_, terms)) = query.parts.iter().find(|(t, _)| *t == text) else {
+            return false;
+        };
+        terms.is_empty() || self.inner.read().unwrap().matches(id, text, terms)
+    }
+
+    /// Fuzzy score of the title of upload `id` against `text`, so partial or
+    /// misspelled words such as `Hel` for "Hello" can still be found. Only
+    /// bare words are matched this way; phrases stay exact.
+    pub fn fuzzy_title(
+        &self,
+        id: &str,
+        text: FreeText<'_>,
+        matcher: &SkimMatcherV2,
+    ) -> Option<i64> {
+        let FreeText::Words(words) = text else {
+            return None;
+        };
+        let inner = self.inner.read().unwrap();
+        let title = &inner.docs.get(id)?.get(&SearchField::Title)?.text;
+        matcher.fuzzy_match(title, words)
+    }
+
+    /// [`Self::fuzzy_title`] summed over the free text of `query`.
+    pub fn fuzzy_score(&self, id: &str, query: &TextQuery<'_>, matcher: &SkimMatcherV2) -> i64 {
+        query
+            .parts
+            .iter()
+            .filter_map(|(text, _)| self.fuzzy_title(id, *text, matcher))
+            .sum()
+    }
+
+    /// Relevance of upload `id`, from the terms of the query that are not
+    /// negated.
+    pub fn score(&self, id: &str, query: &TextQuery<'_>) -> f64 {
+        let terms: Vec<String> = query.positive.iter().cloned().collect();
+        self.inner.read().unwrap().score(id, &terms)
+    }
+
+    /// Text around the first match of `query` in upload `id`, taken from the
+    /// field with the most matching words.
+    pub fn snippet(&self, id: &str, query: &TextQuery<'_>) -> Option<Snippet> {
+        if query.is_empty() {
+            return None;
+        }
+        let inner = self.inner.read().unwrap();
+        let doc = inner.docs.get(id)?;
+        let (field, text) = SearchField::ALL
+            .into_iter()
+            .filter_map(|f| Some((f, doc.get(&f)?)))
+            .map(|(f, text)| {
+                let hits = query
+                    .positive
+                    .iter()
+                    .filter(|t| text.terms.contains_key(*t))
+                    .count();
+                (hits, f, text)
+            })
+            .filter(|(hits, ..)| *hits > 0)
+            .max_by_key(|(hits, f, _)| (*hits, std::cmp::Reverse(*f as u8)))
+            .map(|(_, f, text)| (f, text))?;
+
+        let first = query
+            .positive
+            .iter()
+            .filter_map(|t| text.terms.get(t)?.first())
+            .min()?;
+        let from = (*first as usize).saturating_sub(SNIPPET_LEAD);
+        let offset = text.starts[from] as usize;
+        let mut window: Vec<(usize, usize, String)> = tokens(&text.text[offset..])
+            .take(SNIPPET_TOKENS + 1)
+            .map(|(s, e, t)| (offset + s, offset + e, t))
+            .collect();
+        let more = window.len() > SNIPPET_TOKENS;
+        window.truncate(SNIPPET_TOKENS);
+        let start = if from == 0 { 0 } else { window[0].0 };
+        let end = if more {
+            window[window.len() - 1].1
+        } else {
+            text.text.len()
+        };
+
+        let mut html = String::new();
+        if start > 0 {
+            html.push('…');
+        }
+        let mut at = start;
+        for (s, e, term) in &window {
+            if query.positive.contains(term) {
+                html.push_str(&escape_html(&text.text[at..*s]));
+                html.push_str("<mark>");
+                html.push_str(&escape_html(&text.text[*s..*e]));
+                html.push_str("</mark>");
+                at = *e;
+            }
+        }
+        html.push_str(&escape_html(&text.text[at..end]));
+        if end < text.text.len() {
+            html.push('…');
+        }
+        Some(Snippet { field, html })
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
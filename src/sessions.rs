@@ -0,0 +1,175 @@
+use {
+    crate::models::{PlatformUser, Session},
+    chrono::{Duration, Utc},
+    dashmap::DashMap,
+    rocket::request::{FromRequest, Outcome, Request},
+    std::{
+        convert::Infallible,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Where a login came from, recorded on the session so users can tell their
+/// sessions apart.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            user_agent: req
+                .headers()
+                .get_one("User-Agent")
+                .map(|ua| ua.chars().take(300).collect()),
+            ip: req.client_ip().map(|ip| ip.to_string()),
+        })
+    }
+}
+
+pub struct SessionStore {
+    path: PathBuf,
+    sessions: DashMap<String, Session>,
+}
+
+impl SessionStore {
+    const FILE_NAME: &'static str = "sessions.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let now = Utc::now();
+        let sessions: Vec<Session> = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("could not parse {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path,
+            sessions: sessions
+                .into_iter()
+                .filter(|s| s.expires_at > now)
+                .map(|s| (s.id.clone(), s))
+                .collect(),
+        }
+    }
+
+    pub fn create(&self, user: &PlatformUser, ttl: Duration, client: ClientInfo) -> Session {
+        let now = Utc::now();
+        self.sessions.retain(|_, s| s.expires_at > now);
+
+        let session = Session {
+            id: uuid::Uuid::new_v4().to_string(),
+            provider: user.provider.clone(),
+            user_id: user.id,
+            username: user.username.clone(),
+            created_at: now,
+            last_seen_at: now,
+            expires_at: now + ttl,
+            user_agent: client.user_agent,
+            ip: client.ip,
+        };
+        self.sessions.insert(session.id.clone(), session.clone());
+        self.persist();
+        session
+    }
+
+    /// Returns the session if it is still live, recording that it was just
+    /// used (persisted at most once a minute).
+    pub fn touch(&self, id: &str) -> Option<Session> {
+        let mut entry = self.sessions.get_mut(id)?;
+        let now = Utc::now();
+        if entry.expires_at <= now {
+            drop(entry);
+            self.sessions.remove(id);
+            return None;
+        }
+        let stale = now - entry.last_seen_at > Duration::minutes(1);
+        entry.last_seen_at = now;
+        let session = entry.clone();
+        drop(entry);
+        if stale {
+            self.persist();
+        }
+        Some(session)
+    }
+
+    /// Pushes the expiry of a live session out to `ttl` from now.
+    pub fn extend(&self, id: &str, ttl: Duration) -> Option<Session> {
+        let mut entry = self.sessions.get_mut(id)?;
+        let now = Utc::now();
+        if entry.expires_at <= now {
+            return None;
+        }
+        entry.expires_at = now + ttl;
+        entry.last_seen_at = now;
+        let session = entry.clone();
+        drop(entry);
+        self.persist();
+        Some(session)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Session> {
+        self.sessions.get(id).map(|s| s.clone())
+    }
+
+    pub fn revoke(&self, id: &str) -> Option<Session> {
+        let (_, session) = self.sessions.remove(id)?;
+        self.persist();
+        Some(session)
+    }
+
+    /// Revokes every session belonging to the user and returns how many were
+    /// removed.
+    pub fn revoke_all_for(&self, provider: &str, user_id: u64) -> usize {
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|_, s| !(s.provider == provider && s.user_id == user_id));
+        let removed = before - self.sessions.len();
+        if removed > 0 {
+            self.persist();
+        }
+        removed
+    }
+
+    pub fn list_for(&self, provider: &str, user_id: u64) -> Vec<Session> {
+        let now = Utc::now();
+        let mut out: Vec<Session> = self
+            .sessions
+            .iter()
+            .filter(|e| {
+                let s = e.value();
+                s.provider == provider && s.user_id == user_id && s.expires_at > now
+            })
+            .map(|e| e.value().clone())
+            .collect();
+        out.sort_unstable_by_key(|s| std::cmp::Reverse(s.last_seen_at));
+        out
+    }
+
+    pub fn active_count(&self) -> usize {
+        let now = Utc::now();
+        self.sessions
+            .iter()
+            .filter(|e| e.value().expires_at > now)
+            .count()
+    }
+
+    fn persist(&self) {
+        let sessions: Vec<Session> = self.sessions.iter().map(|e| e.value().clone()).collect();
+        match serde_json::to_string_pretty(&sessions) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&self.path, json) {
+                    tracing::warn!("could not write sessions to {:?}: {}", self.path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize sessions: {}", e),
+        }
+    }
+}
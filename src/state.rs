@@ -1,8 +1,26 @@
 use {
-    crate::models::{Comment, VideoMeta},
+    crate::{
+        auth::hash_api_token,
+        collections::CollectionStore,
+        error::{AppError, AppResult},
+        keys::KeyRing,
+        local::{LOCAL_PROVIDER, LocalAccountStore, LocalAuthConfig},
+        models::{ApiToken, Comment, MediaKind, PlatformUser, Role, User, VideoMeta},
+        moderation::{AuditLog, ReportStore, RulesStore},
+        oauth::{ProviderLink, ProviderRegistry},
+        ratelimit::{RateLimitConfig, RateLimiter},
+        routes::dev::DEV_PROVIDER,
+        sanctions::SanctionStore,
+        search::{MAX_BODY_BYTES, SearchIndex},
+        sessions::SessionStore,
+        tags::TagStore,
+        users::UserStore,
+    },
     dashmap::DashMap,
     hashbrown::{HashMap, HashSet},
-    std::path::Path,
+    rocket::data::ByteUnit,
+    serde::Serialize,
+    std::{io::Read, path::Path},
     tlsh2::TlshDefault,
 };
 
@@ -14,95 +32,177 @@ pub struct UploadSession {
     pub chunk_count: usize,
 }
 
-#[derive(Debug, Clone)]
-pub struct OsuOAuthConfig {
-    pub client_id: u64,
-    pub client_secret: String,
-    pub redirect_uri: String,
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct KindLimits {
+    pub video: u64,
+    pub audio: u64,
+    pub image: u64,
+    pub text: u64,
 }
 
-impl OsuOAuthConfig {
-    pub fn from_env() -> color_eyre::Result<Self> {
-        use color_eyre::eyre::WrapErr;
-        Ok(Self {
-            client_id: std::env::var("OSU_CLIENT_ID")
-                .wrap_err("OSU_CLIENT_ID not set")?
-                .parse()
-                .wrap_err("OSU_CLIENT_ID must be a number")?,
-            client_secret: std::env::var("OSU_CLIENT_SECRET")
-                .wrap_err("OSU_CLIENT_SECRET not set")?,
-            redirect_uri: std::env::var("OSU_REDIRECT_URI").wrap_err("OSU_REDIRECT_URI not set")?,
-        })
+impl KindLimits {
+    pub fn get(&self, kind: MediaKind) -> u64 {
+        match kind {
+            MediaKind::Video => self.video,
+            MediaKind::Audio => self.audio,
+            MediaKind::Image => self.image,
+            MediaKind::Text => self.text,
+        }
+    }
+
+    fn from_lookup(
+        prefix: &str,
+        fallback: &KindLimits,
+        lookup: &dyn Fn(&str) -> Option<String>,
+    ) -> Self {
+        let limit = |kind: &str, default: u64| {
+            parse_byte_limit(lookup, &format!("{}_{}", prefix, kind), default)
+        };
+        Self {
+            video: limit("VIDEO", fallback.video),
+            audio: limit("AUDIO", fallback.audio),
+            image: limit("IMAGE", fallback.image),
+            text: limit("TEXT", fallback.text),
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct GithubOAuthConfig {
-    pub client_id: String,
-    pub client_secret: String,
-    pub redirect_uri: String,
+/// Upload size limits, in bytes. Values are read from env vars such as
+/// `UPLOAD_LIMIT_VIDEO=250MiB`; admin limits fall back to the user limits.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadLimits {
+    pub user: KindLimits,
+    pub admin: KindLimits,
+    pub chunk_bytes: u64,
 }
 
-impl GithubOAuthConfig {
-    pub fn from_env() -> Option<Self> {
-        let client_id = std::env::var("GITHUB_CLIENT_ID").ok()?;
-        let client_secret = std::env::var("GITHUB_CLIENT_SECRET").ok()?;
-        let redirect_uri = std::env::var("GITHUB_REDIRECT_URI").ok()?;
-        Some(Self {
-            client_id,
-            client_secret,
-            redirect_uri,
-        })
+impl UploadLimits {
+    pub const DEFAULT_MAX_BYTES: u64 = 250 * 1024 * 1024;
+    pub const DEFAULT_CHUNK_BYTES: u64 = 6 * 1024 * 1024;
+
+    pub fn from_env() -> Self {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Like [`UploadLimits::from_env`], reading each variable through
+    /// `lookup` instead.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = KindLimits {
+            video: Self::DEFAULT_MAX_BYTES,
+            audio: Self::DEFAULT_MAX_BYTES,
+            image: Self::DEFAULT_MAX_BYTES,
+            text: Self::DEFAULT_MAX_BYTES,
+        };
+        let user = KindLimits::from_lookup("UPLOAD_LIMIT", &defaults, &lookup);
+        let admin = KindLimits::from_lookup("ADMIN_UPLOAD_LIMIT", &user, &lookup);
+        let chunk_bytes =
+            parse_byte_limit(&lookup, "UPLOAD_CHUNK_LIMIT", Self::DEFAULT_CHUNK_BYTES);
+
+        Self {
+            user,
+            admin,
+            chunk_bytes,
+        }
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct DiscordOAuthConfig {
-    pub client_id: String,
-    pub client_secret: String,
-    pub redirect_uri: String,
+    /// The `ADMIN_UPLOAD_LIMIT*` sizes apply to anyone who may bypass quotas.
+    pub fn for_role(&self, bypass_quotas: bool) -> &KindLimits {
+        if bypass_quotas { &self.admin } else { &self.user }
+    }
+
+    pub fn max_bytes(&self, kind: MediaKind, bypass_quotas: bool) -> u64 {
+        self.for_role(bypass_quotas).get(kind)
+    }
 }
 
-impl DiscordOAuthConfig {
-    pub fn from_env() -> Option<Self> {
-        let client_id = std::env::var("DISCORD_CLIENT_ID").ok()?;
-        let client_secret = std::env::var("DISCORD_CLIENT_SECRET").ok()?;
-        let redirect_uri = std::env::var("DISCORD_REDIRECT_URI").ok()?;
-        Some(Self {
-            client_id,
-            client_secret,
-            redirect_uri,
-        })
+/// The size in `env_var` as read through `lookup`, or `default` when it is
+/// unset or not a size.
+pub(crate) fn parse_byte_limit(
+    lookup: &dyn Fn(&str) -> Option<String>,
+    env_var: &str,
+    default: u64,
+) -> u64 {
+    match lookup(env_var) {
+        Some(raw) => match raw.trim().parse::<ByteUnit>() {
+            Ok(n) => n.as_u64(),
+            Err(_) => {
+                tracing::warn!(
+                    "could not parse byte size '{}' from {}, using default",
+                    raw,
+                    env_var
+                );
+                default
+            }
+        },
+        None => default,
     }
 }
 
+/// An OAuth login that has been started but not yet completed.
+pub struct PendingLogin {
+    pub provider: String,
+    /// Set when a signed-in user is linking another identity to their
+    /// account rather than signing in.
+    pub link: Option<PendingLink>,
+}
+
+/// Who started a link. The callback must arrive in the same session, so a
+/// link URL handed to someone else cannot attach their login.
+pub struct PendingLink {
+    pub user_id: String,
+    pub session_id: String,
+    /// Whether the user asked to merge the identity's own account in.
+    pub merge: bool,
+}
+
+/// Provider recorded on uploads and comments made before provider fields
+/// existed.
+const LEGACY_PROVIDER: &str = "osu";
+
 pub struct AppState {
-    pub oauth: OsuOAuthConfig,
-    pub github_oauth: Option<GithubOAuthConfig>,
-    pub discord_oauth: Option<DiscordOAuthConfig>,
-    pub pending_states: DashMap<String, ()>,
-    pub jwt_secret: String,
+    pub providers: ProviderRegistry,
+    /// OAuth CSRF state → the login it belongs to.
+    pub pending_states: DashMap<String, PendingLogin>,
+    pub jwt_keys: KeyRing,
     pub videos: DashMap<String, VideoMeta>,
     pub video_hashes: DashMap<String, String>,
     pub video_tlsh: DashMap<String, String>,
     #[allow(dead_code)]
     pub admin_ids: HashMap<String, HashSet<u64>>,
     pub upload_dir: String,
+    pub upload_limits: UploadLimits,
+    pub rate_limiter: RateLimiter,
     pub upload_sessions: DashMap<String, UploadSession>,
     pub conversion_progress: DashMap<String, u8>,
     pub comments: DashMap<String, Vec<Comment>>,
     pub daily_pick_queue: std::sync::RwLock<Vec<String>>,
     pub current_daily_pick: std::sync::RwLock<Option<(String, String)>>,
+    pub api_tokens: DashMap<String, ApiToken>,
+    pub sessions: SessionStore,
+    pub users: UserStore,
+    pub sanctions: SanctionStore,
+    pub search: SearchIndex,
+    pub tags: TagStore,
+    pub collections: CollectionStore,
+    pub reports: ReportStore,
+    pub audit: AuditLog,
+    pub spam_rules: RulesStore,
+    /// `None` unless local accounts are enabled.
+    pub local_accounts: Option<LocalAccountStore>,
+    /// `DEV_LOGIN`: anyone may sign in as anyone through `/auth/dev/login`.
+    pub dev_login: bool,
 }
 
 impl AppState {
     pub fn new(
-        oauth: OsuOAuthConfig,
-        github_oauth: Option<GithubOAuthConfig>,
-        discord_oauth: Option<DiscordOAuthConfig>,
+        providers: ProviderRegistry,
         admin_ids: HashMap<String, HashSet<u64>>,
         upload_dir: String,
-        jwt_secret: String,
+        upload_limits: UploadLimits,
+        rate_limits: RateLimitConfig,
+        jwt_keys: KeyRing,
+        local_auth: LocalAuthConfig,
+        dev_login: bool,
     ) -> Self {
         let videos: DashMap<String, VideoMeta> = DashMap::new();
         let video_hashes: DashMap<String, String> = DashMap::new();
@@ -176,38 +276,339 @@ impl AppState {
             }
         };
 
+        let api_tokens: DashMap<String, ApiToken> = {
+            let path = Path::new(&upload_dir).join("api_tokens.json");
+            let tokens: Vec<ApiToken> = match std::fs::read_to_string(&path) {
+                Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                    tracing::warn!("could not parse {:?}: {}", path, e);
+                    Vec::new()
+                }),
+                Err(_) => Vec::new(),
+            };
+            tokens
+                .into_iter()
+                .map(|t| (t.token_hash.clone(), t))
+                .collect()
+        };
+
+        let sessions = SessionStore::load(&upload_dir);
+        let users = UserStore::load(&upload_dir);
+        let sanctions = SanctionStore::load(&upload_dir);
+        let tags = TagStore::load(&upload_dir);
+        let collections = CollectionStore::load(&upload_dir);
+        let reports = ReportStore::load(&upload_dir);
+        let audit = AuditLog::load(&upload_dir);
+        let spam_rules = RulesStore::load(&upload_dir);
+        let local_accounts = local_auth
+            .enabled
+            .then(|| LocalAccountStore::load(&upload_dir, &local_auth));
+
         tracing::info!("Loaded {} video(s) from disk.", videos.len());
 
-        Self {
-            oauth,
-            github_oauth,
-            discord_oauth,
+        let state = Self {
+            providers,
             pending_states: DashMap::new(),
-            jwt_secret,
+            jwt_keys,
             videos,
             video_hashes,
             video_tlsh,
             admin_ids,
             upload_dir,
+            upload_limits,
+            rate_limiter: RateLimiter::new(rate_limits),
             upload_sessions: DashMap::new(),
             conversion_progress: DashMap::new(),
             comments,
             daily_pick_queue: std::sync::RwLock::new(daily_pick_queue),
             current_daily_pick: std::sync::RwLock::new(current_daily_pick),
+            api_tokens,
+            sessions,
+            users,
+            sanctions,
+            search: SearchIndex::default(),
+            tags,
+            collections,
+            reports,
+            audit,
+            spam_rules,
+            local_accounts,
+            dev_login,
+        };
+        state.backfill_user_ids();
+        state.build_search_index();
+        state
+    }
+
+    /// Gives uploads, comments and tokens created before internal user
+    /// records existed an owner, creating a user for each identity found.
+    fn backfill_user_ids(&self) {
+        let owner = |provider: &str, id: u64, name: &str| {
+            let provider = if provider.is_empty() {
+                LEGACY_PROVIDER
+            } else {
+                provider
+            };
+            self.users.ensure(provider, id, name)
+        };
+
+        let mut videos = 0;
+        for mut entry in self.videos.iter_mut() {
+            let meta = entry.value_mut();
+            if meta.uploaded_by_user.is_empty() {
+                meta.uploaded_by_user = owner(
+                    &meta.uploaded_by_provider,
+                    meta.uploaded_by_id,
+                    &meta.uploaded_by_name,
+                );
+                self.persist_video(meta);
+                videos += 1;
+            }
+        }
+
+        let mut threads = Vec::new();
+        for mut entry in self.comments.iter_mut() {
+            let mut changed = false;
+            for c in entry
+                .value_mut()
+                .iter_mut()
+                .filter(|c| c.author_user.is_empty())
+            {
+                c.author_user = owner(&c.author_provider, c.author_id, &c.author_name);
+                changed = true;
+            }
+            if changed {
+                threads.push(entry.key().clone());
+            }
+        }
+        for video_id in &threads {
+            self.persist_comments(video_id);
+        }
+
+        let mut tokens = 0;
+        for mut entry in self.api_tokens.iter_mut() {
+            let token = entry.value_mut();
+            if token.owner_user.is_empty() {
+                token.owner_user = owner(&token.owner_provider, token.owner_id, &token.owner_name);
+                tokens += 1;
+            }
+        }
+        if tokens > 0 {
+            self.persist_api_tokens();
+        }
+
+        if videos + threads.len() + tokens > 0 {
+            tracing::info!(
+                "Assigned owners to {} upload(s), {} comment thread(s) and {} token(s).",
+                videos,
+                threads.len(),
+                tokens
+            );
         }
     }
 
-    #[allow(unused)]
-    pub fn is_admin(&self, provider: &str, user_id: u64) -> bool {
-        #[cfg(debug_assertions)]
-        return true;
+    fn build_search_index(&self) {
+        for entry in self.videos.iter() {
+            self.index_media(entry.value());
+        }
+        for entry in self.comments.iter() {
+            self.search.index_comments(entry.key(), entry.value());
+        }
+        tracing::info!("Indexed {} upload(s) for search.", self.videos.len());
+    }
+
+    /// Adds `meta` to the search index. Text files never change after upload,
+    /// so their contents are only read the first time.
+    fn index_media(&self, meta: &VideoMeta) {
+        let body = (MediaKind::from_mime(&meta.content_type) == Some(MediaKind::Text)
+            && !self.search.has_body(&meta.id))
+        .then(|| self.read_text_body(meta))
+        .flatten();
+        self.search.index_media(meta, body.as_deref());
+    }
+
+    fn read_text_body(&self, meta: &VideoMeta) -> Option<String> {
+        let path = Path::new(&self.upload_dir).join(&meta.filename);
+        let mut bytes = Vec::new();
+        match std::fs::File::open(&path)
+            .and_then(|f| f.take(MAX_BODY_BYTES as u64).read_to_end(&mut bytes))
+        {
+            Ok(_) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+            Err(e) => {
+                tracing::warn!("could not read {:?} for indexing: {}", path, e);
+                None
+            }
+        }
+    }
 
-        #[cfg(not(debug_assertions))]
+    pub fn is_admin(&self, provider: &str, user_id: u64) -> bool {
         self.admin_ids
             .get(provider)
             .is_some_and(|ids| ids.contains(&user_id))
     }
 
+    /// Every way to sign in, for login links: the OAuth providers, then
+    /// local accounts and dev login when enabled.
+    pub fn login_links(&self) -> Vec<ProviderLink> {
+        let mut links = self.providers.links();
+        if self.local_accounts.is_some() {
+            links.push(ProviderLink {
+                name: LOCAL_PROVIDER.to_owned(),
+                display_name: "password".to_owned(),
+            });
+        }
+        if self.dev_login {
+            links.push(ProviderLink {
+                name: DEV_PROVIDER.to_owned(),
+                display_name: "dev login".to_owned(),
+            });
+        }
+        links
+    }
+
+    /// Users listed in `ADMIN_*_IDS` are always admins, so a bad role edit
+    /// cannot lock everyone out; anyone else has the role stored on their
+    /// account.
+    pub fn role_of(&self, user: &PlatformUser) -> Role {
+        if self.is_admin_user(user) {
+            return Role::Admin;
+        }
+        self.users
+            .get(&user.user_id)
+            .and_then(|u| u.role)
+            .unwrap_or(Role::User)
+    }
+
+    /// Admin if any identity linked to the user is listed as an admin.
+    pub fn is_admin_user(&self, user: &PlatformUser) -> bool {
+        self.is_admin(&user.provider, user.id)
+            || self.users.get(&user.user_id).is_some_and(|u| {
+                u.identities
+                    .iter()
+                    .any(|i| self.is_admin(&i.provider, i.external_id))
+            })
+    }
+
+    /// Links `identity` to `user_id`. When the identity already has its own
+    /// account this fails unless `merge` is set, in which case that
+    /// account's uploads, comments and tokens move over.
+    pub fn link_identity(
+        &self,
+        user_id: &str,
+        identity: &PlatformUser,
+        merge: bool,
+    ) -> AppResult<User> {
+        if let Some(merged) = self.users.link(user_id, identity, merge)? {
+            self.reassign_owner(&merged, user_id);
+            tracing::info!(from = %merged, into = %user_id, "merged user accounts");
+        }
+        self.users.get(user_id).ok_or(AppError::UserNotFound)
+    }
+
+    fn reassign_owner(&self, from: &str, to: &str) {
+        for mut entry in self.videos.iter_mut() {
+            let meta = entry.value_mut();
+            if meta.uploaded_by_user == from {
+                meta.uploaded_by_user = to.to_owned();
+                self.persist_video(meta);
+            }
+        }
+
+        let mut threads = Vec::new();
+        for mut entry in self.comments.iter_mut() {
+            let mut changed = false;
+            for c in entry
+                .value_mut()
+                .iter_mut()
+                .filter(|c| c.author_user == from)
+            {
+                c.author_user = to.to_owned();
+                changed = true;
+            }
+            if changed {
+                threads.push(entry.key().clone());
+            }
+        }
+        for video_id in &threads {
+            self.persist_comments(video_id);
+        }
+
+        let mut tokens = false;
+        for mut entry in self.api_tokens.iter_mut() {
+            if entry.owner_user == from {
+                entry.owner_user = to.to_owned();
+                tokens = true;
+            }
+        }
+        if tokens {
+            self.persist_api_tokens();
+        }
+
+        self.sanctions.reassign(from, to);
+        self.collections.reassign(from, to);
+        self.reports.reassign(from, to);
+    }
+
+    /// Replaces tag `from` with `into` on every upload, after `from` has
+    /// been made an alias of `into`. Returns how many uploads changed.
+    pub fn merge_tag(&self, from: &str, into: &str) -> usize {
+        let mut changed = 0;
+        for mut entry in self.videos.iter_mut() {
+            let meta = entry.value_mut();
+            if !meta.tags.iter().any(|t| t == from) {
+                continue;
+            }
+            let mut tags = Vec::with_capacity(meta.tags.len());
+            for tag in meta.tags.drain(..) {
+                let tag = if tag == from { into.to_owned() } else { tag };
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            meta.tags = tags;
+            self.persist_video(meta);
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Revokes every session of every identity linked to `user_id`.
+    pub fn revoke_user_sessions(&self, user_id: &str) -> usize {
+        self.users.get(user_id).map_or(0, |u| {
+            u.identities
+                .iter()
+                .map(|i| self.sessions.revoke_all_for(&i.provider, i.external_id))
+                .sum()
+        })
+    }
+
+    /// The uploader's current name; the name recorded at upload time is only
+    /// used when the account no longer exists.
+    pub fn uploader_name(&self, meta: &VideoMeta) -> String {
+        self.users
+            .display_name(
+                &meta.uploaded_by_user,
+                &meta.uploaded_by_provider,
+                meta.uploaded_by_id,
+            )
+            .unwrap_or_else(|| meta.uploaded_by_name.clone())
+    }
+
+    pub fn author_name(&self, comment: &Comment) -> String {
+        self.users
+            .display_name(
+                &comment.author_user,
+                &comment.author_provider,
+                comment.author_id,
+            )
+            .unwrap_or_else(|| comment.author_name.clone())
+    }
+
+    /// Copy of `meta` with the uploader's current name filled in.
+    pub fn with_current_names(&self, mut meta: VideoMeta) -> VideoMeta {
+        meta.uploaded_by_name = self.uploader_name(&meta);
+        meta
+    }
+
     pub fn find_similar_tlsh(&self, new_tlsh_hex: &str) -> Option<String> {
         let new_tlsh: TlshDefault = new_tlsh_hex.parse().ok()?;
 
@@ -223,7 +624,9 @@ impl AppState {
         None
     }
 
+    /// Saves `meta` and updates its search index entry.
     pub fn persist_video(&self, meta: &VideoMeta) {
+        self.index_media(meta);
         let path = Path::new(&self.upload_dir).join(format!("{}.meta.json", meta.id));
         match serde_json::to_string_pretty(meta) {
             Ok(json) => {
@@ -236,6 +639,8 @@ impl AppState {
     }
 
     pub fn delete_video_meta(&self, video_id: &str) {
+        self.search.remove(video_id);
+        self.collections.remove_media(video_id);
         let path = Path::new(&self.upload_dir).join(format!("{}.meta.json", video_id));
         if let Err(e) = std::fs::remove_file(&path)
             && e.kind() != std::io::ErrorKind::NotFound
@@ -251,6 +656,7 @@ impl AppState {
             .get(video_id)
             .map(|c| c.value().clone())
             .unwrap_or_default();
+        self.search.index_comments(video_id, &comments);
         match serde_json::to_string_pretty(&comments) {
             Ok(json) => {
                 if let Err(e) = std::fs::write(&path, json) {
@@ -287,6 +693,36 @@ impl AppState {
         }
     }
 
+    /// Looks up a personal access token by its plaintext value, recording when
+    /// it was last used (persisted at most once a minute).
+    pub fn use_api_token(&self, raw: &str) -> Option<ApiToken> {
+        let mut entry = self.api_tokens.get_mut(&hash_api_token(raw))?;
+        let now = chrono::Utc::now();
+        let stale = entry
+            .last_used_at
+            .is_none_or(|t| now - t > chrono::Duration::minutes(1));
+        entry.last_used_at = Some(now);
+        let token = entry.clone();
+        drop(entry);
+        if stale {
+            self.persist_api_tokens();
+        }
+        Some(token)
+    }
+
+    pub fn persist_api_tokens(&self) {
+        let path = Path::new(&self.upload_dir).join("api_tokens.json");
+        let tokens: Vec<ApiToken> = self.api_tokens.iter().map(|e| e.value().clone()).collect();
+        match serde_json::to_string_pretty(&tokens) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&path, json) {
+                    tracing::warn!("could not write API tokens to {:?}: {}", path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize API tokens: {}", e),
+        }
+    }
+
     pub fn delete_comments(&self, video_id: &str) {
         self.comments.remove(video_id);
         let path = Path::new(&self.upload_dir).join(format!("{}.comments.json", video_id));
@@ -0,0 +1,123 @@
+use {
+    crate::error::{AppError, AppResult},
+    dashmap::DashMap,
+    std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    },
+};
+
+pub const MAX_TAGS: usize = 20;
+pub const MAX_TAG_LEN: usize = 32;
+
+/// Lowercases `raw` and joins its words with `-`, so "Lo Fi" and "lo-fi" are
+/// the same tag. `None` unless the result is 1–32 letters, digits, `-`, `_`
+/// or `.`.
+pub fn normalize(raw: &str) -> Option<String> {
+    let tag = raw
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LEN
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    valid.then_some(tag)
+}
+
+/// Tag aliases curated by staff, kept in `tag_aliases.json`. An alias always
+/// points straight at a canonical tag, never at another alias.
+pub struct TagStore {
+    path: PathBuf,
+    aliases: DashMap<String, String>,
+}
+
+impl TagStore {
+    const FILE_NAME: &'static str = "tag_aliases.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let aliases: BTreeMap<String, String> = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("could not parse {:?}: {}", path, e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path,
+            aliases: aliases.into_iter().collect(),
+        }
+    }
+
+    /// The tag `tag` stands for, which is itself unless it is an alias.
+    pub fn canonical(&self, tag: &str) -> String {
+        self.aliases
+            .get(tag)
+            .map_or_else(|| tag.to_owned(), |t| t.clone())
+    }
+
+    /// Normalizes, de-aliases and de-duplicates tags entered by a user,
+    /// keeping their order.
+    pub fn resolve(&self, raw: &[String]) -> AppResult<Vec<String>> {
+        let mut tags: Vec<String> = Vec::new();
+        for r in raw {
+            let tag = normalize(r).ok_or_else(|| AppError::InvalidTag(r.clone()))?;
+            let tag = self.canonical(&tag);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        if tags.len() > MAX_TAGS {
+            return Err(AppError::TooManyTags);
+        }
+        Ok(tags)
+    }
+
+    /// Makes `alias` stand for `target`. Aliases of `alias` follow it, and an
+    /// alias `target` is resolved first. Returns the canonical target.
+    pub fn set_alias(&self, alias: &str, target: &str) -> AppResult<String> {
+        let alias = normalize(alias).ok_or_else(|| AppError::InvalidTag(alias.to_owned()))?;
+        let target = normalize(target).ok_or_else(|| AppError::InvalidTag(target.to_owned()))?;
+        let target = self.canonical(&target);
+        if alias == target {
+            return Err(AppError::InvalidTag(alias));
+        }
+        for mut entry in self.aliases.iter_mut() {
+            if *entry == alias {
+                *entry = target.clone();
+            }
+        }
+        self.aliases.insert(alias, target.clone());
+        self.persist();
+        Ok(target)
+    }
+
+    /// Takes `alias` as entered, like [`Self::set_alias`] does.
+    pub fn remove_alias(&self, alias: &str) -> AppResult<String> {
+        let alias = normalize(alias).ok_or(AppError::TagNotFound)?;
+        let (_, target) = self.aliases.remove(&alias).ok_or(AppError::TagNotFound)?;
+        self.persist();
+        Ok(target)
+    }
+
+    pub fn aliases(&self) -> BTreeMap<String, String> {
+        self.aliases
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect()
+    }
+
+    fn persist(&self) {
+        match serde_json::to_string_pretty(&self.aliases()) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&self.path, json) {
+                    tracing::warn!("could not write tag aliases to {:?}: {}", self.path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize tag aliases: {}", e),
+        }
+    }
+}
@@ -1,14 +1,28 @@
 use {
     crate::{
+        auth::{
+            API_TOKEN_PREFIX, AuthSource, AuthenticatedUser, create_jwt, generate_api_token,
+            hash_api_token, validate_jwt,
+        },
         error::AppError,
-        models::{Comment, VideoMeta},
-        routes::{
-            media::{
-                extension_for_mime, is_audio_mime, is_image_mime, is_text_mime, is_video_mime,
-                verify_magic_bytes,
-            },
-            ui::format_size,
+        keys::{ENV_KID, KeyAlgorithm, KeyConfig, KeyRing},
+        local::{LocalAccountStore, LocalAuthConfig, RegistrationMode, totp_code, verify_totp},
+        models::{
+            ApiToken, ApiTokenView, CollectionKind, Comment, MediaKind, PlatformUser, Role,
+            SanctionKind, TokenScope, VideoMeta,
+        },
+        oauth::{FieldMapping, ProviderConfig, ProviderRegistry, TokenAuth, external_id},
+        ratelimit::{BucketConfig, RateLimitConfig, RateLimiter, RouteGroup},
+        roles::{Permission, Permissions},
+        routes::media::{
+            extension_for_mime, is_audio_mime, is_image_mime, is_text_mime, is_video_mime,
+            verify_magic_bytes,
         },
+        sanctions::SanctionStore,
+        sessions::{ClientInfo, SessionStore},
+        state::{AppState, UploadLimits, parse_byte_limit},
+        users::UserStore,
+        util::format_size,
     },
     rocket::http::Status,
 };
@@ -199,7 +213,10 @@ fn test_error_status_video_not_found() {
 
 #[test]
 fn test_error_status_file_too_large() {
-    assert_eq!(AppError::FileTooLarge.status(), Status::PayloadTooLarge);
+    assert_eq!(
+        AppError::FileTooLarge(1024).status(),
+        Status::PayloadTooLarge
+    );
 }
 
 #[test]
@@ -255,12 +272,16 @@ fn videometa_serialize_new_source_fields() {
         uploaded_by_provider: "osu".into(),
         uploaded_by_id: 1,
         uploaded_by_name: "user".into(),
+        uploaded_by_user: "u1".into(),
         uploaded_at: chrono::Utc::now(),
         nsfw: false,
         unlisted: false,
+        hidden_by_moderator: false,
         comments_disabled: false,
         references_id: None,
         original_extension: None,
+        duration_secs: None,
+        tags: Vec::new(),
     };
 
     let json = serde_json::to_string(&meta).unwrap();
@@ -301,9 +322,15 @@ fn comment_serialize_with_parent_id() {
         author_provider: "osu".into(),
         author_id: 123,
         author_name: "user".into(),
+        author_user: "u1".into(),
         text: "Hello".into(),
         created_at: chrono::Utc::now(),
         parent_id: Some("c0".into()),
+        edited_at: None,
+        revisions: Vec::new(),
+        reactions: Default::default(),
+        hidden: false,
+        anchor: None,
     };
 
     let json = serde_json::to_string(&comment).unwrap();
@@ -325,3 +352,3286 @@ fn comment_deserialize_without_parent_id() {
     let comment: Comment = serde_json::from_str(json).unwrap();
     assert!(comment.parent_id.is_none());
 }
+
+#[test]
+fn media_kind_from_mime() {
+    assert_eq!(MediaKind::from_mime("video/mp4"), Some(MediaKind::Video));
+    assert_eq!(MediaKind::from_mime("audio/flac"), Some(MediaKind::Audio));
+    assert_eq!(MediaKind::from_mime("image/png"), Some(MediaKind::Image));
+    assert_eq!(MediaKind::from_mime("text/plain"), Some(MediaKind::Text));
+    assert_eq!(MediaKind::from_mime("application/json"), None);
+}
+
+#[test]
+fn parse_byte_limit_units() {
+    let set = |value: &'static str| move |_: &str| Some(value.to_owned());
+    assert_eq!(
+        parse_byte_limit(&set("10 MiB"), "TEST_BYTE_LIMIT_A", 1),
+        10 * 1024 * 1024
+    );
+    assert_eq!(
+        parse_byte_limit(&set("not a size"), "TEST_BYTE_LIMIT_A", 1),
+        1
+    );
+    assert_eq!(parse_byte_limit(&|_| None, "TEST_BYTE_LIMIT_A", 7), 7);
+}
+
+#[test]
+fn upload_limits_per_kind_and_role() {
+    let limits = UploadLimits::from_lookup(|name| match name {
+        "UPLOAD_LIMIT_IMAGE" => Some("5MiB".into()),
+        "ADMIN_UPLOAD_LIMIT_VIDEO" => Some("1GiB".into()),
+        _ => None,
+    });
+
+    assert_eq!(limits.max_bytes(MediaKind::Image, false), 5 * 1024 * 1024);
+    assert_eq!(limits.max_bytes(MediaKind::Image, true), 5 * 1024 * 1024);
+    assert_eq!(
+        limits.max_bytes(MediaKind::Video, false),
+        UploadLimits::DEFAULT_MAX_BYTES
+    );
+    assert_eq!(limits.max_bytes(MediaKind::Video, true), 1024 * 1024 * 1024);
+    assert_eq!(limits.chunk_bytes, UploadLimits::DEFAULT_CHUNK_BYTES);
+}
+
+#[test]
+fn file_too_large_reports_limit() {
+    let msg = AppError::FileTooLarge(250 * 1024 * 1024).to_string();
+    assert_eq!(msg, "Upload exceeds 250 MB limit");
+}
+
+#[test]
+fn bucket_config_parse() {
+    let cfg = BucketConfig::parse("30/60").unwrap().unwrap();
+    assert_eq!(cfg.capacity, 30);
+    assert_eq!(cfg.period_secs, 60);
+    assert!(BucketConfig::parse("off").unwrap().is_none());
+    assert!(BucketConfig::parse("0").unwrap().is_none());
+    assert!(BucketConfig::parse("thirty").is_none());
+}
+
+#[test]
+fn rate_limiter_exhausts_bucket_per_key() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        upload: None,
+        comment: Some(BucketConfig {
+            capacity: 2,
+            period_secs: 3600,
+        }),
+        login: None,
+        search: None,
+    });
+
+    assert!(limiter.check(RouteGroup::Comment, "ip:1.2.3.4").is_ok());
+    assert!(limiter.check(RouteGroup::Comment, "ip:1.2.3.4").is_ok());
+    let wait = limiter
+        .check(RouteGroup::Comment, "ip:1.2.3.4")
+        .unwrap_err();
+    assert!(wait.as_secs() > 0 && wait.as_secs() <= 1800);
+
+    assert!(limiter.check(RouteGroup::Comment, "ip:5.6.7.8").is_ok());
+    assert!(limiter.check(RouteGroup::Upload, "ip:1.2.3.4").is_ok());
+
+    let snapshot = limiter.snapshot();
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(snapshot[0].key, "ip:1.2.3.4");
+    assert_eq!(snapshot[0].remaining, 0);
+}
+
+#[rocket::async_test]
+async fn requests_without_ip_or_user_share_a_bucket() {
+    use rocket::local::asynchronous::Client;
+
+    let mut state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    state.rate_limiter = RateLimiter::new(RateLimitConfig {
+        upload: None,
+        comment: None,
+        login: None,
+        search: Some(BucketConfig {
+            capacity: 2,
+            period_secs: 60,
+        }),
+    });
+    let rocket = rocket::build()
+        .manage(state)
+        .mount("/", rocket::routes![crate::routes::videos::list_videos]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    for _ in 0..2 {
+        assert_eq!(client.get("/videos").dispatch().await.status(), Status::Ok);
+    }
+    assert_eq!(
+        client.get("/videos").dispatch().await.status(),
+        Status::TooManyRequests
+    );
+}
+
+#[test]
+fn rate_limiter_only_charges_when_every_bucket_has_room() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        upload: None,
+        comment: Some(BucketConfig {
+            capacity: 2,
+            period_secs: 3600,
+        }),
+        login: None,
+        search: None,
+    });
+    let keys = ["ip:1.2.3.4".to_owned(), "user:osu:1".to_owned()];
+
+    assert!(limiter.check_all(RouteGroup::Comment, &keys).is_ok());
+    assert!(limiter.check(RouteGroup::Comment, "user:osu:1").is_ok());
+    for _ in 0..3 {
+        let (key, _) = limiter.check_all(RouteGroup::Comment, &keys).unwrap_err();
+        assert_eq!(key, "user:osu:1");
+    }
+    // The rejected requests left the IP's remaining token alone.
+    assert!(limiter.check(RouteGroup::Comment, "ip:1.2.3.4").is_ok());
+    assert!(limiter.check(RouteGroup::Comment, "ip:1.2.3.4").is_err());
+}
+
+#[test]
+fn api_token_generate_and_hash() {
+    let raw = generate_api_token();
+    assert!(raw.starts_with(API_TOKEN_PREFIX));
+    assert_ne!(raw, generate_api_token());
+
+    let hash = hash_api_token(&raw);
+    assert_eq!(hash.len(), 64);
+    assert_eq!(hash, hash_api_token(&raw));
+    assert_ne!(hash, hash_api_token("sk67_other"));
+}
+
+#[test]
+fn api_token_view_omits_hash() {
+    let token = ApiToken {
+        id: "t1".into(),
+        name: "ci".into(),
+        token_hash: "deadbeef".into(),
+        owner_provider: "osu".into(),
+        owner_id: 1,
+        owner_name: "user".into(),
+        owner_avatar_url: String::new(),
+        owner_user: "u1".into(),
+        scopes: vec![TokenScope::Upload],
+        created_at: chrono::Utc::now(),
+        last_used_at: None,
+    };
+
+    let json = serde_json::to_string(&ApiTokenView::from(&token)).unwrap();
+    assert!(json.contains("\"scopes\":[\"upload\"]"));
+    assert!(!json.contains("deadbeef"));
+}
+
+#[test]
+fn token_scopes_limit_authenticated_user() {
+    let platform_user = PlatformUser {
+        provider: "osu".into(),
+        id: 1,
+        username: "user".into(),
+        avatar_url: String::new(),
+        user_id: "u1".into(),
+    };
+    let session = AuthenticatedUser(
+        platform_user.clone(),
+        AuthSource::Session { id: "s1".into() },
+    );
+    assert!(session.is_session());
+    assert!(session.has_scope(TokenScope::Admin));
+
+    let token = AuthenticatedUser(
+        platform_user,
+        AuthSource::Token {
+            id: "t1".into(),
+            scopes: vec![TokenScope::Upload],
+        },
+    );
+    assert!(!token.is_session());
+    assert!(token.has_scope(TokenScope::Upload));
+    assert!(!token.has_scope(TokenScope::Comment));
+    assert!(!token.has_scope(TokenScope::Admin));
+}
+
+fn temp_upload_dir() -> String {
+    let dir = std::env::temp_dir().join(format!("skibidi67-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().into_owned()
+}
+
+fn test_user(id: u64) -> PlatformUser {
+    PlatformUser {
+        provider: "osu".into(),
+        id,
+        username: format!("user{}", id),
+        avatar_url: String::new(),
+        user_id: String::new(),
+    }
+}
+
+fn test_key_config(algorithm: KeyAlgorithm) -> KeyConfig {
+    KeyConfig {
+        algorithm,
+        keys_file: None,
+        grace: chrono::Duration::days(30),
+        rotate_after: None,
+        rsa_private_key_file: None,
+        rsa_public_key_file: None,
+        env_secret: None,
+    }
+}
+
+#[test]
+fn jwt_carries_session_id() {
+    let dir = temp_upload_dir();
+    let store = SessionStore::load(&dir);
+    let keys = KeyRing::load(test_key_config(KeyAlgorithm::HS256)).unwrap();
+    let other_keys = KeyRing::load(test_key_config(KeyAlgorithm::HS256)).unwrap();
+    let user = test_user(1);
+    let session = store.create(&user, chrono::Duration::days(1), ClientInfo::default());
+
+    let jwt = create_jwt(&user, &session, &keys);
+    let claims = validate_jwt(&jwt, &keys).unwrap();
+    assert_eq!(claims.sid, session.id);
+    assert_eq!(claims.exp, session.expires_at.timestamp() as u64);
+    assert!(validate_jwt(&jwt, &other_keys).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn key_rotation_keeps_old_tokens_valid() {
+    let keys = KeyRing::load(test_key_config(KeyAlgorithm::HS256)).unwrap();
+    let user = test_user(1);
+    let session = SessionStore::load(&temp_upload_dir()).create(
+        &user,
+        chrono::Duration::days(1),
+        ClientInfo::default(),
+    );
+
+    let old_kid = keys.signing_kid().unwrap();
+    let old = create_jwt(&user, &session, &keys);
+    let new_kid = keys.rotate().unwrap();
+    assert_ne!(old_kid, new_kid);
+    assert_eq!(keys.signing_kid().as_deref(), Some(new_kid.as_str()));
+
+    let new = create_jwt(&user, &session, &keys);
+    let header = jsonwebtoken::decode_header(&new).unwrap();
+    assert_eq!(header.kid.as_deref(), Some(new_kid.as_str()));
+    assert!(validate_jwt(&old, &keys).is_ok());
+    assert!(validate_jwt(&new, &keys).is_ok());
+
+    let info = keys.info();
+    assert_eq!(info.len(), 2);
+    assert!(info[0].signing && info[0].retired_at.is_none());
+    assert!(info[1].expires_at.is_some());
+}
+
+#[test]
+fn key_rotation_without_grace_drops_old_key() {
+    let mut config = test_key_config(KeyAlgorithm::EdDSA);
+    config.grace = chrono::Duration::zero();
+    let keys = KeyRing::load(config).unwrap();
+    let user = test_user(1);
+    let session = SessionStore::load(&temp_upload_dir()).create(
+        &user,
+        chrono::Duration::days(1),
+        ClientInfo::default(),
+    );
+
+    let old = create_jwt(&user, &session, &keys);
+    assert_eq!(
+        jsonwebtoken::decode_header(&old).unwrap().alg,
+        jsonwebtoken::Algorithm::EdDSA
+    );
+    assert!(validate_jwt(&old, &keys).is_ok());
+    keys.rotate().unwrap();
+    assert!(validate_jwt(&old, &keys).is_err());
+    assert_eq!(keys.info().len(), 1);
+}
+
+#[test]
+fn key_ring_accepts_legacy_jwt_secret() {
+    let mut config = test_key_config(KeyAlgorithm::HS256);
+    config.env_secret = Some("legacy-secret".into());
+    let keys = KeyRing::load(config).unwrap();
+    assert_eq!(keys.signing_kid().as_deref(), Some(ENV_KID));
+
+    let legacy = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &serde_json::json!({ "sub": "x", "exp": jsonwebtoken::get_current_timestamp() + 60 }),
+        &jsonwebtoken::EncodingKey::from_secret(b"legacy-secret"),
+    )
+    .unwrap();
+    assert!(keys.verify::<serde_json::Value>(&legacy).is_ok());
+
+    keys.rotate().unwrap();
+    assert_ne!(keys.signing_kid().as_deref(), Some(ENV_KID));
+    assert!(keys.verify::<serde_json::Value>(&legacy).is_ok());
+    let env = keys.info().into_iter().find(|k| k.from_env).unwrap();
+    assert!(env.expires_at.is_some());
+}
+
+#[test]
+fn jwt_secret_key_expires_after_a_newer_key_takes_over() {
+    let dir = temp_upload_dir();
+    let mut config = test_key_config(KeyAlgorithm::HS256);
+    config.keys_file = Some(std::path::Path::new(&dir).join("jwt_keys.json"));
+    config.env_secret = Some("legacy-secret".into());
+    KeyRing::load(config.clone()).unwrap().rotate().unwrap();
+
+    let legacy = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &serde_json::json!({ "sub": "x", "exp": jsonwebtoken::get_current_timestamp() + 60 }),
+        &jsonwebtoken::EncodingKey::from_secret(b"legacy-secret"),
+    )
+    .unwrap();
+    let reloaded = KeyRing::load(config.clone()).unwrap();
+    assert!(reloaded.verify::<serde_json::Value>(&legacy).is_ok());
+
+    config.grace = chrono::Duration::zero();
+    let reloaded = KeyRing::load(config).unwrap();
+    assert!(reloaded.verify::<serde_json::Value>(&legacy).is_err());
+    assert!(reloaded.info().iter().all(|k| !k.from_env));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn key_ring_persists_keys_file() {
+    let dir = temp_upload_dir();
+    let mut config = test_key_config(KeyAlgorithm::HS256);
+    config.keys_file = Some(std::path::Path::new(&dir).join("jwt_keys.json"));
+
+    let keys = KeyRing::load(config.clone()).unwrap();
+    let kid = keys.signing_kid().unwrap();
+    let token =
+        keys.sign(&serde_json::json!({ "exp": jsonwebtoken::get_current_timestamp() + 60 }));
+
+    let reloaded = KeyRing::load(config).unwrap();
+    assert_eq!(reloaded.signing_kid(), Some(kid));
+    assert!(reloaded.verify::<serde_json::Value>(&token).is_ok());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn session_store_revocation() {
+    let dir = temp_upload_dir();
+    let store = SessionStore::load(&dir);
+    let a = store.create(
+        &test_user(1),
+        chrono::Duration::days(1),
+        ClientInfo::default(),
+    );
+    let b = store.create(
+        &test_user(1),
+        chrono::Duration::days(1),
+        ClientInfo::default(),
+    );
+    let other = store.create(
+        &test_user(2),
+        chrono::Duration::days(1),
+        ClientInfo::default(),
+    );
+
+    assert!(store.touch(&a.id).is_some());
+    assert!(store.revoke(&a.id).is_some());
+    assert!(store.touch(&a.id).is_none());
+    assert_eq!(store.list_for("osu", 1).len(), 1);
+
+    let reloaded = SessionStore::load(&dir);
+    assert!(reloaded.touch(&b.id).is_some());
+    assert_eq!(reloaded.revoke_all_for("osu", 1), 1);
+    assert!(reloaded.touch(&b.id).is_none());
+    assert!(reloaded.touch(&other.id).is_some());
+
+    let expired = store.create(
+        &test_user(3),
+        chrono::Duration::seconds(-1),
+        ClientInfo::default(),
+    );
+    assert!(store.touch(&expired.id).is_none());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn external_id_keeps_numeric_ids() {
+    assert_eq!(external_id(&serde_json::json!(42)), Some(42));
+    assert_eq!(
+        external_id(&serde_json::json!("80351110224678912")),
+        Some(80351110224678912)
+    );
+    let hashed = external_id(&serde_json::json!("f3b0c442-98fc-4c14-9afb-f4c8996fb924")).unwrap();
+    assert_eq!(
+        Some(hashed),
+        external_id(&serde_json::json!("f3b0c442-98fc-4c14-9afb-f4c8996fb924"))
+    );
+    assert_eq!(external_id(&serde_json::json!("")), None);
+    assert_eq!(external_id(&serde_json::json!(null)), None);
+}
+
+#[test]
+fn field_mapping_renders_avatar_template() {
+    let fields = FieldMapping {
+        id: "id".into(),
+        username: "profile.name".into(),
+        avatar_url: Some("https://cdn.example.com/{id}/{avatar}.png".into()),
+        avatar_fallback: Some("https://cdn.example.com/default.png".into()),
+    };
+
+    let user = fields
+        .map_user(
+            "example",
+            &serde_json::json!({ "id": "7", "profile": { "name": "alice" }, "avatar": "abc" }),
+        )
+        .unwrap();
+    assert_eq!(user.provider, "example");
+    assert_eq!(user.id, 7);
+    assert_eq!(user.username, "alice");
+    assert_eq!(user.avatar_url, "https://cdn.example.com/7/abc.png");
+
+    let user = fields
+        .map_user("example", &serde_json::json!({ "id": 8, "avatar": null }))
+        .unwrap();
+    assert_eq!(user.username, "8");
+    assert_eq!(user.avatar_url, "https://cdn.example.com/default.png");
+
+    assert!(
+        fields
+            .map_user("example", &serde_json::json!({ "name": "x" }))
+            .is_err()
+    );
+}
+
+fn mock_provider(name: &str, issuer: Option<String>) -> ProviderConfig {
+    ProviderConfig {
+        name: name.into(),
+        display_name: None,
+        client_id: "client".into(),
+        client_secret: "secret".into(),
+        redirect_uri: format!("http://localhost/auth/{}/callback", name),
+        issuer,
+        authorize_url: None,
+        token_url: None,
+        userinfo_url: None,
+        scopes: None,
+        token_auth: TokenAuth::Post,
+        fields: None,
+    }
+}
+
+#[test]
+fn provider_registry_validates_configs() {
+    assert!(ProviderRegistry::new(vec![mock_provider("ok", Some("http://idp".into()))]).is_ok());
+    assert!(
+        ProviderRegistry::new(vec![mock_provider("Bad Name", Some("http://idp".into()))]).is_err()
+    );
+    assert!(ProviderRegistry::new(vec![mock_provider("no-urls", None)]).is_err());
+    for reserved in ["local", "dev", "sessions", "tokens"] {
+        assert!(
+            ProviderRegistry::new(vec![mock_provider(reserved, Some("http://idp".into()))])
+                .is_err(),
+            "{reserved}"
+        );
+    }
+    assert!(
+        ProviderRegistry::new(vec![
+            mock_provider("dup", Some("http://idp".into())),
+            mock_provider("dup", Some("http://idp".into())),
+        ])
+        .is_err()
+    );
+}
+
+/// A minimal OpenID provider: discovery, a token endpoint that accepts the
+/// code `good-code`, and a userinfo endpoint.
+async fn spawn_mock_idp() -> String {
+    use rocket::tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let issuer = base.clone();
+
+    rocket::tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                return;
+            };
+            let issuer = issuer.clone();
+            rocket::tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let (head, body) = loop {
+                    let n = stream.read(&mut chunk).await.unwrap_or(0);
+                    if n == 0 {
+                        return;
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).into_owned();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let head = text[..end].to_owned();
+                        let len = head
+                            .lines()
+                            .find_map(|l| {
+                                l.to_ascii_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap_or(0))
+                            })
+                            .unwrap_or(0);
+                        if buf.len() >= end + 4 + len {
+                            break (head, text[end + 4..].to_owned());
+                        }
+                    }
+                };
+
+                let path = head.split_whitespace().nth(1).unwrap_or("/");
+                let (status, json) = match path {
+                    "/.well-known/openid-configuration" => (
+                        "200 OK",
+                        serde_json::json!({
+                            "issuer": issuer,
+                            "authorization_endpoint": format!("{}/authorize", issuer),
+                            "token_endpoint": format!("{}/token", issuer),
+                            "userinfo_endpoint": format!("{}/userinfo", issuer),
+                        }),
+                    ),
+                    "/token"
+                        if body.contains("code=good-code")
+                            && body.contains("client_secret=secret") =>
+                    {
+                        (
+                            "200 OK",
+                            serde_json::json!({ "access_token": "mock-access", "token_type": "Bearer" }),
+                        )
+                    }
+                    "/token" if body.contains("code=broken-idp") => (
+                        "503 Service Unavailable",
+                        serde_json::json!({ "error": "temporarily_unavailable" }),
+                    ),
+                    "/token" => (
+                        "400 Bad Request",
+                        serde_json::json!({ "error": "invalid_grant" }),
+                    ),
+                    "/userinfo" if head.contains("Bearer mock-access") => (
+                        "200 OK",
+                        serde_json::json!({
+                            "sub": "f3b0c442-98fc-4c14-9afb-f4c8996fb924",
+                            "preferred_username": "alice",
+                            "picture": "https://idp.example/alice.png",
+                        }),
+                    ),
+                    _ => (
+                        "400 Bad Request",
+                        serde_json::json!({ "error": "invalid_request" }),
+                    ),
+                };
+                let body = json.to_string();
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+
+    base
+}
+
+fn test_state(providers: ProviderRegistry, upload_dir: String) -> AppState {
+    test_state_with_local(
+        providers,
+        upload_dir,
+        LocalAuthConfig {
+            enabled: false,
+            registration: RegistrationMode::Open,
+        },
+    )
+}
+
+fn test_state_with_local(
+    providers: ProviderRegistry,
+    upload_dir: String,
+    local_auth: LocalAuthConfig,
+) -> AppState {
+    AppState::new(
+        providers,
+        hashbrown::HashMap::new(),
+        upload_dir,
+        UploadLimits::from_env(),
+        RateLimitConfig {
+            upload: None,
+            comment: None,
+            login: None,
+            search: None,
+        },
+        KeyRing::load(test_key_config(KeyAlgorithm::HS256)).unwrap(),
+        local_auth,
+        false,
+    )
+}
+
+#[rocket::async_test]
+async fn oidc_login_against_mock_idp() {
+    use rocket::local::asynchronous::Client;
+
+    let issuer = spawn_mock_idp().await;
+    let providers =
+        ProviderRegistry::new(vec![mock_provider("mock", Some(issuer.clone()))]).unwrap();
+    let rocket = rocket::build()
+        .manage(test_state(providers, temp_upload_dir()))
+        .mount(
+            "/",
+            rocket::routes![
+                crate::routes::auth::provider_login,
+                crate::routes::auth::provider_callback,
+                crate::routes::auth::me,
+            ],
+        );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let res = client.get("/auth/mock/login").dispatch().await;
+    assert_eq!(res.status(), Status::SeeOther);
+    let location = res.headers().get_one("Location").unwrap().to_owned();
+    assert!(location.starts_with(&format!("{}/authorize?client_id=client&", issuer)));
+    assert!(location.contains("scope=openid%20profile"));
+    let csrf_state = location.rsplit("state=").next().unwrap().to_owned();
+
+    let res = client
+        .get(format!(
+            "/auth/mock/callback?code=bad-code&state={}",
+            csrf_state
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert!(
+        body["message"].as_str().unwrap().contains("invalid_grant"),
+        "{body}"
+    );
+
+    let res = client.get("/auth/mock/login").dispatch().await;
+    let location = res.headers().get_one("Location").unwrap().to_owned();
+    let csrf_state = location.rsplit("state=").next().unwrap().to_owned();
+    let res = client
+        .get(format!(
+            "/auth/mock/callback?code=broken-idp&state={}",
+            csrf_state
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadGateway);
+
+    let res = client.get("/auth/mock/login").dispatch().await;
+    let location = res.headers().get_one("Location").unwrap().to_owned();
+    let csrf_state = location.rsplit("state=").next().unwrap().to_owned();
+    let res = client
+        .get(format!(
+            "/auth/mock/callback?code=good-code&state={}",
+            csrf_state
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::SeeOther);
+
+    let me: serde_json::Value = client
+        .get("/auth/me")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(me["provider"], "mock");
+    assert_eq!(me["username"], "alice");
+    assert_eq!(me["avatar_url"], "https://idp.example/alice.png");
+    assert_eq!(
+        me["id"].as_u64(),
+        external_id(&serde_json::json!("f3b0c442-98fc-4c14-9afb-f4c8996fb924"))
+    );
+
+    let res = client
+        .get(format!(
+            "/auth/mock/callback?code=good-code&state={}",
+            csrf_state
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    let res = client.get("/auth/unknown/login").dispatch().await;
+    assert_eq!(res.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn account_links_complete_only_in_the_session_that_started_them() {
+    use {
+        crate::auth::{SESSION_COOKIE, session_ttl},
+        rocket::{http::Cookie, local::asynchronous::Client},
+    };
+
+    let issuer = spawn_mock_idp().await;
+    let providers = ProviderRegistry::new(vec![mock_provider("mock", Some(issuer))]).unwrap();
+    let state = test_state(providers, temp_upload_dir());
+    let mut bob = test_user(2);
+    bob.user_id = state.users.sign_in(&bob).id;
+    let session = state
+        .sessions
+        .create(&bob, session_ttl(false), ClientInfo::default());
+    let bob_cookie = Cookie::new(SESSION_COOKIE, create_jwt(&bob, &session, &state.jwt_keys));
+    // The identity the mock IdP signs in as already has its own account.
+    let alice_id = external_id(&serde_json::json!("f3b0c442-98fc-4c14-9afb-f4c8996fb924")).unwrap();
+    let alice = PlatformUser {
+        provider: "mock".into(),
+        ..test_user(alice_id)
+    };
+    let alice_user = state.users.sign_in(&alice).id;
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::auth::provider_link,
+            crate::routes::auth::provider_callback,
+        ],
+    );
+    let client = Client::untracked(rocket).await.unwrap();
+    let start_link = |query: &'static str| {
+        let cookie = bob_cookie.clone();
+        let client = &client;
+        async move {
+            let res = client
+                .get(format!("/auth/mock/link{query}"))
+                .cookie(cookie)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::SeeOther);
+            let location = res.headers().get_one("Location").unwrap().to_owned();
+            location.rsplit("state=").next().unwrap().to_owned()
+        }
+    };
+    let callback =
+        |csrf_state: &str| format!("/auth/mock/callback?code=good-code&state={csrf_state}");
+
+    // Someone else's browser finishing bob's link gets nowhere.
+    let csrf_state = start_link("").await;
+    let res = client.get(callback(&csrf_state)).dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+    let users = &client.rocket().state::<AppState>().unwrap().users;
+    assert_eq!(
+        users.user_id_for("mock", alice_id),
+        Some(alice_user.clone())
+    );
+
+    // Bob's own session may not take over another account by linking...
+    let csrf_state = start_link("").await;
+    let res = client
+        .get(callback(&csrf_state))
+        .cookie(bob_cookie.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Conflict);
+    assert_eq!(
+        users.user_id_for("mock", alice_id),
+        Some(alice_user.clone())
+    );
+
+    // ...unless he asked to merge it in.
+    let csrf_state = start_link("?merge=true").await;
+    let res = client
+        .get(callback(&csrf_state))
+        .cookie(bob_cookie.clone())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::SeeOther);
+    assert_eq!(
+        users.user_id_for("mock", alice_id),
+        Some(bob.user_id.clone())
+    );
+    assert!(users.get(&alice_user).is_none());
+}
+
+#[test]
+fn default_provider_prefers_osu_then_a_sole_provider() {
+    let only =
+        ProviderRegistry::new(vec![mock_provider("gitlab", Some("http://idp".into()))]).unwrap();
+    assert_eq!(only.default_provider().map(|p| p.name()), Some("gitlab"));
+
+    let several = ProviderRegistry::new(vec![
+        mock_provider("gitlab", Some("http://idp".into())),
+        mock_provider("keycloak", Some("http://idp".into())),
+    ])
+    .unwrap();
+    assert!(several.default_provider().is_none());
+
+    let with_osu = ProviderRegistry::new(vec![
+        mock_provider("gitlab", Some("http://idp".into())),
+        mock_provider("osu", Some("http://idp".into())),
+    ])
+    .unwrap();
+    assert_eq!(with_osu.default_provider().map(|p| p.name()), Some("osu"));
+
+    assert!(ProviderRegistry::new(Vec::new()).unwrap().is_empty());
+}
+
+#[test]
+fn user_store_links_and_unlinks_identities() {
+    let dir = temp_upload_dir();
+    let users = UserStore::load(&dir);
+
+    let account = users.sign_in(&test_user(1));
+    assert_eq!(users.ensure("osu", 1, "user1"), account.id);
+
+    let github = PlatformUser {
+        provider: "github".into(),
+        ..test_user(7)
+    };
+    assert_eq!(users.link(&account.id, &github, false).unwrap(), None);
+    assert_eq!(
+        users.user_id_for("github", 7).as_deref(),
+        Some(account.id.as_str())
+    );
+    assert_eq!(users.get(&account.id).unwrap().identities.len(), 2);
+
+    let reloaded = UserStore::load(&dir);
+    assert_eq!(
+        reloaded.user_id_for("github", 7).as_deref(),
+        Some(account.id.as_str())
+    );
+
+    let account = users.unlink(&account.id, "github", 7).unwrap();
+    assert_eq!(account.identities.len(), 1);
+    assert!(users.user_id_for("github", 7).is_none());
+    assert!(matches!(
+        users.unlink(&account.id, "github", 7),
+        Err(AppError::IdentityNotFound)
+    ));
+    assert!(matches!(
+        users.unlink(&account.id, "osu", 1),
+        Err(AppError::LastIdentity)
+    ));
+}
+
+#[test]
+fn linking_accounts_merges_ownership() {
+    let dir = temp_upload_dir();
+    std::fs::write(
+        std::path::Path::new(&dir).join("v1.meta.json"),
+        r#"{
+            "id": "v1",
+            "title": "Legacy",
+            "filename": "v1.mp4",
+            "content_type": "video/mp4",
+            "size_bytes": 1,
+            "sha256": "abc",
+            "uploaded_by_id": 1,
+            "uploaded_by_name": "user1",
+            "uploaded_at": "2024-01-01T00:00:00Z",
+            "nsfw": false,
+            "references_id": null
+        }"#,
+    )
+    .unwrap();
+    std::fs::write(
+        std::path::Path::new(&dir).join("v1.comments.json"),
+        r#"[{
+            "id": "c1",
+            "video_id": "v1",
+            "author_provider": "github",
+            "author_id": 7,
+            "author_name": "user7",
+            "text": "Hello",
+            "created_at": "2024-01-01T00:00:00Z"
+        }]"#,
+    )
+    .unwrap();
+
+    let state = test_state(ProviderRegistry::new(Vec::new()).unwrap(), dir.clone());
+    let osu_user = state.users.user_id_for("osu", 1).unwrap();
+    let github_user = state.users.user_id_for("github", 7).unwrap();
+    assert_ne!(osu_user, github_user);
+    assert_eq!(state.videos.get("v1").unwrap().uploaded_by_user, osu_user);
+    assert_eq!(
+        state.comments.get("v1").unwrap()[0].author_user,
+        github_user
+    );
+
+    let github = PlatformUser {
+        provider: "github".into(),
+        ..test_user(7)
+    };
+    assert!(matches!(
+        state.link_identity(&osu_user, &github, false),
+        Err(AppError::IdentityInUse)
+    ));
+    assert!(state.users.get(&github_user).is_some());
+    let merged = state.link_identity(&osu_user, &github, true).unwrap();
+    assert_eq!(merged.identities.len(), 2);
+    assert!(state.users.get(&github_user).is_none());
+    assert_eq!(state.comments.get("v1").unwrap()[0].author_user, osu_user);
+
+    let reloaded = test_state(ProviderRegistry::new(Vec::new()).unwrap(), dir);
+    assert_eq!(
+        reloaded.users.user_id_for("github", 7),
+        Some(osu_user.clone())
+    );
+    assert_eq!(
+        reloaded.comments.get("v1").unwrap()[0].author_user,
+        osu_user
+    );
+}
+
+#[test]
+fn totp_matches_rfc6238_vectors() {
+    let secret = b"12345678901234567890";
+    assert_eq!(totp_code(secret, 59 / 30), 287082);
+    assert_eq!(totp_code(secret, 1111111109 / 30), 81804);
+    assert_eq!(totp_code(secret, 2000000000 / 30), 279037);
+
+    let encoded = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, secret);
+    assert_eq!(verify_totp(&encoded, "287082", 59), Some(1));
+    assert_eq!(verify_totp(&encoded, "287082", 89), Some(1));
+    assert_eq!(verify_totp(&encoded, "287082", 200), None);
+    assert_eq!(verify_totp(&encoded, "not a code", 59), None);
+}
+
+#[test]
+fn local_registration_modes_and_invites() {
+    let dir = temp_upload_dir();
+    let config = LocalAuthConfig {
+        enabled: true,
+        registration: RegistrationMode::Invite,
+    };
+    let local = LocalAccountStore::load(&dir, &config);
+
+    assert!(matches!(
+        local.register("alice", "hash".into(), None),
+        Err(AppError::InvalidInvite)
+    ));
+    let (code, _) = local.create_invite("admin");
+    let alice = local.register("alice", "hash".into(), Some(&code)).unwrap();
+    assert!(matches!(
+        local.register("bob", "hash".into(), Some(&code)),
+        Err(AppError::InvalidInvite)
+    ));
+
+    local.set_registration(RegistrationMode::Open);
+    assert!(matches!(
+        local.register("ALICE", "hash".into(), None),
+        Err(AppError::UsernameTaken)
+    ));
+    assert!(matches!(
+        local.register("no spaces", "hash".into(), None),
+        Err(AppError::InvalidUsername)
+    ));
+    let bob = local.register("bob", "hash".into(), None).unwrap();
+    assert_ne!(alice.id, bob.id);
+
+    let names: Vec<String> = (0..16).map(|i| format!("user{i}")).collect();
+    let ids: Vec<u64> = std::thread::scope(|s| {
+        let handles: Vec<_> = names
+            .iter()
+            .map(|name| s.spawn(|| local.register(name, "hash".into(), None).unwrap().id))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(
+        ids.iter().collect::<std::collections::HashSet<_>>().len(),
+        names.len()
+    );
+
+    local.set_registration(RegistrationMode::Closed);
+    assert!(matches!(
+        local.register("carol", "hash".into(), None),
+        Err(AppError::RegistrationClosed)
+    ));
+
+    let reloaded = LocalAccountStore::load(&dir, &config);
+    assert_eq!(reloaded.registration(), RegistrationMode::Closed);
+    assert_eq!(reloaded.account_count(), 18);
+    assert_eq!(reloaded.find_by_username("Bob").unwrap().id, bob.id);
+}
+
+#[test]
+fn local_password_reset_links_are_single_use() {
+    let dir = temp_upload_dir();
+    let local = LocalAccountStore::load(
+        &dir,
+        &LocalAuthConfig {
+            enabled: true,
+            registration: RegistrationMode::Open,
+        },
+    );
+    let account = local.register("alice", "old".into(), None).unwrap();
+
+    let (first, _) = local.create_reset(account.id).unwrap();
+    let (second, _) = local.create_reset(account.id).unwrap();
+    assert!(local.reset_account(&first).is_none());
+    assert_eq!(local.reset_account(&second), Some(account.id));
+
+    assert_eq!(
+        local.complete_reset(&second, "new".into()).unwrap(),
+        account.id
+    );
+    assert_eq!(local.get(account.id).unwrap().password_hash, "new");
+    assert!(matches!(
+        local.complete_reset(&second, "newer".into()),
+        Err(AppError::InvalidResetLink)
+    ));
+    assert!(matches!(
+        local.create_reset(account.id + 1),
+        Err(AppError::UserNotFound)
+    ));
+}
+
+#[rocket::async_test]
+async fn local_login_with_totp() {
+    use rocket::{http::ContentType, local::asynchronous::Client};
+
+    let state = test_state_with_local(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+        LocalAuthConfig {
+            enabled: true,
+            registration: RegistrationMode::Open,
+        },
+    );
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::auth::me,
+            crate::routes::auth::logout,
+            crate::routes::local::register,
+            crate::routes::local::login,
+            crate::routes::local::totp_setup,
+            crate::routes::local::totp_enable,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let post = |uri: &'static str, body: serde_json::Value| {
+        client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(body.to_string())
+    };
+
+    let res = post(
+        "/auth/local/register",
+        serde_json::json!({ "username": "alice", "password": "short" }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    let res = post(
+        "/auth/local/register",
+        serde_json::json!({ "username": "alice", "password": "correct horse" }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Created);
+
+    let me: serde_json::Value = client
+        .get("/auth/me")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(me["provider"], "local");
+    assert_eq!(me["username"], "alice");
+
+    let setup: serde_json::Value = client
+        .post("/auth/local/totp/setup")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let secret = base32::decode(
+        base32::Alphabet::Rfc4648 { padding: false },
+        setup["secret"].as_str().unwrap(),
+    )
+    .unwrap();
+    let step = chrono::Utc::now().timestamp() as u64 / 30;
+    let code = |step| format!("{:06}", totp_code(&secret, step));
+
+    let res = post(
+        "/auth/local/totp/enable",
+        serde_json::json!({ "code": code(step) }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::NoContent);
+
+    client.get("/auth/logout").dispatch().await;
+
+    let res = post(
+        "/auth/local/login",
+        serde_json::json!({ "username": "alice", "password": "wrong password" }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+
+    let res = post(
+        "/auth/local/login",
+        serde_json::json!({ "username": "alice", "password": "correct horse" }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["message"], "A two-factor code is required");
+
+    let res = post(
+        "/auth/local/login",
+        serde_json::json!({ "username": "alice", "password": "correct horse", "totp": code(step) }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+
+    let res = post(
+        "/auth/local/login",
+        serde_json::json!({ "username": "Alice", "password": "correct horse", "totp": code(step + 1) }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Ok);
+
+    // With two-factor login on, replacing the secret needs the password.
+    let res = client.post("/auth/local/totp/setup").dispatch().await;
+    assert_eq!(res.status(), Status::Unauthorized);
+    let res = post(
+        "/auth/local/totp/setup",
+        serde_json::json!({ "current_password": "wrong password" }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+    let res = post(
+        "/auth/local/totp/setup",
+        serde_json::json!({ "current_password": "correct horse" }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Ok);
+    let setup: serde_json::Value = res.into_json().await.unwrap();
+    let new_secret = base32::decode(
+        base32::Alphabet::Rfc4648 { padding: false },
+        setup["secret"].as_str().unwrap(),
+    )
+    .unwrap();
+    let new_code = format!("{:06}", totp_code(&new_secret, step));
+    let res = post(
+        "/auth/local/totp/enable",
+        serde_json::json!({ "code": new_code }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+    let res = post(
+        "/auth/local/totp/enable",
+        serde_json::json!({ "code": new_code, "current_password": "correct horse" }),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::NoContent);
+}
+
+#[test]
+fn role_permission_matrix() {
+    assert!(Permission::ALL.into_iter().all(|p| Role::Admin.can(p)));
+    assert!(Role::Banned.permissions().is_empty());
+
+    assert!(Role::Moderator.can(Permission::DeleteAnyMedia));
+    assert!(Role::Moderator.can(Permission::ManageTags));
+    assert!(Role::Moderator.can(Permission::ModerateComments));
+    assert!(!Role::Moderator.can(Permission::ManageUsers));
+    assert!(!Role::Moderator.can(Permission::BypassQuotas));
+
+    assert!(Role::Trusted.can(Permission::BypassQuotas));
+    assert!(!Role::Trusted.can(Permission::ToggleNsfw));
+    assert_eq!(
+        Role::User.permissions(),
+        vec![Permission::Upload, Permission::Comment]
+    );
+
+    assert!(Role::Admin.is_staff());
+    assert!(Role::Moderator.is_staff());
+    assert!(!Role::Trusted.is_staff());
+    assert!(!Role::Banned.is_staff());
+}
+
+#[test]
+fn permissions_follow_token_scopes() {
+    let role = Role::Moderator;
+    let upload_token = AuthSource::Token {
+        id: "t".into(),
+        scopes: vec![TokenScope::Upload],
+    };
+    let user = AuthenticatedUser(test_user(1), upload_token);
+    let can = Permissions::from_fn(|p| user.has_scope(p.scope()) && role.can(p));
+    assert!(can.upload);
+    assert!(!can.comment);
+    assert!(!can.delete_any_media);
+    assert!(!can.moderate);
+
+    let can = Permissions::from_fn(|p| role.can(p));
+    assert!(can.moderate);
+    assert!(can.manage_daily_queue);
+    assert!(!can.manage_users);
+}
+
+#[test]
+fn user_store_roles_persist_and_survive_merges() {
+    let dir = temp_upload_dir();
+    let users = UserStore::load(&dir);
+
+    let alice = users.sign_in(&test_user(1));
+    assert!(alice.role.is_none());
+    users.set_role(&alice.id, Some(Role::Moderator)).unwrap();
+    assert_eq!(
+        UserStore::load(&dir).get(&alice.id).unwrap().role,
+        Some(Role::Moderator)
+    );
+    users.set_role(&alice.id, Some(Role::User)).unwrap();
+    assert!(users.with_roles().is_empty());
+
+    let banned = users.sign_in(&test_user(2));
+    users.set_role(&banned.id, Some(Role::Banned)).unwrap();
+    users.set_role(&alice.id, Some(Role::Trusted)).unwrap();
+    users.link(&alice.id, &test_user(2), true).unwrap();
+    assert_eq!(users.get(&alice.id).unwrap().role, Some(Role::Banned));
+
+    assert!(matches!(
+        users.set_role("missing", Some(Role::Admin)),
+        Err(AppError::UserNotFound)
+    ));
+}
+
+#[rocket::async_test]
+async fn dev_login_picks_identity_and_role() {
+    use rocket::{http::ContentType, local::asynchronous::Client};
+
+    let mut state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let unknown = test_user(9);
+    assert_eq!(state.role_of(&unknown), Role::User);
+    state.dev_login = true;
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::auth::me,
+            crate::routes::auth::me_unauthenticated,
+            crate::routes::dev::login,
+            crate::routes::sessions::admin_revoke_user_sessions,
+            crate::routes::sessions::admin_revoke_user_sessions_forbidden,
+            crate::routes::sessions::admin_revoke_user_sessions_unauthorized,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let dev_login = |body: serde_json::Value| {
+        client
+            .post("/auth/dev/login")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+    };
+
+    let res = client.get("/auth/me").dispatch().await;
+    assert_eq!(res.status(), Status::Unauthorized);
+    let res = client
+        .delete("/admin/users/dev/1/sessions")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Unauthorized);
+
+    let res = dev_login(serde_json::json!({ "id": 1, "username": "plain" }))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["provider"], "dev");
+    assert_eq!(body["role"], "user");
+    let res = client
+        .delete("/admin/users/dev/1/sessions")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Forbidden);
+
+    let res = dev_login(serde_json::json!({
+        "provider": "github",
+        "id": 2,
+        "username": "boss",
+        "role": "admin",
+    }))
+    .dispatch()
+    .await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["role"], "admin");
+    let res = client
+        .delete("/admin/users/dev/1/sessions")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let me: serde_json::Value = client
+        .get("/auth/me")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(me["provider"], "github");
+    assert_eq!(me["username"], "boss");
+}
+
+#[rocket::async_test]
+async fn dev_login_is_off_by_default() {
+    use rocket::{http::ContentType, local::asynchronous::Client};
+
+    let rocket = rocket::build()
+        .manage(test_state(
+            ProviderRegistry::new(Vec::new()).unwrap(),
+            temp_upload_dir(),
+        ))
+        .mount("/", rocket::routes![crate::routes::dev::login]);
+    let client = Client::tracked(rocket).await.unwrap();
+    let res = client
+        .post("/auth/dev/login")
+        .header(ContentType::JSON)
+        .body(serde_json::json!({ "id": 1, "username": "x", "role": "admin" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NotFound);
+}
+
+#[test]
+fn sanctions_expire_lift_and_follow_merges() {
+    let dir = temp_upload_dir();
+    let sanctions = SanctionStore::load(&dir);
+
+    let expired = sanctions.add(
+        "u1",
+        SanctionKind::Ban,
+        "old",
+        "admin",
+        Some(chrono::Utc::now() - chrono::Duration::hours(1)),
+    );
+    assert!(sanctions.check("u1", SanctionKind::Ban).is_ok());
+
+    let mute = sanctions.add("u1", SanctionKind::Comment, "spam", "admin", None);
+    assert!(sanctions.check("u1", SanctionKind::Upload).is_ok());
+    match sanctions.check("u1", SanctionKind::Comment) {
+        Err(AppError::Sanctioned(msg)) => assert_eq!(msg, "suspended from commenting: spam"),
+        other => panic!("expected a sanction, got {:?}", other.map(|_| ())),
+    }
+
+    let until = chrono::Utc::now() + chrono::Duration::hours(2);
+    sanctions.add("u2", SanctionKind::Ban, "abuse", "admin", Some(until));
+    assert!(sanctions.active_for("u2", SanctionKind::Upload).is_some());
+    assert_eq!(sanctions.reassign("u2", "u1"), 1);
+    assert!(sanctions.active_for("u2", SanctionKind::Ban).is_none());
+    assert_eq!(
+        sanctions
+            .active_for("u1", SanctionKind::Comment)
+            .unwrap()
+            .expires_at,
+        None
+    );
+
+    sanctions.lift(&mute.id, "admin").unwrap();
+    assert!(matches!(
+        sanctions.lift(&mute.id, "admin"),
+        Err(AppError::SanctionNotFound)
+    ));
+    assert!(matches!(
+        sanctions.lift(&expired.id, "admin"),
+        Err(AppError::SanctionNotFound)
+    ));
+
+    let reloaded = SanctionStore::load(&dir);
+    assert_eq!(reloaded.list(false).len(), 1);
+    assert_eq!(reloaded.list(true).len(), 3);
+    assert_eq!(
+        reloaded
+            .active_for("u1", SanctionKind::Comment)
+            .unwrap()
+            .kind,
+        SanctionKind::Ban
+    );
+}
+
+#[rocket::async_test]
+async fn banned_users_are_refused_with_the_reason() {
+    use rocket::{http::ContentType, local::asynchronous::Client};
+
+    let mut state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    state.dev_login = true;
+    let rocket = rocket::build()
+        .manage(state)
+        .mount(
+            "/",
+            rocket::routes![crate::routes::auth::me, crate::routes::dev::login],
+        )
+        .register("/", rocket::catchers![crate::auth::forbidden]);
+    let client = Client::tracked(rocket).await.unwrap();
+    let dev_login = || {
+        client
+            .post("/auth/dev/login")
+            .header(ContentType::JSON)
+            .body(serde_json::json!({ "id": 1, "username": "troll" }).to_string())
+    };
+
+    let res = dev_login().dispatch().await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    let user_id = body["user_id"].as_str().unwrap().to_owned();
+
+    let state = client.rocket().state::<AppState>().unwrap();
+    state
+        .sanctions
+        .add(&user_id, SanctionKind::Ban, "spam", "admin", None);
+
+    let res = client.get("/auth/me").dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["message"], "Your account is banned: spam");
+    assert_eq!(body["reason"], "spam");
+
+    let res = dev_login().dispatch().await;
+    assert_eq!(res.status(), Status::Forbidden);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["message"], "Your account is banned: spam");
+}
+
+/// Issues an API token for the given login so tests can call routes behind
+/// user guards.
+fn bearer_token_for(
+    state: &AppState,
+    provider: &str,
+    id: u64,
+    name: &str,
+    scope: TokenScope,
+) -> String {
+    let raw = generate_api_token();
+    state.api_tokens.insert(
+        hash_api_token(&raw),
+        ApiToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: "test".into(),
+            token_hash: hash_api_token(&raw),
+            owner_provider: provider.into(),
+            owner_id: id,
+            owner_name: name.into(),
+            owner_avatar_url: String::new(),
+            owner_user: state.users.ensure(provider, id, name),
+            scopes: vec![scope],
+            created_at: chrono::Utc::now(),
+            last_used_at: None,
+        },
+    );
+    raw
+}
+
+fn owned_meta(id: &str, content_type: &str, user_id: &str, size_bytes: u64) -> VideoMeta {
+    VideoMeta {
+        id: id.into(),
+        title: format!("Title {id}"),
+        source: None,
+        source_name: None,
+        source_link: None,
+        filename: id.into(),
+        content_type: content_type.into(),
+        size_bytes,
+        sha256: id.into(),
+        tlsh_hash: None,
+        uploaded_by_provider: "osu".into(),
+        uploaded_by_id: 1,
+        uploaded_by_name: "alice".into(),
+        uploaded_by_user: user_id.into(),
+        uploaded_at: chrono::Utc::now(),
+        nsfw: false,
+        unlisted: false,
+        hidden_by_moderator: false,
+        comments_disabled: false,
+        references_id: None,
+        original_extension: None,
+        duration_secs: None,
+        tags: Vec::new(),
+    }
+}
+
+#[rocket::async_test]
+async fn profiles_count_uploads_and_hide_unlisted() {
+    use rocket::local::asynchronous::Client;
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let user_id = state.users.ensure("osu", 1, "alice");
+    let other = state.users.ensure("osu", 2, "bob");
+    for meta in [
+        owned_meta("v1", "video/mp4", &user_id, 1000),
+        owned_meta("i1", "image/png", &user_id, 24),
+        VideoMeta {
+            unlisted: true,
+            ..owned_meta("a1", "audio/mpeg", &user_id, 500)
+        },
+        owned_meta("o1", "video/mp4", &other, 7),
+    ] {
+        state.videos.insert(meta.id.clone(), meta);
+    }
+    state.comments.insert(
+        "o1".into(),
+        vec![Comment {
+            id: "c1".into(),
+            video_id: "o1".into(),
+            author_provider: "osu".into(),
+            author_id: 1,
+            author_name: "alice".into(),
+            author_user: user_id.clone(),
+            text: "nice".into(),
+            created_at: chrono::Utc::now(),
+            parent_id: None,
+            edited_at: None,
+            revisions: Vec::new(),
+            reactions: Default::default(),
+            hidden: false,
+            anchor: None,
+        }],
+    );
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::profiles::get_profile,
+            crate::routes::feed::feed_user,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let res = client.get("/users/osu/1?per_page=1").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["username"], "alice");
+    assert_eq!(body["stats"]["total"], 2);
+    assert_eq!(body["stats"]["video"], 1);
+    assert_eq!(body["stats"]["image"], 1);
+    assert_eq!(body["stats"]["audio"], 0);
+    assert_eq!(body["stats"]["total_bytes"], 1024);
+    assert_eq!(body["media"]["pages"], 2);
+    assert_eq!(body["media"]["items"].as_array().unwrap().len(), 1);
+    assert_eq!(body["recent_comments"][0]["media_id"], "o1");
+
+    let res = client.get("/users/osu/3").dispatch().await;
+    assert_eq!(res.status(), Status::NotFound);
+
+    let feed = client
+        .get("/feed/u/osu/1")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(feed.contains("Title v1"));
+    assert!(!feed.contains("Title a1"));
+    assert!(!feed.contains("Title o1"));
+}
+
+#[rocket::async_test]
+async fn display_names_follow_renames() {
+    use rocket::local::asynchronous::Client;
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let user_id = state.users.ensure("osu", 1, "alice");
+    state
+        .videos
+        .insert("v1".into(), owned_meta("v1", "video/mp4", &user_id, 10));
+    state.comments.insert(
+        "v1".into(),
+        vec![Comment {
+            id: "c1".into(),
+            video_id: "v1".into(),
+            author_provider: "osu".into(),
+            author_id: 1,
+            author_name: "alice".into(),
+            author_user: user_id.clone(),
+            text: "first".into(),
+            created_at: chrono::Utc::now(),
+            parent_id: None,
+            edited_at: None,
+            revisions: Vec::new(),
+            reactions: Default::default(),
+            hidden: false,
+            anchor: None,
+        }],
+    );
+    state.users.sign_in(&PlatformUser {
+        username: "alicia".into(),
+        ..test_user(1)
+    });
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::videos::list_videos,
+            crate::routes::videos::get_comments,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let found: serde_json::Value = client
+        .get("/videos?q=uploader.is(%22alicia%22)")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(found[0]["uploaded_by_name"], "alicia");
+    let stale: serde_json::Value = client
+        .get("/videos?q=uploader.is(%22alice%22)")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(stale, serde_json::json!([]));
+
+    let comments: serde_json::Value = client
+        .get("/videos/v1/comments")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(comments[0]["author_name"], "alicia");
+
+    let state = client.rocket().state::<AppState>().unwrap();
+    let orphan = owned_meta("v2", "video/mp4", "gone", 10);
+    assert_eq!(state.uploader_name(&orphan), "alice");
+}
+
+#[rocket::async_test]
+async fn list_endpoints_page_sort_and_select_fields() {
+    use rocket::local::asynchronous::Client;
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let user_id = state.users.ensure("osu", 1, "alice");
+    for (id, size) in [("a", 30), ("b", 10), ("c", 50), ("d", 20), ("e", 40)] {
+        state
+            .videos
+            .insert(id.into(), owned_meta(id, "video/mp4", &user_id, size));
+    }
+    state
+        .videos
+        .insert("img".into(), owned_meta("img", "image/png", &user_id, 1));
+
+    let rocket = rocket::build()
+        .manage(state)
+        .mount("/", rocket::routes![crate::routes::videos::list_videos]);
+    let client = Client::tracked(rocket).await.unwrap();
+    let get = |url: String| {
+        let client = &client;
+        async move {
+            let res = client.get(url).dispatch().await;
+            (res.status(), res.into_json::<serde_json::Value>().await)
+        }
+    };
+
+    let mut cursor = String::new();
+    let mut sizes = Vec::new();
+    loop {
+        let (status, page) = get(format!(
+            "/videos?sort=size&order=asc&limit=2&fields=size_bytes&cursor={cursor}"
+        ))
+        .await;
+        assert_eq!(status, Status::Ok);
+        let page = page.unwrap();
+        assert_eq!(page["total"], 5);
+        for item in page["items"].as_array().unwrap() {
+            assert!(item.get("title").is_none());
+            assert!(item.get("id").is_some());
+            sizes.push(item["size_bytes"].as_u64().unwrap());
+        }
+        match page["next_cursor"].as_str() {
+            Some(next) => cursor = next.to_owned(),
+            None => break,
+        }
+    }
+    assert_eq!(sizes, [10, 20, 30, 40, 50]);
+
+    let (_, page) = get("/videos?sort=title&order=desc&limit=1".into()).await;
+    assert_eq!(page.unwrap()["items"][0]["title"], "Title e");
+
+    // Without `limit` or `cursor` the response keeps its old shape: every
+    // match in a bare array.
+    let (_, all) = get("/videos?sort=size&order=asc&fields=size_bytes".into()).await;
+    let sizes: Vec<u64> = all
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["size_bytes"].as_u64().unwrap())
+        .collect();
+    assert_eq!(sizes, [10, 20, 30, 40, 50]);
+
+    let (status, _) = get("/videos?cursor=zz".into()).await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[test]
+fn query_grammar_precedence_and_legacy_filters() {
+    use crate::query::{Expr, Field, Filter, Op, Value, parse};
+
+    let size_gt = Expr::Filter(Filter {
+        field: Field::Size,
+        op: Op::Gt,
+        value: Value::Bytes(10_000_000),
+    });
+    assert_eq!(parse("   ").unwrap(), None);
+    assert_eq!(
+        parse("funny cat size > 10MB").unwrap().unwrap(),
+        Expr::And(vec![Expr::Text("funny cat".into()), size_gt.clone()])
+    );
+    assert_eq!(
+        parse("a OR b c AND NOT d").unwrap().unwrap(),
+        Expr::Or(vec![
+            Expr::Text("a".into()),
+            Expr::And(vec![
+                Expr::Text("b c".into()),
+                Expr::Not(Box::new(Expr::Text("d".into()))),
+            ]),
+        ])
+    );
+    assert_eq!(
+        parse("-(a OR \"b \\\" c\")").unwrap().unwrap(),
+        Expr::Not(Box::new(Expr::Or(vec![
+            Expr::Text("a".into()),
+            Expr::Phrase("b \" c".into()),
+        ])))
+    );
+    assert_eq!(parse("size.gt(10MB)").unwrap(), parse("size>10MB").unwrap());
+    assert_eq!(
+        parse("date.after(\"2025-01-01\")").unwrap(),
+        parse("date > 2025-01-01").unwrap()
+    );
+    assert_eq!(
+        parse("uploader.is(\"Some One\")").unwrap(),
+        parse("uploader = \"Some One\"").unwrap()
+    );
+    assert_eq!(
+        parse("uploader.is(Some One)").unwrap(),
+        parse("uploader = \"Some One\"").unwrap()
+    );
+    assert_eq!(
+        parse("title.contains(funny cat) cats").unwrap(),
+        parse("title ~ \"funny cat\" cats").unwrap()
+    );
+    assert_eq!(
+        parse("replies.contains(great video!)").unwrap(),
+        parse("replies ~ \"great video!\"").unwrap()
+    );
+    assert_eq!(
+        parse("(title.contains(a=b <3 ~x) OR b)").unwrap(),
+        parse("(title ~ \"a=b <3 ~x\" OR b)").unwrap()
+    );
+
+    let err = |q: &str| parse(q).unwrap_err();
+    assert_eq!(err("title.contians(\"x\")").position, 6);
+    assert_eq!(err("a OR").position, 4);
+    assert_eq!(err("(a OR b").position, 0);
+    assert_eq!(err("a )").position, 2);
+    assert_eq!(err("colour = red").message, "unknown field `colour`");
+    assert_eq!(err("size > big").position, 7);
+    assert_eq!(err("nsfw ~ true").message, "`nsfw` does not support `~`");
+    assert_eq!(err("title ~ \"open").position, 8);
+}
+
+fn arb_filter() -> impl proptest::strategy::Strategy<Value = crate::query::Filter> {
+    use {
+        crate::query::{Field, Filter, Op, Pattern, Value},
+        proptest::prelude::*,
+    };
+
+    (
+        prop::sample::select(Field::ALL.to_vec()),
+        any::<prop::sample::Index>(),
+        any::<u64>(),
+        0u64..40_000,
+        any::<bool>(),
+        "\\PC*",
+    )
+        .prop_map(|(field, op, bytes, days, flag, text)| {
+            let op = field.ops()[op.index(field.ops().len())];
+            let value = match field {
+                _ if op == Op::Matches => {
+                    let literal: String = text.chars().take(64).collect();
+                    Value::Regex(Pattern::new(&regex::escape(&literal)).unwrap())
+                }
+                Field::Kind => Value::Kind(MediaKind::ALL[bytes as usize % 4]),
+                Field::Nsfw => Value::Bool(flag),
+                Field::Size => Value::Bytes(bytes),
+                Field::Date => Value::Date(
+                    chrono::NaiveDate::from_ymd_opt(1970, 1, 1).unwrap() + chrono::Days::new(days),
+                ),
+                _ => Value::Text(text),
+            };
+            Filter { field, op, value }
+        })
+}
+
+fn arb_expr() -> impl proptest::strategy::Strategy<Value = crate::query::Expr> {
+    use {crate::query::Expr, proptest::prelude::*};
+
+    let leaf = prop_oneof![
+        "[a-z0-9]{1,8}( [a-z0-9]{1,8}){0,3}".prop_map(Expr::Text),
+        "\\PC{0,16}".prop_map(Expr::Phrase),
+        arb_filter().prop_map(Expr::Filter),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 2..4).prop_map(Expr::And),
+            prop::collection::vec(inner.clone(), 2..4).prop_map(Expr::Or),
+            inner.prop_map(|e| Expr::Not(Box::new(e))),
+        ]
+    })
+}
+
+proptest::proptest! {
+    #[test]
+    fn query_display_round_trips(expr in arb_expr()) {
+        let printed = expr.to_string();
+        proptest::prop_assume!(printed.len() <= crate::query::MAX_QUERY_LEN);
+        proptest::prop_assert_eq!(crate::query::parse(&printed), Ok(Some(expr)), "{}", printed);
+    }
+
+    #[test]
+    fn query_parser_never_panics(input in "[a-z0-9 ().\"\\\\=<>!~-]{0,40}|\\PC{0,40}") {
+        if let Err(e) = crate::query::parse(&input) {
+            proptest::prop_assert!(e.position <= input.chars().count());
+        }
+    }
+
+    #[test]
+    fn query_negation_follows_de_morgan(a in arb_expr(), b in arb_expr()) {
+        use crate::query::parse;
+
+        let text = |t: crate::query::FreeText| format!("{t:?}").len().is_multiple_of(2);
+        let filter = |f: &crate::query::Filter| f.to_string().len().is_multiple_of(3);
+        let (a, b) = (a.to_string(), b.to_string());
+        proptest::prop_assume!(a.len() + b.len() + 32 <= crate::query::MAX_QUERY_LEN);
+        let lhs = parse(&format!("NOT (({a}) OR ({b}))")).unwrap().unwrap();
+        let rhs = parse(&format!("NOT ({a}) AND NOT ({b})")).unwrap().unwrap();
+        proptest::prop_assert_eq!(lhs.eval(&text, &filter), rhs.eval(&text, &filter));
+    }
+}
+
+#[rocket::async_test]
+async fn list_search_supports_boolean_queries() {
+    use rocket::local::asynchronous::Client;
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let user_id = state.users.ensure("osu", 1, "alice");
+    for (id, size) in [("a", 5_000), ("b", 20_000_000), ("c", 50)] {
+        state
+            .videos
+            .insert(id.into(), owned_meta(id, "video/mp4", &user_id, size));
+    }
+    let rocket = rocket::build()
+        .manage(state)
+        .mount("/", rocket::routes![crate::routes::videos::list_videos]);
+    let client = Client::tracked(rocket).await.unwrap();
+    let ids = |body: serde_json::Value| {
+        let mut ids: Vec<String> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["id"].as_str().unwrap().to_owned())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    let res = client
+        .get("/videos?q=size%20%3E%2010MB%20OR%20title%20~%20%22Title%20c%22")
+        .dispatch()
+        .await;
+    assert_eq!(ids(res.into_json().await.unwrap()), ["b", "c"]);
+
+    let res = client.get("/videos?q=NOT%20id%20%3D%20a").dispatch().await;
+    assert_eq!(ids(res.into_json().await.unwrap()), ["b", "c"]);
+
+    let res = client.get("/videos?q=size%20%3E").dispatch().await;
+    assert_eq!(res.status(), Status::BadRequest);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["position"], 6);
+}
+
+#[rocket::async_test]
+async fn hostile_queries_are_rejected_without_recursing() {
+    use {crate::query::parse, rocket::local::asynchronous::Client};
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let rocket = rocket::build()
+        .manage(state)
+        .mount("/", rocket::routes![crate::routes::videos::list_videos]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    for q in ["(".repeat(10_000), "-x".repeat(10_000), "-(".repeat(10_000)] {
+        let res = client
+            .get(format!("/videos?q={}", q.replace('(', "%28")))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    // Short enough to pass the length cap, but nested too deep.
+    let deep = format!("{}x{}", "(".repeat(100), ")".repeat(100));
+    let err = parse(&deep).unwrap_err();
+    assert!(err.message.contains("nest"), "{err:?}");
+    assert!(parse(&format!("{}x", "NOT ".repeat(100))).is_err());
+    let ok = format!("{}x{}", "(".repeat(20), ")".repeat(20));
+    assert!(parse(&ok).unwrap().is_some());
+}
+
+#[rocket::async_test]
+async fn list_search_supports_regex_filters() {
+    use rocket::local::asynchronous::Client;
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let user_id = state.users.ensure("osu", 1, "alice");
+    for id in ["a", "b", "c"] {
+        state
+            .videos
+            .insert(id.into(), owned_meta(id, "video/mp4", &user_id, 100));
+    }
+    state.videos.get_mut("b").unwrap().source_link = Some("https://youtu.be/xyz".into());
+    state.comments.insert(
+        "c".into(),
+        vec![Comment {
+            id: "c1".into(),
+            video_id: "c".into(),
+            author_provider: "osu".into(),
+            author_id: 1,
+            author_name: "alice".into(),
+            author_user: user_id.clone(),
+            text: "timestamp 12:34 please".into(),
+            created_at: chrono::Utc::now(),
+            parent_id: None,
+            edited_at: None,
+            revisions: Vec::new(),
+            reactions: Default::default(),
+            hidden: false,
+            anchor: None,
+        }],
+    );
+    let rocket = rocket::build()
+        .manage(state)
+        .mount("/", rocket::routes![crate::routes::videos::list_videos]);
+    let client = Client::tracked(rocket).await.unwrap();
+    let search = |q: &str| {
+        let q: String = q.bytes().map(|b| format!("%{b:02X}")).collect();
+        let uri = format!("/videos?q={q}");
+        let client = &client;
+        async move { client.get(uri).dispatch().await }
+    };
+    let ids = |body: serde_json::Value| {
+        let mut ids: Vec<String> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["id"].as_str().unwrap().to_owned())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    let res = search(r#"title =~ "^title [ab]$""#).await;
+    assert_eq!(ids(res.into_json().await.unwrap()), ["a", "b"]);
+    let res = search(r#"source.matches("youtu\.?be")"#).await;
+    assert_eq!(ids(res.into_json().await.unwrap()), ["b"]);
+    let res = search(r#"replies =~ "\d+:\d{2}""#).await;
+    assert_eq!(ids(res.into_json().await.unwrap()), ["c"]);
+
+    let res = search(r#"title =~ "(unclosed""#).await;
+    assert_eq!(res.status(), Status::BadRequest);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["position"], 9);
+    assert!(body["message"].as_str().unwrap().contains("invalid regex"));
+
+    let res = search(r#"title =~ "\w{1000}{1000}""#).await;
+    assert_eq!(res.status(), Status::BadRequest);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert!(body["message"].as_str().unwrap().contains("too complex"));
+}
+
+#[test]
+fn search_index_stems_ranks_and_updates_incrementally() {
+    use crate::{
+        query::FreeText,
+        search::{SearchField, SearchIndex, TextQuery},
+    };
+
+    let index = SearchIndex::default();
+    let mut cats = owned_meta("a", "video/mp4", "u", 1);
+    cats.title = "Cats playing piano".into();
+    let mut dog = owned_meta("b", "text/plain", "u", 1);
+    dog.title = "Notes".into();
+    dog.source_name = Some("The piano cat archive".into());
+    index.index_media(&cats, None);
+    index.index_media(&dog, Some("A dog played <the> piano & sang."));
+
+    let words = FreeText::Words("cat plays");
+    let query = TextQuery::new(&[words], &[words]);
+    assert!(index.matches("a", &query, words));
+    let words = FreeText::Words("cat dogs");
+    let query = TextQuery::new(&[words], &[words]);
+    assert!(!index.matches("a", &query, words));
+    assert!(index.matches("b", &query, words));
+
+    let phrase = FreeText::Phrase("piano cat");
+    let query = TextQuery::new(&[phrase], &[phrase]);
+    assert!(!index.matches("a", &query, phrase));
+    assert!(index.matches("b", &query, phrase));
+
+    let words = FreeText::Words("piano");
+    let query = TextQuery::new(&[words], &[words]);
+    assert!(index.score("a", &query) > index.score("b", &query));
+    let words = FreeText::Words("piano sang");
+    let query = TextQuery::new(&[words], &[words]);
+    let snippet = index.snippet("b", &query).unwrap();
+    assert_eq!(snippet.field, SearchField::Body);
+    assert_eq!(
+        snippet.html,
+        "A dog played &lt;the&gt; <mark>piano</mark> &amp; <mark>sang</mark>."
+    );
+
+    let mut long = owned_meta("c", "text/plain", "u", 1);
+    long.title = "Long".into();
+    let body = format!(
+        "{}the harp was tuned{}",
+        "filler ".repeat(5000),
+        " filler".repeat(5000)
+    );
+    index.index_media(&long, Some(&body));
+    let words = FreeText::Words("harp");
+    let query = TextQuery::new(&[words], &[words]);
+    let snippet = index.snippet("c", &query).unwrap();
+    assert_eq!(
+        snippet.html,
+        format!(
+            "…filler filler filler filler filler the <mark>harp</mark> was tuned{}…",
+            " filler".repeat(15)
+        )
+    );
+
+    let comment = |text: &str| Comment {
+        id: "c".into(),
+        video_id: "a".into(),
+        author_provider: "osu".into(),
+        author_id: 1,
+        author_name: "alice".into(),
+        author_user: "u".into(),
+        text: text.into(),
+        created_at: chrono::Utc::now(),
+        parent_id: None,
+        edited_at: None,
+        revisions: Vec::new(),
+        reactions: Default::default(),
+        hidden: false,
+        anchor: None,
+    };
+    let words = FreeText::Words("encore");
+    let query = TextQuery::new(&[words], &[words]);
+    index.index_comments("a", &[comment("first"), comment("Encore!")]);
+    assert!(index.matches("a", &query, words));
+    let phrase = FreeText::Phrase("first encore");
+    let query = TextQuery::new(&[phrase], &[phrase]);
+    assert!(!index.matches("a", &query, phrase));
+
+    let words = FreeText::Words("encore");
+    let query = TextQuery::new(&[words], &[words]);
+    index.index_comments("a", &[]);
+    assert!(!index.matches("a", &query, words));
+    index.remove("b");
+    let words = FreeText::Words("dog");
+    let query = TextQuery::new(&[words], &[words]);
+    assert!(!index.matches("b", &query, words));
+}
+
+#[rocket::async_test]
+async fn list_search_falls_back_to_fuzzy_titles() {
+    use rocket::local::asynchronous::Client;
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let user_id = state.users.ensure("osu", 1, "alice");
+    for (id, title) in [("hello", "Hello world"), ("other", "Something else")] {
+        let mut meta = owned_meta(id, "video/mp4", &user_id, 10);
+        meta.title = title.into();
+        state.persist_video(&meta);
+        state.videos.insert(id.into(), meta);
+    }
+    let rocket = rocket::build()
+        .manage(state)
+        .mount("/", rocket::routes![crate::routes::videos::list_videos]);
+    let client = Client::tracked(rocket).await.unwrap();
+    let ids = |q: &'static str| {
+        let client = &client;
+        async move {
+            let body: serde_json::Value = client
+                .get(format!("/videos?q={q}"))
+                .dispatch()
+                .await
+                .into_json()
+                .await
+                .unwrap();
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v["id"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(ids("Hel").await, ["hello"]);
+    assert_eq!(ids("helo%20wrld").await, ["hello"]);
+    assert_eq!(ids("world").await, ["hello"]);
+    assert!(ids("zzz").await.is_empty());
+}
+
+#[rocket::async_test]
+async fn list_search_covers_text_bodies_and_comments() {
+    use rocket::local::asynchronous::Client;
+
+    let dir = temp_upload_dir();
+    std::fs::write(
+        std::path::Path::new(&dir).join("notes"),
+        "Shopping list: apples, oranges and bananas.",
+    )
+    .unwrap();
+    let state = test_state(ProviderRegistry::new(Vec::new()).unwrap(), dir);
+    let user_id = state.users.ensure("osu", 1, "alice");
+    for (id, mime) in [("notes", "text/plain"), ("clip", "video/mp4")] {
+        let meta = owned_meta(id, mime, &user_id, 10);
+        state.persist_video(&meta);
+        state.videos.insert(id.into(), meta);
+    }
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::text::list_text,
+            crate::routes::videos::list_videos
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let res = client.get("/text?q=banana").dispatch().await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body[0]["id"], "notes");
+    assert_eq!(body[0]["snippet"]["field"], "body");
+    assert!(
+        body[0]["snippet"]["html"]
+            .as_str()
+            .unwrap()
+            .contains("<mark>bananas</mark>")
+    );
+
+    let res = client
+        .get("/text?q=%22oranges%20apples%22")
+        .dispatch()
+        .await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body, serde_json::json!([]));
+
+    let res = client.get("/videos?q=encore").dispatch().await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body, serde_json::json!([]));
+    let state = client.rocket().state::<AppState>().unwrap();
+    state
+        .comments
+        .entry("clip".into())
+        .or_default()
+        .push(Comment {
+            id: "c1".into(),
+            video_id: "clip".into(),
+            author_provider: "osu".into(),
+            author_id: 1,
+            author_name: "alice".into(),
+            author_user: user_id.clone(),
+            text: "Encore please".into(),
+            created_at: chrono::Utc::now(),
+            parent_id: None,
+            edited_at: None,
+            revisions: Vec::new(),
+            reactions: Default::default(),
+            hidden: false,
+            anchor: None,
+        });
+    state.persist_comments("clip");
+    let res = client.get("/videos?q=encore").dispatch().await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body[0]["id"], "clip");
+
+    let res = client.get("/videos?q=replies%20~%20encore").dispatch().await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    state.comments.get_mut("clip").unwrap()[0].hidden = true;
+    let res = client.get("/videos?q=replies%20~%20encore").dispatch().await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body, serde_json::json!([]));
+}
+
+#[rocket::async_test]
+async fn unified_search_spans_kinds_with_facets() {
+    use rocket::local::asynchronous::Client;
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let alice = state.users.ensure("osu", 1, "alice");
+    let bob = state.users.ensure("osu", 2, "bob");
+    for (id, mime, owner, nsfw) in [
+        ("v", "video/mp4", &alice, false),
+        ("a", "audio/mpeg", &alice, true),
+        ("i", "image/png", &bob, false),
+        ("t", "text/plain", &bob, false),
+    ] {
+        let mut meta = owned_meta(id, mime, owner, 10);
+        meta.title = format!("Sunset {id}");
+        meta.uploaded_by_id = if owner == &alice { 1 } else { 2 };
+        meta.nsfw = nsfw;
+        state.persist_video(&meta);
+        state.videos.insert(id.into(), meta);
+    }
+    let rocket = rocket::build()
+        .manage(state)
+        .mount("/", rocket::routes![crate::routes::search::search]);
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let res = client.get("/search?q=sunsets").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(body["total"], 4);
+    assert_eq!(
+        body["facets"]["kinds"],
+        serde_json::json!({ "video": 1, "audio": 1, "image": 1, "text": 1 })
+    );
+    assert_eq!(
+        body["facets"]["nsfw"],
+        serde_json::json!({ "nsfw": 1, "sfw": 3 })
+    );
+    let uploaders = body["facets"]["uploaders"].as_array().unwrap();
+    assert_eq!(uploaders.len(), 2);
+    assert_eq!(uploaders[0]["count"], 2);
+
+    let res = client
+        .get("/search?q=sunset%20kind.is(image)%20OR%20kind%20%3D%20text")
+        .dispatch()
+        .await;
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    let mut ids: Vec<&str> = body["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["id"].as_str().unwrap())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, ["i", "t"]);
+    assert_eq!(body["facets"]["kinds"]["video"], 0);
+
+    let res = client.get("/search?q=kind%20%3D%20movie").dispatch().await;
+    assert_eq!(res.status(), Status::BadRequest);
+}
+
+#[test]
+fn tags_normalize_resolve_and_merge_through_aliases() {
+    use crate::tags::{MAX_TAGS, TagStore, normalize};
+
+    assert_eq!(normalize("  Lo  Fi "), Some("lo-fi".into()));
+    assert_eq!(normalize("c++"), None);
+    assert_eq!(normalize(""), None);
+    assert_eq!(normalize(&"a".repeat(33)), None);
+
+    let dir = temp_upload_dir();
+    let state = test_state(ProviderRegistry::new(Vec::new()).unwrap(), dir.clone());
+    let user_id = state.users.ensure("osu", 1, "alice");
+    for id in ["v1", "v2"] {
+        let mut meta = owned_meta(id, "video/mp4", &user_id, 10);
+        meta.tags = vec!["lofi".into(), "chill".into()];
+        state.videos.insert(id.into(), meta);
+    }
+
+    assert_eq!(state.tags.set_alias("Lo Fi", "lofi").unwrap(), "lofi");
+    assert_eq!(
+        state.tags.set_alias("lofi", "lo-fi-hiphop").unwrap(),
+        "lo-fi-hiphop"
+    );
+    assert_eq!(state.merge_tag("lofi", "lo-fi-hiphop"), 2);
+    assert_eq!(
+        state.videos.get("v1").unwrap().tags,
+        ["lo-fi-hiphop", "chill"]
+    );
+    // Aliases of a merged tag follow it to the new target.
+    assert_eq!(state.tags.canonical("lo-fi"), "lo-fi-hiphop");
+    assert!(matches!(
+        state.tags.set_alias("chill", "Chill"),
+        Err(AppError::InvalidTag(_))
+    ));
+
+    assert_eq!(
+        state
+            .tags
+            .resolve(&["LOFI".into(), "Lo Fi".into(), "cats".into()])
+            .unwrap(),
+        ["lo-fi-hiphop", "cats"]
+    );
+    assert!(matches!(
+        state.tags.resolve(&["#cats".into()]),
+        Err(AppError::InvalidTag(t)) if t == "#cats"
+    ));
+    let many: Vec<String> = (0..=MAX_TAGS).map(|i| format!("t{i}")).collect();
+    assert!(matches!(
+        state.tags.resolve(&many),
+        Err(AppError::TooManyTags)
+    ));
+
+    let reloaded = TagStore::load(&dir);
+    assert_eq!(reloaded.canonical("lofi"), "lo-fi-hiphop");
+    assert_eq!(reloaded.remove_alias("lofi").unwrap(), "lo-fi-hiphop");
+    assert_eq!(reloaded.remove_alias("Lo Fi").unwrap(), "lo-fi-hiphop");
+    assert!(matches!(
+        reloaded.remove_alias("lofi"),
+        Err(AppError::TagNotFound)
+    ));
+}
+
+#[rocket::async_test]
+async fn collections_keep_order_hide_unlisted_and_feed_tags() {
+    use {crate::collections::MAX_ITEMS, rocket::local::asynchronous::Client};
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let alice = state.users.ensure("osu", 1, "alice");
+    for (id, unlisted) in [("v1", false), ("v2", false), ("v3", true)] {
+        let mut meta = owned_meta(id, "video/mp4", &alice, 10);
+        meta.unlisted = unlisted;
+        meta.tags = vec!["cats".into()];
+        state.persist_video(&meta);
+        state.videos.insert(id.into(), meta);
+    }
+    state.tags.set_alias("kitties", "cats").unwrap();
+
+    assert!(matches!(
+        state
+            .collections
+            .create(&alice, CollectionKind::Collection, "  ", ""),
+        Err(AppError::InvalidCollectionTitle)
+    ));
+    let collection = state
+        .collections
+        .create(&alice, CollectionKind::Collection, "Best cats", "")
+        .unwrap();
+    let id = collection.id.clone();
+    state
+        .collections
+        .update(&id, |c| {
+            c.items = vec!["v2".into(), "v3".into(), "v1".into()];
+            Ok(())
+        })
+        .unwrap();
+    assert!(matches!(
+        state.collections.update(&id, |c| {
+            c.items = vec![String::new(); MAX_ITEMS + 1];
+            Ok(())
+        }),
+        Err(AppError::CollectionFull)
+    ));
+    assert_eq!(state.collections.get(&id).unwrap().items.len(), 3);
+    assert_eq!(state.collections.containing("v1").len(), 1);
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::collections::get_collection,
+            crate::routes::collections::list_collections,
+            crate::routes::feed::feed_tag,
+            crate::routes::feed::feed_collection,
+            crate::routes::search::search,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let body: serde_json::Value = client
+        .get(format!("/collections/{id}"))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let items: Vec<&str> = body["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(items, ["v2", "v1"]);
+    assert_eq!(body["item_count"], 2);
+    assert_eq!(body["owner_name"], "alice");
+
+    let body: serde_json::Value = client
+        .get("/collections?media=v3")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body[0]["id"], id);
+    assert!(body[0].get("items").is_none());
+
+    let body: serde_json::Value = client
+        .get("/search?q=tag.is(Kitties)")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["items"][0]["tags"], serde_json::json!(["cats"]));
+
+    let feed = client
+        .get("/feed/tags/kitties")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(feed.contains("<category>cats</category>"));
+    assert!(feed.contains("Title v1"));
+    assert!(!feed.contains("Title v3"));
+
+    let feed = client
+        .get(format!("/feed/c/{id}"))
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(feed.contains("<title>Best cats</title>"));
+    assert!(!feed.contains("Title v3"));
+
+    let state = client.rocket().state::<AppState>().unwrap();
+    state.videos.remove("v1");
+    state.delete_video_meta("v1");
+    assert_eq!(state.collections.get(&id).unwrap().items, ["v2", "v3"]);
+}
+
+#[rocket::async_test]
+async fn playlists_only_take_playable_media() {
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::asynchronous::Client,
+    };
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let alice = state.users.ensure("osu", 1, "alice");
+    for (id, mime) in [
+        ("v1", "video/mp4"),
+        ("a1", "audio/mpeg"),
+        ("i1", "image/png"),
+    ] {
+        let meta = owned_meta(id, mime, &alice, 10);
+        state.persist_video(&meta);
+        state.videos.insert(id.into(), meta);
+    }
+    let raw = bearer_token_for(&state, "osu", 1, "alice", TokenScope::Upload);
+    let shelf = state
+        .collections
+        .create(&alice, CollectionKind::Collection, "Shelf", "")
+        .unwrap();
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::collections::create_collection,
+            crate::routes::collections::list_collections,
+            crate::routes::collections::add_item,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let auth = || Header::new("Authorization", format!("Bearer {raw}"));
+
+    let res = client
+        .post("/collections")
+        .header(ContentType::JSON)
+        .header(auth())
+        .body(r#"{"kind":"playlist","title":"Mix"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Created);
+    let playlist: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(playlist["kind"], "playlist");
+    let id = playlist["id"].as_str().unwrap();
+
+    for media in ["v1", "a1"] {
+        let res = client
+            .post(format!("/collections/{id}/items"))
+            .header(ContentType::JSON)
+            .header(auth())
+            .body(format!(r#"{{"media_id":"{media}"}}"#))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+    let res = client
+        .post(format!("/collections/{id}/items"))
+        .header(ContentType::JSON)
+        .header(auth())
+        .body(r#"{"media_id":"i1"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::BadRequest);
+    let res = client
+        .post(format!("/collections/{}/items", shelf.id))
+        .header(ContentType::JSON)
+        .header(auth())
+        .body(r#"{"media_id":"i1"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let body: serde_json::Value = client
+        .get("/collections?kind=playlist")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["id"], id);
+    assert_eq!(body[0]["item_count"], 2);
+}
+
+#[rocket::async_test]
+async fn comments_thread_edit_and_react() {
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::asynchronous::Client,
+    };
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let alice = state.users.ensure("osu", 1, "alice");
+    for id in ["v1", "v2"] {
+        let meta = owned_meta(id, "video/mp4", &alice, 10);
+        state.persist_video(&meta);
+        state.videos.insert(id.into(), meta);
+    }
+    let alice_token = bearer_token_for(&state, "osu", 1, "alice", TokenScope::Comment);
+    let bob_token = bearer_token_for(&state, "osu", 2, "bob", TokenScope::Comment);
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::videos::add_comment,
+            crate::routes::videos::get_comment_thread,
+            crate::routes::videos::edit_comment,
+            crate::routes::videos::get_comment_revisions,
+            crate::routes::videos::add_reaction,
+            crate::routes::videos::remove_reaction,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let auth = |raw: &str| Header::new("Authorization", format!("Bearer {raw}"));
+    let post = |media: &'static str, token: &str, body: String| {
+        client
+            .post(format!("/videos/{media}/comments"))
+            .header(ContentType::JSON)
+            .header(auth(token))
+            .body(body)
+    };
+
+    let root: serde_json::Value = post("v1", &alice_token, r#"{"text":"first"}"#.into())
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let root_id = root["id"].as_str().unwrap().to_owned();
+    assert_eq!(root["html"], "<p>first</p>\n");
+    post("v1", &alice_token, r#"{"text":"second"}"#.into())
+        .dispatch()
+        .await;
+    let other: serde_json::Value = post("v2", &alice_token, r#"{"text":"elsewhere"}"#.into())
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+
+    for parent in ["missing", other["id"].as_str().unwrap()] {
+        let res = post(
+            "v1",
+            &bob_token,
+            format!(r#"{{"text":"reply","parent_id":"{parent}"}}"#),
+        )
+        .dispatch()
+        .await;
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    let mut parent = root_id.clone();
+    for _ in 0..crate::comments::MAX_DEPTH {
+        let reply: serde_json::Value = post(
+            "v1",
+            &bob_token,
+            format!(r#"{{"text":"reply","parent_id":"{parent}"}}"#),
+        )
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+        parent = reply["id"].as_str().unwrap().to_owned();
+    }
+    let res = post(
+        "v1",
+        &bob_token,
+        format!(r#"{{"text":"too deep","parent_id":"{parent}"}}"#),
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::BadRequest);
+
+    let page: serde_json::Value = client
+        .get("/videos/v1/comments/thread?limit=1&depth=1")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["next_cursor"], root_id.as_str());
+    let first = &page["comments"][0];
+    assert_eq!(first["reply_count"], 1);
+    assert_eq!(first["replies"][0]["reply_count"], 1);
+    assert!(
+        first["replies"][0]["replies"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+    let page: serde_json::Value = client
+        .get(format!(
+            "/videos/v1/comments/thread?limit=1&cursor={root_id}"
+        ))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(page["comments"][0]["text"], "second");
+    assert!(page["next_cursor"].is_null());
+
+    let edit = |token: &str| {
+        client
+            .patch(format!("/videos/v1/comments/{root_id}"))
+            .header(ContentType::JSON)
+            .header(auth(token))
+            .body(r#"{"text":"first, edited"}"#)
+    };
+    assert_eq!(
+        edit(&bob_token).dispatch().await.status(),
+        Status::Forbidden
+    );
+    let edited: serde_json::Value = edit(&alice_token)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(edited["text"], "first, edited");
+    assert!(edited["edited_at"].is_string());
+    let revisions: serde_json::Value = client
+        .get(format!("/videos/v1/comments/{root_id}/revisions"))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let texts: Vec<&str> = revisions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, ["first", "first, edited"]);
+
+    let react = |token: &str, emoji: &str, on: bool| {
+        let uri = format!("/videos/v1/comments/{root_id}/reactions/{emoji}");
+        let req = if on {
+            client.put(uri)
+        } else {
+            client.delete(uri)
+        };
+        req.header(auth(token))
+    };
+    let thumbs = "%F0%9F%91%8D";
+    for _ in 0..2 {
+        let counts: serde_json::Value = react(&bob_token, thumbs, true)
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(counts[0]["count"], 1);
+        assert_eq!(counts[0]["reacted"], true);
+    }
+    react(&alice_token, thumbs, true).dispatch().await;
+    let counts: serde_json::Value = react(&bob_token, thumbs, false)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(counts[0]["emoji"], "👍");
+    assert_eq!(counts[0]["count"], 1);
+    assert_eq!(counts[0]["reacted"], false);
+    assert_eq!(
+        react(&bob_token, "a", true).dispatch().await.status(),
+        Status::BadRequest
+    );
+}
+
+#[test]
+fn comment_text_is_counted_in_chars_and_edits_are_capped() {
+    use crate::comments::{MAX_REVISIONS, MAX_TEXT_LEN, edit, validate_text};
+
+    assert!(validate_text(&"é".repeat(MAX_TEXT_LEN)).is_ok());
+    assert!(validate_text(&"é".repeat(MAX_TEXT_LEN + 1)).is_err());
+
+    let mut comment: Comment = serde_json::from_value(serde_json::json!({
+        "id": "c1",
+        "video_id": "v1",
+        "author_id": 1,
+        "author_name": "alice",
+        "text": "v0",
+        "created_at": "2024-01-01T00:00:00Z",
+    }))
+    .unwrap();
+    assert!(!edit(&mut comment, "v0".into()).unwrap());
+    for n in 1..=MAX_REVISIONS {
+        assert!(edit(&mut comment, format!("v{n}")).unwrap());
+    }
+    assert!(matches!(
+        edit(&mut comment, "one more".into()),
+        Err(AppError::TooManyEdits)
+    ));
+    assert_eq!(comment.revisions.len(), MAX_REVISIONS);
+    assert_eq!(comment.text, format!("v{MAX_REVISIONS}"));
+}
+
+#[rocket::async_test]
+async fn reports_spam_rules_and_hidden_comments() {
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::asynchronous::Client,
+    };
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let alice = state.users.ensure("osu", 1, "alice");
+    let meta = owned_meta("v1", "video/mp4", &alice, 10);
+    state.persist_video(&meta);
+    state.videos.insert("v1".into(), meta);
+    let alice_token = bearer_token_for(&state, "osu", 1, "alice", TokenScope::Comment);
+    let bob_token = bearer_token_for(&state, "osu", 2, "bob", TokenScope::Comment);
+    let admin_token = bearer_token_for(&state, "osu", 3, "admin", TokenScope::Admin);
+    let admin = state.users.user_id_for("osu", 3).unwrap();
+    state.users.set_role(&admin, Some(Role::Admin)).unwrap();
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::videos::add_comment,
+            crate::routes::videos::get_comments,
+            crate::routes::moderation::create_report,
+            crate::routes::moderation::list_reports,
+            crate::routes::moderation::resolve_report,
+            crate::routes::moderation::put_rules,
+            crate::routes::moderation::list_audit,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let auth = |raw: &str| Header::new("Authorization", format!("Bearer {raw}"));
+    let post = |token: &str, text: &str| {
+        client
+            .post("/videos/v1/comments")
+            .header(ContentType::JSON)
+            .header(auth(token))
+            .body(serde_json::json!({ "text": text }).to_string())
+    };
+
+    let rules: serde_json::Value = client
+        .put("/admin/moderation/rules")
+        .header(ContentType::JSON)
+        .header(auth(&admin_token))
+        .body(
+            r#"{"blocked_words":["Casino"," casino "],"blocked_domains":["https://spam.example/"],"max_links":1}"#,
+        )
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(rules["blocked_words"], serde_json::json!(["casino"]));
+    assert_eq!(
+        rules["blocked_domains"],
+        serde_json::json!(["spam.example"])
+    );
+
+    for spam in [
+        "Best CASINO in town!",
+        "see http://a.example and http://b.example",
+        "cheap stuff at www.shop.spam.example",
+    ] {
+        let res = post(&bob_token, spam).dispatch().await;
+        assert_eq!(res.status(), Status::UnprocessableEntity, "{spam}");
+    }
+    assert_eq!(
+        post(&bob_token, "casinos are a whole other word")
+            .dispatch()
+            .await
+            .status(),
+        Status::Created
+    );
+
+    let comment: serde_json::Value = post(&alice_token, "hello")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let comment_id = comment["id"].as_str().unwrap().to_owned();
+    let report = |body: serde_json::Value| {
+        client
+            .post("/reports")
+            .header(ContentType::JSON)
+            .header(auth(&bob_token))
+            .body(body.to_string())
+            .dispatch()
+    };
+    let body = serde_json::json!({ "media_id": "v1", "comment_id": comment_id, "reason": "spam" });
+    assert_eq!(report(body.clone()).await.status(), Status::Created);
+    assert_eq!(report(body).await.status(), Status::Conflict);
+    assert_eq!(
+        report(serde_json::json!({ "media_id": "v1", "reason": "other" }))
+            .await
+            .status(),
+        Status::BadRequest
+    );
+
+    let queue: serde_json::Value = client
+        .get("/admin/reports")
+        .header(auth(&admin_token))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(queue.as_array().unwrap().len(), 1);
+    assert_eq!(queue[0]["comment_text"], "hello");
+    assert_eq!(queue[0]["reporter_name"], "bob");
+
+    let report_id = queue[0]["id"].as_str().unwrap();
+    let resolved: serde_json::Value = client
+        .post(format!("/admin/reports/{report_id}/resolve"))
+        .header(ContentType::JSON)
+        .header(auth(&admin_token))
+        .body(r#"{"action":"hide","note":"looks like spam"}"#)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(resolved["outcome"], "hidden");
+
+    let text_for = |token: Option<&str>| {
+        let mut req = client.get("/videos/v1/comments");
+        if let Some(token) = token {
+            req = req.header(auth(token));
+        }
+        let comment_id = comment_id.clone();
+        async move {
+            let comments: Vec<serde_json::Value> = req.dispatch().await.into_json().await.unwrap();
+            let c = comments.iter().find(|c| c["id"] == comment_id).unwrap();
+            assert_eq!(c["hidden"], true);
+            c["text"].as_str().unwrap().to_owned()
+        }
+    };
+    assert_eq!(text_for(None).await, "");
+    assert_eq!(text_for(Some(&bob_token)).await, "");
+    assert_eq!(text_for(Some(&alice_token)).await, "hello");
+    assert_eq!(text_for(Some(&admin_token)).await, "hello");
+
+    let audit: Vec<serde_json::Value> = client
+        .get("/admin/audit")
+        .header(auth(&admin_token))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let actions: Vec<&str> = audit
+        .iter()
+        .map(|e| e["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(actions, ["hide_comment", "update_spam_rules"]);
+    assert_eq!(audit[0]["note"], "looks like spam");
+    assert_eq!(
+        client
+            .get("/admin/audit")
+            .header(auth(&bob_token))
+            .dispatch()
+            .await
+            .status(),
+        Status::Forbidden
+    );
+}
+
+#[rocket::async_test]
+async fn owners_cannot_relist_uploads_a_moderator_hid() {
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::asynchronous::Client,
+    };
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let alice = state.users.ensure("osu", 1, "alice");
+    let meta = owned_meta("v1", "video/mp4", &alice, 10);
+    state.persist_video(&meta);
+    state.videos.insert("v1".into(), meta);
+    let alice_token = bearer_token_for(&state, "osu", 1, "alice", TokenScope::Upload);
+    let bob_token = bearer_token_for(&state, "osu", 2, "bob", TokenScope::Comment);
+    let admin_token = bearer_token_for(&state, "osu", 3, "admin", TokenScope::Admin);
+    state
+        .api_tokens
+        .get_mut(&hash_api_token(&admin_token))
+        .unwrap()
+        .scopes
+        .push(TokenScope::Upload);
+    let admin = state.users.user_id_for("osu", 3).unwrap();
+    state.users.set_role(&admin, Some(Role::Admin)).unwrap();
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::videos::patch_meta,
+            crate::routes::moderation::create_report,
+            crate::routes::moderation::resolve_report,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let auth = |raw: &str| Header::new("Authorization", format!("Bearer {raw}"));
+    let report: serde_json::Value = client
+        .post("/reports")
+        .header(ContentType::JSON)
+        .header(auth(&bob_token))
+        .body(r#"{"media_id":"v1","reason":"other","details":"not ok"}"#)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let res = client
+        .post(format!(
+            "/admin/reports/{}/resolve",
+            report["id"].as_str().unwrap()
+        ))
+        .header(ContentType::JSON)
+        .header(auth(&admin_token))
+        .body(r#"{"action":"hide"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+
+    let relist = |token: &str| {
+        client
+            .patch("/videos/v1")
+            .header(ContentType::JSON)
+            .header(auth(token))
+            .body(r#"{"unlisted":false}"#)
+            .dispatch()
+    };
+    assert_eq!(relist(&alice_token).await.status(), Status::Forbidden);
+    let state = client.rocket().state::<AppState>().unwrap();
+    assert!(state.videos.get("v1").unwrap().unlisted);
+
+    assert_eq!(relist(&admin_token).await.status(), Status::Ok);
+    let meta = state.videos.get("v1").unwrap().clone();
+    assert!(!meta.unlisted && !meta.hidden_by_moderator);
+}
+
+#[test]
+fn audit_log_appends_lines_and_migrates_the_old_file() {
+    use crate::{
+        models::ModAction,
+        moderation::{AuditLog, RECENT_AUDIT_ENTRIES},
+    };
+
+    let dir = temp_upload_dir();
+    let legacy = serde_json::json!([{
+        "id": "old",
+        "at": "2024-01-01T00:00:00Z",
+        "actor_user": "osu:1",
+        "action": "hide_comment",
+        "note": "from the array file",
+    }]);
+    std::fs::write(
+        std::path::Path::new(&dir).join("audit_log.json"),
+        legacy.to_string(),
+    )
+    .unwrap();
+
+    let log = AuditLog::load(&dir);
+    assert!(!std::path::Path::new(&dir).join("audit_log.json").exists());
+    assert_eq!(log.recent(10)[0].id, "old");
+    log.record("osu:1", ModAction::DeleteMedia, Some("v1"), None, None, "");
+
+    let lines =
+        std::fs::read_to_string(std::path::Path::new(&dir).join("audit_log.jsonl")).unwrap();
+    assert_eq!(lines.lines().count(), 2);
+    let reloaded = AuditLog::load(&dir);
+    let recent = reloaded.recent(10);
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0].media_id.as_deref(), Some("v1"));
+
+    for _ in 0..RECENT_AUDIT_ENTRIES {
+        reloaded.record("osu:1", ModAction::HideComment, None, None, None, "");
+    }
+    assert_eq!(reloaded.recent(usize::MAX).len(), RECENT_AUDIT_ENTRIES);
+    let reloaded = AuditLog::load(&dir);
+    assert_eq!(reloaded.recent(usize::MAX).len(), RECENT_AUDIT_ENTRIES);
+    assert!(reloaded.recent(usize::MAX).iter().all(|e| e.id != "old"));
+}
+
+#[test]
+fn comment_markdown_is_sanitized_and_linked() {
+    use crate::markdown::{RenderOptions, render};
+
+    let users = UserStore::load(&temp_upload_dir());
+    users.ensure("osu", 7, "alice");
+    let video = RenderOptions {
+        timestamps: true,
+        duration_secs: Some(600.0),
+    };
+    let plain = RenderOptions::default();
+
+    let html = render("**bold** <script>alert(1)</script>", plain, &users);
+    assert!(html.contains("<strong>bold</strong>"), "{html}");
+    assert!(html.contains("&lt;script&gt;"), "{html}");
+    assert!(!html.contains("<script"), "{html}");
+
+    for evil in [
+        "[x](javascript:alert(1))",
+        "![x](data:image/png;base64,AAAA)",
+    ] {
+        let html = render(evil, plain, &users);
+        assert!(
+            !html.contains("javascript:") && !html.contains("data:"),
+            "{html}"
+        );
+    }
+    let html = render("![cat](https://example.com/cat.png)", plain, &users);
+    assert!(!html.contains("<img"), "{html}");
+    assert!(
+        html.contains(
+            r#"<a href="https://example.com/cat.png" rel="nofollow noopener ugc">cat</a>"#
+        ),
+        "{html}"
+    );
+
+    let html = render("see https://example.com/a.", plain, &users);
+    assert!(html.contains(r#"href="https://example.com/a""#), "{html}");
+
+    let html = render("at 1:23, 1:02:03 and 59:99, not 20:00", video, &users);
+    assert!(
+        html.contains(r##"<a href="#t=83" rel="nofollow noopener ugc">1:23</a>"##),
+        "{html}"
+    );
+    assert!(!html.contains("#t=3723"), "past the end: {html}");
+    assert!(
+        !html.contains("59:99</a>") && !html.contains("20:00</a>"),
+        "{html}"
+    );
+    assert!(!render("at 1:23", plain, &users).contains("<a"));
+    assert!(!render("`1:23`", video, &users).contains("<a"));
+
+    let html = render("@Alice hi, @nobody and bob@example.com", plain, &users);
+    assert!(
+        html.contains(r#"<a href="/ui/u/osu/7" rel="nofollow noopener ugc">@Alice</a>"#),
+        "{html}"
+    );
+    assert_eq!(html.matches("<a").count(), 1, "{html}");
+
+    // A name used on two providers only links with the provider spelled out.
+    users.ensure("github", 9, "alice");
+    let html = render("@alice and @github/Alice", plain, &users);
+    assert!(
+        html.contains(r#"<a href="/ui/u/github/9" rel="nofollow noopener ugc">@github/Alice</a>"#),
+        "{html}"
+    );
+    assert_eq!(html.matches("<a").count(), 1, "{html}");
+    assert!(!render("@gitlab/alice", plain, &users).contains("<a"));
+}
+
+#[rocket::async_test]
+async fn comment_anchors_follow_the_media_kind() {
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::asynchronous::Client,
+    };
+
+    let state = test_state(
+        ProviderRegistry::new(Vec::new()).unwrap(),
+        temp_upload_dir(),
+    );
+    let alice = state.users.ensure("osu", 1, "alice");
+    for (id, mime) in [
+        ("v1", "video/mp4"),
+        ("i1", "image/png"),
+        ("t1", "text/plain"),
+    ] {
+        let mut meta = owned_meta(id, mime, &alice, 10);
+        meta.duration_secs = (id == "v1").then_some(60.0);
+        state.persist_video(&meta);
+        state.videos.insert(id.into(), meta);
+    }
+    let token = bearer_token_for(&state, "osu", 1, "alice", TokenScope::Comment);
+
+    let rocket = rocket::build().manage(state).mount(
+        "/",
+        rocket::routes![
+            crate::routes::videos::add_comment,
+            crate::routes::videos::get_comment_thread,
+            crate::routes::images::add_comment,
+            crate::routes::text::add_comment,
+        ],
+    );
+    let client = Client::tracked(rocket).await.unwrap();
+    let post = |path: &'static str, anchor: &str| {
+        client
+            .post(path)
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {token}")))
+            .body(format!(r#"{{"text":"look","anchor":{anchor}}}"#))
+    };
+
+    let res = post("/videos/v1/comments", r#"{"start_ms":1500,"end_ms":4000}"#)
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Created);
+    let created: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(
+        created["anchor"],
+        serde_json::json!({ "start_ms": 1500, "end_ms": 4000 })
+    );
+    let page: serde_json::Value = client
+        .get("/videos/v1/comments/thread")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(page["comments"][0]["anchor"]["start_ms"], 1500);
+
+    for (path, anchor) in [
+        ("/videos/v1/comments", r#"{"end_ms":4000}"#),
+        ("/videos/v1/comments", r#"{"start_ms":4000,"end_ms":4000}"#),
+        ("/videos/v1/comments", r#"{"start_ms":61000}"#),
+        (
+            "/videos/v1/comments",
+            r#"{"start_ms":0,"region":{"x":0,"y":0,"w":1,"h":1}}"#,
+        ),
+        ("/images/i1/comments", r#"{"start_ms":0}"#),
+        (
+            "/images/i1/comments",
+            r#"{"region":{"x":0.5,"y":0.5,"w":0.6,"h":0.1}}"#,
+        ),
+        (
+            "/images/i1/comments",
+            r#"{"region":{"x":0.1,"y":0.1,"w":0,"h":0.1}}"#,
+        ),
+        ("/text/t1/comments", r#"{"start_ms":0}"#),
+    ] {
+        let res = post(path, anchor).dispatch().await;
+        assert_eq!(res.status(), Status::BadRequest, "{path} {anchor}");
+    }
+
+    let res = post(
+        "/images/i1/comments",
+        r#"{"region":{"x":0.25,"y":0.5,"w":0.5,"h":0.5}}"#,
+    )
+    .dispatch()
+    .await;
+    assert_eq!(res.status(), Status::Created);
+    let created: serde_json::Value = res.into_json().await.unwrap();
+    assert_eq!(created["anchor"]["region"]["w"], 0.5);
+    assert!(created["anchor"].get("start_ms").is_none());
+
+    let res = client
+        .post("/videos/v1/comments")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("Bearer {token}")))
+        .body(r#"{"text":"plain"}"#)
+        .dispatch()
+        .await;
+    let created: serde_json::Value = res.into_json().await.unwrap();
+    assert!(created.get("anchor").is_none());
+}
@@ -0,0 +1,259 @@
+use {
+    crate::{
+        error::{AppError, AppResult},
+        models::{Identity, PlatformUser, Role, User},
+    },
+    chrono::Utc,
+    dashmap::DashMap,
+    std::path::{Path, PathBuf},
+};
+
+/// Internal user records and the external identities linked to them.
+pub struct UserStore {
+    path: PathBuf,
+    users: DashMap<String, User>,
+    /// `(provider, external id)` → internal user id.
+    identities: DashMap<(String, u64), String>,
+}
+
+impl UserStore {
+    const FILE_NAME: &'static str = "users.json";
+
+    pub fn load(upload_dir: &str) -> Self {
+        let path = Path::new(upload_dir).join(Self::FILE_NAME);
+        let users: Vec<User> = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("could not parse {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        let identities = users
+            .iter()
+            .flat_map(|u| {
+                u.identities
+                    .iter()
+                    .map(|i| ((i.provider.clone(), i.external_id), u.id.clone()))
+            })
+            .collect();
+        Self {
+            path,
+            users: users.into_iter().map(|u| (u.id.clone(), u)).collect(),
+            identities,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<User> {
+        self.users.get(id).map(|u| u.clone())
+    }
+
+    pub fn user_id_for(&self, provider: &str, external_id: u64) -> Option<String> {
+        self.identities
+            .get(&(provider.to_owned(), external_id))
+            .map(|id| id.clone())
+    }
+
+    /// Returns the id of the user owning this identity, creating a new user
+    /// the first time the identity is seen.
+    pub fn ensure(&self, provider: &str, external_id: u64, username: &str) -> String {
+        if let Some(id) = self.user_id_for(provider, external_id) {
+            return id;
+        }
+        let user = self.create(PlatformUser {
+            provider: provider.to_owned(),
+            id: external_id,
+            username: username.to_owned(),
+            avatar_url: String::new(),
+            user_id: String::new(),
+        });
+        self.persist();
+        user.id
+    }
+
+    /// Records a login: finds or creates the user and refreshes the
+    /// identity's username and avatar.
+    pub fn sign_in(&self, platform_user: &PlatformUser) -> User {
+        let Some(user_id) = self.user_id_for(&platform_user.provider, platform_user.id) else {
+            let user = self.create(platform_user.clone());
+            self.persist();
+            return user;
+        };
+
+        let mut entry = self
+            .users
+            .get_mut(&user_id)
+            .expect("identity index points at a missing user");
+        let mut changed = false;
+        if let Some(identity) = entry
+            .identities
+            .iter_mut()
+            .find(|i| i.provider == platform_user.provider && i.external_id == platform_user.id)
+            && (identity.username != platform_user.username
+                || identity.avatar_url != platform_user.avatar_url)
+        {
+            identity.username = platform_user.username.clone();
+            identity.avatar_url = platform_user.avatar_url.clone();
+            changed = true;
+        }
+        let user = entry.clone();
+        drop(entry);
+        if changed {
+            self.persist();
+        }
+        user
+    }
+
+    /// Links `identity` to `user_id`. If the identity already belongs to
+    /// another user this fails with [`AppError::IdentityInUse`], unless
+    /// `merge` is set: then that user is merged in and its id returned so
+    /// that its content can be reassigned.
+    pub fn link(
+        &self,
+        user_id: &str,
+        identity: &PlatformUser,
+        merge: bool,
+    ) -> AppResult<Option<String>> {
+        if !self.users.contains_key(user_id) {
+            return Err(AppError::UserNotFound);
+        }
+
+        let merged = match self.user_id_for(&identity.provider, identity.id) {
+            Some(existing) if existing == user_id => return Ok(None),
+            Some(_) if !merge => return Err(AppError::IdentityInUse),
+            Some(existing) => {
+                let (_, other) = self
+                    .users
+                    .remove(&existing)
+                    .expect("identity index points at a missing user");
+                for i in &other.identities {
+                    self.identities
+                        .insert((i.provider.clone(), i.external_id), user_id.to_owned());
+                }
+                if let Some(mut user) = self.users.get_mut(user_id) {
+                    user.identities.extend(other.identities);
+                    // Merging must not lift a ban from either side.
+                    user.role = match (user.role, other.role) {
+                        (Some(Role::Banned), _) | (_, Some(Role::Banned)) => Some(Role::Banned),
+                        (role, other) => role.or(other),
+                    };
+                }
+                Some(existing)
+            }
+            None => {
+                self.identities
+                    .insert((identity.provider.clone(), identity.id), user_id.to_owned());
+                if let Some(mut user) = self.users.get_mut(user_id) {
+                    user.identities.push(new_identity(identity));
+                }
+                None
+            }
+        };
+        self.persist();
+        Ok(merged)
+    }
+
+    /// Detaches an identity. A user must keep at least one.
+    pub fn unlink(&self, user_id: &str, provider: &str, external_id: u64) -> AppResult<User> {
+        let mut entry = self.users.get_mut(user_id).ok_or(AppError::UserNotFound)?;
+        let idx = entry
+            .identities
+            .iter()
+            .position(|i| i.provider == provider && i.external_id == external_id)
+            .ok_or(AppError::IdentityNotFound)?;
+        if entry.identities.len() == 1 {
+            return Err(AppError::LastIdentity);
+        }
+        entry.identities.remove(idx);
+        let user = entry.clone();
+        drop(entry);
+        self.identities.remove(&(provider.to_owned(), external_id));
+        self.persist();
+        Ok(user)
+    }
+
+    /// The identity called `username`, ignoring case, for `@mentions`,
+    /// optionally only among identities from `provider`. `None` if no
+    /// account or more than one uses the name, so a mention never picks one.
+    pub fn find_by_username(&self, provider: Option<&str>, username: &str) -> Option<Identity> {
+        let mut matches = self.users.iter().flat_map(|u| {
+            u.identities
+                .iter()
+                .filter(|i| {
+                    provider.is_none_or(|p| i.provider == p)
+                        && i.username.eq_ignore_ascii_case(username)
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        });
+        let identity = matches.next()?;
+        matches.next().is_none().then_some(identity)
+    }
+
+    /// Current username of the identity `<provider>/<external_id>` of
+    /// `user_id`, or of the user's first identity if that one was unlinked.
+    pub fn display_name(&self, user_id: &str, provider: &str, external_id: u64) -> Option<String> {
+        let user = self.users.get(user_id)?;
+        user.identities
+            .iter()
+            .find(|i| i.provider == provider && i.external_id == external_id)
+            .or_else(|| user.identities.first())
+            .map(|i| i.username.clone())
+    }
+
+    /// Users with a role other than the default, for the admin panel.
+    pub fn with_roles(&self) -> Vec<User> {
+        let mut users: Vec<User> = self
+            .users
+            .iter()
+            .filter(|u| u.role.is_some())
+            .map(|u| u.clone())
+            .collect();
+        users.sort_by_key(|u| u.created_at);
+        users
+    }
+
+    /// Sets or, with `None`, clears the stored role.
+    pub fn set_role(&self, user_id: &str, role: Option<Role>) -> AppResult<User> {
+        let mut entry = self.users.get_mut(user_id).ok_or(AppError::UserNotFound)?;
+        entry.role = role.filter(|&r| r != Role::User);
+        let user = entry.clone();
+        drop(entry);
+        self.persist();
+        Ok(user)
+    }
+
+    fn create(&self, platform_user: PlatformUser) -> User {
+        let user = User {
+            id: uuid::Uuid::new_v4().to_string(),
+            created_at: Utc::now(),
+            identities: vec![new_identity(&platform_user)],
+            role: None,
+        };
+        self.identities
+            .insert((platform_user.provider, platform_user.id), user.id.clone());
+        self.users.insert(user.id.clone(), user.clone());
+        user
+    }
+
+    fn persist(&self) {
+        let users: Vec<User> = self.users.iter().map(|e| e.value().clone()).collect();
+        match serde_json::to_string_pretty(&users) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&self.path, json) {
+                    tracing::warn!("could not write users to {:?}: {}", self.path, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize users: {}", e),
+        }
+    }
+}
+
+fn new_identity(user: &PlatformUser) -> Identity {
+    Identity {
+        provider: user.provider.clone(),
+        external_id: user.id,
+        username: user.username.clone(),
+        avatar_url: user.avatar_url.clone(),
+        linked_at: Utc::now(),
+    }
+}
@@ -0,0 +1,16 @@
+/// A byte count in binary units, e.g. `1.5 KB`, for messages and pages.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    for unit in UNITS {
+        if size < 1024.0 {
+            return if size.fract() < 0.05 {
+                format!("{:.0} {}", size, unit)
+            } else {
+                format!("{:.1} {}", size, unit)
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} PB", size)
+}